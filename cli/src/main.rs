@@ -8,7 +8,11 @@ use anyhow::{Context, Result};
 use clap::{Parser, Subcommand};
 use futures::StreamExt;
 use libp2p::{Multiaddr, PeerId};
-use node::{FileMetadata, TunConfig, TunInterface};
+use node::{
+    AddressPool, AddressPoolConfig, ExitNode, FileMetadata, FilteredForwarder, Firewall,
+    FirewallRule, IpPrefix, MeshForwarder, NatConfig, PacketFilter, RuleAction, TapForwarder,
+    TlsConfig, TlsServer, TunConfig, TunInterface,
+};
 use p2p::{
     dial_peer, register_packet_recv_tx, send_file_to_peer, set_file_transfer_callback,
     start_key_rotation, start_listener, start_metrics_server, DataChunk, Libp2pPacketForwarder,
@@ -17,7 +21,14 @@ use p2p::{
 use std::collections::HashMap;
 use std::fs::File;
 use std::io::Write;
-use std::{env, net::SocketAddr, path::PathBuf, sync::Arc, time::Duration};
+use std::{
+    env,
+    net::{IpAddr, SocketAddr},
+    path::PathBuf,
+    str::FromStr,
+    sync::Arc,
+    time::Duration,
+};
 
 #[derive(Parser, Debug)]
 #[command(name = "cryprq", about = "Post-Quantum VPN")]
@@ -37,8 +48,86 @@ struct Args {
     #[arg(long, default_value = "cryprq0", help = "TUN interface name")]
     tun_name: String,
 
-    #[arg(long, default_value = "10.0.0.1", help = "TUN interface IP address")]
-    tun_address: String,
+    #[arg(
+        long,
+        help = "TUN interface IP address (listener: pool gateway, dialer: leased from listener if unset)"
+    )]
+    tun_address: Option<String>,
+
+    #[arg(
+        long,
+        default_value = "10.0.0.0/24",
+        help = "Tunnel address pool leased to dialers (CIDR, listener only)"
+    )]
+    tun_pool: String,
+
+    #[arg(
+        long,
+        help = "File to persist address leases across restarts (listener only)"
+    )]
+    lease_file: Option<PathBuf>,
+
+    #[arg(
+        long,
+        help = "File holding this node's libp2p identity, created if missing (VPN dialers default to ~/.cryprq/identity.key so their leased address survives restarts)"
+    )]
+    identity_file: Option<PathBuf>,
+
+    #[arg(
+        long,
+        help = "Mesh VPN mode: one TUN shared by all peers, routed by destination"
+    )]
+    mesh: bool,
+
+    #[arg(
+        long = "mesh-route",
+        value_name = "PEER_ID=CIDR",
+        help = "Static mesh route to a peer (repeatable)"
+    )]
+    mesh_routes: Vec<String>,
+
+    #[arg(
+        long,
+        requires_all = ["vpn", "listen"],
+        conflicts_with = "mesh",
+        help = "Exit node: NAT peer traffic to the internet through this host (no TUN needed)"
+    )]
+    exit: bool,
+
+    #[arg(
+        long,
+        requires = "vpn",
+        conflicts_with_all = ["mesh", "exit"],
+        help = "TAP mode: bridge Ethernet frames (layer 2) instead of IP packets (Linux only)"
+    )]
+    tap: bool,
+
+    #[arg(
+        long,
+        help = "TUN/TAP interface MTU (listener: also leased to dialers) [default: 1420]"
+    )]
+    mtu: Option<u16>,
+
+    #[arg(
+        long = "allowed-ip",
+        value_name = "CIDR",
+        help = "Source prefix accepted from the peer (repeatable, VPN mode)"
+    )]
+    allowed_ips: Vec<String>,
+
+    #[arg(
+        long = "firewall-rule",
+        value_name = "RULE",
+        help = "Firewall rule, e.g. \"allow in tcp 22\" (repeatable, first match wins)"
+    )]
+    firewall_rules: Vec<String>,
+
+    #[arg(
+        long,
+        default_value = "allow",
+        help = "Firewall action for packets matching no rule (allow|deny)"
+    )]
+    firewall_default: String,
 
     #[arg(long, help = "Metrics server address")]
     metrics: Option<SocketAddr>,
@@ -63,6 +152,32 @@ enum Command {
         /// Output directory for received files
         #[arg(long, default_value = ".")]
         output_dir: PathBuf,
+        /// Also accept TLS streams here (host:port) from peers whose UDP is blocked
+        #[arg(long, requires_all = ["tls_cert", "tls_key", "tls_ca"])]
+        stream_listen: Option<String>,
+        /// TLS server certificate chain (PEM) for --stream-listen
+        #[arg(long)]
+        tls_cert: Option<String>,
+        /// TLS server private key (PEM) for --stream-listen
+        #[arg(long)]
+        tls_key: Option<String>,
+        /// CA (PEM) that issued the client certificates --stream-listen accepts
+        #[arg(long)]
+        tls_ca: Option<String>,
+    },
+    /// Measure round-trip latency to a peer through the tunnel
+    Ping {
+        /// Peer address (multiaddr)
+        peer: String,
+        /// Number of PINGs to send
+        #[arg(short, long, default_value_t = 4)]
+        count: u32,
+        /// Seconds between PINGs
+        #[arg(short, long, default_value_t = 1.0)]
+        interval: f64,
+        /// Seconds to wait for each PONG
+        #[arg(long, default_value_t = 2.0)]
+        timeout: f64,
     },
 }
 
@@ -78,8 +193,33 @@ async fn main() -> Result<()> {
             Command::SendFile { peer, file } => {
                 return handle_send_file(peer, file).await;
             }
-            Command::ReceiveFile { listen, output_dir } => {
-                return handle_receive_file(listen, output_dir).await;
+            Command::ReceiveFile {
+                listen,
+                output_dir,
+                stream_listen,
+                tls_cert,
+                tls_key,
+                tls_ca,
+            } => {
+                let stream_listen = stream_listen.map(|addr| {
+                    let config = TlsConfig {
+                        cert_path: tls_cert,
+                        key_path: tls_key,
+                        ca_cert_path: tls_ca,
+                        require_client_auth: true,
+                        ..TlsConfig::default()
+                    };
+                    (addr, config)
+                });
+                return handle_receive_file(listen, output_dir, stream_listen).await;
+            }
+            Command::Ping {
+                peer,
+                count,
+                interval,
+                timeout,
+            } => {
+                return handle_ping(peer, count, interval, timeout).await;
             }
         }
     }
@@ -104,48 +244,114 @@ async fn main() -> Result<()> {
         start_key_rotation(rotation_interval).await;
     });
 
+    // Keep the peer ID across restarts: listeners key address leases by it
+    let identity_file = args.identity_file.clone().or_else(|| {
+        let leases_address = args.vpn && args.peer.is_some() && args.tun_address.is_none();
+        leases_address
+            .then(|| env::var_os("HOME"))
+            .flatten()
+            .map(|home| PathBuf::from(home).join(".cryprq").join("identity.key"))
+    });
+    if let Some(path) = identity_file {
+        let keypair = p2p::load_or_create_identity(&path)?;
+        p2p::set_local_identity(keypair).await;
+    }
+
     // Handle VPN mode - store TUN interface in shared state for callback access
     let tun_interface_shared: Arc<tokio::sync::Mutex<Option<TunInterface>>> =
         Arc::new(tokio::sync::Mutex::new(None));
 
+    // Dialer routes sent through the connected peer in mesh mode
+    let mut uplink_routes = Vec::new();
+
     if args.vpn {
         log::info!("VPN MODE ENABLED - System-wide routing mode");
         log::info!("Creating TUN interface for packet forwarding...");
 
-        let tun_config = TunConfig {
-            name: args.tun_name.clone(),
-            address: args.tun_address.clone(),
-            netmask: "255.255.255.0".to_string(),
-            mtu: 1420,
+        let tun_config = if args.tap {
+            // TAP is unnumbered unless given an address (e.g. when bridged)
+            Some(TunConfig {
+                name: args.tun_name.clone(),
+                address: args.tun_address.clone().unwrap_or_default(),
+                mtu: args.mtu.unwrap_or(TunConfig::default().mtu),
+                tap: true,
+                ..TunConfig::default()
+            })
+        } else if args.listen.is_some() {
+            // Listener owns the address pool and takes the gateway address
+            let mut pool_config =
+                AddressPoolConfig::from_cidr(&args.tun_pool).context("Invalid --tun-pool")?;
+            pool_config.gateway = args
+                .tun_address
+                .as_deref()
+                .map(str::parse)
+                .transpose()
+                .context("Invalid --tun-address")?;
+            pool_config.state_file = args.lease_file.clone();
+            if let Some(mtu) = args.mtu {
+                pool_config.mtu = mtu;
+            }
+            let pool = AddressPool::new(pool_config).context("Failed to create address pool")?;
+            let tun_config = pool.tun_config(&args.tun_name);
+            p2p::set_address_pool(Arc::new(std::sync::Mutex::new(pool))).await;
+            Some(tun_config)
+        } else {
+            args.tun_address.as_ref().map(|address| TunConfig {
+                name: args.tun_name.clone(),
+                address: address.clone(),
+                mtu: args.mtu.unwrap_or(TunConfig::default().mtu),
+                ..TunConfig::default()
+            })
         };
 
-        let tun = TunInterface::create(tun_config)
-            .await
-            .context("Failed to create TUN interface")?;
-
-        // Try to configure IP (may fail without root/admin)
-        if let Err(e) = tun.configure_ip().await {
-            log::warn!(
-                "Failed to configure TUN interface IP (may need root/admin): {}",
-                e
-            );
-            log::warn!("VPN mode: P2P tunnel encryption is active, but system routing requires Network Extension");
-        } else {
-            log::info!(
-                "TUN interface {} configured with IP {}",
-                tun.name(),
-                args.tun_address
-            );
+        if args.listen.is_none() {
+            uplink_routes = tun_config.as_ref().map(tun_routes).unwrap_or_default();
         }
+        match tun_config {
+            Some(_) if args.exit => {
+                log::info!(
+                    "Exit mode: peer traffic is NATed through this host, no TUN interface created"
+                )
+            }
+            Some(tun_config) => {
+                let tun = create_vpn_tun(tun_config).await?;
+                // Store TUN interface in shared state
+                *tun_interface_shared.lock().await = Some(tun);
+            }
+            None => {
+                log::info!("No --tun-address given - TUN address will be leased from the listener")
+            }
+        }
+    }
 
-        // Store TUN interface in shared state
-        *tun_interface_shared.lock().await = Some(tun);
+    let firewall = build_firewall(&args.firewall_rules, &args.firewall_default)?;
+    let packet_filter = build_packet_filter(&args.allowed_ips, firewall.clone())?;
+
+    if args.vpn && args.mesh {
+        log::info!("Mesh mode: TUN interface shared by all peers, routed by destination");
+        setup_mesh(
+            &args.mesh_routes,
+            firewall,
+            tun_interface_shared.clone(),
+            args.tun_name.clone(),
+            args.listen.is_some(),
+            args.tun_address.is_none(),
+            uplink_routes,
+        )
+        .await?;
+    }
+
+    if args.vpn && args.tap {
+        log::info!("TAP mode: Ethernet frames switched between the TAP interface and all peers");
+        setup_tap(tun_interface_shared.clone()).await?;
     }
 
     // Start listener or dialer
     if let Some(addr) = args.listen {
         println!("Starting listener on {}", addr);
-        if args.vpn {
+        if args.exit {
+            setup_exit().await;
+        } else if args.vpn && !args.mesh && !args.tap {
             log::info!("VPN Mode: Listener will accept connections and route traffic through TUN interface");
             log::warn!(
                 "Note: Full system-wide routing requires Network Extension framework on macOS"
@@ -154,10 +360,12 @@ async fn main() -> Result<()> {
             // Set up callback to start packet forwarding when connection is established
             let tun_shared = tun_interface_shared.clone();
             let tun_name = args.tun_name.clone();
+            let packet_filter = packet_filter.clone();
 
             p2p::set_connection_callback(Arc::new(move |peer_id, swarm, _recv_tx| {
                 let tun_shared_clone = tun_shared.clone();
                 let tun_name_clone = tun_name.clone();
+                let packet_filter = packet_filter.clone();
 
                 tokio::spawn(async move {
                     log::info!("Connection established with {peer_id} - Starting VPN packet forwarding");
@@ -176,11 +384,10 @@ async fn main() -> Result<()> {
                         // Register recv_tx channel so swarm event handler can forward packets
                         register_packet_recv_tx(peer_id, forwarder_recv_tx.clone()).await;
 
-                        let forwarder_arc = Arc::new(tokio::sync::Mutex::new(forwarder));
 
                         // Start packet forwarding loop
                         log::info!("Starting packet forwarding loop - routing system traffic through encrypted tunnel");
-                        if let Err(e) = tun.start_forwarding(forwarder_arc).await {
+                        if let Err(e) = start_peer_forwarding(&mut tun, forwarder, packet_filter).await {
                             log::error!("Failed to start packet forwarding: {}", e);
                         } else {
                             log::info!("Packet forwarding loop started successfully");
@@ -194,7 +401,7 @@ async fn main() -> Result<()> {
         start_listener(&addr).await?;
     } else if let Some(peer_addr) = args.peer {
         println!("Dialing peer {}", peer_addr);
-        if args.vpn {
+        if args.vpn && !args.mesh && !args.tap {
             log::info!("VPN Mode: Dialer will establish encrypted tunnel and route traffic through TUN interface");
             log::warn!(
                 "Note: Full system-wide routing requires Network Extension framework on macOS"
@@ -203,17 +410,29 @@ async fn main() -> Result<()> {
             // Set up callback to start packet forwarding when connection is established
             let tun_shared = tun_interface_shared.clone();
             let tun_name = args.tun_name.clone();
+            let packet_filter = packet_filter.clone();
+            let lease_address = args.tun_address.is_none();
 
             p2p::set_connection_callback(Arc::new(move |peer_id, swarm, _recv_tx| {
                 let tun_shared_clone = tun_shared.clone();
                 let tun_name_clone = tun_name.clone();
+                let packet_filter = packet_filter.clone();
 
                 tokio::spawn(async move {
                     log::info!("Connected to {peer_id} - Starting VPN packet forwarding");
-                    log::info!("TUN interface {} ready - packets will be forwarded through encrypted tunnel", tun_name_clone);
 
-                    // Get TUN interface from shared state
+                    // Get TUN interface from shared state, leasing an address first if needed
                     let mut tun_guard = tun_shared_clone.lock().await;
+                    if tun_guard.is_none() && lease_address {
+                        match p2p::request_address_lease(swarm.clone(), peer_id, None, Duration::from_secs(10)).await {
+                            Ok(lease) => match create_vpn_tun(TunConfig::from_lease(&tun_name_clone, &lease)).await {
+                                Ok(tun) => *tun_guard = Some(tun),
+                                Err(e) => log::error!("Failed to create TUN interface from lease: {}", e),
+                            },
+                            Err(e) => log::error!("Failed to obtain address lease from {}: {}", peer_id, e),
+                        }
+                    }
+                    log::info!("TUN interface {} ready - packets will be forwarded through encrypted tunnel", tun_name_clone);
                     if let Some(mut tun) = tun_guard.take() {
                         // Create packet forwarder
                         let (forwarder, _send_tx, _recv_rx) = Libp2pPacketForwarder::new(swarm.clone(), peer_id);
@@ -222,11 +441,10 @@ async fn main() -> Result<()> {
                         // Register recv_tx channel so swarm event handler can forward packets
                         register_packet_recv_tx(peer_id, forwarder_recv_tx.clone()).await;
 
-                        let forwarder_arc = Arc::new(tokio::sync::Mutex::new(forwarder));
 
                         // Start packet forwarding loop
                         log::info!("Starting packet forwarding loop - routing system traffic through encrypted tunnel");
-                        if let Err(e) = tun.start_forwarding(forwarder_arc).await {
+                        if let Err(e) = start_peer_forwarding(&mut tun, forwarder, packet_filter).await {
                             log::error!("Failed to start packet forwarding: {}", e);
                         } else {
                             log::info!("Packet forwarding loop started successfully");
@@ -250,12 +468,343 @@ async fn main() -> Result<()> {
     Ok(())
 }
 
-async fn handle_send_file(peer_addr: String, file_path: PathBuf) -> Result<()> {
-    use node::FileMetadata;
-    use sha2::{Digest, Sha256};
-    use std::fs::File;
-    use std::io::Read;
+/// Set up mesh VPN mode: one TUN interface shared by every connected peer
+///
+/// Outgoing packets are routed by destination using static `--mesh-route`
+/// entries, addresses leased to peers (hub side) and the tunnel network of
+/// the dialed peer (dialer side). Incoming packets from all peers merge into
+/// the TUN writer.
+async fn setup_mesh(
+    static_routes: &[String],
+    firewall: Option<Arc<Firewall>>,
+    tun_shared: Arc<tokio::sync::Mutex<Option<TunInterface>>>,
+    tun_name: String,
+    hub: bool,
+    lease_address: bool,
+    uplink_routes: Vec<IpPrefix>,
+) -> Result<()> {
+    let forwarder: MeshForwarder<PeerId> = MeshForwarder::new();
+    let router = forwarder.router();
+    if let Some(firewall) = firewall {
+        router.set_firewall(firewall);
+    }
+    for entry in static_routes {
+        let (peer_id, prefix) = parse_mesh_route(entry)?;
+        router.add_route(prefix, peer_id);
+    }
+
+    // Forwarding starts as soon as the TUN exists (dialers may still need a lease)
+    let forwarder = Arc::new(tokio::sync::Mutex::new(Some(forwarder)));
+    start_mesh_forwarding(&tun_shared, &forwarder).await;
+
+    // Addresses leased to peers route straight to them
+    let lease_router = router.clone();
+    p2p::set_lease_callback(Arc::new(move |peer_id, lease| {
+        lease_router.add_route(IpPrefix::host(IpAddr::V4(lease.address)), peer_id);
+    }))
+    .await;
+
+    let disconnect_router = router.clone();
+    p2p::set_disconnect_callback(Arc::new(move |peer_id| {
+        disconnect_router.remove_peer(&peer_id);
+        log::info!(
+            "event=mesh_peer_removed peer_id={} peers={}",
+            peer_id,
+            disconnect_router.peer_count()
+        );
+    }))
+    .await;
 
+    p2p::set_connection_callback(Arc::new(move |peer_id, swarm, _recv_tx| {
+        let router = router.clone();
+        let tun_shared = tun_shared.clone();
+        let forwarder = forwarder.clone();
+        let tun_name = tun_name.clone();
+        let mut routes = uplink_routes.clone();
+
+        tokio::spawn(async move {
+            // Outbound via a per-peer send task, inbound merged into the mesh TUN writer
+            let (_peer_forwarder, send_tx, _recv_rx) =
+                Libp2pPacketForwarder::new(swarm.clone(), peer_id);
+            router.add_peer(peer_id, (*send_tx).clone());
+            register_packet_recv_tx(
+                peer_id,
+                Arc::new(tokio::sync::Mutex::new(router.incoming_for(peer_id))),
+            )
+            .await;
+            log::info!(
+                "event=mesh_peer_added peer_id={} peers={}",
+                peer_id,
+                router.peer_count()
+            );
+
+            if !hub {
+                // Dialer: lease an address from this peer if we have no TUN yet
+                let mut tun_guard = tun_shared.lock().await;
+                let forwarding = forwarder.lock().await.is_none();
+                if tun_guard.is_none() && !forwarding && lease_address {
+                    match p2p::request_address_lease(
+                        swarm.clone(),
+                        peer_id,
+                        None,
+                        Duration::from_secs(10),
+                    )
+                    .await
+                    {
+                        Ok(lease) => {
+                            let config = TunConfig::from_lease(&tun_name, &lease);
+                            routes = tun_routes(&config);
+                            match create_vpn_tun(config).await {
+                                Ok(tun) => *tun_guard = Some(tun),
+                                Err(e) => {
+                                    log::error!("Failed to create TUN interface from lease: {}", e)
+                                }
+                            }
+                        }
+                        Err(e) => {
+                            log::error!("Failed to obtain address lease from {}: {}", peer_id, e)
+                        }
+                    }
+                }
+                drop(tun_guard);
+                for prefix in routes {
+                    router.add_route(prefix, peer_id);
+                }
+            }
+
+            start_mesh_forwarding(&tun_shared, &forwarder).await;
+        });
+    }))
+    .await;
+
+    Ok(())
+}
+
+/// Set up TAP mode: a learning L2 switch between the TAP interface and all peers
+async fn setup_tap(tun_shared: Arc<tokio::sync::Mutex<Option<TunInterface>>>) -> Result<()> {
+    let mut tap = tun_shared
+        .lock()
+        .await
+        .take()
+        .context("TAP interface not created")?;
+    let forwarder: TapForwarder<PeerId> = TapForwarder::new(tap.mtu());
+    let switch = forwarder.switch();
+    tokio::spawn(async move {
+        log::info!("Starting TAP frame forwarding on {}", tap.name());
+        if let Err(e) = tap
+            .start_forwarding(Arc::new(tokio::sync::Mutex::new(forwarder)))
+            .await
+        {
+            log::error!("Failed to start TAP frame forwarding: {}", e);
+        }
+    });
+
+    let disconnect_switch = switch.clone();
+    p2p::set_disconnect_callback(Arc::new(move |peer_id| {
+        disconnect_switch.remove_peer(&peer_id);
+        log::info!(
+            "event=tap_peer_removed peer_id={} peers={}",
+            peer_id,
+            disconnect_switch.peer_count()
+        );
+    }))
+    .await;
+
+    p2p::set_connection_callback(Arc::new(move |peer_id, swarm, _recv_tx| {
+        let switch = switch.clone();
+        tokio::spawn(async move {
+            let (_peer_forwarder, send_tx, _recv_rx) =
+                Libp2pPacketForwarder::new(swarm.clone(), peer_id);
+            switch.add_peer(peer_id, (*send_tx).clone());
+            register_packet_recv_tx(
+                peer_id,
+                Arc::new(tokio::sync::Mutex::new(switch.incoming_for(peer_id))),
+            )
+            .await;
+            log::info!(
+                "event=tap_peer_added peer_id={} peers={}",
+                peer_id,
+                switch.peer_count()
+            );
+        });
+    }))
+    .await;
+
+    Ok(())
+}
+
+/// Set up exit mode: every peer's traffic is NATed through the host's sockets
+async fn setup_exit() {
+    let exit: Arc<ExitNode<PeerId>> = ExitNode::spawn(NatConfig::default());
+
+    // Peers may only send from the address leased to them
+    let lease_exit = exit.clone();
+    p2p::set_lease_callback(Arc::new(move |peer_id, lease| {
+        lease_exit.set_peer_address(peer_id, lease.address);
+    }))
+    .await;
+
+    let disconnect_exit = exit.clone();
+    p2p::set_disconnect_callback(Arc::new(move |peer_id| {
+        disconnect_exit.remove_peer(&peer_id);
+    }))
+    .await;
+
+    p2p::set_connection_callback(Arc::new(move |peer_id, swarm, _recv_tx| {
+        let exit = exit.clone();
+        tokio::spawn(async move {
+            let (_peer_forwarder, send_tx, _recv_rx) =
+                Libp2pPacketForwarder::new(swarm.clone(), peer_id);
+            exit.add_peer(peer_id, (*send_tx).clone());
+            register_packet_recv_tx(
+                peer_id,
+                Arc::new(tokio::sync::Mutex::new(exit.incoming_for(peer_id))),
+            )
+            .await;
+            log::info!("event=exit_peer_added peer_id={}", peer_id);
+        });
+    }))
+    .await;
+}
+
+/// Forward between the TUN and a single peer, applying the packet filter if configured
+async fn start_peer_forwarding(
+    tun: &mut TunInterface,
+    forwarder: Libp2pPacketForwarder,
+    packet_filter: Option<PacketFilter>,
+) -> Result<()> {
+    match packet_filter {
+        Some(filter) => {
+            let filtered = FilteredForwarder::new(forwarder, filter);
+            tun.start_forwarding(Arc::new(tokio::sync::Mutex::new(filtered)))
+                .await
+        }
+        None => {
+            tun.start_forwarding(Arc::new(tokio::sync::Mutex::new(forwarder)))
+                .await
+        }
+    }
+}
+
+/// Build the stateful firewall from `--firewall-rule` (None if nothing to enforce)
+fn build_firewall(rules: &[String], default_action: &str) -> Result<Option<Arc<Firewall>>> {
+    let default_action = RuleAction::from_str(default_action).map_err(|e| anyhow::anyhow!(e))?;
+    if rules.is_empty() && default_action == RuleAction::Allow {
+        return Ok(None);
+    }
+    let rules = rules
+        .iter()
+        .map(|rule| FirewallRule::from_str(rule).map_err(|e| anyhow::anyhow!(e)))
+        .collect::<Result<Vec<_>>>()?;
+    Ok(Some(Arc::new(Firewall::new(rules, default_action))))
+}
+
+/// Build the single-peer packet filter from `--allowed-ip` and the firewall
+///
+/// Without `--allowed-ip` any source is accepted and only the firewall applies.
+fn build_packet_filter(
+    allowed_ips: &[String],
+    firewall: Option<Arc<Firewall>>,
+) -> Result<Option<PacketFilter>> {
+    if allowed_ips.is_empty() && firewall.is_none() {
+        return Ok(None);
+    }
+    let mut prefixes = allowed_ips
+        .iter()
+        .map(|cidr| IpPrefix::from_str(cidr).map_err(|e| anyhow::anyhow!(e)))
+        .collect::<Result<Vec<_>>>()?;
+    if prefixes.is_empty() {
+        for any in ["0.0.0.0/0", "::/0"] {
+            prefixes.push(IpPrefix::from_str(any).map_err(|e| anyhow::anyhow!(e))?);
+        }
+    }
+    let filter = PacketFilter::new(prefixes);
+    Ok(Some(match firewall {
+        Some(firewall) => filter.with_firewall(firewall),
+        None => filter,
+    }))
+}
+
+/// Start the mesh forwarding loop once both the TUN and the forwarder are available
+async fn start_mesh_forwarding(
+    tun_shared: &Arc<tokio::sync::Mutex<Option<TunInterface>>>,
+    forwarder: &Arc<tokio::sync::Mutex<Option<MeshForwarder<PeerId>>>>,
+) {
+    let mut tun_guard = tun_shared.lock().await;
+    let mut forwarder_guard = forwarder.lock().await;
+    if tun_guard.is_none() || forwarder_guard.is_none() {
+        return;
+    }
+    if let (Some(mut tun), Some(mesh)) = (tun_guard.take(), forwarder_guard.take()) {
+        tokio::spawn(async move {
+            log::info!("Starting mesh packet forwarding on {}", tun.name());
+            if let Err(e) = tun
+                .start_forwarding(Arc::new(tokio::sync::Mutex::new(mesh)))
+                .await
+            {
+                log::error!("Failed to start mesh packet forwarding: {}", e);
+            }
+        });
+    }
+}
+
+/// Parse a `PEER_ID=CIDR` mesh route
+fn parse_mesh_route(entry: &str) -> Result<(PeerId, IpPrefix)> {
+    let (peer, prefix) = entry
+        .split_once('=')
+        .with_context(|| format!("Invalid --mesh-route {} (expected PEER_ID=CIDR)", entry))?;
+    let peer_id = PeerId::from_str(peer).context("Invalid peer ID in --mesh-route")?;
+    let prefix = IpPrefix::from_str(prefix).map_err(|e| anyhow::anyhow!(e))?;
+    Ok((peer_id, prefix))
+}
+
+/// Tunnel network and extra routes of a TUN configuration
+fn tun_routes(config: &TunConfig) -> Vec<IpPrefix> {
+    let network = config
+        .address
+        .parse::<std::net::Ipv4Addr>()
+        .ok()
+        .zip(config.netmask.parse::<std::net::Ipv4Addr>().ok())
+        .and_then(|(addr, mask)| {
+            IpPrefix::new(IpAddr::V4(addr), u32::from(mask).count_ones() as u8)
+        });
+    network
+        .into_iter()
+        .chain(
+            config
+                .routes
+                .iter()
+                .filter_map(|(dest, len)| IpPrefix::new(IpAddr::V4(*dest), *len)),
+        )
+        .collect()
+}
+
+/// Create the VPN TUN interface and configure its address
+async fn create_vpn_tun(tun_config: TunConfig) -> Result<TunInterface> {
+    let address = tun_config.address.clone();
+    let tun = TunInterface::create(tun_config)
+        .await
+        .context("Failed to create TUN interface")?;
+
+    // Try to configure IP (may fail without root/admin)
+    if let Err(e) = tun.configure_ip().await {
+        log::warn!(
+            "Failed to configure TUN interface IP (may need root/admin): {}",
+            e
+        );
+        log::warn!("VPN mode: P2P tunnel encryption is active, but system routing requires Network Extension");
+    } else {
+        log::info!(
+            "TUN interface {} configured with IP {}",
+            tun.name(),
+            address
+        );
+    }
+
+    Ok(tun)
+}
+
+async fn handle_send_file(peer_addr: String, file_path: PathBuf) -> Result<()> {
     log::info!("Sending file: {:?} to peer: {}", file_path, peer_addr);
 
     // Parse peer address - extract UDP socket address
@@ -281,6 +830,7 @@ async fn handle_send_file(peer_addr: String, file_path: PathBuf) -> Result<()> {
         .await
         .context("Failed to create tunnel")?,
     );
+    tunnel.set_batch_io(batch_io_enabled());
 
     // Set peer address for sending
     let peer_addr_parsed: std::net::SocketAddr = peer_socket
@@ -296,86 +846,139 @@ async fn handle_send_file(peer_addr: String, file_path: PathBuf) -> Result<()> {
 
     log::info!("Tunnel created, connecting to peer at {}", peer_socket);
 
-    // Calculate file metadata
-    let file_size = std::fs::metadata(&file_path)?.len();
-    let filename = file_path
-        .file_name()
-        .and_then(|n| n.to_str())
-        .ok_or_else(|| anyhow::anyhow!("Invalid filename"))?
-        .to_string();
-
-    // Calculate SHA-256 hash
-    let mut file_for_hash = File::open(&file_path)?;
-    let mut hasher = Sha256::new();
-    let mut buffer = vec![0u8; 65536];
-    loop {
-        let bytes_read = file_for_hash.read(&mut buffer)?;
-        if bytes_read == 0 {
-            break;
+    // Receive loop: stream ACKs drive retransmission and congestion control
+    let tunnel_recv = tunnel.clone();
+    tokio::spawn(async move {
+        loop {
+            if let Err(e) = tunnel_recv.recv_and_handle_record().await {
+                log::debug!("Receive loop error: {}", e);
+            }
         }
-        hasher.update(&buffer[..bytes_read]);
-    }
-    let file_hash: [u8; 32] = hasher.finalize().into();
+    });
 
-    let meta = FileMetadata::new(filename.clone(), file_size, file_hash);
+    // Send over a reliable stream, paced by the congestion window
+    let meta = tunnel
+        .send_file(&file_path)
+        .await
+        .context("Failed to send file")?;
 
-    // Allocate stream ID for this transfer
-    let stream_id = tunnel.file_transfer().alloc_stream_id();
     log::info!(
-        "Allocated stream_id={} for file transfer: {} ({} bytes)",
-        stream_id,
-        filename,
-        file_size
+        "File transfer complete: {} sent ({} bytes, acknowledged by peer)",
+        meta.filename,
+        meta.size
     );
 
-    // Send FILE_META
-    tunnel
-        .send_file_meta(stream_id, &meta)
+    Ok(())
+}
+
+async fn handle_ping(peer_addr: String, count: u32, interval: f64, timeout: f64) -> Result<()> {
+    let (peer_ip, peer_port) = parse_udp_addr(&peer_addr)?;
+    let peer_socket: std::net::SocketAddr = format!("{}:{}", peer_ip, peer_port)
+        .parse()
+        .context("Failed to parse peer socket address")?;
+    let interval = Duration::try_from_secs_f64(interval).context("Invalid --interval")?;
+    let timeout = Duration::try_from_secs_f64(timeout).context("Invalid --timeout")?;
+
+    // Same test-mode keys as send-file
+    let tunnel = Arc::new(
+        node::create_tunnel(
+            &[0x01; 32],
+            &[0x02; 32],
+            &[0x03; 32],
+            &[0x04; 64],
+            "0.0.0.0:0",
+        )
         .await
-        .context("Failed to send file metadata")?;
-    log::info!(
-        "Sent FILE_META: stream_id={}, filename={}",
-        stream_id,
-        filename
+        .context("Failed to create tunnel")?,
     );
+    tunnel.set_batch_io(batch_io_enabled());
+    {
+        let mut peer_addr_guard = tunnel
+            .peer_addr()
+            .write()
+            .map_err(|e| anyhow::anyhow!("Failed to acquire peer_addr lock: {}", e))?;
+        *peer_addr_guard = Some(peer_socket);
+    }
 
-    // Send file chunks
-    let mut file = File::open(&file_path)?;
-    let mut chunk_num = 0;
-    loop {
-        let bytes_read = file.read(&mut buffer)?;
-        if bytes_read == 0 {
-            break;
+    // Receive loop: PONGs are matched to their PINGs as they arrive
+    let tunnel_recv = tunnel.clone();
+    tokio::spawn(async move {
+        loop {
+            if let Err(e) = tunnel_recv.recv_and_handle_record().await {
+                log::debug!("Receive loop error: {}", e);
+            }
         }
+    });
 
-        tunnel
-            .send_file_chunk(stream_id, &buffer[..bytes_read])
-            .await
-            .context(format!("Failed to send chunk {}", chunk_num))?;
+    // Probe the path MTU alongside the pings and report what was confirmed
+    tunnel
+        .start_path_mtu_discovery()
+        .context("Failed to start path MTU discovery")?;
+
+    println!("PING {} through tunnel", peer_socket);
+    let mut rtts = Vec::new();
+    for seq in 0..count {
+        if seq > 0 {
+            tokio::time::sleep(interval).await;
+        }
+        match tunnel.ping(timeout).await {
+            Ok(rtt) => {
+                println!(
+                    "reply from {}: seq={} time={:.3} ms",
+                    peer_socket,
+                    seq,
+                    ms(rtt)
+                );
+                rtts.push(rtt);
+            }
+            Err(e) => println!("no reply from {}: seq={} ({})", peer_socket, seq, e),
+        }
+    }
 
-        log::debug!(
-            "Sent FILE_CHUNK: stream_id={}, chunk={}, size={}",
-            stream_id,
-            chunk_num,
-            bytes_read
+    let lost = count as usize - rtts.len();
+    println!(
+        "--- {} ping statistics ---\n{} sent, {} received, {:.1}% loss",
+        peer_socket,
+        count,
+        rtts.len(),
+        if count == 0 {
+            0.0
+        } else {
+            lost as f64 * 100.0 / count as f64
+        }
+    );
+    if let (Some(min), Some(max)) = (rtts.iter().min(), rtts.iter().max()) {
+        let avg = rtts.iter().sum::<Duration>() / rtts.len() as u32;
+        println!(
+            "rtt min/avg/max = {:.3}/{:.3}/{:.3} ms",
+            ms(*min),
+            ms(avg),
+            ms(*max)
         );
-
-        chunk_num += 1;
     }
-
-    log::info!(
-        "File transfer complete: {} sent ({} chunks) on stream_id={}",
-        filename,
-        chunk_num,
-        stream_id
+    if let Some(stats) = tunnel.rtt_stats() {
+        println!(
+            "smoothed rtt = {:.3} ms, jitter = {:.3} ms",
+            ms(stats.smoothed),
+            ms(stats.jitter)
+        );
+    }
+    println!(
+        "path mtu = {} bytes (tun mtu {})",
+        tunnel.path_mtu(),
+        tunnel.tun_mtu()
     );
 
-    // Wait a bit for final packets to be sent
-    tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
-
+    if rtts.is_empty() && count > 0 {
+        anyhow::bail!("No reply from {}", peer_socket);
+    }
     Ok(())
 }
 
+fn ms(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
 // Old implementation - kept for reference but not used
 #[allow(dead_code)]
 async fn handle_send_file_old(peer_addr: String, file_path: PathBuf) -> Result<()> {
@@ -540,7 +1143,11 @@ struct FileReceiveState {
     total_chunks: u32,
 }
 
-async fn handle_receive_file(listen_addr: String, output_dir: PathBuf) -> Result<()> {
+async fn handle_receive_file(
+    listen_addr: String,
+    output_dir: PathBuf,
+    stream_listen: Option<(String, TlsConfig)>,
+) -> Result<()> {
     log::info!(
         "Receiving files on: {}, output directory: {:?}",
         listen_addr,
@@ -576,6 +1183,7 @@ async fn handle_receive_file(listen_addr: String, output_dir: PathBuf) -> Result
         .await
         .context("Failed to create tunnel")?,
     );
+    tunnel.set_batch_io(batch_io_enabled());
 
     log::info!(
         "Tunnel created, listening for file transfers on {}",
@@ -599,6 +1207,33 @@ async fn handle_receive_file(listen_addr: String, output_dir: PathBuf) -> Result
         }
     });
 
+    // Peers whose UDP is blocked fall back to a TLS stream
+    if let Some((addr, config)) = stream_listen {
+        let mut server = TlsServer::new(config);
+        server
+            .listen(&addr)
+            .await
+            .with_context(|| format!("Failed to listen for TLS streams on {}", addr))?;
+        log::info!("Accepting TLS stream fallback on {}", addr);
+        tunnel
+            .serve_stream_fallback(server)
+            .context("Failed to serve TLS stream fallback")?;
+    }
+
+    // Files arrive as reliable streams; each is written and verified on its own
+    let tunnel_accept = tunnel.clone();
+    tokio::spawn(async move {
+        while let Ok(stream) = tunnel_accept.accept_stream().await {
+            let tunnel_file = tunnel_accept.clone();
+            tokio::spawn(async move {
+                match tunnel_file.receive_file(stream).await {
+                    Ok(path) => log::info!("File received: {:?}", path),
+                    Err(e) => log::error!("File transfer failed: {}", e),
+                }
+            });
+        }
+    });
+
     // Keep process alive
     log::info!("File receiver running. Press Ctrl+C to stop.");
     tokio::signal::ctrl_c().await?;
@@ -607,6 +1242,11 @@ async fn handle_receive_file(listen_addr: String, output_dir: PathBuf) -> Result
     Ok(())
 }
 
+/// Batched UDP I/O (recvmmsg/sendmmsg, GSO/GRO) unless CRYPRQ_BATCH_IO=0
+fn batch_io_enabled() -> bool {
+    env::var("CRYPRQ_BATCH_IO").map_or(true, |value| value != "0")
+}
+
 // Helper to parse UDP address from multiaddr
 fn parse_udp_addr(addr: &str) -> Result<(String, u16)> {
     // Parse multiaddr like "/ip4/0.0.0.0/udp/20440/quic-v1"
@@ -0,0 +1,644 @@
+// Copyright (c) 2025 Thor Thor
+// Author: Thor Thor (GitHub: https://github.com/codethor0)
+// Contact: codethor@gmail.com
+// LinkedIn: https://www.linkedin.com/in/thor-thor0
+// License: MIT (see LICENSE file for details)
+
+use std::fmt;
+use std::io;
+use std::net::Ipv4Addr;
+
+/// Control message type: Liveness probe; the peer answers with PONG
+pub const CTRL_PING: u8 = 0x01;
+
+/// Control message type: Answer to a PING, echoing its ID
+pub const CTRL_PONG: u8 = 0x02;
+
+/// Control message type: Close a stream or the whole session
+pub const CTRL_CLOSE: u8 = 0x03;
+
+/// Control message type: Error code and reason (Section 8.2)
+pub const CTRL_ERROR: u8 = 0x04;
+
+/// Control message type: Keeps an idle session (and NAT bindings) alive
+pub const CTRL_KEEPALIVE: u8 = 0x06;
+
+/// Control message type: Tunnel address request (dialer -> listener)
+pub const CTRL_ADDRESS_REQUEST: u8 = 0x20;
+
+/// Control message type: Tunnel address lease (listener -> dialer)
+pub const CTRL_ADDRESS_LEASE: u8 = 0x21;
+
+/// Control message type: Tunnel address release (dialer -> listener)
+pub const CTRL_ADDRESS_RELEASE: u8 = 0x22;
+
+/// Control message type: Path challenge sent to a new peer address
+pub const CTRL_PATH_CHALLENGE: u8 = 0x30;
+
+/// Control message type: Path response echoing a challenge token
+pub const CTRL_PATH_RESPONSE: u8 = 0x31;
+
+/// Control message type: Padded probe for a larger path MTU
+pub const CTRL_PMTU_PROBE: u8 = 0x32;
+
+/// Control message type: Acknowledges a PMTU probe
+pub const CTRL_PMTU_ACK: u8 = 0x33;
+
+/// Control message type: Open an application stream
+pub const CTRL_STREAM_OPEN: u8 = 0x40;
+
+/// Control message type: Sender finished writing to a stream
+pub const CTRL_STREAM_CLOSE: u8 = 0x41;
+
+/// Control message type: Selective acknowledgement for a reliable stream
+pub const CTRL_STREAM_ACK: u8 = 0x42;
+
+/// Most SACK ranges carried in one STREAM_ACK
+pub const MAX_ACK_RANGES: usize = 16;
+
+/// Longest ERROR reason, in bytes of UTF-8
+pub const MAX_ERROR_REASON_LEN: usize = 256;
+
+/// Error codes carried in ERROR messages (Section 8.1)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorCode {
+    /// A general protocol violation
+    ProtocolViolation,
+    /// A message not expected in the current state
+    UnexpectedMessage,
+    /// Decryption, authentication or replay failure
+    CryptoError,
+    /// Operation on a stream that is already closed
+    StreamClosed,
+    /// The peer does not speak our protocol version
+    UnsupportedVersion,
+    /// Code not in this version's registry
+    Unknown(u8),
+}
+
+impl ErrorCode {
+    /// Wire value of the code
+    pub fn code(self) -> u8 {
+        match self {
+            ErrorCode::ProtocolViolation => 0x01,
+            ErrorCode::UnexpectedMessage => 0x02,
+            ErrorCode::CryptoError => 0x03,
+            ErrorCode::StreamClosed => 0x04,
+            ErrorCode::UnsupportedVersion => 0x05,
+            ErrorCode::Unknown(code) => code,
+        }
+    }
+
+    /// Decode a wire value; unregistered codes are kept as `Unknown`
+    pub fn from_code(code: u8) -> Self {
+        match code {
+            0x01 => ErrorCode::ProtocolViolation,
+            0x02 => ErrorCode::UnexpectedMessage,
+            0x03 => ErrorCode::CryptoError,
+            0x04 => ErrorCode::StreamClosed,
+            0x05 => ErrorCode::UnsupportedVersion,
+            other => ErrorCode::Unknown(other),
+        }
+    }
+
+    /// Whether the session cannot continue after this error
+    ///
+    /// Stream and state errors leave the rest of the session usable.
+    pub fn is_fatal(self) -> bool {
+        !matches!(self, ErrorCode::UnexpectedMessage | ErrorCode::StreamClosed)
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ErrorCode::ProtocolViolation => write!(f, "PROTOCOL_VIOLATION"),
+            ErrorCode::UnexpectedMessage => write!(f, "UNEXPECTED_MESSAGE"),
+            ErrorCode::CryptoError => write!(f, "CRYPTO_ERROR"),
+            ErrorCode::StreamClosed => write!(f, "STREAM_CLOSED"),
+            ErrorCode::UnsupportedVersion => write!(f, "UNSUPPORTED_VERSION"),
+            ErrorCode::Unknown(code) => write!(f, "UNKNOWN(0x{:02x})", code),
+        }
+    }
+}
+
+/// Tunnel address lease handed out by the listener's address pool
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AddressLease {
+    /// Address assigned to the dialer's TUN interface
+    pub address: Ipv4Addr,
+    /// Prefix length of the tunnel network
+    pub prefix_len: u8,
+    /// Routes to install through the tunnel (destination, prefix length)
+    pub routes: Vec<(Ipv4Addr, u8)>,
+    /// DNS servers reachable through the tunnel
+    pub dns_servers: Vec<Ipv4Addr>,
+    /// MTU for the TUN interface
+    pub mtu: u16,
+    /// Lease lifetime in seconds
+    pub lease_secs: u32,
+}
+
+/// Selective acknowledgement of reliable stream data (Section 7.2)
+///
+/// Offsets count stream bytes; a FIN occupies one offset after the last
+/// byte.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StreamAck {
+    /// Stream being acknowledged
+    pub stream_id: u32,
+    /// Every offset below this has been delivered to the reader
+    pub cumulative: u64,
+    /// Blocks received but not yet delivered, as `[start, end)` offsets
+    pub ranges: Vec<(u64, u64)>,
+}
+
+/// Typed CONTROL message payload
+///
+/// Wire format (Section 7.7): control type (1 byte) followed by a
+/// type-specific body. Multi-byte integers are big-endian.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ControlMessage {
+    /// Liveness and RTT probe carrying an ID the PONG echoes
+    Ping(u64),
+    /// Answer to a PING
+    Pong(u64),
+    /// Close one stream in both directions, or the whole session if None
+    Close { stream_id: Option<u32> },
+    /// Error report; the reason is for logs only
+    Error { code: ErrorCode, reason: String },
+    /// Sent when the session has been idle; needs no answer
+    Keepalive,
+    /// Ask the listener for an address, optionally hinting the previous one
+    AddressRequest { requested: Option<Ipv4Addr> },
+    /// Address lease granted by the listener
+    AddressLease(AddressLease),
+    /// Give the current lease back to the pool
+    AddressRelease,
+    /// Probe a new peer address; the peer echoes the token in a PathResponse
+    PathChallenge([u8; 8]),
+    /// Answer to a PathChallenge, sent back to the address it arrived from
+    PathResponse([u8; 8]),
+    /// Path MTU probe padded with `padding` zero bytes to the probed size
+    PmtuProbe { probe_id: u32, padding: usize },
+    /// A PMTU probe arrived whole
+    PmtuAck(u32),
+    /// Announce a new stream; DATA records on it follow
+    StreamOpen { stream_id: u32 },
+    /// No more DATA will be sent on the stream (half-close)
+    StreamClose { stream_id: u32 },
+    /// Acknowledge received data on a reliable stream
+    StreamAck(StreamAck),
+}
+
+impl ControlMessage {
+    /// Returns the control type byte for this message
+    pub fn control_type(&self) -> u8 {
+        match self {
+            ControlMessage::Ping(_) => CTRL_PING,
+            ControlMessage::Pong(_) => CTRL_PONG,
+            ControlMessage::Close { .. } => CTRL_CLOSE,
+            ControlMessage::Error { .. } => CTRL_ERROR,
+            ControlMessage::Keepalive => CTRL_KEEPALIVE,
+            ControlMessage::AddressRequest { .. } => CTRL_ADDRESS_REQUEST,
+            ControlMessage::AddressLease(_) => CTRL_ADDRESS_LEASE,
+            ControlMessage::AddressRelease => CTRL_ADDRESS_RELEASE,
+            ControlMessage::PathChallenge(_) => CTRL_PATH_CHALLENGE,
+            ControlMessage::PathResponse(_) => CTRL_PATH_RESPONSE,
+            ControlMessage::PmtuProbe { .. } => CTRL_PMTU_PROBE,
+            ControlMessage::PmtuAck(_) => CTRL_PMTU_ACK,
+            ControlMessage::StreamOpen { .. } => CTRL_STREAM_OPEN,
+            ControlMessage::StreamClose { .. } => CTRL_STREAM_CLOSE,
+            ControlMessage::StreamAck(_) => CTRL_STREAM_ACK,
+        }
+    }
+
+    /// Serializes the control message to bytes
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = vec![self.control_type()];
+        match self {
+            ControlMessage::Ping(id) | ControlMessage::Pong(id) => {
+                buf.extend_from_slice(&id.to_be_bytes())
+            }
+            ControlMessage::Close { stream_id } => {
+                if let Some(stream_id) = stream_id {
+                    buf.extend_from_slice(&stream_id.to_be_bytes());
+                }
+            }
+            ControlMessage::Error { code, reason } => {
+                buf.push(code.code());
+                buf.extend_from_slice(truncate_reason(reason).as_bytes());
+            }
+            ControlMessage::Keepalive => {}
+            ControlMessage::AddressRequest { requested } => match requested {
+                Some(addr) => {
+                    buf.push(1);
+                    buf.extend_from_slice(&addr.octets());
+                }
+                None => buf.push(0),
+            },
+            ControlMessage::AddressLease(lease) => {
+                buf.extend_from_slice(&lease.address.octets());
+                buf.push(lease.prefix_len);
+                buf.extend_from_slice(&lease.mtu.to_be_bytes());
+                buf.extend_from_slice(&lease.lease_secs.to_be_bytes());
+                buf.push(lease.routes.len().min(u8::MAX as usize) as u8);
+                for (dest, prefix_len) in lease.routes.iter().take(u8::MAX as usize) {
+                    buf.extend_from_slice(&dest.octets());
+                    buf.push(*prefix_len);
+                }
+                buf.push(lease.dns_servers.len().min(u8::MAX as usize) as u8);
+                for server in lease.dns_servers.iter().take(u8::MAX as usize) {
+                    buf.extend_from_slice(&server.octets());
+                }
+            }
+            ControlMessage::AddressRelease => {}
+            ControlMessage::PathChallenge(token) | ControlMessage::PathResponse(token) => {
+                buf.extend_from_slice(token)
+            }
+            ControlMessage::PmtuProbe { probe_id, padding } => {
+                buf.extend_from_slice(&probe_id.to_be_bytes());
+                buf.resize(buf.len() + padding, 0);
+            }
+            ControlMessage::PmtuAck(probe_id) => buf.extend_from_slice(&probe_id.to_be_bytes()),
+            ControlMessage::StreamOpen { stream_id }
+            | ControlMessage::StreamClose { stream_id } => {
+                buf.extend_from_slice(&stream_id.to_be_bytes())
+            }
+            ControlMessage::StreamAck(ack) => {
+                buf.extend_from_slice(&ack.stream_id.to_be_bytes());
+                buf.extend_from_slice(&ack.cumulative.to_be_bytes());
+                buf.push(ack.ranges.len().min(MAX_ACK_RANGES) as u8);
+                for (start, end) in ack.ranges.iter().take(MAX_ACK_RANGES) {
+                    buf.extend_from_slice(&start.to_be_bytes());
+                    buf.extend_from_slice(&end.to_be_bytes());
+                }
+            }
+        }
+        buf
+    }
+
+    /// Deserializes a control message from bytes
+    pub fn from_bytes(buf: &[u8]) -> io::Result<Self> {
+        let (&control_type, body) = buf
+            .split_first()
+            .ok_or_else(|| invalid_data("Empty control message"))?;
+        let mut reader = BodyReader::new(body);
+
+        let message = match control_type {
+            CTRL_PING => ControlMessage::Ping(reader.u64()?),
+            CTRL_PONG => ControlMessage::Pong(reader.u64()?),
+            CTRL_CLOSE => ControlMessage::Close {
+                stream_id: match body.len() {
+                    0 => None,
+                    _ => Some(reader.u32()?),
+                },
+            },
+            CTRL_ERROR => {
+                let code = ErrorCode::from_code(reader.u8()?);
+                let reason = reader.take(body.len() - reader.offset())?;
+                if reason.len() > MAX_ERROR_REASON_LEN {
+                    return Err(invalid_data("ERROR reason too long"));
+                }
+                ControlMessage::Error {
+                    code,
+                    reason: String::from_utf8_lossy(reason).into_owned(),
+                }
+            }
+            CTRL_KEEPALIVE => ControlMessage::Keepalive,
+            CTRL_ADDRESS_REQUEST => {
+                let requested = match reader.u8()? {
+                    0 => None,
+                    _ => Some(reader.ipv4()?),
+                };
+                ControlMessage::AddressRequest { requested }
+            }
+            CTRL_ADDRESS_LEASE => {
+                let address = reader.ipv4()?;
+                let prefix_len = reader.prefix_len()?;
+                let mtu = reader.u16()?;
+                let lease_secs = reader.u32()?;
+                let route_count = reader.u8()?;
+                let mut routes = Vec::with_capacity(route_count as usize);
+                for _ in 0..route_count {
+                    routes.push((reader.ipv4()?, reader.prefix_len()?));
+                }
+                let dns_count = reader.u8()?;
+                let mut dns_servers = Vec::with_capacity(dns_count as usize);
+                for _ in 0..dns_count {
+                    dns_servers.push(reader.ipv4()?);
+                }
+                ControlMessage::AddressLease(AddressLease {
+                    address,
+                    prefix_len,
+                    routes,
+                    dns_servers,
+                    mtu,
+                    lease_secs,
+                })
+            }
+            CTRL_ADDRESS_RELEASE => ControlMessage::AddressRelease,
+            CTRL_PATH_CHALLENGE => ControlMessage::PathChallenge(reader.array()?),
+            CTRL_PATH_RESPONSE => ControlMessage::PathResponse(reader.array()?),
+            CTRL_PMTU_PROBE => ControlMessage::PmtuProbe {
+                probe_id: reader.u32()?,
+                padding: body.len() - reader.offset(),
+            },
+            CTRL_PMTU_ACK => ControlMessage::PmtuAck(reader.u32()?),
+            CTRL_STREAM_OPEN => ControlMessage::StreamOpen {
+                stream_id: reader.u32()?,
+            },
+            CTRL_STREAM_CLOSE => ControlMessage::StreamClose {
+                stream_id: reader.u32()?,
+            },
+            CTRL_STREAM_ACK => {
+                let stream_id = reader.u32()?;
+                let cumulative = reader.u64()?;
+                let range_count = reader.u8()? as usize;
+                if range_count > MAX_ACK_RANGES {
+                    return Err(invalid_data("Too many SACK ranges"));
+                }
+                let mut ranges = Vec::with_capacity(range_count);
+                for _ in 0..range_count {
+                    let start = reader.u64()?;
+                    let end = reader.u64()?;
+                    if start < cumulative || end <= start {
+                        return Err(invalid_data("Invalid SACK range"));
+                    }
+                    ranges.push((start, end));
+                }
+                ControlMessage::StreamAck(StreamAck {
+                    stream_id,
+                    cumulative,
+                    ranges,
+                })
+            }
+            other => {
+                return Err(invalid_data(&format!(
+                    "Unknown control message type: 0x{:02x}",
+                    other
+                )))
+            }
+        };
+
+        Ok(message)
+    }
+}
+
+/// Cut an ERROR reason to the wire limit on a character boundary
+fn truncate_reason(reason: &str) -> &str {
+    if reason.len() <= MAX_ERROR_REASON_LEN {
+        return reason;
+    }
+    let mut end = MAX_ERROR_REASON_LEN;
+    while !reason.is_char_boundary(end) {
+        end -= 1;
+    }
+    &reason[..end]
+}
+
+pub(crate) fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+/// Bounds-checked cursor over a control or handshake message body
+pub(crate) struct BodyReader<'a> {
+    buf: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> BodyReader<'a> {
+    pub(crate) fn new(buf: &'a [u8]) -> Self {
+        Self { buf, offset: 0 }
+    }
+
+    pub(crate) fn take(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        if self.buf.len() < self.offset + len {
+            return Err(invalid_data("Message truncated"));
+        }
+        let slice = &self.buf[self.offset..self.offset + len];
+        self.offset += len;
+        Ok(slice)
+    }
+
+    pub(crate) fn array<const N: usize>(&mut self) -> io::Result<[u8; N]> {
+        let mut out = [0u8; N];
+        out.copy_from_slice(self.take(N)?);
+        Ok(out)
+    }
+
+    pub(crate) fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub(crate) fn u16(&mut self) -> io::Result<u16> {
+        Ok(u16::from_be_bytes(self.array()?))
+    }
+
+    pub(crate) fn u32(&mut self) -> io::Result<u32> {
+        Ok(u32::from_be_bytes(self.array()?))
+    }
+
+    pub(crate) fn u64(&mut self) -> io::Result<u64> {
+        Ok(u64::from_be_bytes(self.array()?))
+    }
+
+    /// Bytes consumed so far
+    pub(crate) fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// Fails if any bytes are left unread
+    pub(crate) fn finish(&self) -> io::Result<()> {
+        if self.offset != self.buf.len() {
+            return Err(invalid_data("Trailing bytes after message"));
+        }
+        Ok(())
+    }
+
+    fn ipv4(&mut self) -> io::Result<Ipv4Addr> {
+        let b = self.take(4)?;
+        Ok(Ipv4Addr::new(b[0], b[1], b[2], b[3]))
+    }
+
+    fn prefix_len(&mut self) -> io::Result<u8> {
+        let len = self.u8()?;
+        if len > 32 {
+            return Err(invalid_data("IPv4 prefix length out of range"));
+        }
+        Ok(len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_address_request_roundtrip() {
+        let msg = ControlMessage::AddressRequest {
+            requested: Some(Ipv4Addr::new(10, 0, 0, 7)),
+        };
+        let bytes = msg.to_bytes();
+        assert_eq!(bytes[0], CTRL_ADDRESS_REQUEST);
+        let decoded = ControlMessage::from_bytes(&bytes).expect("decode request in test");
+        assert_eq!(decoded, msg);
+
+        let msg = ControlMessage::AddressRequest { requested: None };
+        let decoded = ControlMessage::from_bytes(&msg.to_bytes()).expect("decode request in test");
+        assert_eq!(decoded, msg);
+    }
+
+    #[test]
+    fn test_address_lease_roundtrip() {
+        let msg = ControlMessage::AddressLease(AddressLease {
+            address: Ipv4Addr::new(10, 0, 0, 2),
+            prefix_len: 24,
+            routes: vec![(Ipv4Addr::new(192, 168, 10, 0), 24)],
+            dns_servers: vec![Ipv4Addr::new(10, 0, 0, 1), Ipv4Addr::new(1, 1, 1, 1)],
+            mtu: 1420,
+            lease_secs: 3600,
+        });
+        let decoded = ControlMessage::from_bytes(&msg.to_bytes()).expect("decode lease in test");
+        assert_eq!(decoded, msg);
+    }
+
+    #[test]
+    fn test_path_validation_roundtrip() {
+        let token = [1, 2, 3, 4, 5, 6, 7, 8];
+        for msg in [
+            ControlMessage::PathChallenge(token),
+            ControlMessage::PathResponse(token),
+        ] {
+            let bytes = msg.to_bytes();
+            assert_eq!(bytes.len(), 9);
+            let decoded = ControlMessage::from_bytes(&bytes).expect("decode path msg in test");
+            assert_eq!(decoded, msg);
+            assert!(ControlMessage::from_bytes(&bytes[..8]).is_err());
+        }
+    }
+
+    #[test]
+    fn test_stream_messages_roundtrip() {
+        for msg in [
+            ControlMessage::StreamOpen {
+                stream_id: 0x8000_0001,
+            },
+            ControlMessage::StreamClose { stream_id: 7 },
+        ] {
+            let bytes = msg.to_bytes();
+            assert_eq!(bytes.len(), 5);
+            let decoded = ControlMessage::from_bytes(&bytes).expect("decode stream msg in test");
+            assert_eq!(decoded, msg);
+            assert!(ControlMessage::from_bytes(&bytes[..4]).is_err());
+        }
+    }
+
+    #[test]
+    fn test_stream_ack_roundtrip() {
+        let msg = ControlMessage::StreamAck(StreamAck {
+            stream_id: 0x8000_0002,
+            cumulative: 2400,
+            ranges: vec![(3600, 4800), (6000, 6001)],
+        });
+        let bytes = msg.to_bytes();
+        assert_eq!(bytes.len(), 14 + 2 * 16);
+        let decoded = ControlMessage::from_bytes(&bytes).expect("decode ack in test");
+        assert_eq!(decoded, msg);
+
+        // Ranges must not lie below the cumulative point and must be non-empty
+        let bad = ControlMessage::StreamAck(StreamAck {
+            stream_id: 1,
+            cumulative: 2400,
+            ranges: vec![(1200, 2400)],
+        });
+        assert!(ControlMessage::from_bytes(&bad.to_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_ping_pong_keepalive_roundtrip() {
+        for msg in [
+            ControlMessage::Ping(0x0102_0304_0506_0708),
+            ControlMessage::Pong(42),
+            ControlMessage::Keepalive,
+        ] {
+            let bytes = msg.to_bytes();
+            let decoded = ControlMessage::from_bytes(&bytes).expect("decode liveness in test");
+            assert_eq!(decoded, msg);
+        }
+        assert_eq!(ControlMessage::Ping(1).to_bytes().len(), 9);
+        assert_eq!(ControlMessage::Keepalive.to_bytes(), vec![CTRL_KEEPALIVE]);
+    }
+
+    #[test]
+    fn test_close_and_error_roundtrip() {
+        for msg in [
+            ControlMessage::Close { stream_id: None },
+            ControlMessage::Close {
+                stream_id: Some(0x8000_0003),
+            },
+            ControlMessage::Error {
+                code: ErrorCode::UnsupportedVersion,
+                reason: "version 2 not supported".to_string(),
+            },
+            ControlMessage::Error {
+                code: ErrorCode::Unknown(0x7f),
+                reason: String::new(),
+            },
+        ] {
+            let decoded = ControlMessage::from_bytes(&msg.to_bytes()).expect("decode in test");
+            assert_eq!(decoded, msg);
+        }
+        assert_eq!(
+            ControlMessage::Close { stream_id: None }.to_bytes(),
+            vec![CTRL_CLOSE]
+        );
+
+        // Codes match the registry; long reasons are cut at a char boundary
+        assert_eq!(ErrorCode::CryptoError.code(), 0x03);
+        assert_eq!(ErrorCode::from_code(0x05), ErrorCode::UnsupportedVersion);
+        assert!(!ErrorCode::StreamClosed.is_fatal());
+        let long = ControlMessage::Error {
+            code: ErrorCode::ProtocolViolation,
+            reason: "é".repeat(MAX_ERROR_REASON_LEN),
+        };
+        let bytes = long.to_bytes();
+        assert_eq!(bytes.len(), 2 + MAX_ERROR_REASON_LEN);
+        let Ok(ControlMessage::Error { reason, .. }) = ControlMessage::from_bytes(&bytes) else {
+            return;
+        };
+        assert_eq!(reason.chars().count(), MAX_ERROR_REASON_LEN / 2);
+    }
+
+    #[test]
+    fn test_pmtu_probe_roundtrip() {
+        let msg = ControlMessage::PmtuProbe {
+            probe_id: 7,
+            padding: 1400,
+        };
+        let bytes = msg.to_bytes();
+        assert_eq!(bytes.len(), 5 + 1400);
+        let decoded = ControlMessage::from_bytes(&bytes).expect("decode probe in test");
+        assert_eq!(decoded, msg);
+
+        let ack = ControlMessage::PmtuAck(7);
+        let decoded = ControlMessage::from_bytes(&ack.to_bytes()).expect("decode ack in test");
+        assert_eq!(decoded, ack);
+    }
+
+    #[test]
+    fn test_truncated_and_unknown_rejected() {
+        let msg = ControlMessage::AddressLease(AddressLease {
+            address: Ipv4Addr::new(10, 0, 0, 2),
+            prefix_len: 24,
+            routes: vec![],
+            dns_servers: vec![],
+            mtu: 1420,
+            lease_secs: 60,
+        });
+        let bytes = msg.to_bytes();
+        assert!(ControlMessage::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+        assert!(ControlMessage::from_bytes(&[]).is_err());
+        assert!(ControlMessage::from_bytes(&[0xEE]).is_err());
+    }
+}
@@ -0,0 +1,259 @@
+// Copyright (c) 2025 Thor Thor
+// Author: Thor Thor (GitHub: https://github.com/codethor0)
+// Contact: codethor@gmail.com
+// LinkedIn: https://www.linkedin.com/in/thor-thor0
+// License: MIT (see LICENSE file for details)
+
+use std::io;
+
+use crate::control::{invalid_data, BodyReader};
+use crate::handshake::{read_vec16, write_vec16};
+
+/// Extension: Ed25519 identity public key (32 bytes)
+pub const EXT_IDENTITY: u16 = 0x0001;
+
+/// Extension: cookie echoed from a COOKIE message
+pub const EXT_COOKIE: u16 = 0x0002;
+
+/// Extension: protocol versions the initiator speaks (count byte, then versions)
+pub const EXT_SUPPORTED_VERSIONS: u16 = 0x0003;
+
+/// Extension: header protection offered (CLIENT_HELLO) or accepted (SERVER_HELLO); empty
+pub const EXT_HEADER_PROTECTION: u16 = 0x0004;
+
+/// Handshake extension (TLV)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Extension {
+    pub ext_type: u16,
+    pub value: Vec<u8>,
+}
+
+impl Extension {
+    /// Registry range the extension type belongs to
+    pub fn range(&self) -> RegistryRange {
+        RegistryRange::of_extension(self.ext_type)
+    }
+}
+
+/// Registry ranges for message types, extensions and error codes (Section 10.3)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegistryRange {
+    /// 0x00 - 0x0F: core protocol
+    Core,
+    /// 0x10 - 0x7F: standard extensions and message types
+    Standard,
+    /// 0x80 - 0xFE: private or experimental use
+    Private,
+    /// 0xFF: reserved for future use
+    Reserved,
+}
+
+impl RegistryRange {
+    /// Range of a one-byte code
+    pub fn of(code: u8) -> Self {
+        match code {
+            0x00..=0x0F => RegistryRange::Core,
+            0x10..=0x7F => RegistryRange::Standard,
+            0x80..=0xFE => RegistryRange::Private,
+            0xFF => RegistryRange::Reserved,
+        }
+    }
+
+    /// Range of a two-byte extension type, decided by its high byte
+    pub fn of_extension(ext_type: u16) -> Self {
+        Self::of((ext_type >> 8) as u8)
+    }
+}
+
+/// Extension this implementation understands, decoded from its TLV
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KnownExtension {
+    Identity([u8; 32]),
+    Cookie(Vec<u8>),
+    /// Offered versions in the initiator's order of preference; never empty
+    SupportedVersions(Vec<u8>),
+    HeaderProtection,
+}
+
+impl KnownExtension {
+    pub fn ext_type(&self) -> u16 {
+        match self {
+            KnownExtension::Identity(_) => EXT_IDENTITY,
+            KnownExtension::Cookie(_) => EXT_COOKIE,
+            KnownExtension::SupportedVersions(_) => EXT_SUPPORTED_VERSIONS,
+            KnownExtension::HeaderProtection => EXT_HEADER_PROTECTION,
+        }
+    }
+
+    /// Encode as a TLV entry
+    pub fn to_extension(&self) -> Extension {
+        let value = match self {
+            KnownExtension::Identity(key) => key.to_vec(),
+            KnownExtension::Cookie(cookie) => cookie.clone(),
+            KnownExtension::SupportedVersions(versions) => {
+                let count = versions.len().min(u8::MAX as usize);
+                let mut value = vec![count as u8];
+                value.extend_from_slice(&versions[..count]);
+                value
+            }
+            KnownExtension::HeaderProtection => Vec::new(),
+        };
+        Extension {
+            ext_type: self.ext_type(),
+            value,
+        }
+    }
+
+    /// Decode a TLV entry; `Ok(None)` for types this implementation ignores
+    pub fn from_extension(ext: &Extension) -> io::Result<Option<Self>> {
+        let mut reader = BodyReader::new(&ext.value);
+        let known = match ext.ext_type {
+            EXT_IDENTITY => KnownExtension::Identity(reader.array()?),
+            EXT_COOKIE => KnownExtension::Cookie(ext.value.clone()),
+            EXT_SUPPORTED_VERSIONS => {
+                let count = reader.u8()?;
+                if count == 0 {
+                    return Err(invalid_data("Empty supported_versions extension"));
+                }
+                KnownExtension::SupportedVersions(reader.take(count as usize)?.to_vec())
+            }
+            EXT_HEADER_PROTECTION => KnownExtension::HeaderProtection,
+            _ => return Ok(None),
+        };
+        if ext.ext_type != EXT_COOKIE {
+            reader.finish()?;
+        }
+        Ok(Some(known))
+    }
+
+    /// Decode every known entry of an extension list, skipping unknown types
+    pub fn parse_all(extensions: &[Extension]) -> io::Result<Vec<Self>> {
+        let mut known = Vec::new();
+        for ext in extensions {
+            known.extend(Self::from_extension(ext)?);
+        }
+        Ok(known)
+    }
+}
+
+/// Highest version present in both `offered` and `supported`
+pub fn negotiate_version(offered: &[u8], supported: &[u8]) -> Option<u8> {
+    offered
+        .iter()
+        .filter(|version| supported.contains(version))
+        .max()
+        .copied()
+}
+
+pub(crate) fn find_extension(extensions: &[Extension], ext_type: u16) -> Option<&[u8]> {
+    extensions
+        .iter()
+        .find(|ext| ext.ext_type == ext_type)
+        .map(|ext| ext.value.as_slice())
+}
+
+/// Extensions block: total length (2 bytes), then type/length/value entries
+pub(crate) fn write_extensions(buf: &mut Vec<u8>, extensions: &[Extension]) {
+    let mut block = Vec::new();
+    for ext in extensions {
+        block.extend_from_slice(&ext.ext_type.to_be_bytes());
+        write_vec16(&mut block, &ext.value);
+    }
+    write_vec16(buf, &block);
+}
+
+/// Read an extensions block; a type may appear at most once
+pub(crate) fn read_extensions(reader: &mut BodyReader<'_>) -> io::Result<Vec<Extension>> {
+    let block = read_vec16(reader)?;
+    let mut block_reader = BodyReader::new(&block);
+    let mut extensions: Vec<Extension> = Vec::new();
+    while block_reader.offset() < block.len() {
+        let ext_type = block_reader.u16()?;
+        let value = read_vec16(&mut block_reader)?;
+        if extensions.iter().any(|ext| ext.ext_type == ext_type) {
+            return Err(invalid_data("Duplicate handshake extension"));
+        }
+        extensions.push(Extension { ext_type, value });
+    }
+    Ok(extensions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_known_extensions_roundtrip_and_unknown_skipped() {
+        let known = vec![
+            KnownExtension::Identity([5; 32]),
+            KnownExtension::Cookie(vec![0xC0; 16]),
+            KnownExtension::SupportedVersions(vec![2, 1]),
+            KnownExtension::HeaderProtection,
+        ];
+        let mut extensions: Vec<Extension> = known.iter().map(|k| k.to_extension()).collect();
+        extensions.insert(
+            1,
+            Extension {
+                ext_type: 0x8001,
+                value: vec![1, 2, 3],
+            },
+        );
+
+        let mut block = Vec::new();
+        write_extensions(&mut block, &extensions);
+        let decoded = read_extensions(&mut BodyReader::new(&block)).expect("decode in test");
+        assert_eq!(decoded, extensions);
+        assert_eq!(
+            KnownExtension::parse_all(&decoded).expect("parse in test"),
+            known
+        );
+
+        // Malformed values of known types are rejected
+        for value in [vec![], vec![0], vec![2, 1], vec![1, 1, 1]] {
+            let ext = Extension {
+                ext_type: EXT_SUPPORTED_VERSIONS,
+                value,
+            };
+            assert!(KnownExtension::from_extension(&ext).is_err());
+        }
+        let short_identity = Extension {
+            ext_type: EXT_IDENTITY,
+            value: vec![5; 31],
+        };
+        assert!(KnownExtension::from_extension(&short_identity).is_err());
+        let header_protection_with_value = Extension {
+            ext_type: EXT_HEADER_PROTECTION,
+            value: vec![1],
+        };
+        assert!(KnownExtension::from_extension(&header_protection_with_value).is_err());
+
+        // A type may appear only once
+        let mut duplicate = Vec::new();
+        write_extensions(
+            &mut duplicate,
+            &[known[1].to_extension(), known[1].to_extension()],
+        );
+        assert!(read_extensions(&mut BodyReader::new(&duplicate)).is_err());
+    }
+
+    #[test]
+    fn test_registry_ranges_and_version_negotiation() {
+        assert_eq!(RegistryRange::of(0x04), RegistryRange::Core);
+        assert_eq!(RegistryRange::of(0x40), RegistryRange::Standard);
+        assert_eq!(RegistryRange::of(0x80), RegistryRange::Private);
+        assert_eq!(RegistryRange::of(0xFE), RegistryRange::Private);
+        assert_eq!(RegistryRange::of(0xFF), RegistryRange::Reserved);
+        assert_eq!(
+            RegistryRange::of_extension(EXT_SUPPORTED_VERSIONS),
+            RegistryRange::Core
+        );
+        assert_eq!(RegistryRange::of_extension(0x1234), RegistryRange::Standard);
+        assert_eq!(RegistryRange::of_extension(0xFEFF), RegistryRange::Private);
+        assert_eq!(RegistryRange::of_extension(0xFF00), RegistryRange::Reserved);
+
+        assert_eq!(negotiate_version(&[1, 2, 3], &[1, 2]), Some(2));
+        assert_eq!(negotiate_version(&[1], &[1, 2]), Some(1));
+        assert_eq!(negotiate_version(&[3], &[1, 2]), None);
+        assert_eq!(negotiate_version(&[], &[1]), None);
+    }
+}
@@ -161,12 +161,16 @@ pub unsafe extern "C" fn cryprq_write_packet(
 
 /// Notify CrypRQ of a network change event.
 ///
+/// Returns `CRYPRQ_ERR_UNSUPPORTED`: the FFI connection tasks don't run a
+/// tunnel yet, so there is no path to re-validate. Hosts embedding
+/// `node::Tunnel` directly call `Tunnel::revalidate_path` instead.
+///
 /// # Safety
 ///
 /// - `handle` must be a valid pointer to a `CrypRqHandle` created by `cryprq_init`
 #[no_mangle]
 pub unsafe extern "C" fn cryprq_on_network_change(_handle: *mut CrypRqHandle) -> CrypRqErrorCode {
-    CrypRqErrorCode::CRYPRQ_OK
+    CrypRqErrorCode::CRYPRQ_ERR_UNSUPPORTED
 }
 
 /// Close and deallocate a CrypRQ handle.
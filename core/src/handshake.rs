@@ -0,0 +1,360 @@
+// Copyright (c) 2025 Thor Thor
+// Author: Thor Thor (GitHub: https://github.com/codethor0)
+// Contact: codethor@gmail.com
+// LinkedIn: https://www.linkedin.com/in/thor-thor0
+// License: MIT (see LICENSE file for details)
+
+use std::io;
+
+use crate::control::{invalid_data, BodyReader};
+use crate::extension::{
+    find_extension, read_extensions, write_extensions, Extension, KnownExtension,
+};
+
+/// Handshake message type: CRYPRQ_CLIENT_HELLO (initiator -> responder)
+pub const HS_CLIENT_HELLO: u8 = 0x01;
+
+/// Handshake message type: CRYPRQ_SERVER_HELLO (responder -> initiator)
+pub const HS_SERVER_HELLO: u8 = 0x02;
+
+/// Handshake message type: CRYPRQ_CLIENT_FINISH (initiator -> responder)
+pub const HS_CLIENT_FINISH: u8 = 0x03;
+
+/// Handshake message type: HANDSHAKE_DONE (responder -> initiator, encrypted)
+pub const HS_HANDSHAKE_DONE: u8 = 0x04;
+
+/// Handshake message type: COOKIE (responder -> initiator, stateless retry)
+pub const HS_COOKIE: u8 = 0x05;
+
+/// Cipher suite: ChaCha20-Poly1305 with HKDF-SHA256
+pub const CIPHER_SUITE_CHACHA20_POLY1305: u16 = 0x0001;
+
+/// CRYPRQ_CLIENT_HELLO (Section 4.2.1)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientHello {
+    pub version: u8,
+    pub random: [u8; 32],
+    pub cipher_suites: Vec<u16>,
+    pub extensions: Vec<Extension>,
+}
+
+/// CRYPRQ_SERVER_HELLO (Section 4.2.2)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerHello {
+    pub version: u8,
+    pub random: [u8; 32],
+    pub cipher_suite: u16,
+    /// Session ID the initiator tags all later records with
+    pub session_id: u64,
+    pub kem_public_key: Vec<u8>,
+    pub x25519_public_key: [u8; 32],
+    pub extensions: Vec<Extension>,
+    /// Ed25519 signature over the transcript, if the responder has an identity
+    pub signature: Option<[u8; 64]>,
+}
+
+/// CRYPRQ_CLIENT_FINISH (Section 4.2.3)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientFinish {
+    pub session_id: u64,
+    pub kem_ciphertext: Vec<u8>,
+    pub x25519_public_key: [u8; 32],
+    /// Ed25519 signature over the transcript, if the initiator has an identity
+    pub signature: Option<[u8; 64]>,
+    pub verify_data: [u8; 32],
+}
+
+/// Typed handshake message carried in a `HANDSHAKE` record
+///
+/// Wire format (Section 4.2): handshake type (1 byte) followed by the
+/// message fields. Multi-byte integers are big-endian; variable-length
+/// fields carry a 2-byte length prefix.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HandshakeMessage {
+    ClientHello(ClientHello),
+    ServerHello(ServerHello),
+    ClientFinish(ClientFinish),
+    /// Responder confirmation that the session is established
+    HandshakeDone,
+    /// Stateless retry: resend CLIENT_HELLO with this cookie in `EXT_COOKIE`
+    Cookie(Vec<u8>),
+}
+
+impl ClientHello {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = vec![HS_CLIENT_HELLO, self.version];
+        buf.extend_from_slice(&self.random);
+        buf.push(self.cipher_suites.len().min(u8::MAX as usize) as u8);
+        for suite in self.cipher_suites.iter().take(u8::MAX as usize) {
+            buf.extend_from_slice(&suite.to_be_bytes());
+        }
+        write_extensions(&mut buf, &self.extensions);
+        buf
+    }
+
+    /// Value of an extension, if present
+    pub fn extension(&self, ext_type: u16) -> Option<&[u8]> {
+        find_extension(&self.extensions, ext_type)
+    }
+
+    /// Versions the initiator offers (Section 10.1)
+    ///
+    /// Taken from the `supported_versions` extension; a hello without one
+    /// offers only the version in its header.
+    pub fn offered_versions(&self) -> io::Result<Vec<u8>> {
+        for known in KnownExtension::parse_all(&self.extensions)? {
+            if let KnownExtension::SupportedVersions(versions) = known {
+                return Ok(versions);
+            }
+        }
+        Ok(vec![self.version])
+    }
+}
+
+impl ServerHello {
+    /// Encoding without the signature field: the part the signature covers
+    pub fn signed_bytes(&self) -> Vec<u8> {
+        let mut buf = vec![HS_SERVER_HELLO, self.version];
+        buf.extend_from_slice(&self.random);
+        buf.extend_from_slice(&self.cipher_suite.to_be_bytes());
+        buf.extend_from_slice(&self.session_id.to_be_bytes());
+        write_vec16(&mut buf, &self.kem_public_key);
+        buf.extend_from_slice(&self.x25519_public_key);
+        write_extensions(&mut buf, &self.extensions);
+        buf
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = self.signed_bytes();
+        write_signature(&mut buf, self.signature.as_ref());
+        buf
+    }
+
+    /// Value of an extension, if present
+    pub fn extension(&self, ext_type: u16) -> Option<&[u8]> {
+        find_extension(&self.extensions, ext_type)
+    }
+}
+
+impl ClientFinish {
+    /// Encoding without signature and verify_data: the part the signature covers
+    pub fn signed_bytes(&self) -> Vec<u8> {
+        let mut buf = vec![HS_CLIENT_FINISH];
+        buf.extend_from_slice(&self.session_id.to_be_bytes());
+        write_vec16(&mut buf, &self.kem_ciphertext);
+        buf.extend_from_slice(&self.x25519_public_key);
+        buf
+    }
+
+    /// Encoding without verify_data: the part verify_data covers
+    pub fn authenticated_bytes(&self) -> Vec<u8> {
+        let mut buf = self.signed_bytes();
+        write_signature(&mut buf, self.signature.as_ref());
+        buf
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = self.authenticated_bytes();
+        buf.extend_from_slice(&self.verify_data);
+        buf
+    }
+}
+
+impl HandshakeMessage {
+    /// Serializes the handshake message to bytes
+    pub fn to_bytes(&self) -> Vec<u8> {
+        match self {
+            HandshakeMessage::ClientHello(msg) => msg.to_bytes(),
+            HandshakeMessage::ServerHello(msg) => msg.to_bytes(),
+            HandshakeMessage::ClientFinish(msg) => msg.to_bytes(),
+            HandshakeMessage::HandshakeDone => vec![HS_HANDSHAKE_DONE],
+            HandshakeMessage::Cookie(cookie) => {
+                let mut buf = vec![HS_COOKIE];
+                write_vec16(&mut buf, cookie);
+                buf
+            }
+        }
+    }
+
+    /// Deserializes a handshake message from bytes
+    pub fn from_bytes(buf: &[u8]) -> io::Result<Self> {
+        let (&hs_type, body) = buf
+            .split_first()
+            .ok_or_else(|| invalid_data("Empty handshake message"))?;
+        let mut reader = BodyReader::new(body);
+
+        let message = match hs_type {
+            HS_CLIENT_HELLO => {
+                let version = reader.u8()?;
+                let random = reader.array()?;
+                let suite_count = reader.u8()?;
+                let mut cipher_suites = Vec::with_capacity(suite_count as usize);
+                for _ in 0..suite_count {
+                    cipher_suites.push(reader.u16()?);
+                }
+                if cipher_suites.is_empty() {
+                    return Err(invalid_data("CLIENT_HELLO without cipher suites"));
+                }
+                let extensions = read_extensions(&mut reader)?;
+                HandshakeMessage::ClientHello(ClientHello {
+                    version,
+                    random,
+                    cipher_suites,
+                    extensions,
+                })
+            }
+            HS_SERVER_HELLO => HandshakeMessage::ServerHello(ServerHello {
+                version: reader.u8()?,
+                random: reader.array()?,
+                cipher_suite: reader.u16()?,
+                session_id: reader.u64()?,
+                kem_public_key: read_vec16(&mut reader)?,
+                x25519_public_key: reader.array()?,
+                extensions: read_extensions(&mut reader)?,
+                signature: read_signature(&mut reader)?,
+            }),
+            HS_CLIENT_FINISH => HandshakeMessage::ClientFinish(ClientFinish {
+                session_id: reader.u64()?,
+                kem_ciphertext: read_vec16(&mut reader)?,
+                x25519_public_key: reader.array()?,
+                signature: read_signature(&mut reader)?,
+                verify_data: reader.array()?,
+            }),
+            HS_HANDSHAKE_DONE => HandshakeMessage::HandshakeDone,
+            HS_COOKIE => HandshakeMessage::Cookie(read_vec16(&mut reader)?),
+            other => {
+                return Err(invalid_data(&format!(
+                    "Unknown handshake message type: 0x{:02x}",
+                    other
+                )))
+            }
+        };
+        reader.finish()?;
+
+        Ok(message)
+    }
+}
+
+pub(crate) fn write_vec16(buf: &mut Vec<u8>, value: &[u8]) {
+    let len = value.len().min(u16::MAX as usize);
+    buf.extend_from_slice(&(len as u16).to_be_bytes());
+    buf.extend_from_slice(&value[..len]);
+}
+
+pub(crate) fn read_vec16(reader: &mut BodyReader<'_>) -> io::Result<Vec<u8>> {
+    let len = reader.u16()?;
+    Ok(reader.take(len as usize)?.to_vec())
+}
+
+/// Optional signature: presence byte, then 64 bytes when present
+fn write_signature(buf: &mut Vec<u8>, signature: Option<&[u8; 64]>) {
+    match signature {
+        Some(sig) => {
+            buf.push(1);
+            buf.extend_from_slice(sig);
+        }
+        None => buf.push(0),
+    }
+}
+
+fn read_signature(reader: &mut BodyReader<'_>) -> io::Result<Option<[u8; 64]>> {
+    match reader.u8()? {
+        0 => Ok(None),
+        _ => Ok(Some(reader.array()?)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::extension::{EXT_IDENTITY, EXT_SUPPORTED_VERSIONS};
+
+    fn server_hello() -> ServerHello {
+        ServerHello {
+            version: 1,
+            random: [7; 32],
+            cipher_suite: CIPHER_SUITE_CHACHA20_POLY1305,
+            session_id: 0xDEAD_BEEF,
+            kem_public_key: vec![3; 1184],
+            x25519_public_key: [9; 32],
+            extensions: vec![Extension {
+                ext_type: EXT_IDENTITY,
+                value: vec![5; 32],
+            }],
+            signature: Some([6; 64]),
+        }
+    }
+
+    #[test]
+    fn test_handshake_messages_roundtrip() {
+        let messages = [
+            HandshakeMessage::ClientHello(ClientHello {
+                version: 1,
+                random: [1; 32],
+                cipher_suites: vec![CIPHER_SUITE_CHACHA20_POLY1305, 0x7777],
+                extensions: vec![],
+            }),
+            HandshakeMessage::ServerHello(server_hello()),
+            HandshakeMessage::ClientFinish(ClientFinish {
+                session_id: 42,
+                kem_ciphertext: vec![2; 1088],
+                x25519_public_key: [4; 32],
+                signature: None,
+                verify_data: [8; 32],
+            }),
+            HandshakeMessage::HandshakeDone,
+            HandshakeMessage::Cookie(vec![0xC0; 16]),
+        ];
+        for msg in messages {
+            let decoded =
+                HandshakeMessage::from_bytes(&msg.to_bytes()).expect("decode handshake in test");
+            assert_eq!(decoded, msg);
+        }
+    }
+
+    #[test]
+    fn test_signed_bytes_are_prefix() {
+        let hello = server_hello();
+        assert!(hello.to_bytes().starts_with(&hello.signed_bytes()));
+        assert_eq!(hello.extension(EXT_IDENTITY), Some(&[5u8; 32][..]));
+    }
+
+    #[test]
+    fn test_offered_versions() {
+        let mut hello = ClientHello {
+            version: 1,
+            random: [1; 32],
+            cipher_suites: vec![CIPHER_SUITE_CHACHA20_POLY1305],
+            extensions: vec![],
+        };
+        assert_eq!(hello.offered_versions().expect("versions in test"), [1]);
+
+        hello
+            .extensions
+            .push(KnownExtension::SupportedVersions(vec![1, 2]).to_extension());
+        assert_eq!(hello.offered_versions().expect("versions in test"), [1, 2]);
+
+        hello.extensions[0] = Extension {
+            ext_type: EXT_SUPPORTED_VERSIONS,
+            value: vec![0],
+        };
+        assert!(hello.offered_versions().is_err());
+    }
+
+    #[test]
+    fn test_malformed_handshake_rejected() {
+        let bytes = HandshakeMessage::ServerHello(server_hello()).to_bytes();
+        assert!(HandshakeMessage::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert!(HandshakeMessage::from_bytes(&trailing).is_err());
+
+        // CLIENT_HELLO must offer at least one cipher suite
+        let mut hello = vec![HS_CLIENT_HELLO, 1];
+        hello.extend_from_slice(&[0; 32]);
+        hello.extend_from_slice(&[0, 0, 0]);
+        assert!(HandshakeMessage::from_bytes(&hello).is_err());
+        assert!(HandshakeMessage::from_bytes(&[0xEE]).is_err());
+    }
+}
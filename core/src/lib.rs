@@ -4,16 +4,36 @@
 // LinkedIn: https://www.linkedin.com/in/thor-thor0
 // License: MIT (see LICENSE file for details)
 
+mod control;
 mod error;
+mod extension;
 mod ffi;
 mod handle;
+mod handshake;
 mod record;
 mod util;
 
+pub use control::{
+    AddressLease, ControlMessage, ErrorCode, StreamAck, CTRL_ADDRESS_LEASE, CTRL_ADDRESS_RELEASE,
+    CTRL_ADDRESS_REQUEST, CTRL_CLOSE, CTRL_ERROR, CTRL_KEEPALIVE, CTRL_PATH_CHALLENGE,
+    CTRL_PATH_RESPONSE, CTRL_PING, CTRL_PMTU_ACK, CTRL_PMTU_PROBE, CTRL_PONG, CTRL_STREAM_ACK,
+    CTRL_STREAM_CLOSE, CTRL_STREAM_OPEN, MAX_ACK_RANGES, MAX_ERROR_REASON_LEN,
+};
 pub use error::CrypRqErrorCode;
+pub use extension::{
+    negotiate_version, Extension, KnownExtension, RegistryRange, EXT_COOKIE, EXT_HEADER_PROTECTION,
+    EXT_IDENTITY, EXT_SUPPORTED_VERSIONS,
+};
 pub use ffi::*;
+pub use handshake::{
+    ClientFinish, ClientHello, HandshakeMessage, ServerHello, CIPHER_SUITE_CHACHA20_POLY1305,
+    HS_CLIENT_FINISH, HS_CLIENT_HELLO, HS_COOKIE, HS_HANDSHAKE_DONE, HS_SERVER_HELLO,
+};
 pub use record::{
-    Record, RecordHeader, MSG_TYPE_CONTROL, MSG_TYPE_DATA, MSG_TYPE_FILE_ACK, MSG_TYPE_FILE_CHUNK,
-    MSG_TYPE_FILE_META, MSG_TYPE_VPN_PACKET, PROTOCOL_VERSION, RECORD_HEADER_SIZE,
+    protect_header, unprotect_header, Record, RecordHeader, FLAG_DATA_FIN, FLAG_DATA_RELIABLE,
+    FLAG_FRAGMENT, FLAG_PADDED, FLAG_PROTECTED, FLAG_SESSION_ID, HP_SAMPLE_SIZE, MSG_TYPE_CONTROL,
+    MSG_TYPE_DATA, MSG_TYPE_ETHERNET_FRAME, MSG_TYPE_FILE_ACK, MSG_TYPE_FILE_CHUNK,
+    MSG_TYPE_FILE_META, MSG_TYPE_HANDSHAKE, MSG_TYPE_PADDING, MSG_TYPE_VPN_PACKET,
+    PROTOCOL_VERSION, RECORD_HEADER_SIZE, SESSION_ID_SIZE, SUPPORTED_VERSIONS,
 };
 pub use util::CrypRqStrView;
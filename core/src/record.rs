@@ -4,6 +4,8 @@
 // LinkedIn: https://www.linkedin.com/in/thor-thor0
 // License: MIT (see LICENSE file for details)
 
+use chacha20::cipher::{KeyIvInit, StreamCipher, StreamCipherSeek};
+use chacha20::ChaCha20;
 use chacha20poly1305::{
     aead::{Aead, KeyInit, Payload},
     ChaCha20Poly1305, Nonce,
@@ -13,6 +15,9 @@ use std::io::{self, Read, Write};
 /// Protocol version for CrypRQ v1.0
 pub const PROTOCOL_VERSION: u8 = 0x01;
 
+/// Protocol versions this implementation speaks, lowest first (Section 10.1)
+pub const SUPPORTED_VERSIONS: &[u8] = &[PROTOCOL_VERSION];
+
 /// Record header size (20 bytes) as specified in Section 6.1.1
 pub const RECORD_HEADER_SIZE: usize = 20;
 
@@ -31,9 +36,44 @@ pub const MSG_TYPE_FILE_ACK: u8 = 0x04;
 /// Message type: VPN packet
 pub const MSG_TYPE_VPN_PACKET: u8 = 0x05;
 
+/// Message type: Ethernet frame (TAP / layer 2 mode)
+pub const MSG_TYPE_ETHERNET_FRAME: u8 = 0x06;
+
+/// Message type: Handshake message (CLIENT_HELLO / SERVER_HELLO / CLIENT_FINISH)
+pub const MSG_TYPE_HANDSHAKE: u8 = 0x07;
+
+/// Message type: Cover traffic filler; receivers discard it
+pub const MSG_TYPE_PADDING: u8 = 0x08;
+
 /// Message type: Control message
 pub const MSG_TYPE_CONTROL: u8 = 0x10;
 
+/// Header flag: an 8-byte session ID follows the header (Section 6.1.3)
+pub const FLAG_SESSION_ID: u8 = 0x80;
+
+/// Header flag: the payload is one fragment of a larger record (Section 6.4)
+pub const FLAG_FRAGMENT: u8 = 0x40;
+
+/// Header flag: the plaintext ends with padding and its 2-byte length (Section 6.5)
+pub const FLAG_PADDED: u8 = 0x20;
+
+/// Header flag: message type, epoch, stream ID, sequence number and the
+/// low four flag bits are masked with header protection (Section 6.6)
+pub const FLAG_PROTECTED: u8 = 0x10;
+
+/// Ciphertext bytes sampled to derive the header protection mask
+pub const HP_SAMPLE_SIZE: usize = 16;
+
+/// DATA flag: reliable stream segment; the payload starts with an 8-byte
+/// stream offset (Section 7.2)
+pub const FLAG_DATA_RELIABLE: u8 = 0x01;
+
+/// DATA flag: last segment of a reliable stream (sender half-close)
+pub const FLAG_DATA_FIN: u8 = 0x02;
+
+/// Size of the session ID carried after the header when `FLAG_SESSION_ID` is set
+pub const SESSION_ID_SIZE: usize = 8;
+
 /// CrypRQ record header structure (20 bytes)
 ///
 /// As specified in Section 6.1.1:
@@ -99,7 +139,7 @@ impl RecordHeader {
         }
 
         let version = buf[0];
-        if version != PROTOCOL_VERSION {
+        if !SUPPORTED_VERSIONS.contains(&version) {
             return Err(io::Error::new(
                 io::ErrorKind::InvalidData,
                 format!("Unsupported protocol version: {}", version),
@@ -140,10 +180,12 @@ impl RecordHeader {
     }
 }
 
-/// Complete CrypRQ record (header + ciphertext)
+/// Complete CrypRQ record (header + optional session ID + ciphertext)
 #[derive(Debug, Clone)]
 pub struct Record {
     pub header: RecordHeader,
+    /// Session ID, present when the header has `FLAG_SESSION_ID` set
+    pub session_id: Option<u64>,
     pub ciphertext: Vec<u8>,
 }
 
@@ -165,21 +207,65 @@ impl Record {
             sequence_number,
             ciphertext.len() as u32,
         );
-        Self { header, ciphertext }
+        Self {
+            header,
+            session_id: None,
+            ciphertext,
+        }
+    }
+
+    /// Tags the record with a session ID, setting `FLAG_SESSION_ID`
+    ///
+    /// Only for records whose payload is not encrypted (handshake messages);
+    /// encrypted records must use `encrypt_for_session` so the ID is
+    /// covered by the AAD.
+    pub fn with_session_id(mut self, session_id: u64) -> Self {
+        self.header.flags |= FLAG_SESSION_ID;
+        self.session_id = Some(session_id);
+        self
+    }
+
+    /// Reads the session ID of an encoded record without decoding it
+    pub fn peek_session_id(buf: &[u8]) -> Option<u64> {
+        if buf.len() < RECORD_HEADER_SIZE + SESSION_ID_SIZE || buf[2] & FLAG_SESSION_ID == 0 {
+            return None;
+        }
+        let mut id = [0u8; SESSION_ID_SIZE];
+        id.copy_from_slice(&buf[RECORD_HEADER_SIZE..RECORD_HEADER_SIZE + SESSION_ID_SIZE]);
+        Some(u64::from_be_bytes(id))
+    }
+
+    /// Header bytes plus session ID: the AEAD associated data
+    fn aad(header: &RecordHeader, session_id: Option<u64>) -> Vec<u8> {
+        let mut aad = header.to_bytes().to_vec();
+        if let Some(id) = session_id {
+            aad.extend_from_slice(&id.to_be_bytes());
+        }
+        aad
     }
 
-    /// Serializes the entire record (header + ciphertext)
+    /// Serializes the entire record (header + session ID + ciphertext)
     pub fn to_bytes(&self) -> Vec<u8> {
-        let mut buf = Vec::with_capacity(RECORD_HEADER_SIZE + self.ciphertext.len());
-        buf.extend_from_slice(&self.header.to_bytes());
+        let mut buf = Self::aad(&self.header, self.session_id);
         buf.extend_from_slice(&self.ciphertext);
         buf
     }
 
     /// Deserializes a record from bytes
     pub fn from_bytes(buf: &[u8]) -> io::Result<Self> {
-        let header = RecordHeader::from_bytes(&buf[..RECORD_HEADER_SIZE])?;
-        let ciphertext = buf[RECORD_HEADER_SIZE..].to_vec();
+        let header = RecordHeader::from_bytes(buf)?;
+        let (session_id, body_offset) = if header.flags & FLAG_SESSION_ID != 0 {
+            let id = Self::peek_session_id(buf).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Buffer too short for session ID",
+                )
+            })?;
+            (Some(id), RECORD_HEADER_SIZE + SESSION_ID_SIZE)
+        } else {
+            (None, RECORD_HEADER_SIZE)
+        };
+        let ciphertext = buf[body_offset..].to_vec();
 
         if ciphertext.len() != header.ciphertext_length as usize {
             return Err(io::Error::new(
@@ -192,20 +278,38 @@ impl Record {
             ));
         }
 
-        Ok(Self { header, ciphertext })
+        Ok(Self {
+            header,
+            session_id,
+            ciphertext,
+        })
     }
 
     /// Reads a record from a reader
     pub fn read_from<R: Read>(reader: &mut R) -> io::Result<Self> {
         let header = RecordHeader::read_from(reader)?;
+        let session_id = if header.flags & FLAG_SESSION_ID != 0 {
+            let mut id = [0u8; SESSION_ID_SIZE];
+            reader.read_exact(&mut id)?;
+            Some(u64::from_be_bytes(id))
+        } else {
+            None
+        };
         let mut ciphertext = vec![0u8; header.ciphertext_length as usize];
         reader.read_exact(&mut ciphertext)?;
-        Ok(Self { header, ciphertext })
+        Ok(Self {
+            header,
+            session_id,
+            ciphertext,
+        })
     }
 
     /// Writes a record to a writer
     pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
         self.header.write_to(writer)?;
+        if let Some(id) = self.session_id {
+            writer.write_all(&id.to_be_bytes())?;
+        }
         writer.write_all(&self.ciphertext)?;
         Ok(())
     }
@@ -218,7 +322,7 @@ impl Record {
     /// - Uses header as AAD
     #[allow(clippy::too_many_arguments)]
     pub fn encrypt(
-        _version: u8,
+        version: u8,
         message_type: u8,
         flags: u8,
         epoch: u8,
@@ -228,6 +332,48 @@ impl Record {
         key: &[u8; 32],
         static_iv: &[u8; 12],
     ) -> io::Result<Self> {
+        Self::encrypt_for_session(
+            version,
+            None,
+            message_type,
+            flags,
+            epoch,
+            stream_id,
+            sequence_number,
+            plaintext,
+            key,
+            static_iv,
+        )
+    }
+
+    /// Encrypts plaintext into a record carrying an optional session ID
+    ///
+    /// With a session ID, `FLAG_SESSION_ID` is set and the ID is
+    /// authenticated together with the header (Section 6.1.3). `version`
+    /// is the session's negotiated protocol version.
+    #[allow(clippy::too_many_arguments)]
+    pub fn encrypt_for_session(
+        version: u8,
+        session_id: Option<u64>,
+        message_type: u8,
+        flags: u8,
+        epoch: u8,
+        stream_id: u32,
+        sequence_number: u64,
+        plaintext: &[u8],
+        key: &[u8; 32],
+        static_iv: &[u8; 12],
+    ) -> io::Result<Self> {
+        if !SUPPORTED_VERSIONS.contains(&version) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Unsupported protocol version: {}", version),
+            ));
+        }
+        let flags = match session_id {
+            Some(_) => flags | FLAG_SESSION_ID,
+            None => flags & !FLAG_SESSION_ID,
+        };
         // Construct nonce using TLS 1.3-style XOR
         let seq_be = sequence_number.to_be_bytes();
         let mut nonce_bytes = *static_iv;
@@ -244,23 +390,26 @@ impl Record {
         let ciphertext_length = (plaintext.len() + POLY1305_TAG_SIZE) as u32;
 
         // Create header with ciphertext length (as per spec Section 6.1)
-        let header = RecordHeader::new(
-            message_type,
-            flags,
-            epoch,
-            stream_id,
-            sequence_number,
-            ciphertext_length,
-        );
+        let header = RecordHeader {
+            version,
+            ..RecordHeader::new(
+                message_type,
+                flags,
+                epoch,
+                stream_id,
+                sequence_number,
+                ciphertext_length,
+            )
+        };
 
-        // Encrypt with header as AAD (as per spec Section 6.2)
-        let header_bytes = header.to_bytes();
+        // Encrypt with header (and session ID) as AAD (as per spec Section 6.2)
+        let aad = Self::aad(&header, session_id);
         let ciphertext = cipher
             .encrypt(
                 &nonce,
                 Payload {
                     msg: plaintext,
-                    aad: &header_bytes,
+                    aad: &aad,
                 },
             )
             .map_err(|e| {
@@ -270,7 +419,11 @@ impl Record {
                 )
             })?;
 
-        Ok(Self { header, ciphertext })
+        Ok(Self {
+            header,
+            session_id,
+            ciphertext,
+        })
     }
 
     /// Decrypts the record and returns plaintext
@@ -290,14 +443,14 @@ impl Record {
         // Create cipher
         let cipher = ChaCha20Poly1305::new(key.into());
 
-        // Decrypt with header as AAD
-        let header_bytes = self.header.to_bytes();
+        // Decrypt with header (and session ID) as AAD
+        let aad = Self::aad(&self.header, self.session_id);
         let plaintext = cipher
             .decrypt(
                 &nonce,
                 Payload {
                     msg: &self.ciphertext,
-                    aad: &header_bytes,
+                    aad: &aad,
                 },
             )
             .map_err(|e| {
@@ -311,6 +464,65 @@ impl Record {
     }
 }
 
+/// Mask the header fields of an encoded `FLAG_PROTECTED` record (Section 6.6)
+///
+/// The record must already be sealed: the mask is derived from the first
+/// `HP_SAMPLE_SIZE` bytes of its ciphertext, so the AAD is the unmasked
+/// header.
+pub fn protect_header(hp_key: &[u8; 32], record: &mut [u8]) -> io::Result<()> {
+    apply_header_mask(hp_key, record)
+}
+
+/// Remove header protection from an encoded record before decrypting it
+pub fn unprotect_header(hp_key: &[u8; 32], record: &mut [u8]) -> io::Result<()> {
+    apply_header_mask(hp_key, record)
+}
+
+/// XOR the protected header bytes with the mask; the same operation both ways
+fn apply_header_mask(hp_key: &[u8; 32], record: &mut [u8]) -> io::Result<()> {
+    if record.len() < RECORD_HEADER_SIZE || record[2] & FLAG_PROTECTED == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Record is not header protected",
+        ));
+    }
+    let sample_offset = match record[2] & FLAG_SESSION_ID {
+        0 => RECORD_HEADER_SIZE,
+        _ => RECORD_HEADER_SIZE + SESSION_ID_SIZE,
+    };
+    let sample = record
+        .get(sample_offset..sample_offset + HP_SAMPLE_SIZE)
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Ciphertext too short for header protection sample",
+            )
+        })?;
+
+    // ChaCha20 keystream with the block counter and nonce taken from the sample
+    let counter = u32::from_le_bytes([sample[0], sample[1], sample[2], sample[3]]);
+    let mut nonce = [0u8; 12];
+    nonce.copy_from_slice(&sample[4..]);
+    let mut mask = [0u8; 15];
+    let mask_failed = || io::Error::new(io::ErrorKind::InvalidData, "Header mask failed");
+    let mut cipher = ChaCha20::new(hp_key.into(), &nonce.into());
+    cipher
+        .try_seek(counter as u64 * 64)
+        .map_err(|_| mask_failed())?;
+    cipher
+        .try_apply_keystream(&mut mask)
+        .map_err(|_| mask_failed())?;
+
+    // Message type, low flag bits, epoch, then stream ID and sequence number
+    record[1] ^= mask[0];
+    record[2] ^= mask[1] & 0x0F;
+    record[3] ^= mask[2];
+    for (byte, m) in record[4..16].iter_mut().zip(&mask[3..]) {
+        *byte ^= m;
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -344,6 +556,94 @@ mod tests {
         assert_eq!(record.ciphertext, deserialized.ciphertext);
     }
 
+    #[test]
+    fn test_session_id_authenticated() {
+        let key = [0x42u8; 32];
+        let iv = [0x24u8; 12];
+        let record = Record::encrypt_for_session(
+            PROTOCOL_VERSION,
+            Some(0x0102_0304_0506_0708),
+            MSG_TYPE_DATA,
+            0,
+            0,
+            1,
+            7,
+            b"hello",
+            &key,
+            &iv,
+        )
+        .expect("encrypt in test");
+        assert_ne!(record.header.flags & FLAG_SESSION_ID, 0);
+
+        let mut bytes = record.to_bytes();
+        assert_eq!(Record::peek_session_id(&bytes), Some(0x0102_0304_0506_0708));
+        let decoded = Record::from_bytes(&bytes).expect("decode in test");
+        assert_eq!(
+            decoded.decrypt(&key, &iv).expect("decrypt in test"),
+            b"hello"
+        );
+
+        // A record moved to another session fails authentication
+        bytes[RECORD_HEADER_SIZE + SESSION_ID_SIZE - 1] ^= 0xFF;
+        let moved = Record::from_bytes(&bytes).expect("decode in test");
+        assert!(moved.decrypt(&key, &iv).is_err());
+
+        // Truncated session ID is rejected rather than panicking
+        assert!(Record::from_bytes(&bytes[..RECORD_HEADER_SIZE + 3]).is_err());
+        assert_eq!(Record::peek_session_id(&bytes[..RECORD_HEADER_SIZE]), None);
+    }
+
+    #[test]
+    fn test_header_protection_roundtrip() {
+        let key = [0x42u8; 32];
+        let iv = [0x24u8; 12];
+        let hp_key = [0x11u8; 32];
+        let record = Record::encrypt_for_session(
+            PROTOCOL_VERSION,
+            Some(9),
+            MSG_TYPE_DATA,
+            FLAG_PROTECTED | FLAG_DATA_RELIABLE,
+            3,
+            5,
+            77,
+            b"hello",
+            &key,
+            &iv,
+        )
+        .expect("encrypt in test");
+        let clear = record.to_bytes();
+
+        let mut bytes = clear.clone();
+        protect_header(&hp_key, &mut bytes).expect("protect in test");
+        assert_eq!(bytes[0], clear[0]);
+        assert_eq!(bytes[2] & 0xF0, clear[2] & 0xF0);
+        assert_ne!(bytes[1..16], clear[1..16]);
+        assert_eq!(bytes[16..], clear[16..]);
+
+        unprotect_header(&hp_key, &mut bytes).expect("unprotect in test");
+        assert_eq!(bytes, clear);
+        let decoded = Record::from_bytes(&bytes).expect("decode in test");
+        assert_eq!(decoded.header.sequence_number, 77);
+        assert_eq!(
+            decoded.decrypt(&key, &iv).expect("decrypt in test"),
+            b"hello"
+        );
+
+        // Unmasking with the wrong key leaves a header the AEAD rejects
+        let mut bytes = clear.clone();
+        protect_header(&hp_key, &mut bytes).expect("protect in test");
+        unprotect_header(&[0x12; 32], &mut bytes).expect("unprotect in test");
+        assert!(Record::from_bytes(&bytes)
+            .and_then(|record| record.decrypt(&key, &iv))
+            .is_err());
+
+        // Only records flagged as protected are masked
+        let mut plain = Record::new(MSG_TYPE_DATA, 0, 0, 1, 1, vec![0; 16]).to_bytes();
+        assert!(protect_header(&hp_key, &mut plain).is_err());
+        let mut short = Record::new(MSG_TYPE_DATA, FLAG_PROTECTED, 0, 1, 1, vec![0; 8]).to_bytes();
+        assert!(protect_header(&hp_key, &mut short).is_err());
+    }
+
     #[test]
     fn test_epoch_wrapping() {
         // Test that epoch is 8-bit
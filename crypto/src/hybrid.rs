@@ -4,9 +4,12 @@
 // LinkedIn: https://www.linkedin.com/in/thor-thor0
 // License: MIT (see LICENSE file for details)
 
+use alloc::vec::Vec;
 use pqcrypto_mlkem::mlkem768::{
-    keypair as kyber_keypair, PublicKey as KyberPublicKey, SecretKey as KyberSecretKey,
+    decapsulate, encapsulate, keypair as kyber_keypair, Ciphertext as KyberCiphertext,
+    PublicKey as KyberPublicKey, SecretKey as KyberSecretKey,
 };
+use pqcrypto_traits::kem::{Ciphertext as _, PublicKey as _, SharedSecret as _};
 use rand::rngs::OsRng;
 use x25519_dalek::StaticSecret;
 
@@ -40,6 +43,32 @@ impl HybridHandshake {
     pub fn kyber_secret_key(&self) -> &KyberSecretKey {
         &self.kyber_sk
     }
+
+    /// Encoded ML-KEM public key, as sent in SERVER_HELLO
+    pub fn kyber_public_key_bytes(&self) -> &[u8] {
+        self.kyber_pk.as_bytes()
+    }
+
+    /// Decapsulates an ML-KEM ciphertext from CLIENT_FINISH
+    ///
+    /// Returns `None` if the ciphertext is malformed.
+    pub fn decapsulate(&self, ciphertext: &[u8]) -> Option<[u8; 32]> {
+        let ct = KyberCiphertext::from_bytes(ciphertext).ok()?;
+        shared_secret_32(decapsulate(&ct, &self.kyber_sk).as_bytes())
+    }
+}
+
+/// Encapsulates to an encoded ML-KEM public key
+///
+/// Returns `(ciphertext, shared_secret)`, or `None` if the key is malformed.
+pub fn kyber_encapsulate(public_key: &[u8]) -> Option<(Vec<u8>, [u8; 32])> {
+    let pk = KyberPublicKey::from_bytes(public_key).ok()?;
+    let (ss, ct) = encapsulate(&pk);
+    Some((ct.as_bytes().to_vec(), shared_secret_32(ss.as_bytes())?))
+}
+
+fn shared_secret_32(bytes: &[u8]) -> Option<[u8; 32]> {
+    bytes.try_into().ok()
 }
 
 impl Default for HybridHandshake {
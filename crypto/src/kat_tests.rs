@@ -119,4 +119,22 @@ mod kat_tests {
             "Roundtrip encaps/decaps must produce matching shared secret"
         );
     }
+
+    #[test]
+    fn test_hybrid_handshake_encoded_kem() {
+        // Encoded-key helpers used by the UDP handshake
+        let responder = crate::HybridHandshake::new();
+        let encapsulated = crate::kyber_encapsulate(responder.kyber_public_key_bytes());
+        assert!(
+            encapsulated.is_some(),
+            "Encapsulation to a valid key must succeed"
+        );
+        let Some((ct, ss_initiator)) = encapsulated else {
+            return;
+        };
+        assert_eq!(responder.decapsulate(&ct), Some(ss_initiator));
+
+        assert!(crate::kyber_encapsulate(&[0u8; 16]).is_none());
+        assert!(responder.decapsulate(&ct[..100]).is_none());
+    }
 }
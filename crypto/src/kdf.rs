@@ -30,6 +30,12 @@ pub const LABEL_RI_KEY: &[u8] = b"cryp-rq ri key";
 /// Label for Responder→Initiator IV
 pub const LABEL_RI_IV: &[u8] = b"cryp-rq ri iv";
 
+/// Label for Initiator→Responder header protection key
+pub const LABEL_IR_HP: &[u8] = b"cryp-rq ir hp";
+
+/// Label for Responder→Initiator header protection key
+pub const LABEL_RI_HP: &[u8] = b"cryp-rq ri hp";
+
 /// Derives handshake authentication key and master secret from hybrid shared secrets
 ///
 /// As specified in Section 4.4:
@@ -184,6 +190,33 @@ pub fn derive_epoch_keys(
     (key_ir, iv_ir, key_ri, iv_ri)
 }
 
+/// Derives the header protection keys for both directions
+///
+/// As specified in Section 6.6, these keys are not epoch-scoped: a receiver
+/// must remove header protection before it can read the epoch.
+///
+/// # Returns
+///
+/// * `(hp_ir, hp_ri)` - Header protection keys for both directions
+///
+/// # Note
+///
+/// HKDF expand is guaranteed not to fail for these sizes; expect is acceptable here.
+#[allow(clippy::expect_used)]
+pub fn derive_header_protection_keys(master_secret: &[u8; 32]) -> ([u8; 32], [u8; 32]) {
+    let (_, hk) = Hkdf::<Sha256>::extract(None, master_secret);
+
+    let mut hp_ir = [0u8; 32];
+    hk.expand(LABEL_IR_HP, &mut hp_ir)
+        .expect("HKDF expand should not fail");
+
+    let mut hp_ri = [0u8; 32];
+    hk.expand(LABEL_RI_HP, &mut hp_ri)
+        .expect("HKDF expand should not fail");
+
+    (hp_ir, hp_ri)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -225,4 +258,15 @@ mod tests {
         assert_ne!(key_ri_0, key_ri_1);
         assert_ne!(iv_ri_0, iv_ri_1);
     }
+
+    #[test]
+    fn test_derive_header_protection_keys() {
+        let master_secret = [0x42u8; 32];
+        let (hp_ir, hp_ri) = derive_header_protection_keys(&master_secret);
+        let (key_ir, _, key_ri, _) = derive_epoch_keys(&master_secret, 0, 32, 12);
+
+        assert_ne!(hp_ir, hp_ri);
+        assert_ne!(hp_ir.to_vec(), key_ir);
+        assert_ne!(hp_ri.to_vec(), key_ri);
+    }
 }
@@ -17,8 +17,9 @@ mod pqc_suite;
 mod zkp;
 
 pub use kdf::{
-    derive_epoch_keys, derive_handshake_keys, derive_traffic_keys, LABEL_HS_AUTH, LABEL_IR_IV,
-    LABEL_IR_KEY, LABEL_MASTER_SECRET, LABEL_RI_IV, LABEL_RI_KEY, SALT_HS,
+    derive_epoch_keys, derive_handshake_keys, derive_header_protection_keys, derive_traffic_keys,
+    LABEL_HS_AUTH, LABEL_IR_HP, LABEL_IR_IV, LABEL_IR_KEY, LABEL_MASTER_SECRET, LABEL_RI_HP,
+    LABEL_RI_IV, LABEL_RI_KEY, SALT_HS,
 };
 
 #[cfg(test)]
@@ -28,7 +29,7 @@ mod kat_tests;
 mod property_tests;
 
 // Publicly export items needed by other crates
-pub use crate::hybrid::{HybridHandshake, SharedSecret32};
+pub use crate::hybrid::{kyber_encapsulate, HybridHandshake, SharedSecret32};
 pub use crate::ppk::{PPKStore, PostQuantumPSK};
 pub use crate::pqc_suite::{PQCKeyExchange, PQCSignature, PQCSuite};
 pub use crate::zkp::{generate_proof, verify_proof, ZkProof};
@@ -0,0 +1,113 @@
+// Copyright (c) 2025 Thor Thor
+// Author: Thor Thor (GitHub: https://github.com/codethor0)
+// Contact: codethor@gmail.com
+// LinkedIn: https://www.linkedin.com/in/thor-thor0
+// License: MIT (see LICENSE file for details)
+
+//! Loopback VPN packet rate with one syscall per datagram vs batched I/O
+//!
+//! Run with `cargo bench -p node --bench udp_batch`.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use node::{create_tunnel, BucketLimits, RateLimitConfig, Tunnel, TunnelError};
+
+/// VPN packet size, typical of a full-MTU TUN read
+const PACKET_SIZE: usize = 1200;
+/// Packets handed to the tunnel at once
+const BURST: usize = 32;
+/// How long each mode sends for
+const RUN_TIME: Duration = Duration::from_secs(3);
+
+struct Rates {
+    sent: f64,
+    received: f64,
+}
+
+async fn tunnel() -> Result<Tunnel, TunnelError> {
+    create_tunnel(&[1; 32], &[1; 32], &[1; 32], &[1; 64], "127.0.0.1:0").await
+}
+
+async fn run(batched: bool) -> Result<Rates, TunnelError> {
+    let receiver = Arc::new(tunnel().await?);
+    let sender = tunnel().await?;
+    receiver.set_batch_io(batched);
+    sender.set_batch_io(batched);
+    // Measure the I/O path, not the ingress limits
+    let unlimited = BucketLimits {
+        packets_per_second: u32::MAX,
+        packet_burst: u32::MAX,
+        bytes_per_second: 0,
+        byte_burst: 0,
+    };
+    receiver.set_rate_limits(RateLimitConfig {
+        per_source: unlimited,
+        per_session: unlimited,
+        ..RateLimitConfig::default()
+    });
+    *sender
+        .peer_addr()
+        .write()
+        .map_err(|e| TunnelError::LockPoisoned(e.to_string()))? = Some(receiver.local_addr()?);
+
+    let received = Arc::new(AtomicU64::new(0));
+    let counter = received.clone();
+    let receive_loop = tokio::spawn(async move {
+        loop {
+            if receiver.recv_record().await.is_ok() {
+                counter.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    });
+
+    let packets: Vec<Vec<u8>> = (0..BURST).map(|i| vec![i as u8; PACKET_SIZE]).collect();
+    let burst: Vec<&[u8]> = packets.iter().map(|p| p.as_slice()).collect();
+    let mut sent = 0u64;
+    let start = Instant::now();
+    while start.elapsed() < RUN_TIME {
+        if batched {
+            sender.send_vpn_packets(&burst).await?;
+        } else {
+            for packet in &burst {
+                sender.send_vpn_packet(packet).await?;
+            }
+        }
+        sent += BURST as u64;
+    }
+    let elapsed = start.elapsed().as_secs_f64();
+    // Let the receiver drain what is still queued on the socket
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    receive_loop.abort();
+
+    Ok(Rates {
+        sent: sent as f64 / elapsed,
+        received: received.load(Ordering::Relaxed) as f64 / elapsed,
+    })
+}
+
+#[tokio::main]
+async fn main() -> Result<(), TunnelError> {
+    println!(
+        "loopback, {} byte packets, bursts of {}, {:?} per mode",
+        PACKET_SIZE, BURST, RUN_TIME
+    );
+    println!("{:<14} {:>12} {:>12}", "mode", "sent pps", "recv pps");
+    let per_datagram = run(false).await?;
+    println!(
+        "{:<14} {:>12.0} {:>12.0}",
+        "per-datagram", per_datagram.sent, per_datagram.received
+    );
+    let batched = run(true).await?;
+    println!(
+        "{:<14} {:>12.0} {:>12.0}",
+        "batched", batched.sent, batched.received
+    );
+    println!(
+        "speedup: {:.2}x sent, {:.2}x received",
+        batched.sent / per_datagram.sent,
+        batched.received / per_datagram.received
+    );
+    Ok(())
+}
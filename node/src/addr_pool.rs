@@ -0,0 +1,450 @@
+// Copyright (c) 2025 Thor Thor
+// Author: Thor Thor (GitHub: https://github.com/codethor0)
+// Contact: codethor@gmail.com
+// LinkedIn: https://www.linkedin.com/in/thor-thor0
+// License: MIT (see LICENSE file for details)
+
+use cryprq_core::AddressLease;
+use std::collections::HashMap;
+use std::net::Ipv4Addr;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::tun::TunConfig;
+
+/// Address pool configuration (listener side)
+#[derive(Debug, Clone)]
+pub struct AddressPoolConfig {
+    /// Tunnel network address
+    pub network: Ipv4Addr,
+    /// Tunnel network prefix length
+    pub prefix_len: u8,
+    /// Listener's own address (defaults to the first host in the network)
+    pub gateway: Option<Ipv4Addr>,
+    /// Extra routes pushed to dialers (destination, prefix length)
+    pub routes: Vec<(Ipv4Addr, u8)>,
+    /// DNS servers pushed to dialers
+    pub dns_servers: Vec<Ipv4Addr>,
+    /// MTU pushed to dialers
+    pub mtu: u16,
+    /// Lease lifetime
+    pub lease_duration: Duration,
+    /// File used to persist leases across restarts
+    pub state_file: Option<PathBuf>,
+}
+
+impl Default for AddressPoolConfig {
+    fn default() -> Self {
+        Self {
+            network: Ipv4Addr::new(10, 0, 0, 0),
+            prefix_len: 24,
+            gateway: None,
+            routes: Vec::new(),
+            dns_servers: Vec::new(),
+            mtu: 1420,
+            lease_duration: Duration::from_secs(3600),
+            state_file: None,
+        }
+    }
+}
+
+impl AddressPoolConfig {
+    /// Creates a configuration from a CIDR string such as `10.0.0.0/24`
+    pub fn from_cidr(cidr: &str) -> Result<Self, AddressPoolError> {
+        let (network, prefix_len) = parse_ipv4_cidr(cidr)?;
+        Ok(Self {
+            network,
+            prefix_len,
+            ..Self::default()
+        })
+    }
+}
+
+/// Parses an IPv4 CIDR string (`a.b.c.d/len`)
+pub fn parse_ipv4_cidr(cidr: &str) -> Result<(Ipv4Addr, u8), AddressPoolError> {
+    let (addr, len) = cidr
+        .split_once('/')
+        .ok_or_else(|| AddressPoolError::InvalidNetwork(format!("missing prefix in {}", cidr)))?;
+    let addr: Ipv4Addr = addr
+        .parse()
+        .map_err(|_| AddressPoolError::InvalidNetwork(format!("invalid address in {}", cidr)))?;
+    let len: u8 = len
+        .parse()
+        .map_err(|_| AddressPoolError::InvalidNetwork(format!("invalid prefix in {}", cidr)))?;
+    if len > 32 {
+        return Err(AddressPoolError::InvalidNetwork(format!(
+            "prefix out of range in {}",
+            cidr
+        )));
+    }
+    Ok((addr, len))
+}
+
+/// Converts a prefix length to a dotted IPv4 netmask
+pub fn prefix_to_netmask(prefix_len: u8) -> Ipv4Addr {
+    Ipv4Addr::from(
+        u32::MAX
+            .checked_shl(32 - prefix_len.min(32) as u32)
+            .unwrap_or(0),
+    )
+}
+
+/// Lease held by a peer identity
+#[derive(Debug, Clone)]
+struct LeaseEntry {
+    address: Ipv4Addr,
+    /// Expiry as UNIX timestamp (seconds)
+    expires_at: u64,
+}
+
+/// Tunnel address pool owned by the listener
+///
+/// Hands out one address per peer identity. A peer that reconnects gets its
+/// previous address back and the lease is renewed. Expired leases are only
+/// reclaimed when the pool would otherwise be exhausted, so addresses stay
+/// stable across short disconnects.
+pub struct AddressPool {
+    config: AddressPoolConfig,
+    network: u32,
+    gateway: Ipv4Addr,
+    leases: HashMap<String, LeaseEntry>,
+}
+
+impl AddressPool {
+    /// Create a new address pool, loading persisted leases if configured
+    pub fn new(config: AddressPoolConfig) -> Result<Self, AddressPoolError> {
+        if config.prefix_len > 30 {
+            return Err(AddressPoolError::InvalidNetwork(format!(
+                "/{} leaves no room for peers",
+                config.prefix_len
+            )));
+        }
+
+        let mask = u32::from(prefix_to_netmask(config.prefix_len));
+        let network = u32::from(config.network) & mask;
+        let gateway = config
+            .gateway
+            .unwrap_or_else(|| Ipv4Addr::from(network + 1));
+        if u32::from(gateway) & mask != network {
+            return Err(AddressPoolError::InvalidNetwork(format!(
+                "gateway {} outside {}/{}",
+                gateway,
+                Ipv4Addr::from(network),
+                config.prefix_len
+            )));
+        }
+
+        let mut pool = Self {
+            config,
+            network,
+            gateway,
+            leases: HashMap::new(),
+        };
+        pool.load()?;
+        Ok(pool)
+    }
+
+    /// Listener's own address inside the tunnel network
+    pub fn gateway(&self) -> Ipv4Addr {
+        self.gateway
+    }
+
+    /// Prefix length of the tunnel network
+    pub fn prefix_len(&self) -> u8 {
+        self.config.prefix_len
+    }
+
+    /// Number of leases currently tracked (including expired ones kept for reuse)
+    pub fn lease_count(&self) -> usize {
+        self.leases.len()
+    }
+
+    /// TUN configuration for the listener's own interface
+    pub fn tun_config(&self, name: &str) -> TunConfig {
+        TunConfig {
+            name: name.to_string(),
+            address: self.gateway.to_string(),
+            netmask: prefix_to_netmask(self.config.prefix_len).to_string(),
+            mtu: self.config.mtu,
+            routes: Vec::new(),
+            tap: false,
+        }
+    }
+
+    /// Grant or renew the lease for a peer identity
+    ///
+    /// `requested` is the address the peer held previously; it is honoured when
+    /// it is free and inside the pool.
+    pub fn lease_for(
+        &mut self,
+        identity: &[u8],
+        requested: Option<Ipv4Addr>,
+    ) -> Result<AddressLease, AddressPoolError> {
+        let key = hex::encode(identity);
+        let now = unix_now();
+        let expires_at = now + self.config.lease_duration.as_secs();
+
+        let address = if let Some(entry) = self.leases.get_mut(&key) {
+            entry.expires_at = expires_at;
+            entry.address
+        } else {
+            let address = match requested.filter(|addr| self.is_free(*addr)) {
+                Some(addr) => addr,
+                None => self.allocate(now)?,
+            };
+            self.leases.insert(
+                key,
+                LeaseEntry {
+                    address,
+                    expires_at,
+                },
+            );
+            address
+        };
+
+        self.save();
+
+        log::info!(
+            "event=address_lease identity={} address={}/{} lease_secs={}",
+            hex::encode(&identity[..identity.len().min(8)]),
+            address,
+            self.config.prefix_len,
+            self.config.lease_duration.as_secs()
+        );
+
+        Ok(AddressLease {
+            address,
+            prefix_len: self.config.prefix_len,
+            routes: self.config.routes.clone(),
+            dns_servers: self.config.dns_servers.clone(),
+            mtu: self.config.mtu,
+            lease_secs: self.config.lease_duration.as_secs().min(u32::MAX as u64) as u32,
+        })
+    }
+
+    /// Return a peer's lease to the pool
+    pub fn release(&mut self, identity: &[u8]) {
+        if self.leases.remove(&hex::encode(identity)).is_some() {
+            self.save();
+        }
+    }
+
+    /// Address currently leased to a peer identity, if any
+    pub fn address_of(&self, identity: &[u8]) -> Option<Ipv4Addr> {
+        self.leases.get(&hex::encode(identity)).map(|e| e.address)
+    }
+
+    fn host_range(&self) -> (u32, u32) {
+        let size = 1u32 << (32 - self.config.prefix_len as u32);
+        // Skip network and broadcast addresses
+        (self.network + 1, self.network + size - 2)
+    }
+
+    fn is_free(&self, addr: Ipv4Addr) -> bool {
+        let (first, last) = self.host_range();
+        let value = u32::from(addr);
+        value >= first
+            && value <= last
+            && addr != self.gateway
+            && !self.leases.values().any(|e| e.address == addr)
+    }
+
+    fn allocate(&mut self, now: u64) -> Result<Ipv4Addr, AddressPoolError> {
+        let (first, last) = self.host_range();
+        for value in first..=last {
+            let addr = Ipv4Addr::from(value);
+            if addr != self.gateway && !self.leases.values().any(|e| e.address == addr) {
+                return Ok(addr);
+            }
+        }
+
+        // Pool full: reclaim the longest-expired lease
+        let reclaim = self
+            .leases
+            .iter()
+            .filter(|(_, e)| e.expires_at <= now)
+            .min_by_key(|(_, e)| e.expires_at)
+            .map(|(k, e)| (k.clone(), e.address));
+        match reclaim {
+            Some((key, addr)) => {
+                self.leases.remove(&key);
+                Ok(addr)
+            }
+            None => Err(AddressPoolError::Exhausted),
+        }
+    }
+
+    fn load(&mut self) -> Result<(), AddressPoolError> {
+        let path = match &self.config.state_file {
+            Some(path) if path.exists() => path.clone(),
+            _ => return Ok(()),
+        };
+        let contents = std::fs::read_to_string(&path)
+            .map_err(|e| AddressPoolError::Io(format!("{}: {}", path.display(), e)))?;
+
+        for line in contents.lines() {
+            let mut fields = line.split_whitespace();
+            let parsed = match (fields.next(), fields.next(), fields.next()) {
+                (Some(key), Some(addr), Some(expires)) => addr
+                    .parse::<Ipv4Addr>()
+                    .ok()
+                    .zip(expires.parse::<u64>().ok())
+                    .map(|(address, expires_at)| (key.to_string(), address, expires_at)),
+                _ => None,
+            };
+            match parsed {
+                Some((key, address, expires_at)) => {
+                    self.leases.insert(
+                        key,
+                        LeaseEntry {
+                            address,
+                            expires_at,
+                        },
+                    );
+                }
+                None => log::warn!("Ignoring malformed lease entry: {}", line),
+            }
+        }
+
+        log::info!(
+            "Loaded {} address leases from {}",
+            self.leases.len(),
+            path.display()
+        );
+        Ok(())
+    }
+
+    fn save(&self) {
+        let path = match &self.config.state_file {
+            Some(path) => path,
+            None => return,
+        };
+        let mut contents = String::new();
+        for (key, entry) in &self.leases {
+            contents.push_str(&format!("{} {} {}\n", key, entry.address, entry.expires_at));
+        }
+        let tmp = path.with_extension("tmp");
+        if let Err(e) = std::fs::write(&tmp, contents).and_then(|_| std::fs::rename(&tmp, path)) {
+            log::warn!(
+                "Failed to persist address leases to {}: {}",
+                path.display(),
+                e
+            );
+        }
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum AddressPoolError {
+    #[error("Invalid tunnel network: {0}")]
+    InvalidNetwork(String),
+    #[error("Address pool exhausted")]
+    Exhausted,
+    #[error("Lease state error: {0}")]
+    Io(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn small_pool() -> AddressPool {
+        let config = AddressPoolConfig::from_cidr("10.9.0.0/29").expect("valid cidr in test");
+        AddressPool::new(config).expect("pool creation in test")
+    }
+
+    #[test]
+    fn test_gateway_defaults_to_first_host() {
+        let pool = small_pool();
+        assert_eq!(pool.gateway(), Ipv4Addr::new(10, 9, 0, 1));
+        let tun = pool.tun_config("cryprq0");
+        assert_eq!(tun.address, "10.9.0.1");
+        assert_eq!(tun.netmask, "255.255.255.248");
+    }
+
+    #[test]
+    fn test_lease_is_stable_per_identity() {
+        let mut pool = small_pool();
+        let a = pool.lease_for(b"peer-a", None).expect("lease a");
+        let b = pool.lease_for(b"peer-b", None).expect("lease b");
+        assert_ne!(a.address, b.address);
+        assert_ne!(a.address, pool.gateway());
+
+        // Reconnect renews the same address
+        let again = pool.lease_for(b"peer-a", None).expect("renew a");
+        assert_eq!(again.address, a.address);
+        assert_eq!(again.prefix_len, 29);
+    }
+
+    #[test]
+    fn test_requested_address_honoured_when_free() {
+        let mut pool = small_pool();
+        let lease = pool
+            .lease_for(b"peer-a", Some(Ipv4Addr::new(10, 9, 0, 5)))
+            .expect("lease a");
+        assert_eq!(lease.address, Ipv4Addr::new(10, 9, 0, 5));
+
+        // Taken address and out-of-pool address are ignored
+        let lease = pool
+            .lease_for(b"peer-b", Some(Ipv4Addr::new(10, 9, 0, 5)))
+            .expect("lease b");
+        assert_ne!(lease.address, Ipv4Addr::new(10, 9, 0, 5));
+        let lease = pool
+            .lease_for(b"peer-c", Some(Ipv4Addr::new(192, 168, 1, 5)))
+            .expect("lease c");
+        assert_ne!(lease.address, Ipv4Addr::new(192, 168, 1, 5));
+    }
+
+    #[test]
+    fn test_pool_exhaustion() {
+        let mut pool = small_pool();
+        // /29 has 6 hosts, one is the gateway
+        for i in 0..5u8 {
+            assert!(pool.lease_for(&[i], None).is_ok());
+        }
+        assert!(matches!(
+            pool.lease_for(&[99], None),
+            Err(AddressPoolError::Exhausted)
+        ));
+
+        pool.release(&[0]);
+        assert!(pool.lease_for(&[99], None).is_ok());
+    }
+
+    #[test]
+    fn test_leases_persist_to_state_file() {
+        let path = std::env::temp_dir().join(format!("cryprq-leases-{}.txt", std::process::id()));
+        let mut config = AddressPoolConfig::from_cidr("10.9.1.0/24").expect("valid cidr in test");
+        config.state_file = Some(path.clone());
+
+        let address = {
+            let mut pool = AddressPool::new(config.clone()).expect("pool creation in test");
+            pool.lease_for(b"peer-a", None).expect("lease a").address
+        };
+
+        let mut reloaded = AddressPool::new(config).expect("pool reload in test");
+        assert_eq!(reloaded.address_of(b"peer-a"), Some(address));
+        assert_eq!(
+            reloaded
+                .lease_for(b"peer-a", None)
+                .expect("renew a")
+                .address,
+            address
+        );
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn test_prefix_to_netmask() {
+        assert_eq!(prefix_to_netmask(24), Ipv4Addr::new(255, 255, 255, 0));
+        assert_eq!(prefix_to_netmask(0), Ipv4Addr::new(0, 0, 0, 0));
+        assert_eq!(prefix_to_netmask(32), Ipv4Addr::new(255, 255, 255, 255));
+    }
+}
@@ -0,0 +1,200 @@
+// Copyright (c) 2025 Thor Thor
+// Author: Thor Thor (GitHub: https://github.com/codethor0)
+// Contact: codethor@gmail.com
+// LinkedIn: https://www.linkedin.com/in/thor-thor0
+// License: MIT (see LICENSE file for details)
+
+use std::time::{Duration, Instant};
+
+/// Initial congestion window in segments (RFC 6928)
+const INITIAL_WINDOW_SEGMENTS: u64 = 10;
+
+/// Smallest congestion window after a loss, in segments
+const MIN_WINDOW_SEGMENTS: u64 = 2;
+
+/// Pacing rate as a multiple of cwnd / srtt, leaving room for window growth
+const PACING_GAIN: f64 = 1.25;
+
+/// Segments the pacer lets through back to back
+const PACING_BURST_SEGMENTS: u64 = 4;
+
+/// NewReno congestion control (RFC 5681, RFC 6582) in bytes
+///
+/// Slow start until the first loss, then additive increase. A loss halves
+/// the window once per round trip: further losses among data sent before
+/// the first one belong to the same recovery period.
+#[derive(Debug, Clone)]
+pub struct NewReno {
+    mss: u64,
+    cwnd: u64,
+    ssthresh: u64,
+    /// Data sent below this offset was in flight at the last reduction
+    recovery_point: Option<u64>,
+}
+
+impl NewReno {
+    /// New controller for segments of at most `mss` bytes
+    pub fn new(mss: u64) -> Self {
+        Self {
+            mss,
+            cwnd: INITIAL_WINDOW_SEGMENTS * mss,
+            ssthresh: u64::MAX,
+            recovery_point: None,
+        }
+    }
+
+    /// Congestion window in bytes
+    pub fn window(&self) -> u64 {
+        self.cwnd
+    }
+
+    /// Whether another full segment fits next to `in_flight` bytes
+    pub fn can_send(&self, in_flight: u64) -> bool {
+        in_flight + self.mss <= self.cwnd
+    }
+
+    /// `bytes` newly acknowledged; `acked_offset` is the highest offset the
+    /// peer has now acknowledged
+    pub fn on_ack(&mut self, bytes: u64, acked_offset: u64) {
+        if let Some(point) = self.recovery_point {
+            if acked_offset < point {
+                // Still repairing the last loss: hold the window
+                return;
+            }
+            self.recovery_point = None;
+        }
+        if self.cwnd < self.ssthresh {
+            self.cwnd += bytes;
+        } else {
+            self.cwnd += (self.mss * bytes / self.cwnd).max(1);
+        }
+    }
+
+    /// Data at `lost_offset` was lost; `next_offset` is where new data
+    /// would be sent next
+    pub fn on_loss(&mut self, lost_offset: u64, in_flight: u64, next_offset: u64) {
+        if self.recovery_point.is_some_and(|point| lost_offset < point) {
+            return;
+        }
+        self.ssthresh = (in_flight / 2).max(MIN_WINDOW_SEGMENTS * self.mss);
+        self.cwnd = self.ssthresh;
+        self.recovery_point = Some(next_offset);
+    }
+
+    /// Retransmission timeout: collapse to one segment and slow start again
+    pub fn on_timeout(&mut self, in_flight: u64, next_offset: u64) {
+        self.ssthresh = (in_flight / 2).max(MIN_WINDOW_SEGMENTS * self.mss);
+        self.cwnd = self.mss;
+        self.recovery_point = Some(next_offset);
+    }
+}
+
+/// Spreads a window's worth of sends over a round trip
+///
+/// Token bucket refilled at `PACING_GAIN * cwnd / srtt`, with a small burst
+/// allowance. Until an RTT is known, sends are not delayed.
+#[derive(Debug, Clone)]
+pub struct Pacer {
+    /// Bytes per second; None before the first RTT sample
+    rate: Option<f64>,
+    capacity: f64,
+    tokens: f64,
+    last: Instant,
+}
+
+impl Pacer {
+    /// New pacer for segments of at most `mss` bytes
+    pub fn new(mss: u64) -> Self {
+        let capacity = (PACING_BURST_SEGMENTS * mss) as f64;
+        Self {
+            rate: None,
+            capacity,
+            tokens: capacity,
+            last: Instant::now(),
+        }
+    }
+
+    /// Follow the current congestion window and smoothed RTT
+    pub fn set_rate(&mut self, cwnd: u64, srtt: Duration) {
+        let secs = srtt.as_secs_f64().max(1e-4);
+        self.rate = Some(PACING_GAIN * cwnd as f64 / secs);
+    }
+
+    /// Take `bytes` of budget, or say how long to wait for it
+    pub fn try_send(&mut self, bytes: usize, now: Instant) -> Result<(), Duration> {
+        let Some(rate) = self.rate else {
+            return Ok(());
+        };
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate).min(self.capacity);
+        self.last = now;
+        let needed = (bytes as f64).min(self.capacity);
+        if self.tokens >= needed {
+            self.tokens -= needed;
+            return Ok(());
+        }
+        Err(Duration::from_secs_f64((needed - self.tokens) / rate))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MSS: u64 = 1000;
+
+    #[test]
+    fn test_slow_start_then_congestion_avoidance() {
+        let mut cc = NewReno::new(MSS);
+        assert_eq!(cc.window(), 10 * MSS);
+        cc.on_ack(10 * MSS, 10 * MSS);
+        assert_eq!(cc.window(), 20 * MSS);
+
+        // Loss: halve once, hold during recovery, then grow linearly
+        cc.on_loss(12 * MSS, 20 * MSS, 30 * MSS);
+        assert_eq!(cc.window(), 10 * MSS);
+        cc.on_loss(15 * MSS, 20 * MSS, 30 * MSS);
+        assert_eq!(cc.window(), 10 * MSS);
+        cc.on_ack(5 * MSS, 25 * MSS);
+        assert_eq!(cc.window(), 10 * MSS);
+        cc.on_ack(10 * MSS, 30 * MSS);
+        assert_eq!(cc.window(), 11 * MSS);
+        assert!(cc.can_send(10 * MSS));
+        assert!(!cc.can_send(10 * MSS + 1));
+    }
+
+    #[test]
+    fn test_timeout_collapses_window() {
+        let mut cc = NewReno::new(MSS);
+        cc.on_timeout(10 * MSS, 10 * MSS);
+        assert_eq!(cc.window(), MSS);
+        // Slow start back up to ssthresh after recovery
+        cc.on_ack(MSS, 10 * MSS);
+        assert_eq!(cc.window(), 2 * MSS);
+    }
+
+    #[test]
+    fn test_pacer_spreads_sends() {
+        let start = Instant::now();
+        let mut pacer = Pacer::new(MSS);
+        // No RTT yet: unpaced
+        for _ in 0..20 {
+            assert!(pacer.try_send(MSS as usize, start).is_ok());
+        }
+
+        // 10 segments per 100ms RTT, times the gain: 125 KB/s
+        pacer.set_rate(10 * MSS, Duration::from_millis(100));
+        let mut sent = 0;
+        while pacer.try_send(MSS as usize, start).is_ok() {
+            sent += 1;
+        }
+        assert!(sent <= PACING_BURST_SEGMENTS);
+        let Err(wait) = pacer.try_send(MSS as usize, start) else {
+            return;
+        };
+        assert!(wait <= Duration::from_millis(8));
+        assert!(pacer
+            .try_send(MSS as usize, start + wait + Duration::from_micros(10))
+            .is_ok());
+    }
+}
@@ -0,0 +1,167 @@
+// Copyright (c) 2025 Thor Thor
+// Author: Thor Thor (GitHub: https://github.com/codethor0)
+// Contact: codethor@gmail.com
+// LinkedIn: https://www.linkedin.com/in/thor-thor0
+// License: MIT (see LICENSE file for details)
+
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use rand::rngs::OsRng;
+use rand::RngCore;
+use ring::hmac;
+
+/// How long a cookie secret is used for new cookies
+///
+/// Cookies stay valid for up to two lifetimes: the previous secret is kept
+/// for one rotation.
+const COOKIE_SECRET_LIFETIME: Duration = Duration::from_secs(120);
+
+struct CookieSecrets {
+    current: hmac::Key,
+    previous: hmac::Key,
+    rotated_at: Instant,
+}
+
+impl CookieSecrets {
+    /// Rotate if the current secret has outlived its lifetime
+    ///
+    /// Runs on every issue and verify. After two lifetimes without a
+    /// rotation both secrets are replaced, so no cookie outlives them.
+    fn rotate_if_due(&mut self) {
+        let age = self.rotated_at.elapsed();
+        if age < COOKIE_SECRET_LIFETIME {
+            return;
+        }
+        let retired = std::mem::replace(&mut self.current, random_key());
+        self.previous = if age >= 2 * COOKIE_SECRET_LIFETIME {
+            random_key()
+        } else {
+            retired
+        };
+        self.rotated_at = Instant::now();
+    }
+}
+
+/// Stateless handshake cookies (Section 9.7)
+///
+/// A cookie is an HMAC of the client's source address under a rotating
+/// secret. The server keeps no per-client state until a CLIENT_HELLO echoes
+/// a valid cookie, which proves the client receives at that address.
+pub(crate) struct CookieJar {
+    secrets: Mutex<CookieSecrets>,
+}
+
+impl CookieJar {
+    pub(crate) fn new() -> Self {
+        Self {
+            secrets: Mutex::new(CookieSecrets {
+                current: random_key(),
+                previous: random_key(),
+                rotated_at: Instant::now(),
+            }),
+        }
+    }
+
+    /// Cookie for `addr` under the current secret
+    pub(crate) fn issue(&self, addr: SocketAddr) -> Vec<u8> {
+        let Ok(mut secrets) = self.secrets.lock() else {
+            return Vec::new();
+        };
+        secrets.rotate_if_due();
+        hmac::sign(&secrets.current, &address_bytes(addr))
+            .as_ref()
+            .to_vec()
+    }
+
+    /// Whether `cookie` was issued to `addr` under the current or previous secret
+    pub(crate) fn verify(&self, addr: SocketAddr, cookie: &[u8]) -> bool {
+        let Ok(mut secrets) = self.secrets.lock() else {
+            return false;
+        };
+        secrets.rotate_if_due();
+        let input = address_bytes(addr);
+        hmac::verify(&secrets.current, &input, cookie).is_ok()
+            || hmac::verify(&secrets.previous, &input, cookie).is_ok()
+    }
+
+    #[cfg(test)]
+    fn rotate(&self) {
+        self.backdate(COOKIE_SECRET_LIFETIME);
+    }
+
+    /// Pretend the last rotation happened `age` ago
+    #[cfg(test)]
+    fn backdate(&self, age: Duration) {
+        if let Ok(mut secrets) = self.secrets.lock() {
+            secrets.rotated_at = Instant::now() - age;
+        }
+    }
+}
+
+fn random_key() -> hmac::Key {
+    let mut secret = [0u8; 32];
+    OsRng.fill_bytes(&mut secret);
+    hmac::Key::new(hmac::HMAC_SHA256, &secret)
+}
+
+fn address_bytes(addr: SocketAddr) -> Vec<u8> {
+    let mut buf = match addr.ip() {
+        IpAddr::V4(ip) => ip.octets().to_vec(),
+        IpAddr::V6(ip) => ip.octets().to_vec(),
+    };
+    buf.extend_from_slice(&addr.port().to_be_bytes());
+    buf
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cookie_bound_to_address() {
+        let jar = CookieJar::new();
+        let addr: SocketAddr = "192.0.2.1:4000".parse().expect("addr in test");
+        let cookie = jar.issue(addr);
+        assert!(jar.verify(addr, &cookie));
+        assert!(!jar.verify("192.0.2.1:4001".parse().expect("addr in test"), &cookie));
+        assert!(!jar.verify("192.0.2.2:4000".parse().expect("addr in test"), &cookie));
+        assert!(!jar.verify(addr, &cookie[..16]));
+    }
+
+    #[test]
+    fn test_cookie_survives_one_rotation() {
+        let jar = CookieJar::new();
+        let addr: SocketAddr = "[2001:db8::1]:4000".parse().expect("addr in test");
+        let cookie = jar.issue(addr);
+
+        jar.rotate();
+        let fresh = jar.issue(addr);
+        assert_ne!(fresh, cookie);
+        assert!(jar.verify(addr, &cookie));
+
+        jar.rotate();
+        jar.issue(addr);
+        assert!(!jar.verify(addr, &cookie));
+        assert!(jar.verify(addr, &fresh));
+    }
+
+    #[test]
+    fn test_cookie_expires_without_new_cookies() {
+        let jar = CookieJar::new();
+        let addr: SocketAddr = "192.0.2.1:4000".parse().expect("addr in test");
+        let cookie = jar.issue(addr);
+
+        // Verification alone rotates the secrets
+        jar.rotate();
+        assert!(jar.verify(addr, &cookie));
+        jar.rotate();
+        assert!(!jar.verify(addr, &cookie));
+
+        // An idle server drops both secrets after two lifetimes
+        let cookie = jar.issue(addr);
+        jar.backdate(2 * COOKIE_SECRET_LIFETIME);
+        assert!(!jar.verify(addr, &cookie));
+    }
+}
@@ -0,0 +1,161 @@
+// Copyright (c) 2025 Thor Thor
+// Author: Thor Thor (GitHub: https://github.com/codethor0)
+// Contact: codethor@gmail.com
+// LinkedIn: https://www.linkedin.com/in/thor-thor0
+// License: MIT (see LICENSE file for details)
+
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::{Arc, OnceLock};
+use std::thread;
+
+use crossbeam::channel::{self, Sender};
+use tokio::sync::oneshot;
+
+/// Fewest records handed to a worker; below this, dispatch costs more
+/// than the AEAD work it saves
+const MIN_CHUNK: usize = 4;
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// Threads running AEAD work for all tunnels in the process
+///
+/// Work is split into chunks for the workers while the calling task waits,
+/// so AEAD work stays off the async executor's threads; results come back
+/// in input order. Batches under `MIN_CHUNK` items, and pools without
+/// workers, run inline on the caller.
+pub(crate) struct CryptoPool {
+    jobs: Option<Sender<Job>>,
+    workers: usize,
+}
+
+static POOL: OnceLock<CryptoPool> = OnceLock::new();
+
+/// Process-wide pool with one worker per core
+pub(crate) fn crypto_pool() -> &'static CryptoPool {
+    POOL.get_or_init(|| {
+        let cores = thread::available_parallelism().map_or(1, |n| n.get());
+        CryptoPool::new(cores)
+    })
+}
+
+impl CryptoPool {
+    pub(crate) fn new(workers: usize) -> Self {
+        let (tx, rx) = channel::unbounded::<Job>();
+        let mut spawned = 0;
+        for i in 0..workers {
+            let rx = rx.clone();
+            let worker = thread::Builder::new()
+                .name(format!("cryprq-crypto-{}", i))
+                .spawn(move || {
+                    while let Ok(job) = rx.recv() {
+                        // A panicking job only loses its own chunk
+                        let _ = catch_unwind(AssertUnwindSafe(job));
+                    }
+                });
+            match worker {
+                Ok(_) => spawned += 1,
+                Err(e) => log::warn!("event=crypto_worker_spawn_failed error={}", e),
+            }
+        }
+        log::debug!("event=crypto_pool_ready workers={}", spawned);
+        Self {
+            jobs: (spawned > 0).then_some(tx),
+            workers: spawned,
+        }
+    }
+
+    /// Apply `f` to every item across the pool, returning the results in
+    /// input order (`None` if a worker failed mid-chunk)
+    pub(crate) async fn map<T, R, F>(&self, items: Vec<T>, f: F) -> Option<Vec<R>>
+    where
+        T: Send + 'static,
+        R: Send + 'static,
+        F: Fn(T) -> R + Send + Sync + 'static,
+    {
+        let chunks = (items.len() / MIN_CHUNK).min(self.workers);
+        let Some(jobs) = self.jobs.as_ref().filter(|_| chunks > 0) else {
+            return Some(items.into_iter().map(f).collect());
+        };
+        let f = Arc::new(f);
+        let chunk_len = items.len().div_ceil(chunks);
+        let mut rest = items;
+        let mut pending = Vec::with_capacity(chunks);
+        while !rest.is_empty() {
+            let tail = rest.split_off(chunk_len.min(rest.len()));
+            let chunk = std::mem::replace(&mut rest, tail);
+            let (tx, rx) = oneshot::channel();
+            let f = f.clone();
+            let job: Job = Box::new(move || {
+                let _ = tx.send(chunk.into_iter().map(|item| f(item)).collect::<Vec<R>>());
+            });
+            if let Err(channel::SendError(job)) = jobs.send(job) {
+                job();
+            }
+            pending.push(rx);
+        }
+
+        let mut results = Vec::with_capacity(chunks * chunk_len);
+        for rx in pending {
+            results.extend(rx.await.ok()?);
+        }
+        Some(results)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+    use std::sync::Mutex;
+
+    #[tokio::test]
+    async fn test_map_spreads_work_and_keeps_order() {
+        let pool = CryptoPool::new(3);
+        let threads = Arc::new(Mutex::new(HashSet::new()));
+        let seen = threads.clone();
+        let items: Vec<u32> = (0..1000).collect();
+        let results = pool
+            .map(items, move |i| {
+                seen.lock()
+                    .expect("thread set lock in test")
+                    .insert(thread::current().id());
+                // Enough work that every worker gets a chunk
+                thread::sleep(std::time::Duration::from_micros(10));
+                i * 2
+            })
+            .await
+            .expect("map in test");
+        assert_eq!(results, (0..1000).map(|i| i * 2).collect::<Vec<_>>());
+        let workers = threads.lock().expect("thread set lock in test").clone();
+        assert!(workers.len() > 1);
+        // The caller only waits; AEAD work never runs on the executor
+        assert!(!workers.contains(&thread::current().id()));
+
+        // Small batches and pools without workers run inline
+        assert_eq!(
+            pool.map(vec![1, 2, 3], |i| i + 1).await,
+            Some(vec![2, 3, 4])
+        );
+        let inline = CryptoPool::new(0);
+        assert_eq!(
+            inline.map((0..100).collect(), |i: u32| i).await,
+            Some((0..100).collect())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_map_reports_failed_chunk() {
+        let pool = CryptoPool::new(2);
+        let results = pool
+            .map((0..100).collect::<Vec<u32>>(), |i| {
+                if i == 99 {
+                    std::panic::panic_any("worker failure in test");
+                }
+                i
+            })
+            .await;
+        assert!(results.is_none());
+        // The worker survives the failed job
+        assert!(pool.map((0..100).collect(), |i: u32| i).await.is_some());
+    }
+}
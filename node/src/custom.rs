@@ -0,0 +1,182 @@
+// Copyright (c) 2025 Thor Thor
+// Author: Thor Thor (GitHub: https://github.com/codethor0)
+// Contact: codethor@gmail.com
+// LinkedIn: https://www.linkedin.com/in/thor-thor0
+// License: MIT (see LICENSE file for details)
+
+use std::collections::HashMap;
+use std::io;
+use std::sync::{Arc, RwLock, Weak};
+
+use async_trait::async_trait;
+use cryprq_core::RegistryRange;
+use tokio::sync::mpsc;
+use tokio::sync::mpsc::error::TrySendError;
+
+use crate::record_layer::CONTROL_STREAM_ID;
+use crate::stats::{record_drop, DropReason};
+use crate::{Tunnel, TunnelError};
+
+/// Records of one custom type waiting for their handler before new ones
+/// are dropped
+const HANDLER_QUEUE: usize = 64;
+
+/// Application payload carried in its own record message type
+///
+/// `MESSAGE_TYPE` must lie in the private-use range 0x80 - 0xFE
+/// (Section 10.3); the built-in types and the standard range stay free for
+/// the protocol.
+pub trait CustomMessage: Sized + Send + 'static {
+    const MESSAGE_TYPE: u8;
+
+    fn to_bytes(&self) -> Vec<u8>;
+
+    fn from_bytes(payload: &[u8]) -> io::Result<Self>;
+}
+
+/// Receives decoded custom messages of one type from an authenticated peer
+#[async_trait]
+pub trait MessageHandler<M: CustomMessage>: Send + Sync + 'static {
+    /// Called on the type's own task, one message at a time in arrival
+    /// order; `tunnel` can be kept to reply after returning
+    async fn handle(&self, tunnel: Arc<Tunnel>, message: M) -> Result<(), TunnelError>;
+}
+
+/// Queues feeding the handlers of private-use record message types
+pub(crate) struct CustomHandlers {
+    queues: RwLock<HashMap<u8, mpsc::Sender<Vec<u8>>>>,
+}
+
+impl CustomHandlers {
+    pub(crate) fn new() -> Self {
+        Self {
+            queues: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Whether records of this type are routed here rather than dropped
+    pub(crate) fn is_custom(message_type: u8) -> bool {
+        RegistryRange::of(message_type) == RegistryRange::Private
+    }
+
+    fn get(&self, message_type: u8) -> Result<Option<mpsc::Sender<Vec<u8>>>, TunnelError> {
+        let queues = self
+            .queues
+            .read()
+            .map_err(|e| TunnelError::LockPoisoned(e.to_string()))?;
+        Ok(queues.get(&message_type).cloned())
+    }
+}
+
+fn check_message_type(message_type: u8) -> Result<(), TunnelError> {
+    if !CustomHandlers::is_custom(message_type) {
+        return Err(TunnelError::InvalidMessageType(message_type));
+    }
+    Ok(())
+}
+
+/// Decode queued records of one type and hand them to `handler` until the
+/// handler is replaced or unregistered, or the tunnel is dropped
+async fn handler_loop<M, H>(tunnel: Weak<Tunnel>, handler: H, mut queue: mpsc::Receiver<Vec<u8>>)
+where
+    M: CustomMessage,
+    H: MessageHandler<M>,
+{
+    while let Some(payload) = queue.recv().await {
+        let Some(tunnel) = tunnel.upgrade() else {
+            return;
+        };
+        let message = match M::from_bytes(&payload) {
+            Ok(message) => message,
+            Err(e) => {
+                record_drop(DropReason::Malformed);
+                log::warn!(
+                    "event=custom_message_dropped type={:#04x} reason=malformed error={}",
+                    M::MESSAGE_TYPE,
+                    e
+                );
+                continue;
+            }
+        };
+        if let Err(e) = handler.handle(tunnel, message).await {
+            log::warn!(
+                "event=custom_message_failed type={:#04x} error={}",
+                M::MESSAGE_TYPE,
+                e
+            );
+        }
+    }
+}
+
+impl Tunnel {
+    /// Route incoming records of `M::MESSAGE_TYPE` to `handler`
+    ///
+    /// The handler runs on a task of its own, so a slow handler holds up
+    /// only its own type: up to 64 records wait for it, further ones are
+    /// dropped. Replaces any handler already registered for that type.
+    /// Records of a private-use type without a handler are dropped.
+    pub fn register_handler<M, H>(self: &Arc<Self>, handler: H) -> Result<(), TunnelError>
+    where
+        M: CustomMessage,
+        H: MessageHandler<M>,
+    {
+        check_message_type(M::MESSAGE_TYPE)?;
+        let (tx, rx) = mpsc::channel(HANDLER_QUEUE);
+        tokio::spawn(handler_loop(Arc::downgrade(self), handler, rx));
+        self.custom_handlers
+            .queues
+            .write()
+            .map_err(|e| TunnelError::LockPoisoned(e.to_string()))?
+            .insert(M::MESSAGE_TYPE, tx);
+        Ok(())
+    }
+
+    /// Stop routing a message type; returns whether a handler was registered
+    ///
+    /// Records already queued for the handler are still delivered.
+    pub fn unregister_handler(&self, message_type: u8) -> Result<bool, TunnelError> {
+        Ok(self
+            .custom_handlers
+            .queues
+            .write()
+            .map_err(|e| TunnelError::LockPoisoned(e.to_string()))?
+            .remove(&message_type)
+            .is_some())
+    }
+
+    /// Send a custom message to the peer in a record of its own type
+    ///
+    /// Large messages are fragmented like any other record.
+    pub async fn send_message<M: CustomMessage>(&self, message: &M) -> Result<(), TunnelError> {
+        check_message_type(M::MESSAGE_TYPE)?;
+        self.send_record(CONTROL_STREAM_ID, M::MESSAGE_TYPE, 0, &message.to_bytes())
+            .await
+    }
+
+    /// Queue a private-use record for its handler without waiting for it
+    pub(crate) fn handle_custom_record(
+        &self,
+        message_type: u8,
+        payload: Vec<u8>,
+    ) -> Result<(), TunnelError> {
+        let queued = match self.custom_handlers.get(message_type)? {
+            Some(queue) => queue.try_send(payload),
+            None => Err(TrySendError::Closed(payload)),
+        };
+        match queued {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => {
+                record_drop(DropReason::HandlerBackpressure);
+                log::debug!(
+                    "event=custom_message_dropped type={:#04x} reason=handler_backpressure",
+                    message_type
+                );
+            }
+            Err(TrySendError::Closed(_)) => log::debug!(
+                "event=custom_message_dropped type={:#04x} reason=no_handler",
+                message_type
+            ),
+        }
+        Ok(())
+    }
+}
@@ -6,6 +6,8 @@
 
 use std::fmt;
 
+use cryprq_core::ErrorCode;
+
 #[derive(Debug)]
 pub enum TunnelError {
     LockPoisoned(String),
@@ -18,7 +20,45 @@ pub enum TunnelError {
     InvalidPeerIdentity,
     HandshakeFailed(String),
     NetworkError(String),
+    Timeout(String),
     IoError(std::io::Error),
+    UnsupportedVersion(u8),
+    UnexpectedMessage(String),
+    StreamClosed(u32),
+    SessionClosed,
+    PeerError { code: ErrorCode, reason: String },
+    InvalidMessageType(u8),
+    InvalidConfig(String),
+}
+
+impl TunnelError {
+    /// Wire code to report this error to the peer in an ERROR message
+    ///
+    /// None for local conditions (I/O, locks, limits) and for errors the
+    /// peer itself reported.
+    pub fn error_code(&self) -> Option<ErrorCode> {
+        match self {
+            TunnelError::DecryptionFailed
+            | TunnelError::InvalidNonce
+            | TunnelError::ReplayDetected
+            | TunnelError::InvalidPeerIdentity => Some(ErrorCode::CryptoError),
+            TunnelError::HandshakeFailed(_) => Some(ErrorCode::ProtocolViolation),
+            TunnelError::UnexpectedMessage(_) => Some(ErrorCode::UnexpectedMessage),
+            TunnelError::UnsupportedVersion(_) => Some(ErrorCode::UnsupportedVersion),
+            TunnelError::StreamClosed(_) => Some(ErrorCode::StreamClosed),
+            TunnelError::LockPoisoned(_)
+            | TunnelError::EncryptionFailed
+            | TunnelError::NonceOverflow
+            | TunnelError::RateLimitExceeded
+            | TunnelError::NetworkError(_)
+            | TunnelError::Timeout(_)
+            | TunnelError::IoError(_)
+            | TunnelError::SessionClosed
+            | TunnelError::PeerError { .. }
+            | TunnelError::InvalidMessageType(_)
+            | TunnelError::InvalidConfig(_) => None,
+        }
+    }
 }
 
 impl fmt::Display for TunnelError {
@@ -36,7 +76,23 @@ impl fmt::Display for TunnelError {
             TunnelError::InvalidPeerIdentity => write!(f, "Peer identity verification failed"),
             TunnelError::HandshakeFailed(msg) => write!(f, "Handshake failed: {}", msg),
             TunnelError::NetworkError(msg) => write!(f, "Network error: {}", msg),
+            TunnelError::Timeout(msg) => write!(f, "Timed out: {}", msg),
             TunnelError::IoError(e) => write!(f, "I/O error: {}", e),
+            TunnelError::UnsupportedVersion(version) => {
+                write!(f, "Unsupported protocol version: {}", version)
+            }
+            TunnelError::UnexpectedMessage(msg) => write!(f, "Unexpected message: {}", msg),
+            TunnelError::StreamClosed(stream_id) => write!(f, "Stream {} is closed", stream_id),
+            TunnelError::SessionClosed => write!(f, "Session closed"),
+            TunnelError::PeerError { code, reason } => {
+                write!(f, "Peer reported {}: {}", code, reason)
+            }
+            TunnelError::InvalidMessageType(message_type) => write!(
+                f,
+                "Message type {:#04x} is outside the private-use range",
+                message_type
+            ),
+            TunnelError::InvalidConfig(msg) => write!(f, "Invalid configuration: {}", msg),
         }
     }
 }
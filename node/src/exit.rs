@@ -0,0 +1,689 @@
+// Copyright (c) 2025 Thor Thor
+// Author: Thor Thor (GitHub: https://github.com/codethor0)
+// Contact: codethor@gmail.com
+// LinkedIn: https://www.linkedin.com/in/thor-thor0
+// License: MIT (see LICENSE file for details)
+
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::hash::Hash;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock, Weak};
+use std::time::{Duration, Instant};
+use tokio::net::UdpSocket;
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::mpsc::{channel, unbounded_channel, Receiver, Sender, UnboundedSender};
+
+use crate::exit_tcp::{relay_tcp, reset_for};
+use crate::filter::{drop_packet, Direction};
+use crate::nat::{ConnTrack, FlowKey, NatConfig};
+use crate::packet::{build_ipv4_packet, IpHeader, PROTO_ICMP, PROTO_TCP, PROTO_UDP};
+use crate::stats::DropReason;
+
+/// How often idle flows are swept
+const EXPIRY_INTERVAL: Duration = Duration::from_secs(5);
+/// Packets queued per flow while its upstream socket catches up
+const FLOW_QUEUE_LEN: usize = 256;
+const ICMP_ECHO_REQUEST: u8 = 8;
+const ICMP_ECHO_REPLY: u8 = 0;
+const TCP_SYN: u8 = 0x02;
+const TCP_ACK: u8 = 0x10;
+
+/// Relay handle stored in the connection table
+struct FlowHandle {
+    id: u64,
+    tx: Sender<Vec<u8>>,
+}
+
+/// Internet exit for VPN peers with built-in userspace NAT
+///
+/// Inner IPv4 packets from peers are relayed through the host's own sockets
+/// (slirp-style): UDP and ICMP echo go through per-flow datagram sockets and
+/// TCP connections are terminated locally and re-opened to the destination.
+/// Replies come back from the host's address, so no iptables MASQUERADE
+/// rules or IP forwarding are needed.
+///
+/// A peer's packets are only translated once it holds a lease
+/// (`set_peer_address`), and only from the leased address. Private and
+/// other non-public destinations are refused unless `NatConfig` allows
+/// them.
+pub struct ExitNode<K> {
+    config: NatConfig,
+    conntrack: Mutex<ConnTrack<K, FlowHandle>>,
+    /// Reply channel per connected peer
+    peers: RwLock<HashMap<K, UnboundedSender<Vec<u8>>>>,
+    /// Tunnel address leased to each peer
+    addresses: RwLock<HashMap<K, Ipv4Addr>>,
+    next_flow_id: AtomicU64,
+}
+
+impl<K> ExitNode<K>
+where
+    K: Clone + Eq + Hash + Display + Send + Sync + 'static,
+{
+    /// Create the exit node and start sweeping idle flows
+    pub fn spawn(config: NatConfig) -> Arc<Self> {
+        let node = Arc::new(Self {
+            conntrack: Mutex::new(ConnTrack::new(config.clone())),
+            config,
+            peers: RwLock::new(HashMap::new()),
+            addresses: RwLock::new(HashMap::new()),
+            next_flow_id: AtomicU64::new(1),
+        });
+        let weak = Arc::downgrade(&node);
+        tokio::spawn(expire_flows(weak));
+        node
+    }
+
+    /// Register a connected peer and the channel its replies are sent on
+    pub fn add_peer(&self, peer: K, tx: UnboundedSender<Vec<u8>>) {
+        if let Ok(mut peers) = self.peers.write() {
+            peers.insert(peer, tx);
+        }
+    }
+
+    /// Record the tunnel address leased to a peer, the only source address
+    /// its packets may use
+    pub fn set_peer_address(&self, peer: K, address: Ipv4Addr) {
+        if let Ok(mut addresses) = self.addresses.write() {
+            addresses.insert(peer, address);
+        }
+    }
+
+    /// Remove a disconnected peer and close all of its flows
+    pub fn remove_peer(&self, peer: &K) {
+        if let Ok(mut peers) = self.peers.write() {
+            peers.remove(peer);
+        }
+        if let Ok(mut addresses) = self.addresses.write() {
+            addresses.remove(peer);
+        }
+        let closed = match self.conntrack.lock() {
+            Ok(mut table) => table.remove_peer(peer).len(),
+            Err(_) => 0,
+        };
+        log::info!(
+            "event=nat_peer_removed peer={} flows_closed={}",
+            peer,
+            closed
+        );
+    }
+
+    /// Number of tracked NAT flows
+    pub fn flow_count(&self) -> usize {
+        self.conntrack.lock().map(|t| t.len()).unwrap_or(0)
+    }
+
+    /// Sender used by a peer to deliver packets to the exit
+    pub fn incoming_for(self: &Arc<Self>, peer: K) -> UnboundedSender<Vec<u8>> {
+        let (tx, mut rx) = unbounded_channel::<Vec<u8>>();
+        let node = self.clone();
+        tokio::spawn(async move {
+            while let Some(packet) = rx.recv().await {
+                node.deliver(&peer, packet);
+            }
+        });
+        tx
+    }
+
+    /// Translate a packet from `peer`, opening a flow if needed
+    pub fn deliver(self: &Arc<Self>, peer: &K, packet: Vec<u8>) {
+        let len = packet.len();
+        if let Err(reason) = self.translate(peer, packet) {
+            drop_packet(reason, Direction::Inbound, len);
+        }
+    }
+
+    fn translate(self: &Arc<Self>, peer: &K, mut packet: Vec<u8>) -> Result<(), DropReason> {
+        let header = IpHeader::parse(&packet).ok_or(DropReason::Malformed)?;
+        let (src, dst) = match (header.src, header.dst) {
+            (IpAddr::V4(src), IpAddr::V4(dst)) => (src, dst),
+            _ => return Err(DropReason::NatUnsupported),
+        };
+        if is_fragment(&packet) {
+            return Err(DropReason::NatUnsupported);
+        }
+        if !self.source_allowed(peer, src) {
+            return Err(DropReason::SourceNotAllowed);
+        }
+        if !self.destination_allowed(dst) {
+            return Err(DropReason::Firewall);
+        }
+        let segment = &packet[header.header_len..];
+        let (sport, dport) = flow_ports(header.protocol, segment)?;
+        let opens_tcp = segment.get(13).map(|f| f & (TCP_SYN | TCP_ACK)) == Some(TCP_SYN);
+        let key = FlowKey {
+            peer: peer.clone(),
+            protocol: header.protocol,
+            src: SocketAddrV4::new(src, sport),
+            dst: SocketAddrV4::new(dst, dport),
+        };
+
+        let now = Instant::now();
+        let mut table = self
+            .conntrack
+            .lock()
+            .map_err(|_| DropReason::NatUnsupported)?;
+        if let Some(flow) = table.touch(&key, now) {
+            // A closed channel means the relay finished; open a fresh flow
+            match flow.tx.try_send(packet) {
+                Ok(()) => return Ok(()),
+                Err(TrySendError::Full(_)) => return Err(DropReason::NatBackpressure),
+                Err(TrySendError::Closed(unsent)) => packet = unsent,
+            }
+        }
+
+        // Only a SYN may open a TCP flow; anything else gets a reset
+        if header.protocol == PROTO_TCP && !opens_tcp {
+            drop(table);
+            if let Some(reset) = reset_for(&key, &packet) {
+                self.send_to_peer(peer, reset);
+            }
+            return Ok(());
+        }
+
+        let id = self.next_flow_id.fetch_add(1, Ordering::Relaxed);
+        let (tx, rx) = channel(FLOW_QUEUE_LEN);
+        let _ = tx.try_send(packet);
+        table.insert(key.clone(), FlowHandle { id, tx }, now)?;
+        drop(table);
+
+        log::debug!(
+            "event=nat_flow_opened peer={} protocol={} src={} dst={}",
+            key.peer,
+            key.protocol,
+            key.src,
+            key.dst
+        );
+        let node = self.clone();
+        match key.protocol {
+            PROTO_TCP => tokio::spawn(relay_tcp(node, key, id, rx)),
+            PROTO_UDP => tokio::spawn(relay_udp(node, key, id, rx)),
+            _ => tokio::spawn(relay_icmp(node, key, id, rx)),
+        };
+        Ok(())
+    }
+
+    fn source_allowed(&self, peer: &K, src: Ipv4Addr) -> bool {
+        self.addresses
+            .read()
+            .map(|addresses| addresses.get(peer) == Some(&src))
+            .unwrap_or(false)
+    }
+
+    fn destination_allowed(&self, dst: Ipv4Addr) -> bool {
+        if dst.is_loopback() {
+            return self.config.allow_loopback;
+        }
+        if is_non_public(dst) {
+            return self.config.allow_private;
+        }
+        !(dst.is_unspecified() || dst.is_broadcast() || dst.is_multicast())
+    }
+
+    /// Send a reply packet for a flow back to its peer
+    pub(crate) fn reply(&self, key: &FlowKey<K>, packet: Vec<u8>) {
+        if let Ok(mut table) = self.conntrack.lock() {
+            table.touch(key, Instant::now());
+        }
+        self.send_to_peer(&key.peer, packet);
+    }
+
+    /// Mark a flow established so it gets the longer idle timeout
+    pub(crate) fn set_established(&self, key: &FlowKey<K>) {
+        if let Ok(mut table) = self.conntrack.lock() {
+            table.set_established(key, true);
+        }
+    }
+
+    /// Stop tracking a flow whose relay has finished
+    pub(crate) fn close(&self, key: &FlowKey<K>, id: u64) {
+        if let Ok(mut table) = self.conntrack.lock() {
+            if table.touch(key, Instant::now()).map(|f| f.id) == Some(id) {
+                table.remove(key);
+            }
+        }
+        log::debug!(
+            "event=nat_flow_closed peer={} protocol={} dst={}",
+            key.peer,
+            key.protocol,
+            key.dst
+        );
+    }
+
+    fn send_to_peer(&self, peer: &K, packet: Vec<u8>) {
+        if let Ok(peers) = self.peers.read() {
+            if let Some(tx) = peers.get(peer) {
+                let _ = tx.send(packet);
+            }
+        }
+    }
+}
+
+/// Periodically drop idle flows; ends when the exit node is dropped
+async fn expire_flows<K>(node: Weak<ExitNode<K>>)
+where
+    K: Clone + Eq + Hash + Display + Send + Sync + 'static,
+{
+    let mut interval = tokio::time::interval(EXPIRY_INTERVAL);
+    loop {
+        interval.tick().await;
+        let Some(node) = node.upgrade() else {
+            break;
+        };
+        let expired = match node.conntrack.lock() {
+            Ok(mut table) => table.expire(Instant::now()),
+            Err(_) => continue,
+        };
+        for (key, _) in expired {
+            log::debug!(
+                "event=nat_flow_expired peer={} protocol={} dst={}",
+                key.peer,
+                key.protocol,
+                key.dst
+            );
+        }
+    }
+}
+
+/// Private (RFC 1918), link-local, shared (RFC 6598), documentation and
+/// "this network" addresses
+fn is_non_public(addr: Ipv4Addr) -> bool {
+    let [a, b, c, _] = addr.octets();
+    addr.is_private()
+        || addr.is_link_local()
+        || addr.is_documentation()
+        || a == 0
+        || (a == 100 && (b & 0xC0) == 64)
+        // Benchmarking (RFC 2544) and IETF protocol assignments
+        || (a == 198 && (b & 0xFE) == 18)
+        || (a == 192 && b == 0 && c == 0)
+}
+
+/// Returns true for non-initial fragments and packets with more fragments
+fn is_fragment(packet: &[u8]) -> bool {
+    let flags_offset = u16::from_be_bytes([packet[6], packet[7]]);
+    flags_offset & 0x3FFF != 0
+}
+
+/// Conntrack ports for a packet: TCP/UDP ports, or (echo id, 0) for ICMP
+fn flow_ports(protocol: u8, segment: &[u8]) -> Result<(u16, u16), DropReason> {
+    match protocol {
+        PROTO_TCP | PROTO_UDP => {
+            let ports = segment.get(0..4).ok_or(DropReason::Malformed)?;
+            Ok((
+                u16::from_be_bytes([ports[0], ports[1]]),
+                u16::from_be_bytes([ports[2], ports[3]]),
+            ))
+        }
+        PROTO_ICMP => {
+            let echo = segment.get(0..8).ok_or(DropReason::Malformed)?;
+            if echo[0] != ICMP_ECHO_REQUEST {
+                return Err(DropReason::NatUnsupported);
+            }
+            Ok((u16::from_be_bytes([echo[4], echo[5]]), 0))
+        }
+        _ => Err(DropReason::NatUnsupported),
+    }
+}
+
+/// Payload of an inner UDP packet
+fn udp_payload(packet: &[u8]) -> Option<&[u8]> {
+    let header = IpHeader::parse(packet)?;
+    let udp = packet.get(header.header_len..)?;
+    let len = u16::from_be_bytes([*udp.get(4)?, *udp.get(5)?]) as usize;
+    udp.get(8..len)
+}
+
+/// Relay one UDP flow through a connected host socket
+async fn relay_udp<K>(node: Arc<ExitNode<K>>, key: FlowKey<K>, id: u64, mut rx: Receiver<Vec<u8>>)
+where
+    K: Clone + Eq + Hash + Display + Send + Sync + 'static,
+{
+    let socket = match connect_udp(key.dst).await {
+        Ok(socket) => socket,
+        Err(e) => {
+            log::debug!("event=nat_udp_connect_failed dst={} error={}", key.dst, e);
+            node.close(&key, id);
+            return;
+        }
+    };
+    let mut buf = vec![0u8; 65535];
+    loop {
+        tokio::select! {
+            packet = rx.recv() => {
+                // Channel closes when the flow expires or the peer leaves
+                let Some(packet) = packet else {
+                    return;
+                };
+                if let Some(payload) = udp_payload(&packet) {
+                    if let Err(e) = socket.send(payload).await {
+                        log::debug!("event=nat_udp_send_failed dst={} error={}", key.dst, e);
+                    }
+                }
+            }
+            received = socket.recv(&mut buf) => match received {
+                Ok(n) => node.reply(&key, udp_reply(&key, &buf[..n])),
+                // ICMP errors surface as recv errors on connected sockets
+                Err(e) => log::debug!("event=nat_udp_recv_failed dst={} error={}", key.dst, e),
+            },
+        }
+    }
+}
+
+async fn connect_udp(dst: SocketAddrV4) -> std::io::Result<UdpSocket> {
+    let socket = UdpSocket::bind(SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0))).await?;
+    socket.connect(dst).await?;
+    Ok(socket)
+}
+
+/// Wrap a datagram from the destination as a UDP packet back to the peer
+fn udp_reply<K>(key: &FlowKey<K>, payload: &[u8]) -> Vec<u8> {
+    let mut udp = Vec::with_capacity(8 + payload.len());
+    udp.extend_from_slice(&key.dst.port().to_be_bytes());
+    udp.extend_from_slice(&key.src.port().to_be_bytes());
+    udp.extend_from_slice(&((8 + payload.len()) as u16).to_be_bytes());
+    udp.extend_from_slice(&[0, 0]);
+    udp.extend_from_slice(payload);
+    build_ipv4_packet(*key.dst.ip(), *key.src.ip(), PROTO_UDP, udp)
+}
+
+/// Relay ICMP echo through an unprivileged ping socket
+///
+/// Ping sockets need `net.ipv4.ping_group_range` to cover the exit's group
+/// on Linux; the kernel picks the echo identifier, so the peer's identifier
+/// is restored on replies.
+async fn relay_icmp<K>(node: Arc<ExitNode<K>>, key: FlowKey<K>, id: u64, mut rx: Receiver<Vec<u8>>)
+where
+    K: Clone + Eq + Hash + Display + Send + Sync + 'static,
+{
+    let socket = match ping_socket(*key.dst.ip()) {
+        Ok(socket) => socket,
+        Err(e) => {
+            log::warn!(
+                "event=nat_icmp_unavailable dst={} error={} (check net.ipv4.ping_group_range)",
+                key.dst,
+                e
+            );
+            node.close(&key, id);
+            return;
+        }
+    };
+    let mut buf = vec![0u8; 65535];
+    loop {
+        tokio::select! {
+            packet = rx.recv() => {
+                let Some(packet) = packet else {
+                    return;
+                };
+                let Some(echo) = IpHeader::parse(&packet).and_then(|h| packet.get(h.header_len..)) else {
+                    continue;
+                };
+                if let Err(e) = socket.send(echo).await {
+                    log::debug!("event=nat_icmp_send_failed dst={} error={}", key.dst, e);
+                }
+            }
+            received = socket.recv(&mut buf) => match received {
+                Ok(n) => {
+                    if let Some(reply) = icmp_reply(&key, &buf[..n]) {
+                        node.reply(&key, reply);
+                    }
+                }
+                Err(e) => log::debug!("event=nat_icmp_recv_failed dst={} error={}", key.dst, e),
+            },
+        }
+    }
+}
+
+fn ping_socket(dst: Ipv4Addr) -> std::io::Result<UdpSocket> {
+    use socket2::{Domain, Protocol, SockAddr, Socket, Type};
+
+    let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::ICMPV4))?;
+    socket.set_nonblocking(true)?;
+    socket.connect(&SockAddr::from(SocketAddrV4::new(dst, 0)))?;
+    UdpSocket::from_std(socket.into())
+}
+
+/// Rebuild an echo reply for the peer, restoring its echo identifier
+fn icmp_reply<K>(key: &FlowKey<K>, received: &[u8]) -> Option<Vec<u8>> {
+    // Some platforms include the IP header on ping sockets
+    let icmp = match IpHeader::parse(received) {
+        Some(header) => received.get(header.header_len..)?,
+        None => received,
+    };
+    if icmp.len() < 8 || icmp[0] != ICMP_ECHO_REPLY {
+        return None;
+    }
+    let mut echo = icmp.to_vec();
+    echo[2..4].copy_from_slice(&[0, 0]);
+    echo[4..6].copy_from_slice(&key.src.port().to_be_bytes());
+    Some(build_ipv4_packet(
+        *key.dst.ip(),
+        *key.src.ip(),
+        PROTO_ICMP,
+        echo,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exit_tcp::build_tcp_packet;
+
+    const PEER_ADDR: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 2);
+
+    fn loopback_exit() -> (
+        Arc<ExitNode<String>>,
+        tokio::sync::mpsc::UnboundedReceiver<Vec<u8>>,
+    ) {
+        let node = ExitNode::spawn(NatConfig {
+            allow_loopback: true,
+            ..NatConfig::default()
+        });
+        let (tx, rx) = unbounded_channel();
+        node.add_peer("a".to_string(), tx);
+        node.set_peer_address("a".to_string(), PEER_ADDR);
+        (node, rx)
+    }
+
+    fn udp_packet(dst: SocketAddrV4, payload: &[u8]) -> Vec<u8> {
+        let key = FlowKey {
+            peer: (),
+            protocol: PROTO_UDP,
+            src: dst,
+            dst: SocketAddrV4::new(PEER_ADDR, 40000),
+        };
+        // A reply in the opposite direction is an outbound packet
+        udp_reply(&key, payload)
+    }
+
+    fn tcp_segment(
+        src: SocketAddrV4,
+        dst: SocketAddrV4,
+        seq: u32,
+        ack: u32,
+        flags: u8,
+        payload: &[u8],
+    ) -> Vec<u8> {
+        build_tcp_packet(src, dst, seq, ack, flags, 65535, &[], payload)
+    }
+
+    async fn next_packet(rx: &mut tokio::sync::mpsc::UnboundedReceiver<Vec<u8>>) -> Vec<u8> {
+        tokio::time::timeout(Duration::from_secs(5), rx.recv())
+            .await
+            .expect("reply before timeout")
+            .expect("open channel")
+    }
+
+    #[tokio::test]
+    async fn test_udp_round_trip() {
+        let server = UdpSocket::bind("127.0.0.1:0").await.expect("bind");
+        let server_addr = match server.local_addr().expect("addr") {
+            SocketAddr::V4(addr) => addr,
+            SocketAddr::V6(_) => return,
+        };
+        let (node, mut rx) = loopback_exit();
+
+        node.deliver(&"a".to_string(), udp_packet(server_addr, b"ping"));
+        let mut buf = [0u8; 16];
+        let (n, from) = server.recv_from(&mut buf).await.expect("recv");
+        assert_eq!(&buf[..n], b"ping");
+        server.send_to(b"pong", from).await.expect("send");
+
+        let reply = next_packet(&mut rx).await;
+        let header = IpHeader::parse(&reply).expect("IPv4 reply");
+        assert_eq!(header.src, IpAddr::V4(*server_addr.ip()));
+        assert_eq!(header.dst, IpAddr::V4(PEER_ADDR));
+        assert_eq!(udp_payload(&reply), Some(&b"pong"[..]));
+        assert_eq!(&reply[22..24], &40000u16.to_be_bytes());
+        assert_eq!(node.flow_count(), 1);
+
+        node.remove_peer(&"a".to_string());
+        assert_eq!(node.flow_count(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_tcp_round_trip() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("bind");
+        let server_addr = match listener.local_addr().expect("addr") {
+            SocketAddr::V4(addr) => addr,
+            SocketAddr::V6(_) => return,
+        };
+        let (node, mut rx) = loopback_exit();
+        let peer = "a".to_string();
+        let client = SocketAddrV4::new(PEER_ADDR, 40001);
+
+        node.deliver(
+            &peer,
+            tcp_segment(client, server_addr, 100, 0, TCP_SYN, b""),
+        );
+        let (mut upstream, _) = listener.accept().await.expect("accept");
+        let syn_ack = next_packet(&mut rx).await;
+        assert_eq!(syn_ack[33] & (TCP_SYN | TCP_ACK), TCP_SYN | TCP_ACK);
+        let server_seq = u32::from_be_bytes([syn_ack[24], syn_ack[25], syn_ack[26], syn_ack[27]]);
+        assert_eq!(&syn_ack[28..32], &101u32.to_be_bytes());
+
+        let ack = server_seq.wrapping_add(1);
+        node.deliver(
+            &peer,
+            tcp_segment(client, server_addr, 101, ack, TCP_ACK, b"hello"),
+        );
+        let mut buf = [0u8; 5];
+        upstream.read_exact(&mut buf).await.expect("read");
+        assert_eq!(&buf, b"hello");
+
+        upstream.write_all(b"world").await.expect("write");
+        loop {
+            let packet = next_packet(&mut rx).await;
+            let header = IpHeader::parse(&packet).expect("IPv4 reply");
+            let data_offset = ((packet[header.header_len + 12] >> 4) as usize) * 4;
+            let payload = &packet[header.header_len + data_offset..];
+            if !payload.is_empty() {
+                assert_eq!(payload, b"world");
+                break;
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_rejects_untranslatable_packets() {
+        let node: Arc<ExitNode<String>> = ExitNode::spawn(NatConfig::default());
+        let peer = "a".to_string();
+        node.set_peer_address(peer.clone(), PEER_ADDR);
+        let loopback = SocketAddrV4::new(Ipv4Addr::LOCALHOST, 53);
+        assert_eq!(
+            node.translate(&peer, udp_packet(loopback, b"x")),
+            Err(DropReason::Firewall)
+        );
+
+        let mut ipv6 = vec![0u8; 48];
+        ipv6[0] = 0x60;
+        ipv6[6] = PROTO_UDP;
+        assert_eq!(node.translate(&peer, ipv6), Err(DropReason::NatUnsupported));
+
+        let mut unreachable = udp_packet(SocketAddrV4::new(Ipv4Addr::new(1, 1, 1, 1), 53), b"");
+        unreachable[9] = PROTO_ICMP;
+        unreachable[20] = 3;
+        assert_eq!(
+            node.translate(&peer, unreachable),
+            Err(DropReason::NatUnsupported)
+        );
+        assert_eq!(node.flow_count(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_denies_non_public_destinations_and_spoofed_sources() {
+        let node: Arc<ExitNode<String>> = ExitNode::spawn(NatConfig::default());
+        let peer = "a".to_string();
+        let public = SocketAddrV4::new(Ipv4Addr::new(1, 1, 1, 1), 53);
+
+        // No lease yet, then a source other than the leased address
+        assert_eq!(
+            node.translate(&peer, udp_packet(public, b"x")),
+            Err(DropReason::SourceNotAllowed)
+        );
+        node.set_peer_address(peer.clone(), Ipv4Addr::new(10, 0, 0, 3));
+        assert_eq!(
+            node.translate(&peer, udp_packet(public, b"x")),
+            Err(DropReason::SourceNotAllowed)
+        );
+        node.set_peer_address(peer.clone(), PEER_ADDR);
+
+        for dst in [
+            Ipv4Addr::new(10, 1, 2, 3),
+            Ipv4Addr::new(172, 16, 0, 1),
+            Ipv4Addr::new(192, 168, 1, 1),
+            Ipv4Addr::new(169, 254, 169, 254),
+            Ipv4Addr::new(100, 64, 0, 1),
+            Ipv4Addr::new(100, 127, 255, 254),
+            Ipv4Addr::new(192, 0, 2, 1),
+            Ipv4Addr::new(198, 18, 0, 1),
+            Ipv4Addr::new(0, 1, 2, 3),
+        ] {
+            let packet = udp_packet(SocketAddrV4::new(dst, 53), b"x");
+            assert_eq!(
+                node.translate(&peer, packet),
+                Err(DropReason::Firewall),
+                "{}",
+                dst
+            );
+        }
+        assert_eq!(node.flow_count(), 0);
+        assert!(node.destination_allowed(Ipv4Addr::new(100, 128, 0, 1)));
+        assert!(node.destination_allowed(*public.ip()));
+
+        let open: Arc<ExitNode<String>> = ExitNode::spawn(NatConfig {
+            allow_private: true,
+            ..NatConfig::default()
+        });
+        assert!(open.destination_allowed(Ipv4Addr::new(192, 168, 1, 1)));
+        assert!(!open.destination_allowed(Ipv4Addr::LOCALHOST));
+    }
+
+    #[tokio::test]
+    async fn test_full_flow_queue_drops_packets() {
+        let server = UdpSocket::bind("127.0.0.1:0").await.expect("bind");
+        let server_addr = match server.local_addr().expect("addr") {
+            SocketAddr::V4(addr) => addr,
+            SocketAddr::V6(_) => return,
+        };
+        let (node, _rx) = loopback_exit();
+        let peer = "a".to_string();
+
+        // The relay task can't run until this test yields, so the flow's
+        // queue fills up instead of growing
+        for _ in 0..FLOW_QUEUE_LEN {
+            assert_eq!(node.translate(&peer, udp_packet(server_addr, b"x")), Ok(()));
+        }
+        assert_eq!(
+            node.translate(&peer, udp_packet(server_addr, b"x")),
+            Err(DropReason::NatBackpressure)
+        );
+        assert_eq!(node.flow_count(), 1);
+    }
+}
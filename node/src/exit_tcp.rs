@@ -0,0 +1,505 @@
+// Copyright (c) 2025 Thor Thor
+// Author: Thor Thor (GitHub: https://github.com/codethor0)
+// Contact: codethor@gmail.com
+// LinkedIn: https://www.linkedin.com/in/thor-thor0
+// License: MIT (see LICENSE file for details)
+
+//! Userspace TCP termination for the exit NAT
+//!
+//! The peer's TCP connection is answered locally and its byte stream is
+//! spliced onto a host `TcpStream` to the real destination. Only what a
+//! single in-order relay needs is implemented: no SACK, window scaling or
+//! out-of-order reassembly (segments past `rcv_nxt` are re-ACKed and the
+//! peer retransmits them).
+
+use std::collections::VecDeque;
+use std::fmt::Display;
+use std::hash::Hash;
+use std::net::SocketAddrV4;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::tcp::OwnedWriteHalf;
+use tokio::net::TcpStream;
+use tokio::sync::mpsc::Receiver;
+use tokio::time::Instant;
+
+use crate::exit::ExitNode;
+use crate::nat::FlowKey;
+use crate::packet::{build_ipv4_packet, IpHeader, PROTO_TCP};
+
+const FIN: u8 = 0x01;
+const SYN: u8 = 0x02;
+const RST: u8 = 0x04;
+const PSH: u8 = 0x08;
+const ACK: u8 = 0x10;
+
+/// MSS advertised to the peer (default tunnel MTU minus IPv4/TCP headers)
+const RELAY_MSS: u16 = 1380;
+/// MSS assumed when the peer's SYN carries none (RFC 9293)
+const DEFAULT_PEER_MSS: u16 = 536;
+/// Receive window advertised to the peer
+const RECV_WINDOW: u16 = 65535;
+/// Upstream bytes buffered but not yet acknowledged by the peer
+const MAX_UNACKED: usize = 256 * 1024;
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+const INITIAL_RTO: Duration = Duration::from_secs(1);
+const MAX_RTO: Duration = Duration::from_secs(60);
+const MAX_RETRANSMITS: u32 = 8;
+
+/// Parsed TCP segment from the peer
+struct Segment<'a> {
+    seq: u32,
+    ack: u32,
+    flags: u8,
+    window: u16,
+    mss: Option<u16>,
+    payload: &'a [u8],
+}
+
+impl<'a> Segment<'a> {
+    fn parse(packet: &'a [u8]) -> Option<Self> {
+        let header = IpHeader::parse(packet)?;
+        let total_len = u16::from_be_bytes([packet[2], packet[3]]) as usize;
+        let tcp = packet.get(header.header_len..total_len.min(packet.len()))?;
+        let data_offset = ((*tcp.get(12)? >> 4) as usize) * 4;
+        if data_offset < 20 || tcp.len() < data_offset {
+            return None;
+        }
+        Some(Self {
+            seq: u32::from_be_bytes([tcp[4], tcp[5], tcp[6], tcp[7]]),
+            ack: u32::from_be_bytes([tcp[8], tcp[9], tcp[10], tcp[11]]),
+            flags: tcp[13],
+            window: u16::from_be_bytes([tcp[14], tcp[15]]),
+            mss: parse_mss(&tcp[20..data_offset]),
+            payload: &tcp[data_offset..],
+        })
+    }
+
+    /// Sequence space consumed by this segment
+    fn len(&self) -> u32 {
+        self.payload.len() as u32 + (self.flags & SYN != 0) as u32 + (self.flags & FIN != 0) as u32
+    }
+}
+
+/// MSS option from a SYN's TCP options
+fn parse_mss(mut options: &[u8]) -> Option<u16> {
+    while let Some(&kind) = options.first() {
+        match kind {
+            0 => return None,
+            1 => options = &options[1..],
+            _ => {
+                let len = *options.get(1)? as usize;
+                if len < 2 || options.len() < len {
+                    return None;
+                }
+                if kind == 2 && len == 4 {
+                    return Some(u16::from_be_bytes([options[2], options[3]]));
+                }
+                options = &options[len..];
+            }
+        }
+    }
+    None
+}
+
+/// Serial number comparison (RFC 1982): `a` is before `b`
+fn seq_lt(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) < 0
+}
+
+/// Build an IPv4 TCP packet
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn build_tcp_packet(
+    src: SocketAddrV4,
+    dst: SocketAddrV4,
+    seq: u32,
+    ack: u32,
+    flags: u8,
+    window: u16,
+    options: &[u8],
+    payload: &[u8],
+) -> Vec<u8> {
+    let header_len = 20 + options.len();
+    let mut tcp = Vec::with_capacity(header_len + payload.len());
+    tcp.extend_from_slice(&src.port().to_be_bytes());
+    tcp.extend_from_slice(&dst.port().to_be_bytes());
+    tcp.extend_from_slice(&seq.to_be_bytes());
+    tcp.extend_from_slice(&ack.to_be_bytes());
+    tcp.push(((header_len / 4) as u8) << 4);
+    tcp.push(flags);
+    tcp.extend_from_slice(&window.to_be_bytes());
+    tcp.extend_from_slice(&[0, 0, 0, 0]);
+    tcp.extend_from_slice(options);
+    tcp.extend_from_slice(payload);
+    build_ipv4_packet(*src.ip(), *dst.ip(), PROTO_TCP, tcp)
+}
+
+/// Reset answering a segment that matches no flow
+pub(crate) fn reset_for<K>(key: &FlowKey<K>, packet: &[u8]) -> Option<Vec<u8>> {
+    let segment = Segment::parse(packet)?;
+    if segment.flags & RST != 0 {
+        return None;
+    }
+    let (seq, ack, flags) = if segment.flags & ACK != 0 {
+        (segment.ack, 0, RST)
+    } else {
+        (0, segment.seq.wrapping_add(segment.len()), RST | ACK)
+    };
+    Some(build_tcp_packet(
+        key.dst,
+        key.src,
+        seq,
+        ack,
+        flags,
+        0,
+        &[],
+        &[],
+    ))
+}
+
+/// Relay one TCP connection; runs until either side closes or the flow expires
+pub(crate) async fn relay_tcp<K>(
+    node: Arc<ExitNode<K>>,
+    key: FlowKey<K>,
+    id: u64,
+    mut rx: Receiver<Vec<u8>>,
+) where
+    K: Clone + Eq + Hash + Display + Send + Sync + 'static,
+{
+    let Some(syn) = rx.recv().await else {
+        return;
+    };
+    let Some((irs, peer_mss)) = Segment::parse(&syn).map(|s| (s.seq, s.mss)) else {
+        node.close(&key, id);
+        return;
+    };
+
+    let stream = match tokio::time::timeout(CONNECT_TIMEOUT, TcpStream::connect(key.dst)).await {
+        Ok(Ok(stream)) => stream,
+        Ok(Err(e)) => {
+            log::debug!("event=nat_tcp_connect_failed dst={} error={}", key.dst, e);
+            node.reply(
+                &key,
+                build_tcp_packet(
+                    key.dst,
+                    key.src,
+                    0,
+                    irs.wrapping_add(1),
+                    RST | ACK,
+                    0,
+                    &[],
+                    &[],
+                ),
+            );
+            node.close(&key, id);
+            return;
+        }
+        Err(_) => {
+            log::debug!("event=nat_tcp_connect_timeout dst={}", key.dst);
+            node.close(&key, id);
+            return;
+        }
+    };
+    let _ = stream.set_nodelay(true);
+    let (mut reader, writer) = stream.into_split();
+
+    let iss: u32 = rand::random();
+    let mut relay = TcpRelay {
+        node: node.clone(),
+        key: key.clone(),
+        writer,
+        snd_una: iss,
+        snd_nxt: iss.wrapping_add(1),
+        rcv_nxt: irs.wrapping_add(1),
+        peer_window: 0,
+        peer_mss: peer_mss.unwrap_or(DEFAULT_PEER_MSS).clamp(64, RELAY_MSS) as usize,
+        unacked: VecDeque::new(),
+        syn_acked: false,
+        fin_sent: false,
+        fin_received: false,
+        upstream_eof: false,
+        rto: INITIAL_RTO,
+        retransmits: 0,
+        last_send: Instant::now(),
+    };
+    relay.send_syn_ack();
+
+    let mut buf = vec![0u8; 16 * 1024];
+    loop {
+        if relay.finished() {
+            break;
+        }
+        let can_read = relay.syn_acked && !relay.upstream_eof && relay.unacked.len() < MAX_UNACKED;
+        let outstanding = relay.snd_una != relay.snd_nxt;
+        let deadline = relay.last_send + relay.rto;
+        tokio::select! {
+            packet = rx.recv() => {
+                // Channel closes when the flow expires or the peer leaves
+                let Some(packet) = packet else {
+                    relay.send_reset();
+                    return;
+                };
+                if !relay.on_segment(&packet).await {
+                    break;
+                }
+            }
+            read = reader.read(&mut buf), if can_read => match read {
+                Ok(0) => {
+                    relay.upstream_eof = true;
+                    relay.send_pending();
+                }
+                Ok(n) => {
+                    relay.unacked.extend(&buf[..n]);
+                    relay.send_pending();
+                }
+                Err(e) => {
+                    log::debug!("event=nat_tcp_read_failed dst={} error={}", key.dst, e);
+                    relay.send_reset();
+                    break;
+                }
+            },
+            _ = tokio::time::sleep_until(deadline), if outstanding => {
+                if !relay.retransmit() {
+                    break;
+                }
+            }
+        }
+    }
+    node.close(&key, id);
+}
+
+/// Per-connection TCP state toward the peer
+struct TcpRelay<K> {
+    node: Arc<ExitNode<K>>,
+    key: FlowKey<K>,
+    writer: OwnedWriteHalf,
+    /// Oldest unacknowledged sequence number
+    snd_una: u32,
+    /// Next sequence number to send
+    snd_nxt: u32,
+    /// Next sequence number expected from the peer
+    rcv_nxt: u32,
+    peer_window: u32,
+    peer_mss: usize,
+    /// Upstream bytes from `snd_una` onwards (sent or not)
+    unacked: VecDeque<u8>,
+    syn_acked: bool,
+    fin_sent: bool,
+    fin_received: bool,
+    upstream_eof: bool,
+    rto: Duration,
+    retransmits: u32,
+    last_send: Instant,
+}
+
+impl<K> TcpRelay<K>
+where
+    K: Clone + Eq + Hash + Display + Send + Sync + 'static,
+{
+    /// Both directions closed and everything acknowledged
+    fn finished(&self) -> bool {
+        self.fin_received && self.fin_sent && self.snd_una == self.snd_nxt
+    }
+
+    /// Handle a segment from the peer; returns false to close the relay
+    async fn on_segment(&mut self, packet: &[u8]) -> bool {
+        let Some(segment) = Segment::parse(packet) else {
+            return true;
+        };
+        if segment.flags & RST != 0 {
+            return false;
+        }
+        if segment.flags & SYN != 0 {
+            // Retransmitted SYN: our SYN-ACK was lost
+            if !self.syn_acked {
+                self.send_syn_ack();
+            }
+            return true;
+        }
+        if segment.flags & ACK != 0 {
+            self.on_ack(segment.ack, segment.window);
+        }
+
+        let consumes = !segment.payload.is_empty() || segment.flags & FIN != 0;
+        if !consumes {
+            return true;
+        }
+        if segment.seq != self.rcv_nxt || self.fin_received {
+            // Duplicate or out of order: re-ACK so the peer retransmits
+            self.send_ack();
+            return true;
+        }
+        if !segment.payload.is_empty() {
+            if let Err(e) = self.writer.write_all(segment.payload).await {
+                log::debug!(
+                    "event=nat_tcp_write_failed dst={} error={}",
+                    self.key.dst,
+                    e
+                );
+                self.send_reset();
+                return false;
+            }
+            self.rcv_nxt = self.rcv_nxt.wrapping_add(segment.payload.len() as u32);
+        }
+        if segment.flags & FIN != 0 {
+            self.rcv_nxt = self.rcv_nxt.wrapping_add(1);
+            self.fin_received = true;
+            let _ = self.writer.shutdown().await;
+        }
+        self.send_ack();
+        true
+    }
+
+    fn on_ack(&mut self, ack: u32, window: u16) {
+        self.peer_window = window as u32;
+        if !seq_lt(self.snd_una, ack) || seq_lt(self.snd_nxt, ack) {
+            self.send_pending();
+            return;
+        }
+        let mut acked = ack.wrapping_sub(self.snd_una) as usize;
+        if !self.syn_acked {
+            self.syn_acked = true;
+            acked -= 1;
+            self.node.set_established(&self.key);
+        }
+        let data = acked.min(self.unacked.len());
+        self.unacked.drain(..data);
+        self.snd_una = ack;
+        self.rto = INITIAL_RTO;
+        self.retransmits = 0;
+        self.last_send = Instant::now();
+        self.send_pending();
+    }
+
+    /// Send unsent upstream data the peer's window allows, then FIN at EOF
+    fn send_pending(&mut self) {
+        if !self.syn_acked {
+            return;
+        }
+        loop {
+            let in_flight = self.snd_nxt.wrapping_sub(self.snd_una) as usize;
+            let unsent = self.unacked.len().saturating_sub(in_flight);
+            let window = (self.peer_window as usize).saturating_sub(in_flight);
+            let len = unsent.min(window).min(self.peer_mss);
+            if len == 0 {
+                break;
+            }
+            self.send_data(in_flight, len, self.snd_nxt);
+            self.snd_nxt = self.snd_nxt.wrapping_add(len as u32);
+        }
+        let all_sent = self.snd_nxt.wrapping_sub(self.snd_una) as usize == self.unacked.len();
+        if self.upstream_eof && !self.fin_sent && all_sent {
+            self.send(self.snd_nxt, FIN | ACK, &[], &[]);
+            self.snd_nxt = self.snd_nxt.wrapping_add(1);
+            self.fin_sent = true;
+        }
+    }
+
+    /// Resend the oldest unacknowledged segment; returns false to give up
+    fn retransmit(&mut self) -> bool {
+        self.retransmits += 1;
+        if self.retransmits > MAX_RETRANSMITS {
+            log::debug!("event=nat_tcp_timeout dst={}", self.key.dst);
+            self.send_reset();
+            return false;
+        }
+        self.rto = (self.rto * 2).min(MAX_RTO);
+        if !self.syn_acked {
+            self.send_syn_ack();
+        } else if !self.unacked.is_empty() {
+            let len = self.unacked.len().min(self.peer_mss);
+            self.send_data(0, len, self.snd_una);
+        } else if self.fin_sent {
+            self.send(self.snd_una, FIN | ACK, &[], &[]);
+        }
+        true
+    }
+
+    fn send_data(&mut self, offset: usize, len: usize, seq: u32) {
+        let payload: Vec<u8> = self.unacked.range(offset..offset + len).copied().collect();
+        self.send(seq, PSH | ACK, &[], &payload);
+    }
+
+    fn send_syn_ack(&mut self) {
+        let mss = RELAY_MSS.to_be_bytes();
+        self.send(self.snd_una, SYN | ACK, &[2, 4, mss[0], mss[1]], &[]);
+    }
+
+    fn send_ack(&mut self) {
+        let packet = build_tcp_packet(
+            self.key.dst,
+            self.key.src,
+            self.snd_nxt,
+            self.rcv_nxt,
+            ACK,
+            RECV_WINDOW,
+            &[],
+            &[],
+        );
+        self.node.reply(&self.key, packet);
+    }
+
+    fn send_reset(&mut self) {
+        let packet = build_tcp_packet(
+            self.key.dst,
+            self.key.src,
+            self.snd_nxt,
+            self.rcv_nxt,
+            RST | ACK,
+            0,
+            &[],
+            &[],
+        );
+        self.node.reply(&self.key, packet);
+    }
+
+    fn send(&mut self, seq: u32, flags: u8, options: &[u8], payload: &[u8]) {
+        let packet = build_tcp_packet(
+            self.key.dst,
+            self.key.src,
+            seq,
+            self.rcv_nxt,
+            flags,
+            RECV_WINDOW,
+            options,
+            payload,
+        );
+        self.last_send = Instant::now();
+        self.node.reply(&self.key, packet);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_segment_round_trip() {
+        let src = SocketAddrV4::new([10, 0, 0, 2].into(), 40000);
+        let dst = SocketAddrV4::new([192, 0, 2, 1].into(), 80);
+        let packet = build_tcp_packet(src, dst, 7, 9, SYN, 1000, &[2, 4, 0x05, 0xB4], b"");
+        let segment = Segment::parse(&packet).expect("valid segment in test");
+        assert_eq!(segment.seq, 7);
+        assert_eq!(segment.ack, 9);
+        assert_eq!(segment.window, 1000);
+        assert_eq!(segment.mss, Some(1460));
+        assert_eq!(segment.len(), 1);
+    }
+
+    #[test]
+    fn test_reset_for_stray_segment() {
+        let key = FlowKey {
+            peer: (),
+            protocol: PROTO_TCP,
+            src: SocketAddrV4::new([10, 0, 0, 2].into(), 40000),
+            dst: SocketAddrV4::new([192, 0, 2, 1].into(), 80),
+        };
+        let stray = build_tcp_packet(key.src, key.dst, 5, 1234, ACK, 100, &[], b"data");
+        let reset = reset_for(&key, &stray).expect("reset in test");
+        let segment = Segment::parse(&reset).expect("valid segment in test");
+        assert_eq!(segment.flags, RST);
+        assert_eq!(segment.seq, 1234);
+        assert!(seq_lt(u32::MAX, 1));
+    }
+}
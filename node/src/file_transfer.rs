@@ -5,12 +5,19 @@
 // License: MIT (see LICENSE file for details)
 
 use anyhow::{Context, Result};
+use ring::digest;
 use std::collections::HashMap;
 use std::fs::File;
 use std::io::Write;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicU32, Ordering};
-use std::sync::Mutex;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use crate::{Tunnel, TunnelError, TunnelStream};
+
+/// Longest filename accepted in a file stream header
+const MAX_FILENAME_LEN: u32 = 4096;
 
 /// File metadata (duplicated from p2p to avoid dependency cycle)
 #[derive(Debug, Clone)]
@@ -241,7 +248,8 @@ impl FileTransferManager {
             stream_id,
             control_data.len()
         );
-        // TODO: Handle control messages (end-of-file, error, etc.)
+        // Typed control messages (Section 7.7) are handled by the tunnel;
+        // anything reaching here did not decode as one
         Ok(())
     }
 
@@ -262,3 +270,116 @@ impl FileTransferManager {
         outgoing.insert(stream_id, transfer);
     }
 }
+
+impl Tunnel {
+    /// Send a file over a reliable stream
+    ///
+    /// The stream carries the serialized `FileMetadata` followed by the file
+    /// contents, so it gets acknowledgements, retransmission, and congestion
+    /// control. Returns once the peer has acknowledged every byte.
+    pub async fn send_file(self: &Arc<Self>, path: &Path) -> Result<FileMetadata, TunnelError> {
+        let filename = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .ok_or_else(|| TunnelError::IoError(std::io::ErrorKind::InvalidInput.into()))?
+            .to_string();
+        let mut file = tokio::fs::File::open(path).await?;
+        let mut hasher = digest::Context::new(&digest::SHA256);
+        let mut size = 0u64;
+        let mut buf = vec![0u8; 65536];
+        loop {
+            let n = file.read(&mut buf).await?;
+            if n == 0 {
+                break;
+            }
+            hasher.update(&buf[..n]);
+            size += n as u64;
+        }
+        let mut hash = [0u8; 32];
+        hash.copy_from_slice(hasher.finish().as_ref());
+        let metadata = FileMetadata::new(filename, size, hash);
+
+        let mut stream = self.open_stream().await?;
+        stream.write_all(&metadata.serialize()).await?;
+        let mut file = tokio::fs::File::open(path).await?.take(size);
+        tokio::io::copy(&mut file, &mut stream).await?;
+        stream.finish().await?;
+        log::info!(
+            "event=file_sent stream={} filename={} size={}",
+            stream.id(),
+            metadata.filename,
+            metadata.size
+        );
+        Ok(metadata)
+    }
+
+    /// Receive a file sent with `send_file` into the output directory
+    ///
+    /// The SHA-256 hash is checked before returning the file's path; a
+    /// short or corrupt file is removed.
+    pub async fn receive_file(&self, mut stream: TunnelStream) -> Result<PathBuf, TunnelError> {
+        let invalid = |msg: &str| {
+            TunnelError::IoError(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                msg.to_string(),
+            ))
+        };
+        let mut header = [0u8; 8];
+        stream.read_exact(&mut header).await?;
+        let (packet_type, name_len) = header.split_at(4);
+        let name_len = u32::from_be_bytes(name_len.try_into().unwrap_or_default());
+        if packet_type != [0, 0, 0, 0] || name_len > MAX_FILENAME_LEN {
+            return Err(invalid("not a file stream"));
+        }
+        let mut name = vec![0u8; name_len as usize];
+        stream.read_exact(&mut name).await?;
+        let mut size = [0u8; 8];
+        stream.read_exact(&mut size).await?;
+        let mut hash = [0u8; 32];
+        stream.read_exact(&mut hash).await?;
+        let size = u64::from_be_bytes(size);
+
+        // Only the final path component: the peer picks no directories
+        let name = String::from_utf8(name).map_err(|_| invalid("filename not UTF-8"))?;
+        let filename = Path::new(&name)
+            .file_name()
+            .ok_or_else(|| invalid("empty filename"))?;
+        let output_path = self.file_transfer.output_dir.join(filename);
+
+        let mut file = tokio::fs::File::create(&output_path).await?;
+        let mut hasher = digest::Context::new(&digest::SHA256);
+        let mut received = 0u64;
+        let mut buf = vec![0u8; 65536];
+        let mut body = (&mut stream).take(size);
+        loop {
+            let n = body.read(&mut buf).await?;
+            if n == 0 {
+                break;
+            }
+            hasher.update(&buf[..n]);
+            file.write_all(&buf[..n]).await?;
+            received += n as u64;
+        }
+        file.flush().await?;
+
+        if received != size || hasher.finish().as_ref() != hash {
+            drop(file);
+            let _ = tokio::fs::remove_file(&output_path).await;
+            log::warn!(
+                "event=file_received status=corrupt stream={} filename={} received={} size={}",
+                stream.id(),
+                name,
+                received,
+                size
+            );
+            return Err(invalid("file truncated or hash mismatch"));
+        }
+        log::info!(
+            "event=file_received stream={} path={:?} size={}",
+            stream.id(),
+            output_path,
+            size
+        );
+        Ok(output_path)
+    }
+}
@@ -0,0 +1,479 @@
+// Copyright (c) 2025 Thor Thor
+// Author: Thor Thor (GitHub: https://github.com/codethor0)
+// Contact: codethor@gmail.com
+// LinkedIn: https://www.linkedin.com/in/thor-thor0
+// License: MIT (see LICENSE file for details)
+
+use anyhow::Result;
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::packet::{IpHeader, IpPrefix, PROTO_ICMP, PROTO_ICMPV6, PROTO_TCP, PROTO_UDP};
+use crate::stats::{record_drop, DropReason};
+use crate::tun::PacketForwarder;
+
+/// Idle timeout for tracked flows
+const FLOW_IDLE_TIMEOUT: Duration = Duration::from_secs(120);
+/// Maximum number of tracked flows
+const MAX_TRACKED_FLOWS: usize = 65536;
+
+/// Packet direction relative to the TUN interface
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// Peer -> TUN
+    Inbound,
+    /// TUN -> peer
+    Outbound,
+}
+
+/// Firewall rule verdict
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RuleAction {
+    Allow,
+    Deny,
+}
+
+impl FromStr for RuleAction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "allow" => Ok(RuleAction::Allow),
+            "deny" => Ok(RuleAction::Deny),
+            other => Err(format!("unknown firewall action: {}", other)),
+        }
+    }
+}
+
+/// Firewall rule matching protocol, destination port and direction
+///
+/// Text form: `<allow|deny> [in|out] [tcp|udp|icmp|any] [port|port-port]`,
+/// e.g. `allow in tcp 22` or `deny out udp 53`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FirewallRule {
+    pub action: RuleAction,
+    /// None matches both directions
+    pub direction: Option<Direction>,
+    /// IP protocol number; None matches any protocol
+    pub protocol: Option<u8>,
+    /// Inclusive destination port range (TCP/UDP only)
+    pub ports: Option<(u16, u16)>,
+}
+
+impl FirewallRule {
+    fn matches(&self, direction: Direction, protocol: u8, dst_port: Option<u16>) -> bool {
+        if self.direction.is_some_and(|d| d != direction) {
+            return false;
+        }
+        if self.protocol.is_some_and(|p| p != protocol) {
+            return false;
+        }
+        match (self.ports, dst_port) {
+            (None, _) => true,
+            (Some((low, high)), Some(port)) => port >= low && port <= high,
+            (Some(_), None) => false,
+        }
+    }
+}
+
+impl FromStr for FirewallRule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut tokens = s.split_whitespace().peekable();
+        let action = tokens
+            .next()
+            .ok_or_else(|| "empty firewall rule".to_string())?
+            .parse()?;
+
+        let direction = match tokens.peek() {
+            Some(&"in") => Some(Direction::Inbound),
+            Some(&"out") => Some(Direction::Outbound),
+            _ => None,
+        };
+        if direction.is_some() {
+            tokens.next();
+        }
+
+        let protocol = match tokens.peek() {
+            Some(&"tcp") => Some(Some(PROTO_TCP)),
+            Some(&"udp") => Some(Some(PROTO_UDP)),
+            Some(&"icmp") => Some(Some(PROTO_ICMP)),
+            Some(&"icmpv6") => Some(Some(PROTO_ICMPV6)),
+            Some(&"any") => Some(None),
+            _ => None,
+        };
+        if protocol.is_some() {
+            tokens.next();
+        }
+        let protocol = protocol.flatten();
+
+        let ports = match tokens.next() {
+            Some(spec) => {
+                if !matches!(protocol, Some(PROTO_TCP) | Some(PROTO_UDP)) {
+                    return Err(format!("ports need tcp or udp in rule: {}", s));
+                }
+                let (low, high) = spec.split_once('-').unwrap_or((spec, spec));
+                let low: u16 = low
+                    .parse()
+                    .map_err(|_| format!("invalid port in rule: {}", s))?;
+                let high: u16 = high
+                    .parse()
+                    .map_err(|_| format!("invalid port in rule: {}", s))?;
+                if low > high {
+                    return Err(format!("invalid port range in rule: {}", s));
+                }
+                Some((low, high))
+            }
+            None => None,
+        };
+
+        if tokens.next().is_some() {
+            return Err(format!("trailing tokens in rule: {}", s));
+        }
+
+        Ok(Self {
+            action,
+            direction,
+            protocol,
+            ports,
+        })
+    }
+}
+
+/// Flow identity from the local (TUN) side's point of view
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct FlowKey {
+    protocol: u8,
+    local: (IpAddr, u16),
+    remote: (IpAddr, u16),
+}
+
+/// Stateful firewall
+///
+/// Rules are evaluated in order and the first match wins; unmatched packets
+/// get the default action. Accepted flows are tracked in both directions, so
+/// replies pass even when the rules would reject them (e.g. the SYN-ACK of
+/// an inbound connection allowed by "allow in tcp 22").
+pub struct Firewall {
+    rules: Vec<FirewallRule>,
+    default_action: RuleAction,
+    flows: Mutex<HashMap<FlowKey, Instant>>,
+}
+
+impl Firewall {
+    pub fn new(rules: Vec<FirewallRule>, default_action: RuleAction) -> Self {
+        Self {
+            rules,
+            default_action,
+            flows: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Decide whether a packet may pass in the given direction
+    pub fn allows(&self, direction: Direction, header: &IpHeader, packet: &[u8]) -> bool {
+        let ports = transport_ports(header, packet);
+        let key = match direction {
+            Direction::Outbound => FlowKey {
+                protocol: header.protocol,
+                local: (header.src, ports.map_or(0, |p| p.0)),
+                remote: (header.dst, ports.map_or(0, |p| p.1)),
+            },
+            Direction::Inbound => FlowKey {
+                protocol: header.protocol,
+                local: (header.dst, ports.map_or(0, |p| p.1)),
+                remote: (header.src, ports.map_or(0, |p| p.0)),
+            },
+        };
+
+        let now = Instant::now();
+        let mut flows = match self.flows.lock() {
+            Ok(flows) => flows,
+            Err(_) => return false,
+        };
+
+        // Established flows pass in both directions
+        if let Some(last_seen) = flows.get_mut(&key) {
+            if now.duration_since(*last_seen) < FLOW_IDLE_TIMEOUT {
+                *last_seen = now;
+                return true;
+            }
+            flows.remove(&key);
+        }
+
+        let action = self
+            .rules
+            .iter()
+            .find(|rule| rule.matches(direction, header.protocol, ports.map(|p| p.1)))
+            .map_or(self.default_action, |rule| rule.action);
+        if action == RuleAction::Deny {
+            return false;
+        }
+
+        // Track the accepted flow (keyed local/remote) so replies get through
+        if flows.len() >= MAX_TRACKED_FLOWS {
+            flows.retain(|_, seen| now.duration_since(*seen) < FLOW_IDLE_TIMEOUT);
+        }
+        if flows.len() < MAX_TRACKED_FLOWS {
+            flows.insert(key, now);
+        }
+        true
+    }
+}
+
+/// Source and destination ports of TCP/UDP packets
+fn transport_ports(header: &IpHeader, packet: &[u8]) -> Option<(u16, u16)> {
+    if header.protocol != PROTO_TCP && header.protocol != PROTO_UDP {
+        return None;
+    }
+    let ports = packet.get(header.header_len..header.header_len + 4)?;
+    Some((
+        u16::from_be_bytes([ports[0], ports[1]]),
+        u16::from_be_bytes([ports[2], ports[3]]),
+    ))
+}
+
+/// Ingress/egress filter for one peer's VPN packets
+///
+/// Inbound packets must come from one of the peer's allowed source prefixes
+/// (cryptokey routing) before the optional firewall is consulted.
+#[derive(Clone)]
+pub struct PacketFilter {
+    allowed_ips: Vec<IpPrefix>,
+    firewall: Option<Arc<Firewall>>,
+}
+
+impl PacketFilter {
+    /// Filter accepting only the given source prefixes from the peer
+    pub fn new(allowed_ips: Vec<IpPrefix>) -> Self {
+        Self {
+            allowed_ips,
+            firewall: None,
+        }
+    }
+
+    /// Attach a (possibly shared) stateful firewall
+    pub fn with_firewall(mut self, firewall: Arc<Firewall>) -> Self {
+        self.firewall = Some(firewall);
+        self
+    }
+
+    /// Check a packet received from the peer before it is written to TUN
+    pub fn check_inbound(&self, packet: &[u8]) -> Result<(), DropReason> {
+        let header = IpHeader::parse(packet).ok_or(DropReason::Malformed)?;
+        if !self.allowed_ips.iter().any(|p| p.contains(header.src)) {
+            return Err(DropReason::SourceNotAllowed);
+        }
+        check_firewall(
+            self.firewall.as_deref(),
+            Direction::Inbound,
+            &header,
+            packet,
+        )
+    }
+
+    /// Check a packet read from TUN before it is sent to the peer
+    pub fn check_outbound(&self, packet: &[u8]) -> Result<(), DropReason> {
+        let header = IpHeader::parse(packet).ok_or(DropReason::Malformed)?;
+        check_firewall(
+            self.firewall.as_deref(),
+            Direction::Outbound,
+            &header,
+            packet,
+        )
+    }
+}
+
+/// Run the firewall (if any) on a parsed packet
+pub(crate) fn check_firewall(
+    firewall: Option<&Firewall>,
+    direction: Direction,
+    header: &IpHeader,
+    packet: &[u8],
+) -> Result<(), DropReason> {
+    match firewall {
+        Some(fw) if !fw.allows(direction, header, packet) => Err(DropReason::Firewall),
+        _ => Ok(()),
+    }
+}
+
+/// Count and log a dropped packet
+pub(crate) fn drop_packet(reason: DropReason, direction: Direction, len: usize) {
+    record_drop(reason);
+    log::debug!(
+        "event=packet_dropped reason={} direction={:?} len={}",
+        reason.as_str(),
+        direction,
+        len
+    );
+}
+
+/// Packet forwarder wrapper applying a `PacketFilter`
+pub struct FilteredForwarder<F> {
+    inner: F,
+    filter: PacketFilter,
+}
+
+impl<F> FilteredForwarder<F> {
+    pub fn new(inner: F, filter: PacketFilter) -> Self {
+        Self { inner, filter }
+    }
+}
+
+#[async_trait]
+impl<F: PacketForwarder> PacketForwarder for FilteredForwarder<F> {
+    async fn send_packet(&self, packet: &[u8]) -> Result<()> {
+        match self.filter.check_outbound(packet) {
+            Ok(()) => self.inner.send_packet(packet).await,
+            Err(reason) => {
+                drop_packet(reason, Direction::Outbound, packet.len());
+                Ok(())
+            }
+        }
+    }
+
+    async fn recv_packet(&mut self) -> Result<Vec<u8>> {
+        loop {
+            let packet = self.inner.recv_packet().await?;
+            match self.filter.check_inbound(&packet) {
+                Ok(()) => return Ok(packet),
+                Err(reason) => drop_packet(reason, Direction::Inbound, packet.len()),
+            }
+        }
+    }
+    async fn send_packets(&self, packets: &[&[u8]]) -> Result<()> {
+        let allowed: Vec<&[u8]> = packets
+            .iter()
+            .copied()
+            .filter(|packet| match self.filter.check_outbound(packet) {
+                Ok(()) => true,
+                Err(reason) => {
+                    drop_packet(reason, Direction::Outbound, packet.len());
+                    false
+                }
+            })
+            .collect();
+        match allowed.is_empty() {
+            true => Ok(()),
+            false => self.inner.send_packets(&allowed).await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn udp_packet(src: [u8; 4], dst: [u8; 4], sport: u16, dport: u16) -> Vec<u8> {
+        let mut packet = vec![0u8; 28];
+        packet[0] = 0x45;
+        packet[9] = PROTO_UDP;
+        packet[12..16].copy_from_slice(&src);
+        packet[16..20].copy_from_slice(&dst);
+        packet[20..22].copy_from_slice(&sport.to_be_bytes());
+        packet[22..24].copy_from_slice(&dport.to_be_bytes());
+        packet
+    }
+
+    fn tcp_packet(src: [u8; 4], dst: [u8; 4], sport: u16, dport: u16) -> Vec<u8> {
+        let mut packet = udp_packet(src, dst, sport, dport);
+        packet[9] = PROTO_TCP;
+        packet
+    }
+
+    fn rule(s: &str) -> FirewallRule {
+        s.parse().expect("valid rule in test")
+    }
+
+    #[test]
+    fn test_rule_parsing() {
+        assert_eq!(
+            rule("allow in tcp 22"),
+            FirewallRule {
+                action: RuleAction::Allow,
+                direction: Some(Direction::Inbound),
+                protocol: Some(PROTO_TCP),
+                ports: Some((22, 22)),
+            }
+        );
+        assert_eq!(rule("deny out udp 5000-5100").ports, Some((5000, 5100)));
+        assert_eq!(rule("deny any").protocol, None);
+        assert!("allow in icmp 22".parse::<FirewallRule>().is_err());
+        assert!("permit in tcp".parse::<FirewallRule>().is_err());
+        assert!("allow in tcp 90-80".parse::<FirewallRule>().is_err());
+    }
+
+    #[test]
+    fn test_source_not_allowed() {
+        let filter = PacketFilter::new(vec!["10.0.0.2/32".parse().expect("prefix")]);
+        assert!(filter
+            .check_inbound(&udp_packet([10, 0, 0, 2], [10, 0, 0, 1], 1000, 53))
+            .is_ok());
+        assert_eq!(
+            filter.check_inbound(&udp_packet([192, 168, 1, 9], [10, 0, 0, 1], 1000, 53)),
+            Err(DropReason::SourceNotAllowed)
+        );
+        assert_eq!(filter.check_inbound(&[0xFF; 8]), Err(DropReason::Malformed));
+    }
+
+    #[test]
+    fn test_firewall_rules_first_match() {
+        let firewall = Arc::new(Firewall::new(
+            vec![rule("allow in tcp 22"), rule("deny out udp 53")],
+            RuleAction::Deny,
+        ));
+        let filter =
+            PacketFilter::new(vec!["0.0.0.0/0".parse().expect("prefix")]).with_firewall(firewall);
+
+        assert!(filter
+            .check_inbound(&tcp_packet([10, 0, 0, 2], [10, 0, 0, 1], 40000, 22))
+            .is_ok());
+        // The server's reply passes despite the default deny
+        assert!(filter
+            .check_outbound(&tcp_packet([10, 0, 0, 1], [10, 0, 0, 2], 22, 40000))
+            .is_ok());
+        // ...but it opens nothing else outbound
+        assert_eq!(
+            filter.check_outbound(&tcp_packet([10, 0, 0, 1], [10, 0, 0, 2], 22, 40001)),
+            Err(DropReason::Firewall)
+        );
+        assert_eq!(
+            filter.check_inbound(&tcp_packet([10, 0, 0, 2], [10, 0, 0, 1], 40000, 80)),
+            Err(DropReason::Firewall)
+        );
+        assert_eq!(
+            filter.check_outbound(&udp_packet([10, 0, 0, 1], [10, 0, 0, 2], 5353, 53)),
+            Err(DropReason::Firewall)
+        );
+    }
+
+    #[test]
+    fn test_firewall_tracks_outbound_flows() {
+        let firewall = Arc::new(Firewall::new(
+            vec![rule("allow out any"), rule("deny in any")],
+            RuleAction::Deny,
+        ));
+        let filter =
+            PacketFilter::new(vec!["0.0.0.0/0".parse().expect("prefix")]).with_firewall(firewall);
+
+        // Unsolicited inbound is rejected
+        let reply = udp_packet([10, 0, 0, 2], [10, 0, 0, 1], 53, 5353);
+        assert_eq!(filter.check_inbound(&reply), Err(DropReason::Firewall));
+
+        // Reply to an outbound flow is accepted
+        assert!(filter
+            .check_outbound(&udp_packet([10, 0, 0, 1], [10, 0, 0, 2], 5353, 53))
+            .is_ok());
+        assert!(filter.check_inbound(&reply).is_ok());
+
+        // A different source port is a different flow
+        assert_eq!(
+            filter.check_inbound(&udp_packet([10, 0, 0, 2], [10, 0, 0, 1], 54, 5353)),
+            Err(DropReason::Firewall)
+        );
+    }
+}
@@ -0,0 +1,250 @@
+// Copyright (c) 2025 Thor Thor
+// Author: Thor Thor (GitHub: https://github.com/codethor0)
+// Contact: codethor@gmail.com
+// LinkedIn: https://www.linkedin.com/in/thor-thor0
+// License: MIT (see LICENSE file for details)
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use cryprq_core::RecordHeader;
+
+/// Fragment header: message ID (4 bytes), index (2 bytes), count (2 bytes)
+pub(crate) const FRAGMENT_HEADER_SIZE: usize = 8;
+
+/// Most fragments one record may be split into
+pub(crate) const MAX_FRAGMENTS: usize = 256;
+
+/// Largest record payload that may be fragmented and reassembled
+pub(crate) const MAX_REASSEMBLED_SIZE: usize = 256 * 1024;
+
+/// Bytes buffered across all partially reassembled records
+const MAX_PENDING_BYTES: usize = 1024 * 1024;
+
+/// Partially reassembled records kept at once
+const MAX_PENDING_RECORDS: usize = 64;
+
+/// How long a partial record waits for its missing fragments
+const REASSEMBLY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Split a record payload into fragment payloads of at most `max_len` bytes
+///
+/// Each fragment starts with the fragment header; the caller seals every
+/// one as its own record with `FLAG_FRAGMENT` set. Returns None if the
+/// payload is too large to reassemble.
+pub(crate) fn split(payload: &[u8], max_len: usize, message_id: u32) -> Option<Vec<Vec<u8>>> {
+    let chunk_len = max_len.checked_sub(FRAGMENT_HEADER_SIZE)?.max(1);
+    let count = payload.len().div_ceil(chunk_len).max(1);
+    if payload.len() > MAX_REASSEMBLED_SIZE || count > MAX_FRAGMENTS {
+        return None;
+    }
+    let fragments = payload
+        .chunks(chunk_len)
+        .enumerate()
+        .map(|(index, chunk)| {
+            let mut fragment = Vec::with_capacity(FRAGMENT_HEADER_SIZE + chunk.len());
+            fragment.extend_from_slice(&message_id.to_be_bytes());
+            fragment.extend_from_slice(&(index as u16).to_be_bytes());
+            fragment.extend_from_slice(&(count as u16).to_be_bytes());
+            fragment.extend_from_slice(chunk);
+            fragment
+        })
+        .collect();
+    Some(fragments)
+}
+
+/// Record whose fragments are still arriving
+struct Partial {
+    message_type: u8,
+    stream_id: u32,
+    flags: u8,
+    fragments: Vec<Option<Vec<u8>>>,
+    received: usize,
+    bytes: usize,
+    started: Instant,
+}
+
+/// Reassembles fragmented records within fixed memory limits
+///
+/// Fragments are authenticated records, so only the peer can fill these
+/// buffers; the limits bound what a buggy or lossy peer can leave behind.
+/// Partial records time out, and the oldest is evicted when a limit would
+/// be exceeded.
+pub(crate) struct Reassembler {
+    pending: HashMap<u32, Partial>,
+    pending_bytes: usize,
+}
+
+impl Reassembler {
+    pub(crate) fn new() -> Self {
+        Self {
+            pending: HashMap::new(),
+            pending_bytes: 0,
+        }
+    }
+
+    /// Add one fragment; returns the record payload once it is complete
+    pub(crate) fn push(
+        &mut self,
+        header: &RecordHeader,
+        fragment: &[u8],
+        now: Instant,
+    ) -> Option<Vec<u8>> {
+        self.expire(now);
+        if fragment.len() < FRAGMENT_HEADER_SIZE {
+            log::debug!("event=fragment_dropped reason=truncated");
+            return None;
+        }
+        let (head, data) = fragment.split_at(FRAGMENT_HEADER_SIZE);
+        let message_id = u32::from_be_bytes([head[0], head[1], head[2], head[3]]);
+        let index = u16::from_be_bytes([head[4], head[5]]) as usize;
+        let count = u16::from_be_bytes([head[6], head[7]]) as usize;
+        if count == 0 || count > MAX_FRAGMENTS || index >= count {
+            log::debug!("event=fragment_dropped reason=bad_header id={}", message_id);
+            return None;
+        }
+
+        let matches = self.pending.get(&message_id).map(|partial| {
+            partial.message_type == header.message_type
+                && partial.stream_id == header.stream_id
+                && partial.flags == header.flags
+                && partial.fragments.len() == count
+        });
+        if matches == Some(false) {
+            log::debug!("event=fragment_dropped reason=mismatch id={}", message_id);
+            self.remove(message_id);
+            return None;
+        }
+        if matches.is_none() {
+            while self.pending.len() >= MAX_PENDING_RECORDS {
+                self.evict_oldest();
+            }
+            self.pending.insert(
+                message_id,
+                Partial {
+                    message_type: header.message_type,
+                    stream_id: header.stream_id,
+                    flags: header.flags,
+                    fragments: vec![None; count],
+                    received: 0,
+                    bytes: 0,
+                    started: now,
+                },
+            );
+        }
+
+        let partial = self.pending.get_mut(&message_id)?;
+        if partial.fragments[index].is_some() {
+            return None;
+        }
+        if partial.bytes + data.len() > MAX_REASSEMBLED_SIZE {
+            log::debug!("event=fragment_dropped reason=too_large id={}", message_id);
+            self.remove(message_id);
+            return None;
+        }
+        partial.fragments[index] = Some(data.to_vec());
+        partial.received += 1;
+        partial.bytes += data.len();
+        self.pending_bytes += data.len();
+
+        if partial.received == count {
+            let partial = self.remove(message_id)?;
+            return Some(partial.fragments.into_iter().flatten().flatten().collect());
+        }
+        while self.pending_bytes > MAX_PENDING_BYTES {
+            self.evict_oldest();
+        }
+        None
+    }
+
+    /// Number of partially reassembled records
+    #[cfg(test)]
+    fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    fn expire(&mut self, now: Instant) {
+        let expired: Vec<u32> = self
+            .pending
+            .iter()
+            .filter(|(_, p)| now.saturating_duration_since(p.started) >= REASSEMBLY_TIMEOUT)
+            .map(|(id, _)| *id)
+            .collect();
+        for id in expired {
+            log::debug!("event=fragment_dropped reason=timeout id={}", id);
+            self.remove(id);
+        }
+    }
+
+    fn evict_oldest(&mut self) {
+        let oldest = self
+            .pending
+            .iter()
+            .min_by_key(|(_, p)| p.started)
+            .map(|(id, _)| *id);
+        if let Some(id) = oldest {
+            log::debug!("event=fragment_dropped reason=evicted id={}", id);
+            self.remove(id);
+        }
+    }
+
+    fn remove(&mut self, message_id: u32) -> Option<Partial> {
+        let partial = self.pending.remove(&message_id)?;
+        self.pending_bytes -= partial.bytes;
+        Some(partial)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cryprq_core::{FLAG_FRAGMENT, MSG_TYPE_FILE_CHUNK};
+
+    fn header() -> RecordHeader {
+        RecordHeader::new(MSG_TYPE_FILE_CHUNK, FLAG_FRAGMENT, 0, 2, 0, 0)
+    }
+
+    #[test]
+    fn test_split_and_reassemble_out_of_order() {
+        let payload: Vec<u8> = (0..5000u32).map(|i| i as u8).collect();
+        let fragments = split(&payload, 1000, 9).expect("split in test");
+        assert_eq!(fragments.len(), 6);
+        assert!(fragments.iter().all(|f| f.len() <= 1000));
+
+        let now = Instant::now();
+        let mut reassembler = Reassembler::new();
+        for fragment in fragments.iter().skip(1).rev() {
+            assert!(reassembler.push(&header(), fragment, now).is_none());
+        }
+        // Duplicates are ignored
+        assert!(reassembler.push(&header(), &fragments[3], now).is_none());
+        let whole = reassembler.push(&header(), &fragments[0], now);
+        assert_eq!(whole, Some(payload));
+        assert_eq!(reassembler.pending(), 0);
+    }
+
+    #[test]
+    fn test_limits_and_timeout() {
+        assert!(split(&vec![0; MAX_REASSEMBLED_SIZE + 1], 1400, 1).is_none());
+        assert!(split(&vec![0; 300 * 100], 108, 1).is_none());
+
+        let now = Instant::now();
+        let mut reassembler = Reassembler::new();
+        let fragments = split(&[1; 3000], 1000, 1).expect("split in test");
+        assert!(reassembler.push(&header(), &fragments[0], now).is_none());
+        // The rest arrives too late: the partial record is gone
+        let late = now + REASSEMBLY_TIMEOUT;
+        assert!(reassembler.push(&header(), &fragments[1], late).is_none());
+        assert!(reassembler.push(&header(), &fragments[2], late).is_none());
+        assert_eq!(reassembler.pending(), 1);
+
+        // Too many partial records: the oldest go first
+        for id in 100..100 + MAX_PENDING_RECORDS as u32 {
+            let fragments = split(&[2; 2000], 1000, id).expect("split in test");
+            reassembler.push(&header(), &fragments[0], late);
+        }
+        assert_eq!(reassembler.pending(), MAX_PENDING_RECORDS);
+        assert!(reassembler.push(&header(), &fragments[0], late).is_none());
+        assert!(reassembler.pending() <= MAX_PENDING_RECORDS);
+    }
+}
@@ -0,0 +1,667 @@
+// Copyright (c) 2025 Thor Thor
+// Author: Thor Thor (GitHub: https://github.com/codethor0)
+// Contact: codethor@gmail.com
+// LinkedIn: https://www.linkedin.com/in/thor-thor0
+// License: MIT (see LICENSE file for details)
+
+use cryprq_core::{
+    negotiate_version, ClientFinish, ClientHello, Extension, HandshakeMessage, KnownExtension,
+    ServerHello, CIPHER_SUITE_CHACHA20_POLY1305, EXT_COOKIE, EXT_HEADER_PROTECTION, EXT_IDENTITY,
+    SUPPORTED_VERSIONS,
+};
+use cryprq_crypto::{
+    derive_epoch_keys, derive_handshake_keys, derive_header_protection_keys, kyber_encapsulate,
+    HybridHandshake,
+};
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use rand::rngs::OsRng;
+use rand::RngCore;
+use ring::{digest, hmac};
+use x25519_dalek::{EphemeralSecret, PublicKey};
+use zeroize::Zeroize;
+
+use crate::record_layer::{DirectionKeys, HeaderProtectionKeys};
+
+/// Context strings for the transcript signatures
+const CLIENT_SIG_CONTEXT: &[u8] = b"cryp-rq v1.0 client signature";
+const SERVER_SIG_CONTEXT: &[u8] = b"cryp-rq v1.0 server signature";
+
+/// Identity and trust settings for one side of the handshake
+#[derive(Clone, Default)]
+pub struct HandshakeConfig {
+    /// Long-term Ed25519 key used to sign the transcript (anonymous if None)
+    pub identity: Option<SigningKey>,
+    /// Peer identity keys to accept; any peer (even anonymous) if None
+    pub trusted_peers: Option<Vec<[u8; 32]>>,
+    /// Offer (initiator) or accept (responder) header protection
+    pub header_protection: bool,
+}
+
+/// Traffic keys and peer details produced by a completed handshake
+pub struct SessionKeys {
+    pub session_id: u64,
+    /// Protocol version negotiated for the session
+    pub version: u8,
+    pub outbound: DirectionKeys,
+    pub inbound: DirectionKeys,
+    /// Ed25519 identity the peer proved, if it presented one
+    pub peer_identity: Option<[u8; 32]>,
+    /// Set when both sides agreed on header protection
+    pub header_protection: Option<HeaderProtectionKeys>,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum HandshakeError {
+    #[error("malformed handshake message: {0}")]
+    Malformed(String),
+    #[error("unexpected handshake message")]
+    UnexpectedMessage,
+    #[error("unsupported protocol version {0}")]
+    UnsupportedVersion(u8),
+    #[error("no common cipher suite")]
+    NoCommonCipherSuite,
+    #[error("key encapsulation failed")]
+    KemFailure,
+    #[error("handshake transcript verification failed")]
+    VerifyFailed,
+    #[error("peer identity not trusted")]
+    UntrustedPeer,
+}
+
+impl From<HandshakeError> for crate::TunnelError {
+    fn from(err: HandshakeError) -> Self {
+        match err {
+            HandshakeError::UntrustedPeer => crate::TunnelError::InvalidPeerIdentity,
+            HandshakeError::UnsupportedVersion(version) => {
+                crate::TunnelError::UnsupportedVersion(version)
+            }
+            HandshakeError::UnexpectedMessage => {
+                crate::TunnelError::UnexpectedMessage("handshake".to_string())
+            }
+            other => crate::TunnelError::HandshakeFailed(other.to_string()),
+        }
+    }
+}
+
+/// Initiator side: CLIENT_HELLO out, SERVER_HELLO in, CLIENT_FINISH out
+pub struct Initiator {
+    config: HandshakeConfig,
+    hello: ClientHello,
+    client_hello: Vec<u8>,
+}
+
+impl Initiator {
+    /// Start a handshake; send `client_hello()` to the responder
+    pub fn new(config: &HandshakeConfig) -> Self {
+        let mut random = [0u8; 32];
+        OsRng.fill_bytes(&mut random);
+        // The header carries our lowest version so a responder that
+        // predates supported_versions still accepts the hello
+        let mut extensions = identity_extension(config);
+        extensions
+            .push(KnownExtension::SupportedVersions(SUPPORTED_VERSIONS.to_vec()).to_extension());
+        if config.header_protection {
+            extensions.push(KnownExtension::HeaderProtection.to_extension());
+        }
+        let hello = ClientHello {
+            version: SUPPORTED_VERSIONS[0],
+            random,
+            cipher_suites: vec![CIPHER_SUITE_CHACHA20_POLY1305],
+            extensions,
+        };
+        Self {
+            config: config.clone(),
+            client_hello: hello.to_bytes(),
+            hello,
+        }
+    }
+
+    /// Encoded CLIENT_HELLO (resent until SERVER_HELLO arrives)
+    pub fn client_hello(&self) -> &[u8] {
+        &self.client_hello
+    }
+
+    /// Echo a COOKIE from the responder in the next CLIENT_HELLO
+    pub fn set_cookie(&mut self, cookie: &[u8]) {
+        self.hello
+            .extensions
+            .retain(|ext| ext.ext_type != EXT_COOKIE);
+        self.hello.extensions.push(Extension {
+            ext_type: EXT_COOKIE,
+            value: cookie.to_vec(),
+        });
+        self.client_hello = self.hello.to_bytes();
+    }
+
+    /// Process SERVER_HELLO, returning the session keys and CLIENT_FINISH
+    pub fn finish(&self, server_hello: &[u8]) -> Result<(SessionKeys, Vec<u8>), HandshakeError> {
+        let hello = match decode(server_hello)? {
+            HandshakeMessage::ServerHello(hello) => hello,
+            _ => return Err(HandshakeError::UnexpectedMessage),
+        };
+        // A downgraded choice is caught by the transcript checks below
+        if !SUPPORTED_VERSIONS.contains(&hello.version) {
+            return Err(HandshakeError::UnsupportedVersion(hello.version));
+        }
+        if hello.cipher_suite != CIPHER_SUITE_CHACHA20_POLY1305 {
+            return Err(HandshakeError::NoCommonCipherSuite);
+        }
+        let header_protection = hello.extension(EXT_HEADER_PROTECTION).is_some();
+        if header_protection && !self.config.header_protection {
+            return Err(HandshakeError::Malformed(
+                "header protection accepted but not offered".to_string(),
+            ));
+        }
+
+        // Responder signs CLIENT_HELLO || SERVER_HELLO (without signature)
+        let server_transcript = [self.client_hello.as_slice(), &hello.signed_bytes()].concat();
+        let peer_identity = check_identity(
+            &self.config,
+            hello.extension(EXT_IDENTITY),
+            hello.signature.as_ref(),
+            SERVER_SIG_CONTEXT,
+            &server_transcript,
+        )?;
+
+        let (kem_ciphertext, mut ss_kem) =
+            kyber_encapsulate(&hello.kem_public_key).ok_or(HandshakeError::KemFailure)?;
+        let x_secret = EphemeralSecret::random_from_rng(rand_core::OsRng);
+        let x25519_public_key = PublicKey::from(&x_secret).to_bytes();
+        let mut ss_x = *x_secret
+            .diffie_hellman(&PublicKey::from(hello.x25519_public_key))
+            .as_bytes();
+        let (hs_auth_key, master_secret) = derive_handshake_keys(&ss_kem, &ss_x);
+        ss_kem.zeroize();
+        ss_x.zeroize();
+
+        let mut finish = ClientFinish {
+            session_id: hello.session_id,
+            kem_ciphertext,
+            x25519_public_key,
+            signature: None,
+            verify_data: [0; 32],
+        };
+        let mut transcript = [self.client_hello.as_slice(), server_hello].concat();
+        if let Some(identity) = &self.config.identity {
+            let signed = [transcript.as_slice(), &finish.signed_bytes()].concat();
+            finish.signature = Some(sign(identity, CLIENT_SIG_CONTEXT, &signed));
+        }
+        transcript.extend_from_slice(&finish.authenticated_bytes());
+        finish.verify_data = verify_data(&hs_auth_key, &transcript);
+
+        let header_protection = header_protection.then(|| hp_keys(&master_secret, true));
+        let (outbound, inbound) = traffic_keys(master_secret, true);
+        let keys = SessionKeys {
+            session_id: hello.session_id,
+            version: hello.version,
+            outbound,
+            inbound,
+            peer_identity,
+            header_protection,
+        };
+        Ok((keys, finish.to_bytes()))
+    }
+}
+
+/// Responder side: SERVER_HELLO sent, waiting for CLIENT_FINISH
+pub struct Responder {
+    config: HandshakeConfig,
+    version: u8,
+    ephemeral: HybridHandshake,
+    session_id: u64,
+    header_protection: bool,
+    client_identity: Option<Vec<u8>>,
+    transcript: Vec<u8>,
+    server_hello: Vec<u8>,
+}
+
+impl Responder {
+    /// Answer a CLIENT_HELLO using the unused ephemeral keys `ephemeral`
+    ///
+    /// Send `server_hello()` back to the initiator.
+    pub fn accept(
+        config: &HandshakeConfig,
+        client_hello: &[u8],
+        session_id: u64,
+        ephemeral: HybridHandshake,
+    ) -> Result<Self, HandshakeError> {
+        let hello = match decode(client_hello)? {
+            HandshakeMessage::ClientHello(hello) => hello,
+            _ => return Err(HandshakeError::UnexpectedMessage),
+        };
+        let offered = hello
+            .offered_versions()
+            .map_err(|e| HandshakeError::Malformed(e.to_string()))?;
+        let version = negotiate_version(&offered, SUPPORTED_VERSIONS).ok_or_else(|| {
+            HandshakeError::UnsupportedVersion(offered.iter().copied().max().unwrap_or(0))
+        })?;
+        if !hello
+            .cipher_suites
+            .contains(&CIPHER_SUITE_CHACHA20_POLY1305)
+        {
+            return Err(HandshakeError::NoCommonCipherSuite);
+        }
+
+        let header_protection =
+            config.header_protection && hello.extension(EXT_HEADER_PROTECTION).is_some();
+        let mut extensions = identity_extension(config);
+        if header_protection {
+            extensions.push(KnownExtension::HeaderProtection.to_extension());
+        }
+
+        let mut random = [0u8; 32];
+        OsRng.fill_bytes(&mut random);
+        let mut server_hello = ServerHello {
+            version,
+            random,
+            cipher_suite: CIPHER_SUITE_CHACHA20_POLY1305,
+            session_id,
+            kem_public_key: ephemeral.kyber_public_key_bytes().to_vec(),
+            x25519_public_key: PublicKey::from(ephemeral.x25519_secret()).to_bytes(),
+            extensions,
+            signature: None,
+        };
+        if let Some(identity) = &config.identity {
+            let signed = [client_hello, &server_hello.signed_bytes()].concat();
+            server_hello.signature = Some(sign(identity, SERVER_SIG_CONTEXT, &signed));
+        }
+        let server_hello = server_hello.to_bytes();
+
+        Ok(Self {
+            config: config.clone(),
+            version,
+            ephemeral,
+            session_id,
+            header_protection,
+            client_identity: hello.extension(EXT_IDENTITY).map(<[u8]>::to_vec),
+            transcript: [client_hello, &server_hello].concat(),
+            server_hello,
+        })
+    }
+
+    /// Encoded SERVER_HELLO (resent for a retransmitted CLIENT_HELLO)
+    pub fn server_hello(&self) -> &[u8] {
+        &self.server_hello
+    }
+
+    /// Session ID assigned in SERVER_HELLO
+    pub fn session_id(&self) -> u64 {
+        self.session_id
+    }
+
+    /// Verify CLIENT_FINISH and derive the session keys
+    pub fn finish(&self, client_finish: &[u8]) -> Result<SessionKeys, HandshakeError> {
+        let finish = match decode(client_finish)? {
+            HandshakeMessage::ClientFinish(finish) => finish,
+            _ => return Err(HandshakeError::UnexpectedMessage),
+        };
+        if finish.session_id != self.session_id {
+            return Err(HandshakeError::UnexpectedMessage);
+        }
+
+        let mut ss_kem = self
+            .ephemeral
+            .decapsulate(&finish.kem_ciphertext)
+            .ok_or(HandshakeError::KemFailure)?;
+        let mut ss_x = *self
+            .ephemeral
+            .x25519_secret()
+            .diffie_hellman(&PublicKey::from(finish.x25519_public_key))
+            .as_bytes();
+        let (hs_auth_key, master_secret) = derive_handshake_keys(&ss_kem, &ss_x);
+        ss_kem.zeroize();
+        ss_x.zeroize();
+
+        let transcript = [self.transcript.as_slice(), &finish.authenticated_bytes()].concat();
+        let key = hmac::Key::new(hmac::HMAC_SHA256, &hs_auth_key);
+        hmac::verify(&key, &transcript, &finish.verify_data)
+            .map_err(|_| HandshakeError::VerifyFailed)?;
+
+        let signed = [self.transcript.as_slice(), &finish.signed_bytes()].concat();
+        let peer_identity = check_identity(
+            &self.config,
+            self.client_identity.as_deref(),
+            finish.signature.as_ref(),
+            CLIENT_SIG_CONTEXT,
+            &signed,
+        )?;
+
+        let header_protection = self
+            .header_protection
+            .then(|| hp_keys(&master_secret, false));
+        let (outbound, inbound) = traffic_keys(master_secret, false);
+        Ok(SessionKeys {
+            session_id: self.session_id,
+            version: self.version,
+            outbound,
+            inbound,
+            peer_identity,
+            header_protection,
+        })
+    }
+}
+
+fn decode(buf: &[u8]) -> Result<HandshakeMessage, HandshakeError> {
+    HandshakeMessage::from_bytes(buf).map_err(|e| HandshakeError::Malformed(e.to_string()))
+}
+
+fn identity_extension(config: &HandshakeConfig) -> Vec<Extension> {
+    config
+        .identity
+        .iter()
+        .map(|key| Extension {
+            ext_type: EXT_IDENTITY,
+            value: key.verifying_key().to_bytes().to_vec(),
+        })
+        .collect()
+}
+
+fn sign(identity: &SigningKey, context: &[u8], transcript: &[u8]) -> [u8; 64] {
+    identity
+        .sign(transcript_hash(context, transcript).as_ref())
+        .to_bytes()
+}
+
+fn transcript_hash(context: &[u8], transcript: &[u8]) -> digest::Digest {
+    let mut ctx = digest::Context::new(&digest::SHA256);
+    ctx.update(context);
+    ctx.update(transcript);
+    ctx.finish()
+}
+
+/// Verify the peer's identity proof against local trust settings
+///
+/// A peer that presents an identity must sign the transcript with it; with
+/// `trusted_peers` set, the identity must also be on the list.
+fn check_identity(
+    config: &HandshakeConfig,
+    identity: Option<&[u8]>,
+    signature: Option<&[u8; 64]>,
+    context: &[u8],
+    transcript: &[u8],
+) -> Result<Option<[u8; 32]>, HandshakeError> {
+    let identity = match (identity, signature) {
+        (Some(identity), Some(signature)) => {
+            let identity: [u8; 32] = identity
+                .try_into()
+                .map_err(|_| HandshakeError::Malformed("identity length".to_string()))?;
+            let key =
+                VerifyingKey::from_bytes(&identity).map_err(|_| HandshakeError::UntrustedPeer)?;
+            key.verify(
+                transcript_hash(context, transcript).as_ref(),
+                &Signature::from_bytes(signature),
+            )
+            .map_err(|_| HandshakeError::VerifyFailed)?;
+            Some(identity)
+        }
+        (None, None) => None,
+        _ => return Err(HandshakeError::VerifyFailed),
+    };
+
+    match (&config.trusted_peers, identity) {
+        (None, _) => Ok(identity),
+        (Some(trusted), Some(identity)) if trusted.contains(&identity) => Ok(Some(identity)),
+        (Some(_), _) => Err(HandshakeError::UntrustedPeer),
+    }
+}
+
+fn verify_data(hs_auth_key: &[u8; 32], transcript: &[u8]) -> [u8; 32] {
+    let key = hmac::Key::new(hmac::HMAC_SHA256, hs_auth_key);
+    let mut out = [0u8; 32];
+    out.copy_from_slice(hmac::sign(&key, transcript).as_ref());
+    out
+}
+
+/// Header protection keys for our role
+fn hp_keys(master_secret: &[u8; 32], initiator: bool) -> HeaderProtectionKeys {
+    let (hp_ir, hp_ri) = derive_header_protection_keys(master_secret);
+    if initiator {
+        HeaderProtectionKeys {
+            outbound: hp_ir,
+            inbound: hp_ri,
+        }
+    } else {
+        HeaderProtectionKeys {
+            outbound: hp_ri,
+            inbound: hp_ir,
+        }
+    }
+}
+
+/// Epoch 0 traffic keys as (outbound, inbound) for our role
+fn traffic_keys(mut master_secret: [u8; 32], initiator: bool) -> (DirectionKeys, DirectionKeys) {
+    let (mut key_ir, mut iv_ir, mut key_ri, mut iv_ri) =
+        derive_epoch_keys(&master_secret, 0, 32, 12);
+    master_secret.zeroize();
+    let to_keys = |key: &[u8], iv: &[u8]| {
+        let mut keys = DirectionKeys {
+            key: [0; 32],
+            iv: [0; 12],
+        };
+        keys.key.copy_from_slice(key);
+        keys.iv.copy_from_slice(iv);
+        keys
+    };
+    let ir = to_keys(&key_ir, &iv_ir);
+    let ri = to_keys(&key_ri, &iv_ri);
+    for secret in [&mut key_ir, &mut iv_ir, &mut key_ri, &mut iv_ri] {
+        secret.zeroize();
+    }
+    if initiator {
+        (ir, ri)
+    } else {
+        (ri, ir)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cryprq_core::PROTOCOL_VERSION;
+
+    fn identity(seed: u8) -> SigningKey {
+        SigningKey::from_bytes(&[seed; 32])
+    }
+
+    fn run(
+        client: &HandshakeConfig,
+        server: &HandshakeConfig,
+    ) -> Result<(SessionKeys, SessionKeys), HandshakeError> {
+        let initiator = Initiator::new(client);
+        let responder =
+            Responder::accept(server, initiator.client_hello(), 77, HybridHandshake::new())?;
+        let (client_keys, finish) = initiator.finish(responder.server_hello())?;
+        let server_keys = responder.finish(&finish)?;
+        Ok((client_keys, server_keys))
+    }
+
+    #[test]
+    fn test_handshake_derives_matching_keys() {
+        let (client, server) = run(&HandshakeConfig::default(), &HandshakeConfig::default())
+            .expect("handshake in test");
+        assert_eq!(client.session_id, 77);
+        assert_eq!(server.session_id, 77);
+        assert_eq!(client.outbound.key, server.inbound.key);
+        assert_eq!(client.inbound.key, server.outbound.key);
+        assert_ne!(client.outbound.key, client.inbound.key);
+        assert_eq!(client.peer_identity, None);
+    }
+
+    #[test]
+    fn test_tampered_finish_rejected() {
+        let initiator = Initiator::new(&HandshakeConfig::default());
+        let responder = Responder::accept(
+            &HandshakeConfig::default(),
+            initiator.client_hello(),
+            1,
+            HybridHandshake::new(),
+        )
+        .expect("accept in test");
+        let (_, mut finish) = initiator
+            .finish(responder.server_hello())
+            .expect("finish in test");
+        let last = finish.len() - 1;
+        finish[last] ^= 1;
+        assert!(matches!(
+            responder.finish(&finish),
+            Err(HandshakeError::VerifyFailed)
+        ));
+    }
+
+    #[test]
+    fn test_identities_checked_against_trust_list() {
+        let client_id = identity(1);
+        let server_id = identity(2);
+        let client = HandshakeConfig {
+            identity: Some(client_id.clone()),
+            trusted_peers: Some(vec![server_id.verifying_key().to_bytes()]),
+            ..HandshakeConfig::default()
+        };
+        let server = HandshakeConfig {
+            identity: Some(server_id.clone()),
+            trusted_peers: Some(vec![client_id.verifying_key().to_bytes()]),
+            ..HandshakeConfig::default()
+        };
+        let (client_keys, server_keys) = run(&client, &server).expect("handshake in test");
+        assert_eq!(
+            client_keys.peer_identity,
+            Some(server_id.verifying_key().to_bytes())
+        );
+        assert_eq!(
+            server_keys.peer_identity,
+            Some(client_id.verifying_key().to_bytes())
+        );
+
+        // Anonymous or unknown clients are refused
+        let stranger = HandshakeConfig {
+            identity: Some(identity(3)),
+            ..HandshakeConfig::default()
+        };
+        assert!(matches!(
+            run(&stranger, &server),
+            Err(HandshakeError::UntrustedPeer)
+        ));
+        assert!(matches!(
+            run(&HandshakeConfig::default(), &server),
+            Err(HandshakeError::UntrustedPeer)
+        ));
+    }
+
+    #[test]
+    fn test_header_protection_negotiated() {
+        let enabled = HandshakeConfig {
+            header_protection: true,
+            ..HandshakeConfig::default()
+        };
+        let (client, server) = run(&enabled, &enabled).expect("handshake in test");
+        let client_hp = client.header_protection.expect("client hp keys in test");
+        let server_hp = server.header_protection.expect("server hp keys in test");
+        assert_eq!(client_hp.outbound, server_hp.inbound);
+        assert_eq!(client_hp.inbound, server_hp.outbound);
+        assert_ne!(client_hp.outbound, client.outbound.key);
+
+        // Both sides must want it
+        for (client_config, server_config) in [
+            (&enabled, &HandshakeConfig::default()),
+            (&HandshakeConfig::default(), &enabled),
+        ] {
+            let (client, server) = run(client_config, server_config).expect("handshake in test");
+            assert!(client.header_protection.is_none());
+            assert!(server.header_protection.is_none());
+        }
+
+        // Stripping the offer is caught by the transcript MAC
+        let initiator = Initiator::new(&enabled);
+        let hello = match decode(initiator.client_hello()).expect("decode in test") {
+            HandshakeMessage::ClientHello(mut hello) => {
+                hello
+                    .extensions
+                    .retain(|ext| ext.ext_type != EXT_HEADER_PROTECTION);
+                HandshakeMessage::ClientHello(hello).to_bytes()
+            }
+            _ => Vec::new(),
+        };
+        let responder =
+            Responder::accept(&enabled, &hello, 1, HybridHandshake::new()).expect("accept in test");
+        let (_, finish) = initiator
+            .finish(responder.server_hello())
+            .expect("finish in test");
+        assert!(matches!(
+            responder.finish(&finish),
+            Err(HandshakeError::VerifyFailed)
+        ));
+    }
+
+    fn hello_offering(version: u8, offered: Option<Vec<u8>>) -> Vec<u8> {
+        let hello = ClientHello {
+            version,
+            random: [3; 32],
+            cipher_suites: vec![CIPHER_SUITE_CHACHA20_POLY1305],
+            extensions: offered
+                .map(|versions| vec![KnownExtension::SupportedVersions(versions).to_extension()])
+                .unwrap_or_default(),
+        };
+        HandshakeMessage::ClientHello(hello).to_bytes()
+    }
+
+    #[test]
+    fn test_unsupported_version_rejected() {
+        let accept = |hello: &[u8]| {
+            Responder::accept(
+                &HandshakeConfig::default(),
+                hello,
+                1,
+                HybridHandshake::new(),
+            )
+        };
+        assert!(matches!(
+            accept(&hello_offering(0x02, None)),
+            Err(HandshakeError::UnsupportedVersion(2))
+        ));
+        assert!(matches!(
+            accept(&hello_offering(0x01, Some(vec![0x09]))),
+            Err(HandshakeError::UnsupportedVersion(9))
+        ));
+        assert!(matches!(
+            accept(&hello_offering(0x01, Some(vec![]))),
+            Err(HandshakeError::Malformed(_))
+        ));
+    }
+
+    #[test]
+    fn test_version_negotiation() {
+        // The highest common version wins, whatever the header says
+        let responder = Responder::accept(
+            &HandshakeConfig::default(),
+            &hello_offering(0x01, Some(vec![0x01, 0x09])),
+            1,
+            HybridHandshake::new(),
+        )
+        .expect("accept in test");
+        assert_eq!(responder.server_hello()[1], PROTOCOL_VERSION);
+
+        let (client, server) = run(&HandshakeConfig::default(), &HandshakeConfig::default())
+            .expect("handshake in test");
+        assert_eq!(client.version, PROTOCOL_VERSION);
+        assert_eq!(server.version, PROTOCOL_VERSION);
+
+        // The offer is covered by the transcript, so tampering is caught
+        let initiator = Initiator::new(&HandshakeConfig::default());
+        let mut hello = initiator.client_hello().to_vec();
+        hello[1] = 0x09;
+        let responder = Responder::accept(
+            &HandshakeConfig::default(),
+            &hello,
+            1,
+            HybridHandshake::new(),
+        )
+        .expect("accept in test");
+        let (_, finish) = initiator
+            .finish(responder.server_hello())
+            .expect("finish in test");
+        assert!(matches!(
+            responder.finish(&finish),
+            Err(HandshakeError::VerifyFailed)
+        ));
+    }
+}
@@ -0,0 +1,85 @@
+// Copyright (c) 2025 Thor Thor
+// Author: Thor Thor (GitHub: https://github.com/codethor0)
+// Contact: codethor@gmail.com
+// LinkedIn: https://www.linkedin.com/in/thor-thor0
+// License: MIT (see LICENSE file for details)
+
+use std::sync::Mutex;
+
+use cryprq_crypto::HybridHandshake;
+use tokio::sync::Notify;
+
+/// Pre-generated ephemeral ML-KEM/X25519 key pairs for the responder
+///
+/// Key generation is the expensive part of answering a CLIENT_HELLO. The
+/// server hands out pooled keys and refills the pool off the receive path.
+/// Every key is used for exactly one handshake.
+pub(crate) struct KemPool {
+    keys: Mutex<Vec<HybridHandshake>>,
+    capacity: usize,
+    refill_needed: Notify,
+}
+
+impl KemPool {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            keys: Mutex::new(Vec::with_capacity(capacity)),
+            capacity,
+            refill_needed: Notify::new(),
+        }
+    }
+
+    /// Take a key pair, generating one inline if the pool is empty
+    pub(crate) fn take(&self) -> HybridHandshake {
+        let pooled = self.keys.lock().ok().and_then(|mut keys| keys.pop());
+        self.refill_needed.notify_one();
+        pooled.unwrap_or_else(|| {
+            log::debug!("event=kem_pool_empty");
+            HybridHandshake::new()
+        })
+    }
+
+    /// Number of pooled key pairs
+    pub(crate) fn len(&self) -> usize {
+        self.keys.lock().map(|keys| keys.len()).unwrap_or(0)
+    }
+
+    /// Generate key pairs until the pool is full (blocking; run off the reactor)
+    pub(crate) fn refill(&self) {
+        while self.len() < self.capacity {
+            let key = HybridHandshake::new();
+            match self.keys.lock() {
+                Ok(mut keys) if keys.len() < self.capacity => keys.push(key),
+                _ => break,
+            }
+        }
+    }
+
+    /// Wait until a key has been taken
+    pub(crate) async fn wait_for_refill(&self) {
+        self.refill_needed.notified().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pool_refills_to_capacity() {
+        let pool = KemPool::new(3);
+        assert_eq!(pool.len(), 0);
+
+        // An empty pool still hands out fresh keys
+        let first = pool.take();
+        pool.refill();
+        assert_eq!(pool.len(), 3);
+
+        let second = pool.take();
+        assert_eq!(pool.len(), 2);
+        assert_ne!(
+            first.kyber_public_key_bytes(),
+            second.kyber_public_key_bytes()
+        );
+    }
+}
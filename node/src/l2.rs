@@ -0,0 +1,370 @@
+// Copyright (c) 2025 Thor Thor
+// Author: Thor Thor (GitHub: https://github.com/codethor0)
+// Contact: codethor@gmail.com
+// LinkedIn: https://www.linkedin.com/in/thor-thor0
+// License: MIT (see LICENSE file for details)
+
+use anyhow::Result;
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::hash::Hash;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+
+use crate::filter::{drop_packet, Direction};
+use crate::stats::DropReason;
+use crate::tun::PacketForwarder;
+use cryprq_core::MSG_TYPE_ETHERNET_FRAME;
+
+/// Ethernet header length (destination MAC, source MAC, EtherType)
+pub const ETHERNET_HEADER_LEN: usize = 14;
+/// Room for one 802.1Q VLAN tag on top of the MTU
+pub(crate) const VLAN_TAG_LEN: usize = 4;
+/// Learned MAC addresses are forgotten after this long without traffic
+const MAC_AGING: Duration = Duration::from_secs(300);
+
+type MacAddr = [u8; 6];
+
+/// Frame an Ethernet frame for a transport without record headers
+///
+/// The frame is prefixed with `MSG_TYPE_ETHERNET_FRAME` so it can't be
+/// mistaken for an IP packet or a control frame.
+pub fn encode_frame(frame: &[u8]) -> Vec<u8> {
+    let mut encoded = Vec::with_capacity(frame.len() + 1);
+    encoded.push(MSG_TYPE_ETHERNET_FRAME);
+    encoded.extend_from_slice(frame);
+    encoded
+}
+
+/// Strip the framing added by `encode_frame`
+pub fn decode_frame(encoded: &[u8]) -> Option<&[u8]> {
+    match encoded.split_first() {
+        Some((&MSG_TYPE_ETHERNET_FRAME, frame)) => Some(frame),
+        _ => None,
+    }
+}
+
+fn dst_mac(frame: &[u8]) -> MacAddr {
+    let mut mac = [0u8; 6];
+    mac.copy_from_slice(&frame[0..6]);
+    mac
+}
+
+fn src_mac(frame: &[u8]) -> MacAddr {
+    let mut mac = [0u8; 6];
+    mac.copy_from_slice(&frame[6..12]);
+    mac
+}
+
+/// Group bit set: broadcast or multicast
+fn is_group(mac: &MacAddr) -> bool {
+    mac[0] & 0x01 != 0
+}
+
+fn format_mac(mac: &MacAddr) -> String {
+    mac.iter()
+        .map(|b| format!("{:02x}", b))
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+/// Learning Ethernet switch shared by all peers of a TAP interface
+///
+/// Source MAC addresses seen on frames from a peer are learned so later
+/// frames for them go only to that peer. Broadcast, multicast and unknown
+/// destinations are flooded. Frames from peers reach the TAP writer and,
+/// when addressed elsewhere, the other peers, so every site shares one
+/// layer 2 segment.
+pub struct L2Switch<K> {
+    mac_table: RwLock<HashMap<MacAddr, (K, Instant)>>,
+    /// Outbound frame channel per connected peer (frames already encoded)
+    peers: RwLock<HashMap<K, UnboundedSender<Vec<u8>>>>,
+    tap_tx: UnboundedSender<Vec<u8>>,
+    /// Largest accepted frame: MTU + Ethernet header + one VLAN tag
+    max_frame: usize,
+}
+
+impl<K> L2Switch<K>
+where
+    K: Clone + Eq + Hash + Display,
+{
+    /// Register a connected peer and its outbound channel
+    pub fn add_peer(&self, peer: K, tx: UnboundedSender<Vec<u8>>) {
+        if let Ok(mut peers) = self.peers.write() {
+            peers.insert(peer, tx);
+        }
+    }
+
+    /// Remove a disconnected peer and forget the MACs learned from it
+    pub fn remove_peer(&self, peer: &K) {
+        if let Ok(mut peers) = self.peers.write() {
+            peers.remove(peer);
+        }
+        if let Ok(mut table) = self.mac_table.write() {
+            table.retain(|_, (owner, _)| owner != peer);
+        }
+    }
+
+    /// Number of connected peers
+    pub fn peer_count(&self) -> usize {
+        self.peers.read().map(|p| p.len()).unwrap_or(0)
+    }
+
+    /// Peer a MAC address was learned from, if still fresh
+    pub fn lookup(&self, mac: &MacAddr) -> Option<K> {
+        let table = self.mac_table.read().ok()?;
+        table
+            .get(mac)
+            .filter(|(_, seen)| seen.elapsed() < MAC_AGING)
+            .map(|(peer, _)| peer.clone())
+    }
+
+    fn learn(&self, mac: MacAddr, peer: &K) {
+        if is_group(&mac) {
+            return;
+        }
+        if let Ok(mut table) = self.mac_table.write() {
+            let moved = table.get(&mac).is_some_and(|(owner, _)| owner != peer);
+            if moved || !table.contains_key(&mac) {
+                log::debug!(
+                    "event=l2_mac_learned mac={} peer={}",
+                    format_mac(&mac),
+                    peer
+                );
+            }
+            table.insert(mac, (peer.clone(), Instant::now()));
+        }
+    }
+
+    fn check_frame(&self, frame: &[u8]) -> Result<(), DropReason> {
+        if frame.len() < ETHERNET_HEADER_LEN {
+            return Err(DropReason::Malformed);
+        }
+        if frame.len() > self.max_frame {
+            return Err(DropReason::Oversized);
+        }
+        Ok(())
+    }
+
+    /// Send a frame to every peer except `except`
+    fn flood(&self, frame: &[u8], except: Option<&K>) {
+        if let Ok(peers) = self.peers.read() {
+            for (peer, tx) in peers.iter() {
+                if Some(peer) != except {
+                    let _ = tx.send(encode_frame(frame));
+                }
+            }
+        }
+    }
+
+    fn send_to(&self, peer: &K, frame: &[u8]) -> bool {
+        self.peers
+            .read()
+            .ok()
+            .and_then(|peers| {
+                peers
+                    .get(peer)
+                    .map(|tx| tx.send(encode_frame(frame)).is_ok())
+            })
+            .unwrap_or(false)
+    }
+
+    /// Forward a frame read from the TAP interface
+    pub fn route(&self, frame: &[u8]) -> Result<()> {
+        if let Err(reason) = self.check_frame(frame) {
+            drop_packet(reason, Direction::Outbound, frame.len());
+            return Ok(());
+        }
+        let dst = dst_mac(frame);
+        match self.lookup(&dst) {
+            Some(peer) if !is_group(&dst) => {
+                if !self.send_to(&peer, frame) {
+                    // Peer went away since it was learned: fall back to flooding
+                    self.flood(frame, None);
+                }
+            }
+            _ => self.flood(frame, None),
+        }
+        Ok(())
+    }
+
+    /// Switch a frame received from `peer`
+    pub fn deliver(&self, peer: &K, frame: &[u8]) {
+        if let Err(reason) = self.check_frame(frame) {
+            drop_packet(reason, Direction::Inbound, frame.len());
+            return;
+        }
+        self.learn(src_mac(frame), peer);
+
+        let dst = dst_mac(frame);
+        match self.lookup(&dst) {
+            // Destination sits behind the sender itself
+            Some(owner) if owner == *peer && !is_group(&dst) => {}
+            Some(owner) if !is_group(&dst) && self.send_to(&owner, frame) => {}
+            _ => {
+                let _ = self.tap_tx.send(frame.to_vec());
+                self.flood(frame, Some(peer));
+            }
+        }
+    }
+}
+
+impl<K> L2Switch<K>
+where
+    K: Clone + Eq + Hash + Display + Send + Sync + 'static,
+{
+    /// Sender used by a peer to deliver encoded frames
+    ///
+    /// Frames without the Ethernet framing byte are dropped.
+    pub fn incoming_for(self: &Arc<Self>, peer: K) -> UnboundedSender<Vec<u8>> {
+        let (tx, mut rx) = unbounded_channel::<Vec<u8>>();
+        let switch = self.clone();
+        tokio::spawn(async move {
+            while let Some(encoded) = rx.recv().await {
+                match decode_frame(&encoded) {
+                    Some(frame) => switch.deliver(&peer, frame),
+                    None => drop_packet(DropReason::Malformed, Direction::Inbound, encoded.len()),
+                }
+            }
+        });
+        tx
+    }
+}
+
+/// Packet forwarder serving one TAP interface for many peers
+pub struct TapForwarder<K> {
+    switch: Arc<L2Switch<K>>,
+    tap_rx: UnboundedReceiver<Vec<u8>>,
+}
+
+impl<K> TapForwarder<K>
+where
+    K: Clone + Eq + Hash + Display,
+{
+    /// Create a forwarder for a TAP interface with the given MTU
+    pub fn new(mtu: u16) -> Self {
+        let (tap_tx, tap_rx) = unbounded_channel();
+        Self {
+            switch: Arc::new(L2Switch {
+                mac_table: RwLock::new(HashMap::new()),
+                peers: RwLock::new(HashMap::new()),
+                tap_tx,
+                max_frame: mtu as usize + ETHERNET_HEADER_LEN + VLAN_TAG_LEN,
+            }),
+            tap_rx,
+        }
+    }
+
+    /// Switch handle for adding peers while forwarding runs
+    pub fn switch(&self) -> Arc<L2Switch<K>> {
+        self.switch.clone()
+    }
+}
+
+#[async_trait]
+impl<K> PacketForwarder for TapForwarder<K>
+where
+    K: Clone + Eq + Hash + Display + Send + Sync,
+{
+    async fn send_packet(&self, packet: &[u8]) -> Result<()> {
+        self.switch.route(packet)
+    }
+
+    async fn recv_packet(&mut self) -> Result<Vec<u8>> {
+        // Use timeout so the TAP read side can take the forwarder lock
+        match tokio::time::timeout(tokio::time::Duration::from_millis(100), self.tap_rx.recv())
+            .await
+        {
+            Ok(Some(frame)) => Ok(frame),
+            Ok(None) => Err(anyhow::anyhow!("Channel closed")),
+            Err(_) => Err(anyhow::anyhow!("Timeout waiting for frame")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BROADCAST: MacAddr = [0xff; 6];
+
+    fn frame(dst: MacAddr, src: MacAddr, len: usize) -> Vec<u8> {
+        let mut frame = vec![0u8; len];
+        frame[0..6].copy_from_slice(&dst);
+        frame[6..12].copy_from_slice(&src);
+        frame[12..14].copy_from_slice(&0x0806u16.to_be_bytes());
+        frame
+    }
+
+    fn mac(last: u8) -> MacAddr {
+        [0x02, 0, 0, 0, 0, last]
+    }
+
+    #[tokio::test]
+    async fn test_learns_macs_and_switches_between_peers() {
+        let mut forwarder: TapForwarder<String> = TapForwarder::new(1500);
+        let switch = forwarder.switch();
+        let (tx_a, mut rx_a) = unbounded_channel();
+        let (tx_b, mut rx_b) = unbounded_channel();
+        switch.add_peer("a".to_string(), tx_a);
+        switch.add_peer("b".to_string(), tx_b);
+
+        // Broadcast from a reaches the TAP and b, not a
+        let arp = frame(BROADCAST, mac(1), 60);
+        switch.deliver(&"a".to_string(), &arp);
+        assert_eq!(forwarder.recv_packet().await.expect("tap frame"), arp);
+        assert_eq!(rx_b.try_recv().ok(), Some(encode_frame(&arp)));
+        assert!(rx_a.try_recv().is_err());
+        assert_eq!(switch.lookup(&mac(1)), Some("a".to_string()));
+
+        // Reply from b to a's MAC goes straight to a
+        let reply = frame(mac(1), mac(2), 60);
+        switch.deliver(&"b".to_string(), &reply);
+        assert_eq!(rx_a.try_recv().ok(), Some(encode_frame(&reply)));
+        assert!(rx_b.try_recv().is_err());
+
+        // TAP traffic for a learned MAC is unicast
+        forwarder
+            .send_packet(&frame(mac(2), mac(9), 60))
+            .await
+            .expect("route in test");
+        assert!(rx_b.try_recv().is_ok());
+        assert!(rx_a.try_recv().is_err());
+
+        switch.remove_peer(&"b".to_string());
+        assert_eq!(switch.lookup(&mac(2)), None);
+    }
+
+    #[tokio::test]
+    async fn test_frames_over_mtu_dropped() {
+        let forwarder: TapForwarder<String> = TapForwarder::new(1500);
+        let switch = forwarder.switch();
+        let (tx, mut rx) = unbounded_channel();
+        switch.add_peer("a".to_string(), tx);
+
+        let before = crate::stats::dropped_packets(DropReason::Oversized);
+        forwarder
+            .send_packet(&frame(BROADCAST, mac(1), 1500 + 18 + 1))
+            .await
+            .expect("route in test");
+        assert!(rx.try_recv().is_err());
+        assert!(crate::stats::dropped_packets(DropReason::Oversized) > before);
+
+        // A full-size VLAN-tagged frame still fits
+        forwarder
+            .send_packet(&frame(BROADCAST, mac(1), 1500 + 18))
+            .await
+            .expect("route in test");
+        assert!(rx.try_recv().is_ok());
+    }
+
+    #[test]
+    fn test_frame_encoding() {
+        let raw = frame(BROADCAST, mac(1), 60);
+        assert_eq!(decode_frame(&encode_frame(&raw)), Some(&raw[..]));
+        // IPv4 packets are not Ethernet frames
+        assert_eq!(decode_frame(&[0x45, 0, 0, 20]), None);
+    }
+}
@@ -4,6 +4,7 @@
 // LinkedIn: https://www.linkedin.com/in/thor-thor0
 // License: MIT (see LICENSE file for details)
 
+use arc_swap::ArcSwap;
 use bytes::BytesMut;
 use chacha20poly1305::{
     aead::{Aead, KeyInit, Payload},
@@ -13,40 +14,110 @@ use crossbeam::queue::ArrayQueue;
 use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
 use rand::rngs::OsRng as RandOsRng;
 use rand_core::OsRng;
-use std::sync::{Arc, RwLock};
+use std::borrow::Cow;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Arc, Mutex, RwLock, Weak};
 use std::time::{Duration, Instant};
 use tokio::net::UdpSocket;
 use tokio::time;
 use x25519_dalek::{EphemeralSecret, PublicKey};
 use zeroize::Zeroize;
 
+mod addr_pool;
+mod congestion;
+mod cookie;
+mod crypto_pool;
 mod crypto_utils;
+mod custom;
 mod dns;
 mod error;
+mod exit;
+mod exit_tcp;
 mod file_transfer;
+mod filter;
+mod fragment;
+mod handshake;
+mod kem_pool;
+mod l2;
+mod liveness;
+mod mesh;
+mod nat;
+mod obfs4;
+mod packet;
 mod padding;
+mod path;
+mod pmtu;
+mod rate_limit;
 mod record_layer;
+mod reliable;
 mod seq_counters;
+mod session;
+pub mod stats;
+mod stream;
+mod stream_transport;
 mod tls;
+mod tls_kx;
 mod traffic_shaping;
+mod transport;
 pub mod tun;
+mod udp_batch;
 
+pub use addr_pool::{
+    parse_ipv4_cidr, prefix_to_netmask, AddressPool, AddressPoolConfig, AddressPoolError,
+};
+pub use congestion::{NewReno, Pacer};
+use crypto_pool::crypto_pool;
 pub use crypto_utils::{make_nonce, Epoch};
+use custom::CustomHandlers;
+pub use custom::{CustomMessage, MessageHandler};
+pub use exit::ExitNode;
 pub use file_transfer::{FileMetadata, FileTransferManager};
-pub use record_layer::{alloc_stream_id, recv_record, send_record, DirectionKeys, VPN_STREAM_ID};
+pub use filter::{Direction, FilteredForwarder, Firewall, FirewallRule, PacketFilter, RuleAction};
+pub use handshake::{HandshakeConfig, HandshakeError, Initiator, Responder, SessionKeys};
+pub use l2::{decode_frame, encode_frame, L2Switch, TapForwarder, ETHERNET_HEADER_LEN};
+use liveness::{KeepaliveAction, Liveness};
+pub use liveness::{KeepaliveConfig, PeerStatus, RttStats};
+pub use mesh::{MeshForwarder, MeshRouter};
+pub use nat::{ConnTrack, FlowKey, NatConfig};
+pub use obfs4::{Obfs4ServerKey, Obfs4Transport};
+pub use packet::{IpHeader, IpPrefix};
+pub(crate) use rate_limit::RateLimiter;
+use rate_limit::SourceRateLimiter;
+pub use rate_limit::{BucketLimits, RateLimitConfig};
+use record_layer::TrafficKeys;
+pub use record_layer::{
+    alloc_stream_id, recv_record, send_record, DirectionKeys, CONTROL_STREAM_ID, VPN_STREAM_ID,
+};
 pub use seq_counters::SeqCounters;
+pub use session::{connect_session, Session, TunnelServer, TunnelServerConfig};
+use stream::StreamRegistry;
+pub use stream::{TunnelStream, MAX_STREAM_CHUNK};
+use stream_transport::{is_droppable, StreamFrame, StreamLink};
+pub use stream_transport::{StreamFallback, TransportKind};
 
 // Re-export RecordHeader for use in recv_record logging
 use cryprq_core::RecordHeader;
+use fragment::Reassembler;
+use path::PathValidator;
+pub use pmtu::tun_mtu_for_path;
+use pmtu::PathMtu;
+
+pub use cryprq_core::{AddressLease, ControlMessage, ErrorCode};
 
 // Re-export generate_handshake_auth for CLI use (function is already pub, no need to re-export)
 
 pub use dns::{resolve_hostname, DnsConfig, DnsError};
 pub use error::TunnelError;
+#[allow(deprecated)]
 pub use padding::{pad_packet, unpad_packet, PaddingConfig};
-pub use tls::{TlsClient, TlsConfig, TlsError, TlsServer, TlsStream};
-pub use traffic_shaping::TrafficShaper;
+pub use padding::{pad_plaintext, unpad_plaintext, PaddingPolicy};
+pub use tls::{TlsClient, TlsConfig, TlsError, TlsHandshake, TlsServer, TlsStream};
+use traffic_shaping::CoverQueue;
+pub use traffic_shaping::{CoverTrafficConfig, TrafficShaper};
+pub use transport::{DtlsTransport, PluggableTransport};
 pub use tun::{TunConfig, TunInterface};
+use udp_batch::UdpBatch;
 
 const MAX_NONCE_VALUE: u64 = u64::MAX - 1000; // Force rekey before overflow
 const REPLAY_WINDOW_SIZE: usize = 2048; // Track last 2048 nonces
@@ -97,58 +168,6 @@ impl Clone for BufferPool {
     }
 }
 
-/// Token bucket rate limiter
-///
-/// Implements a token bucket algorithm to limit incoming packet rate.
-/// Prevents DoS attacks by rejecting excessive traffic.
-pub(crate) struct RateLimiter {
-    /// Maximum tokens (burst capacity)
-    capacity: u32,
-    /// Current token count
-    tokens: f64,
-    /// Tokens added per second (steady rate)
-    refill_rate: f64,
-    /// Last refill timestamp
-    last_refill: Instant,
-}
-
-impl RateLimiter {
-    /// Create new rate limiter
-    ///
-    /// # Arguments
-    /// * `packets_per_second` - Sustained rate limit
-    /// * `burst_size` - Maximum burst capacity
-    fn new(packets_per_second: u32, burst_size: u32) -> Self {
-        Self {
-            capacity: burst_size,
-            tokens: burst_size as f64,
-            refill_rate: packets_per_second as f64,
-            last_refill: Instant::now(),
-        }
-    }
-
-    /// Check if packet should be accepted
-    /// Returns Ok(()) if within limit, Err if rate exceeded
-    fn check_and_consume(&mut self) -> Result<(), TunnelError> {
-        // Calculate tokens to add based on elapsed time
-        let now = Instant::now();
-        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
-
-        // Refill tokens based on elapsed time
-        self.tokens += elapsed * self.refill_rate;
-        self.tokens = self.tokens.min(self.capacity as f64);
-        self.last_refill = now;
-
-        // Check if we have tokens available
-        if self.tokens >= 1.0 {
-            self.tokens -= 1.0;
-            Ok(())
-        } else {
-            Err(TunnelError::RateLimitExceeded)
-        }
-    }
-}
-
 /// Anti-replay window using sliding bitmap
 ///
 /// Tracks recently seen nonces to detect and reject replay attacks.
@@ -302,7 +321,7 @@ pub fn generate_handshake_auth(peer_pk: &[u8; 32]) -> (SigningKey, [u8; 32], [u8
 /// - ChaCha20-Poly1305 AEAD encryption
 /// - Automatic nonce management
 /// - Replay attack protection
-/// - Rate limiting (1000 pps sustained, 2000 burst)
+/// - Rate limiting per source IP and for the authenticated peer, by packets and bytes
 /// - Buffer pooling for memory efficiency
 ///
 /// # Security
@@ -311,21 +330,53 @@ pub fn generate_handshake_auth(peer_pk: &[u8; 32]) -> (SigningKey, [u8; 32], [u8
 /// - Automatic key rotation every 5 minutes
 /// - Nonce overflow protection (rekey at u64::MAX - 1000)
 /// - Anti-replay window tracks 2048 recent nonces
+/// - Peer address only moves after an authenticated PATH_CHALLENGE/PATH_RESPONSE
+///
+/// Records larger than the path MTU are fragmented (Section 6.4); see
+/// `start_path_mtu_discovery`.
 pub struct Tunnel {
     socket: Arc<UdpSocket>,
     session_key: Arc<RwLock<[u8; 32]>>, // Legacy - will be replaced by DirectionKeys
     static_iv: Arc<RwLock<[u8; 12]>>,   // Legacy - now part of DirectionKeys
-    epoch: Arc<RwLock<Epoch>>,          // Current epoch (u8)
-    keys_outbound: Arc<RwLock<DirectionKeys>>, // Outbound encryption keys
-    keys_inbound: Arc<RwLock<DirectionKeys>>, // Inbound decryption keys
-    seq_counters: Arc<SeqCounters>,     // Per-message-type sequence counters
+    keys: Arc<ArcSwap<TrafficKeys>>,    // Epoch, traffic keys and sequence numbers
     peer_addr: Arc<RwLock<Option<std::net::SocketAddr>>>,
     nonce_counter: Arc<RwLock<u64>>, // Legacy - will be removed
     replay_window: Arc<RwLock<ReplayWindow>>,
-    rate_limiter: Arc<RwLock<RateLimiter>>,
+    source_limiter: Arc<RwLock<Arc<SourceRateLimiter>>>, // Per-source-IP buckets (pre-auth)
+    session_limiter: Arc<Mutex<RateLimiter>>,            // Authenticated peer bucket
     buffer_pool: BufferPool,
     tun_write_tx: Arc<RwLock<Option<tokio::sync::mpsc::UnboundedSender<Vec<u8>>>>>, // Channel to write VPN packets to TUN
+    tap_write_tx: RwLock<Option<TapWriter>>, // Set only for TAP sessions (ETHERNET_FRAME)
     file_transfer: Arc<FileTransferManager>, // File transfer manager
+    peer_identity: [u8; 32],                 // Peer identity key (address lease owner)
+    address_pool: Arc<RwLock<Option<Arc<Mutex<AddressPool>>>>>, // Listener-side address pool
+    address_lease: tokio::sync::watch::Sender<Option<AddressLease>>, // Lease granted by the listener
+    packet_filter: Arc<RwLock<Option<PacketFilter>>>, // AllowedIPs + firewall for VPN packets
+    path_validator: Arc<PathValidator>,               // New peer address under validation
+    streams: Arc<StreamRegistry>,                     // Application streams over DATA
+    path_mtu: Arc<PathMtu>,                           // Discovered path MTU (DPLPMTUD)
+    fragment_ids: AtomicU32,                          // Next fragmented record's message ID
+    reassembly: Mutex<Reassembler>,                   // Fragments of incoming records
+    liveness: Arc<Liveness>,                          // Keepalive, RTT and dead-peer state
+    closed: Arc<AtomicBool>,                          // Session ended; keys erased
+    master_secret: Arc<RwLock<[u8; 32]>>,             // Key rotation secret
+    peer_error: Mutex<Option<(ErrorCode, String)>>,   // Fatal ERROR that ended the session
+    custom_handlers: CustomHandlers,                  // Handlers for private-use message types
+    padding: RwLock<PaddingPolicy>,                   // Record padding inside the AEAD
+    cover: RwLock<Option<CoverQueue>>,                // Records waiting for a cover slot
+    transport: RwLock<Option<Arc<dyn PluggableTransport>>>, // Datagram obfuscation
+    stream_link: RwLock<Option<Arc<StreamLink>>>,     // TCP/TLS stream in place of UDP
+    stream_frames_tx: tokio::sync::mpsc::Sender<StreamFrame>, // Records read from streams
+    stream_frames_rx: tokio::sync::Mutex<tokio::sync::mpsc::Receiver<StreamFrame>>,
+    udp_batch: UdpBatch,                   // recvmmsg/sendmmsg with GSO/GRO
+    opened: Mutex<VecDeque<OpenedRecord>>, // Decrypted records awaiting replay check
+}
+
+/// Where a TAP session's incoming Ethernet frames go
+struct TapWriter {
+    tx: tokio::sync::mpsc::UnboundedSender<Vec<u8>>,
+    /// Largest accepted frame: MTU + Ethernet header + one VLAN tag
+    max_frame: usize,
 }
 
 impl Tunnel {
@@ -336,6 +387,22 @@ impl Tunnel {
         }
     }
 
+    /// Open the session in TAP mode: incoming ETHERNET_FRAME records go to
+    /// `tx`
+    ///
+    /// Frames larger than `mtu` plus the Ethernet header and one VLAN tag
+    /// are dropped as `oversized`. Sessions without a TAP writer drop
+    /// ETHERNET_FRAME records as `unexpected_type`, so a peer cannot slip
+    /// IP packets past the packet filter by labelling them as frames.
+    pub fn set_tap_writer(&self, tx: tokio::sync::mpsc::UnboundedSender<Vec<u8>>, mtu: u16) {
+        if let Ok(mut guard) = self.tap_write_tx.write() {
+            *guard = Some(TapWriter {
+                tx,
+                max_frame: mtu as usize + ETHERNET_HEADER_LEN + l2::VLAN_TAG_LEN,
+            });
+        }
+    }
+
     /// Get peer address (for setting peer address)
     pub fn peer_addr(&self) -> &Arc<RwLock<Option<std::net::SocketAddr>>> {
         &self.peer_addr
@@ -346,9 +413,452 @@ impl Tunnel {
         &self.file_transfer
     }
 
+    /// Set AllowedIPs/firewall filter applied to VPN packets in both directions
+    pub fn set_packet_filter(&self, filter: PacketFilter) {
+        if let Ok(mut guard) = self.packet_filter.write() {
+            *guard = Some(filter);
+        }
+    }
+
+    /// Pad outgoing records inside the AEAD (Section 6.5)
+    ///
+    /// Padded records are always accepted; this only affects what we send.
+    pub fn set_padding(&self, policy: PaddingPolicy) {
+        if let Ok(mut guard) = self.padding.write() {
+            *guard = policy;
+        }
+    }
+
+    /// Obfuscate every datagram with `transport` (Section 3.5)
+    ///
+    /// The peer must use the matching transport; datagrams it cannot
+    /// deobfuscate are dropped and counted as `transport`.
+    pub fn set_transport(&self, transport: Arc<dyn PluggableTransport>) {
+        if let Ok(mut guard) = self.transport.write() {
+            *guard = Some(transport);
+        }
+    }
+
+    fn transport(&self) -> Result<Option<Arc<dyn PluggableTransport>>, TunnelError> {
+        Ok(self
+            .transport
+            .read()
+            .map_err(|e| TunnelError::LockPoisoned(e.to_string()))?
+            .clone())
+    }
+
+    /// Send one datagram to `addr` through the transport, if one is set
+    ///
+    /// `droppable` marks records that a backed-up stream may drop (see
+    /// `StreamLink::send`); it is decided from the message type before
+    /// sealing, since the sealed bytes do not show it.
+    async fn send_datagram(
+        &self,
+        datagram: &[u8],
+        addr: std::net::SocketAddr,
+        droppable: bool,
+    ) -> Result<(), TunnelError> {
+        let obfuscated = match self.transport()? {
+            Some(transport) => Cow::Owned(
+                transport
+                    .obfuscate(addr, datagram)
+                    .map_err(|e| TunnelError::NetworkError(e.to_string()))?,
+            ),
+            None => Cow::Borrowed(datagram),
+        };
+        if let Some(link) = self.stream_link()? {
+            if link.peer() == addr {
+                return link.send(obfuscated.into_owned(), droppable).await;
+            }
+        }
+        self.socket
+            .send_to(&obfuscated, addr)
+            .await
+            .map_err(|e| TunnelError::NetworkError(e.to_string()))?;
+        Ok(())
+    }
+
+    /// Send datagrams to `addr` through the transport, batching the
+    /// syscalls where the OS allows
+    async fn send_datagrams(
+        &self,
+        datagrams: &[&[u8]],
+        addr: std::net::SocketAddr,
+        droppable: bool,
+    ) -> Result<(), TunnelError> {
+        if let [datagram] = datagrams {
+            return self.send_datagram(datagram, addr, droppable).await;
+        }
+        let obfuscated = match self.transport()? {
+            Some(transport) => datagrams
+                .iter()
+                .map(|datagram| {
+                    transport
+                        .obfuscate(addr, datagram)
+                        .map(Cow::Owned)
+                        .map_err(|e| TunnelError::NetworkError(e.to_string()))
+                })
+                .collect::<Result<Vec<_>, _>>()?,
+            None => datagrams.iter().map(|d| Cow::Borrowed(*d)).collect(),
+        };
+        if let Some(link) = self.stream_link()? {
+            if link.peer() == addr {
+                for frame in obfuscated {
+                    link.send(frame.into_owned(), droppable).await?;
+                }
+                return Ok(());
+            }
+        }
+        let obfuscated: Vec<&[u8]> = obfuscated.iter().map(|d| d.as_ref()).collect();
+        self.udp_batch
+            .send_to(&self.socket, &obfuscated, addr)
+            .await
+            .map_err(|e| TunnelError::NetworkError(e.to_string()))
+    }
+
+    /// Move datagrams with recvmmsg/sendmmsg and UDP GSO/GRO where the OS
+    /// supports them (the default); when off, each datagram takes its own
+    /// syscall
+    pub fn set_batch_io(&self, enabled: bool) {
+        self.udp_batch.set_enabled(&self.socket, enabled);
+    }
+
+    /// Replace the ingress rate limits (resets all buckets)
+    pub fn set_rate_limits(&self, config: RateLimitConfig) {
+        let limiter = SourceRateLimiter::new(config);
+        if let Ok(mut session) = self.session_limiter.lock() {
+            *session = limiter.session_limiter();
+        }
+        if let Ok(mut guard) = self.source_limiter.write() {
+            *guard = Arc::new(limiter);
+        }
+    }
+
+    /// Run the packet filter (if configured), counting drops
+    fn filter_vpn_packet(&self, direction: Direction, packet: &[u8]) -> bool {
+        let guard = match self.packet_filter.read() {
+            Ok(guard) => guard,
+            Err(_) => return false,
+        };
+        let verdict = match (guard.as_ref(), direction) {
+            (None, _) => Ok(()),
+            (Some(filter), Direction::Inbound) => filter.check_inbound(packet),
+            (Some(filter), Direction::Outbound) => filter.check_outbound(packet),
+        };
+        match verdict {
+            Ok(()) => true,
+            Err(reason) => {
+                filter::drop_packet(reason, direction, packet.len());
+                false
+            }
+        }
+    }
+
+    /// Get the local UDP socket address
+    pub fn local_addr(&self) -> Result<std::net::SocketAddr, TunnelError> {
+        Ok(self.socket.local_addr()?)
+    }
+
+    /// Set address pool used to answer address requests (listener side)
+    pub fn set_address_pool(&self, pool: Arc<Mutex<AddressPool>>) {
+        if let Ok(mut guard) = self.address_pool.write() {
+            *guard = Some(pool);
+        }
+    }
+
+    /// Get the address lease granted by the listener (dialer side)
+    pub fn address_lease(&self) -> Option<AddressLease> {
+        self.address_lease.borrow().clone()
+    }
+
+    /// Send a typed CONTROL message to peer
+    pub async fn send_control(&self, msg: &ControlMessage) -> Result<(), TunnelError> {
+        self.send_record(
+            CONTROL_STREAM_ID,
+            cryprq_core::MSG_TYPE_CONTROL,
+            0,
+            &msg.to_bytes(),
+        )
+        .await
+    }
+
+    /// Send a typed CONTROL message to a specific address
+    ///
+    /// Used for path validation, where the probe must go to the candidate
+    /// address rather than the current peer address.
+    async fn send_control_to(
+        &self,
+        addr: std::net::SocketAddr,
+        msg: &ControlMessage,
+    ) -> Result<(), TunnelError> {
+        // The candidate path's MTU is unknown: pad only up to the base PLPMTU
+        let padding = self.padding_policy()?;
+        let record_bytes = self.seal_padded(
+            CONTROL_STREAM_ID,
+            cryprq_core::MSG_TYPE_CONTROL,
+            0,
+            &msg.to_bytes(),
+            &padding,
+            pmtu::BASE_PLPMTU as usize - pmtu::RECORD_OVERHEAD,
+        )?;
+        self.send_datagram(&record_bytes, addr, false).await
+    }
+
+    /// Re-validate the path to the peer after a local network change
+    ///
+    /// Sends a PATH_CHALLENGE from the current socket. The peer sees the
+    /// record arrive from our new address, challenges it in turn and moves
+    /// its send target once we answer, so the session survives the switch.
+    pub async fn revalidate_path(&self) -> Result<(), TunnelError> {
+        let peer = *self
+            .peer_addr
+            .read()
+            .map_err(|e| TunnelError::LockPoisoned(e.to_string()))?;
+        let Some(peer) = peer else {
+            return Ok(());
+        };
+        let token = self.path_validator.challenge(peer)?;
+        log::info!("event=path_challenge peer={} reason=network_change", peer);
+        self.send_control_to(peer, &ControlMessage::PathChallenge(token))
+            .await
+    }
+
+    /// Track the peer address once a record from it has authenticated
+    async fn on_authenticated_record(
+        &self,
+        addr: std::net::SocketAddr,
+        msg_type: u8,
+        payload: &[u8],
+    ) -> Result<(), TunnelError> {
+        let replies =
+            self.path_validator
+                .on_authenticated(&self.peer_addr, addr, msg_type, payload)?;
+        for reply in replies {
+            self.send_control_to(addr, &reply).await?;
+        }
+        Ok(())
+    }
+
+    /// Request a tunnel address lease from the listener (dialer side)
+    ///
+    /// Sends ADDRESS_REQUEST (hinting the previous lease, if any) and waits for
+    /// the ADDRESS_LEASE reply, resending once per second until `timeout`.
+    /// Incoming records must be processed concurrently via `recv_and_handle_record`.
+    pub async fn request_address_lease(
+        &self,
+        timeout: Duration,
+    ) -> Result<AddressLease, TunnelError> {
+        let mut lease_rx = self.address_lease.subscribe();
+        let requested = lease_rx.borrow_and_update().as_ref().map(|l| l.address);
+        let deadline = Instant::now() + timeout;
+
+        loop {
+            self.send_control(&ControlMessage::AddressRequest { requested })
+                .await?;
+
+            let wait = deadline
+                .saturating_duration_since(Instant::now())
+                .min(Duration::from_secs(1));
+            if let Ok(Ok(())) = time::timeout(wait, lease_rx.changed()).await {
+                if let Some(lease) = lease_rx.borrow_and_update().clone() {
+                    return Ok(lease);
+                }
+            }
+
+            if Instant::now() >= deadline {
+                return Err(TunnelError::Timeout("address lease request".to_string()));
+            }
+        }
+    }
+
+    /// Give the current address lease back to the listener (dialer side)
+    pub async fn release_address_lease(&self) -> Result<(), TunnelError> {
+        if self.address_lease.send_replace(None).is_some() {
+            self.send_control(&ControlMessage::AddressRelease).await?;
+        }
+        Ok(())
+    }
+
+    /// Close the session gracefully (Section 8.3)
+    ///
+    /// Sends CLOSE, aborts every stream and erases the traffic keys. Sends
+    /// and receives fail with `TunnelError::SessionClosed` afterwards.
+    pub async fn close(&self) -> Result<(), TunnelError> {
+        if self.is_closed() {
+            return Ok(());
+        }
+        let sent = self
+            .send_control(&ControlMessage::Close { stream_id: None })
+            .await;
+        self.terminate("local_close", None);
+        sent
+    }
+
+    /// Report `err` to the peer in an ERROR message and close the session
+    ///
+    /// Errors without a wire code are local conditions; the peer gets a
+    /// plain CLOSE instead.
+    pub async fn close_with_error(&self, err: &TunnelError) -> Result<(), TunnelError> {
+        let Some(code) = err.error_code() else {
+            return self.close().await;
+        };
+        if self.is_closed() {
+            return Ok(());
+        }
+        let sent = self
+            .send_control(&ControlMessage::Error {
+                code,
+                reason: err.to_string(),
+            })
+            .await;
+        self.terminate("local_error", None);
+        sent
+    }
+
+    /// Whether the session was closed by either side
+    pub fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::Acquire)
+    }
+
+    /// End the session locally and erase its keys
+    fn terminate(&self, reason: &str, peer_error: Option<(ErrorCode, String)>) {
+        if let Ok(mut slot) = self.peer_error.lock() {
+            if slot.is_none() {
+                *slot = peer_error;
+            }
+        }
+        if self.closed.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        // The old keys are erased once the last record using them is
+        // sealed; key rotation only replaces the snapshot it derived from,
+        // so it cannot reinstate keys after this
+        let epoch = self.keys.load().epoch;
+        self.keys.store(Arc::new(TrafficKeys::erased(epoch)));
+        if let Ok(mut key) = self.session_key.write() {
+            key.zeroize();
+        }
+        if let Ok(mut iv) = self.static_iv.write() {
+            iv.zeroize();
+        }
+        if let Ok(mut secret) = self.master_secret.write() {
+            secret.zeroize();
+        }
+        if let Err(e) = self.streams.abort_all() {
+            log::warn!(
+                "event=session_closed status=stream_abort_failed error={}",
+                e
+            );
+        }
+        log::info!("event=session_closed reason={}", reason);
+    }
+
+    /// Error returned by sends and receives on a closed session
+    fn closed_error(&self) -> TunnelError {
+        match self.peer_error.lock().ok().and_then(|slot| slot.clone()) {
+            Some((code, reason)) => TunnelError::PeerError { code, reason },
+            None => TunnelError::SessionClosed,
+        }
+    }
+
+    /// Handle a typed CONTROL message
+    async fn handle_control_message(&self, msg: ControlMessage) -> Result<(), TunnelError> {
+        match msg {
+            ControlMessage::AddressRequest { requested } => {
+                let pool = self
+                    .address_pool
+                    .read()
+                    .map_err(|e| TunnelError::LockPoisoned(e.to_string()))?
+                    .clone();
+                let Some(pool) = pool else {
+                    log::debug!("Ignoring address request: no address pool configured");
+                    return Ok(());
+                };
+                let lease = pool
+                    .lock()
+                    .map_err(|e| TunnelError::LockPoisoned(e.to_string()))?
+                    .lease_for(&self.peer_identity, requested);
+                match lease {
+                    Ok(lease) => {
+                        self.send_control(&ControlMessage::AddressLease(lease))
+                            .await?
+                    }
+                    Err(e) => log::warn!("event=address_lease status=failed error={}", e),
+                }
+            }
+            ControlMessage::AddressLease(lease) => {
+                log::info!(
+                    "event=address_lease status=granted address={}/{} mtu={} lease_secs={}",
+                    lease.address,
+                    lease.prefix_len,
+                    lease.mtu,
+                    lease.lease_secs
+                );
+                self.address_lease.send_replace(Some(lease));
+            }
+            ControlMessage::PathChallenge(_) | ControlMessage::PathResponse(_) => {
+                // Handled in recv_record, which knows the source address
+            }
+            ControlMessage::Ping(id) => self.send_control(&ControlMessage::Pong(id)).await?,
+            ControlMessage::Pong(id) => {
+                if let Some(rtt) = self.liveness.on_pong(id, Instant::now())? {
+                    log::debug!("event=pong id={} rtt_us={}", id, rtt.as_micros());
+                }
+            }
+            ControlMessage::Keepalive => {
+                // Counted as a sign of life when it was received
+            }
+            ControlMessage::Close {
+                stream_id: Some(stream_id),
+            } => self.streams.abort(stream_id)?,
+            ControlMessage::Close { stream_id: None } => {
+                self.terminate("peer_close", None);
+                return Err(TunnelError::SessionClosed);
+            }
+            ControlMessage::Error { code, reason } => {
+                log::warn!("event=peer_error code={} reason={:?}", code, reason);
+                if code.is_fatal() {
+                    self.terminate("peer_error", Some((code, reason.clone())));
+                    return Err(TunnelError::PeerError { code, reason });
+                }
+            }
+            ControlMessage::PmtuProbe { probe_id, .. } => {
+                self.send_control(&ControlMessage::PmtuAck(probe_id))
+                    .await?
+            }
+            ControlMessage::PmtuAck(probe_id) => {
+                self.path_mtu
+                    .update(|discovery| discovery.on_ack(probe_id, Instant::now()))?;
+                self.path_mtu.acked.notify_one();
+            }
+            ControlMessage::StreamOpen { stream_id } => match self.streams.on_open(stream_id) {
+                Err(TunnelError::StreamClosed(_)) => self.refuse_stream(stream_id).await?,
+                result => result?,
+            },
+            ControlMessage::StreamClose { stream_id } => self.streams.on_close(stream_id)?,
+            ControlMessage::StreamAck(ack) => self.streams.on_ack(&ack)?,
+            ControlMessage::AddressRelease => {
+                let pool = self
+                    .address_pool
+                    .read()
+                    .map_err(|e| TunnelError::LockPoisoned(e.to_string()))?
+                    .clone();
+                if let Some(pool) = pool {
+                    pool.lock()
+                        .map_err(|e| TunnelError::LockPoisoned(e.to_string()))?
+                        .release(&self.peer_identity);
+                }
+            }
+        }
+        Ok(())
+    }
+
     /// Send a CrypRQ record to peer
     ///
     /// Wraps payload in a CrypRQ record with proper header, encryption, and sequence numbering.
+    /// A payload that would not fit the path MTU is sent as `FLAG_FRAGMENT`
+    /// records, each sealed on its own. While cover traffic runs, records
+    /// wait for the next cover slot instead (see `start_cover_traffic`).
     pub async fn send_record(
         &self,
         stream_id: u32,
@@ -356,153 +866,600 @@ impl Tunnel {
         flags: u8,
         payload: &[u8],
     ) -> Result<(), TunnelError> {
-        // Get epoch and keys
-        let epoch = *self
-            .epoch
+        if let Some(cover) = self.cover_queue()? {
+            return self
+                .queue_cover_record(&cover, stream_id, message_type, flags, payload)
+                .await;
+        }
+        let padding = self.padding_policy()?;
+        let limit = self.path_mtu.get() as usize - pmtu::RECORD_OVERHEAD;
+        let records =
+            self.seal_records(stream_id, message_type, flags, payload, &padding, limit)?;
+
+        self.send_sealed_batch(&records, is_droppable(message_type))
+            .await
+    }
+
+    /// Send an already sealed record to the peer address, if one is known
+    pub(crate) async fn send_sealed(
+        &self,
+        record_bytes: &[u8],
+        droppable: bool,
+    ) -> Result<(), TunnelError> {
+        let peer_addr = *self
+            .peer_addr
             .read()
             .map_err(|e| TunnelError::LockPoisoned(e.to_string()))?;
-        let keys = self
-            .keys_outbound
-            .read()
-            .map_err(|e| TunnelError::LockPoisoned(e.to_string()))?
-            .clone();
-
-        // Get sequence number based on message type
-        let seq = match message_type {
-            cryprq_core::MSG_TYPE_VPN_PACKET => self
-                .seq_counters
-                .next_vpn()
-                .map_err(|_| TunnelError::NonceOverflow)?,
-            cryprq_core::MSG_TYPE_FILE_META
-            | cryprq_core::MSG_TYPE_FILE_CHUNK
-            | cryprq_core::MSG_TYPE_FILE_ACK
-            | cryprq_core::MSG_TYPE_CONTROL => self.seq_counters.next_file(),
-            _ => self.seq_counters.next_data(),
-        };
 
-        // Construct and encrypt record
-        let record_bytes = send_record(epoch, stream_id, seq, message_type, flags, payload, &keys)
-            .map_err(|_| TunnelError::EncryptionFailed)?;
+        if let Some(addr) = peer_addr {
+            self.send_datagram(record_bytes, addr, droppable).await?;
+            self.liveness.on_send(Instant::now())?;
+        }
+        Ok(())
+    }
 
-        // Send over UDP
+    /// Send KEEPALIVEs and PINGs and watch for a dead peer (Section 11.1)
+    ///
+    /// Runs in the background for as long as the tunnel lives; calling it
+    /// again only replaces the configuration. Incoming records must be
+    /// processed concurrently via `recv_and_handle_record`.
+    pub fn start_keepalive(self: &Arc<Self>, config: KeepaliveConfig) -> Result<(), TunnelError> {
+        if self.liveness.configure(config)? {
+            tokio::spawn(keepalive_loop(Arc::downgrade(self), self.liveness.clone()));
+        }
+        Ok(())
+    }
+
+    /// Send sealed records to the peer address, if one is known, in as
+    /// few syscalls as possible
+    pub(crate) async fn send_sealed_batch(
+        &self,
+        records: &[Vec<u8>],
+        droppable: bool,
+    ) -> Result<(), TunnelError> {
         let peer_addr = *self
             .peer_addr
             .read()
             .map_err(|e| TunnelError::LockPoisoned(e.to_string()))?;
 
-        if let Some(addr) = peer_addr {
-            self.socket
-                .send_to(&record_bytes, addr)
-                .await
-                .map_err(|e| TunnelError::NetworkError(e.to_string()))?;
+        if let Some(addr) = peer_addr.filter(|_| !records.is_empty()) {
+            let records: Vec<&[u8]> = records.iter().map(Vec::as_slice).collect();
+            self.send_datagrams(&records, addr, droppable).await?;
+            self.liveness.on_send(Instant::now())?;
+        }
+        Ok(())
+    }
+
+    /// PING the peer and wait for its PONG, returning the round-trip time
+    pub async fn ping(&self, timeout: Duration) -> Result<Duration, TunnelError> {
+        let (id, pong) = self.liveness.new_ping(Instant::now())?;
+        self.send_control(&ControlMessage::Ping(id)).await?;
+        match time::timeout(timeout, pong).await {
+            Ok(Ok(rtt)) => Ok(rtt),
+            _ => Err(TunnelError::Timeout(format!("PING {}", id))),
         }
+    }
+
+    /// Round-trip time estimates from PING/PONG, once a PONG has arrived
+    pub fn rtt_stats(&self) -> Option<RttStats> {
+        self.liveness.rtt()
+    }
+
+    /// Whether the peer was heard from within the dead-peer timeout
+    pub fn peer_status(&self) -> PeerStatus {
+        self.liveness.status()
+    }
+
+    /// Watch the peer status; `PeerStatus::Down` is the session-down event
+    pub fn subscribe_peer_status(&self) -> tokio::sync::watch::Receiver<PeerStatus> {
+        self.liveness.subscribe()
+    }
+
+    /// Largest datagram known to reach the peer (starts at the 1232-byte base)
+    pub fn path_mtu(&self) -> u16 {
+        self.path_mtu.get()
+    }
+
+    /// TUN MTU that keeps VPN packets in a single datagram on this path
+    pub fn tun_mtu(&self) -> u16 {
+        tun_mtu_for_path(self.path_mtu())
+    }
 
+    /// Watch the path MTU, e.g. to resize the TUN interface as it changes
+    pub fn subscribe_path_mtu(&self) -> tokio::sync::watch::Receiver<u16> {
+        self.path_mtu.subscribe()
+    }
+
+    /// Discover the path MTU to the current peer (Section 3.4)
+    ///
+    /// Probes with padded PMTU_PROBE records in the background for as long
+    /// as the tunnel lives, and restarts from the base size if called again
+    /// (e.g. after the path changed). Incoming records must be processed
+    /// concurrently via `recv_and_handle_record`.
+    pub fn start_path_mtu_discovery(self: &Arc<Self>) -> Result<(), TunnelError> {
+        let peer = *self
+            .peer_addr
+            .read()
+            .map_err(|e| TunnelError::LockPoisoned(e.to_string()))?;
+        let peer = peer.ok_or_else(|| TunnelError::NetworkError("no peer address".to_string()))?;
+        if self.path_mtu.start(peer)? {
+            tokio::spawn(pmtu_loop(Arc::downgrade(self), self.path_mtu.clone()));
+        }
         Ok(())
     }
 
+    /// Send one PMTU probe padded to `size` bytes, bypassing fragmentation
+    async fn send_pmtu_probe(&self, probe_id: u32, size: u16) -> Result<(), TunnelError> {
+        let probe = ControlMessage::PmtuProbe {
+            probe_id,
+            padding: 0,
+        };
+        let padding =
+            (size as usize).saturating_sub(pmtu::RECORD_OVERHEAD + probe.to_bytes().len());
+        let record_bytes = self.seal_record(
+            CONTROL_STREAM_ID,
+            cryprq_core::MSG_TYPE_CONTROL,
+            0,
+            &ControlMessage::PmtuProbe { probe_id, padding }.to_bytes(),
+        )?;
+        let peer = *self
+            .peer_addr
+            .read()
+            .map_err(|e| TunnelError::LockPoisoned(e.to_string()))?;
+        let Some(peer) = peer else {
+            return Ok(());
+        };
+        log::debug!(
+            "event=pmtu_probe id={} size={}",
+            probe_id,
+            record_bytes.len()
+        );
+        if let Err(e) = self.send_datagram(&record_bytes, peer, false).await {
+            // EMSGSIZE: larger than the local interface allows
+            log::debug!(
+                "event=pmtu_probe status=send_failed size={} error={}",
+                size,
+                e
+            );
+            self.path_mtu
+                .update(|discovery| discovery.on_send_error(probe_id, Instant::now()))?;
+        }
+        Ok(())
+    }
+
+    /// Build and encrypt a record with the next outbound sequence number
+    fn seal_record(
+        &self,
+        stream_id: u32,
+        message_type: u8,
+        flags: u8,
+        payload: &[u8],
+    ) -> Result<Vec<u8>, TunnelError> {
+        let keys = self.traffic_keys()?;
+        let record = PlainRecord {
+            stream_id,
+            message_type,
+            flags,
+            plaintext: Cow::Borrowed(payload),
+        };
+        seal_plain(&keys, next_seq(&keys)?, &record)
+    }
+
+    /// Current traffic keys, unless the session is closed
+    fn traffic_keys(&self) -> Result<Arc<TrafficKeys>, TunnelError> {
+        if self.is_closed() {
+            return Err(self.closed_error());
+        }
+        Ok(self.keys.load_full())
+    }
+
+    fn padding_policy(&self) -> Result<PaddingPolicy, TunnelError> {
+        Ok(self
+            .padding
+            .read()
+            .map_err(|e| TunnelError::LockPoisoned(e.to_string()))?
+            .clone())
+    }
+
+    /// Seal a payload as one record, or as fragments if it does not fit
+    /// `limit` bytes of plaintext
+    fn seal_records(
+        &self,
+        stream_id: u32,
+        message_type: u8,
+        flags: u8,
+        payload: &[u8],
+        padding: &PaddingPolicy,
+        limit: usize,
+    ) -> Result<Vec<Vec<u8>>, TunnelError> {
+        let keys = self.traffic_keys()?;
+        self.plain_records(stream_id, message_type, flags, payload, padding, limit)?
+            .iter()
+            .map(|record| seal_plain(&keys, next_seq(&keys)?, record))
+            .collect()
+    }
+
+    /// Split and pad a payload as `seal_records` does, without sealing
+    fn plain_records<'a>(
+        &self,
+        stream_id: u32,
+        message_type: u8,
+        flags: u8,
+        payload: &'a [u8],
+        padding: &PaddingPolicy,
+        limit: usize,
+    ) -> Result<Vec<PlainRecord<'a>>, TunnelError> {
+        let max_payload = match padding.is_enabled() {
+            true => limit - padding::PADDING_TRAILER_SIZE,
+            false => limit,
+        };
+        if payload.len() <= max_payload {
+            return Ok(vec![PlainRecord::padded(
+                stream_id,
+                message_type,
+                flags,
+                Cow::Borrowed(payload),
+                padding,
+                limit,
+            )]);
+        }
+        let message_id = self.fragment_ids.fetch_add(1, Ordering::Relaxed);
+        let fragments = fragment::split(payload, max_payload, message_id).ok_or_else(|| {
+            TunnelError::NetworkError(format!(
+                "record payload of {} bytes too large to fragment",
+                payload.len()
+            ))
+        })?;
+        Ok(fragments
+            .into_iter()
+            .map(|fragment| {
+                PlainRecord::padded(
+                    stream_id,
+                    message_type,
+                    flags | cryprq_core::FLAG_FRAGMENT,
+                    Cow::Owned(fragment),
+                    padding,
+                    limit,
+                )
+            })
+            .collect())
+    }
+
+    /// Seal a record, padding it to at most `limit` bytes of plaintext
+    fn seal_padded(
+        &self,
+        stream_id: u32,
+        message_type: u8,
+        flags: u8,
+        payload: &[u8],
+        padding: &PaddingPolicy,
+        limit: usize,
+    ) -> Result<Vec<u8>, TunnelError> {
+        let keys = self.traffic_keys()?;
+        let record = PlainRecord::padded(
+            stream_id,
+            message_type,
+            flags,
+            Cow::Borrowed(payload),
+            padding,
+            limit,
+        );
+        seal_plain(&keys, next_seq(&keys)?, &record)
+    }
+
+    /// Seal records in order, spreading the AEAD work over the crypto pool
+    ///
+    /// Sequence numbers are taken up front in submission order, so the
+    /// sealed records come back in the order they must be sent.
+    async fn seal_parallel(
+        &self,
+        records: Vec<PlainRecord<'static>>,
+    ) -> Result<Vec<Vec<u8>>, TunnelError> {
+        let keys = self.traffic_keys()?;
+        let numbered = records
+            .into_iter()
+            .map(|record| Ok((next_seq(&keys)?, record)))
+            .collect::<Result<Vec<_>, TunnelError>>()?;
+        crypto_pool()
+            .map(numbered, move |(seq, record)| {
+                seal_plain(&keys, seq, &record)
+            })
+            .await
+            .ok_or(TunnelError::EncryptionFailed)?
+            .into_iter()
+            .collect()
+    }
+
     /// Receive and decrypt a CrypRQ record from peer
     ///
     /// Returns (message_type, stream_id, payload)
     pub async fn recv_record(&self) -> Result<(u8, u32, Vec<u8>), TunnelError> {
-        // Get buffer from pool
-        let mut buf = self.buffer_pool.get();
-        buf.resize(BUFFER_SIZE, 0);
+        let (header, payload) = self.recv_record_with_header().await?;
+        Ok((header.message_type, header.stream_id, payload))
+    }
 
+    /// Receive and decrypt a record, keeping its header (flags included)
+    ///
+    /// Fragments are collected until their record is complete; the returned
+    /// header has `FLAG_FRAGMENT` cleared.
+    async fn recv_record_with_header(&self) -> Result<(RecordHeader, Vec<u8>), TunnelError> {
+        loop {
+            let (mut header, payload) = self.recv_datagram_record().await?;
+            if header.flags & cryprq_core::FLAG_FRAGMENT == 0 {
+                return Ok((header, payload));
+            }
+            let whole = self
+                .reassembly
+                .lock()
+                .map_err(|e| TunnelError::LockPoisoned(e.to_string()))?
+                .push(&header, &payload, Instant::now());
+            if let Some(whole) = whole {
+                header.flags &= !cryprq_core::FLAG_FRAGMENT;
+                return Ok((header, whole));
+            }
+        }
+    }
+
+    /// Receive and decrypt the record in one datagram
+    ///
+    /// Datagrams are read from the socket a batch at a time and decrypted
+    /// in parallel on the crypto pool. Each record then passes the replay
+    /// window in arrival order before it is returned, so a sequence number
+    /// is only marked as seen once its record has authenticated.
+    async fn recv_datagram_record(&self) -> Result<(RecordHeader, Vec<u8>), TunnelError> {
+        loop {
+            let opened = self
+                .opened
+                .lock()
+                .map_err(|e| TunnelError::LockPoisoned(e.to_string()))?
+                .pop_front();
+            if let Some(opened) = opened {
+                if self.is_closed() {
+                    return Err(self.closed_error());
+                }
+                return self.accept_opened(opened?).await;
+            }
+            let datagrams = self.recv_datagrams().await?;
+            let opened = self.open_datagrams(datagrams).await?;
+            self.opened
+                .lock()
+                .map_err(|e| TunnelError::LockPoisoned(e.to_string()))?
+                .extend(opened);
+        }
+    }
+
+    /// Wait for datagrams from the socket or an attached stream
+    async fn recv_datagrams(&self) -> Result<Vec<(BytesMut, std::net::SocketAddr)>, TunnelError> {
+        let mut datagrams = Vec::new();
         log::debug!("cryp-rq: waiting for incoming record...");
-        let (len, addr) = self
-            .socket
-            .recv_from(&mut buf)
+        tokio::select! {
+            received = self.udp_batch.recv_batch(&self.socket, &self.buffer_pool, &mut datagrams) => {
+                received.map_err(|e| TunnelError::NetworkError(e.to_string()))?
+            }
+            Some((frame, addr)) = self.recv_stream_frame() => {
+                let mut buf = self.buffer_pool.get();
+                buf.extend_from_slice(&frame);
+                datagrams.push((buf, addr));
+            }
+        }
+        log::debug!("cryp-rq: received {} datagrams", datagrams.len());
+        if self.is_closed() {
+            return Err(self.closed_error());
+        }
+        Ok(datagrams)
+    }
+
+    /// Check and decrypt received datagrams, keeping their arrival order
+    ///
+    /// Everything before decryption runs here in order; decryption itself
+    /// is spread over the crypto pool. The replay window is left alone
+    /// until `accept_opened`.
+    async fn open_datagrams(
+        &self,
+        datagrams: Vec<(BytesMut, std::net::SocketAddr)>,
+    ) -> Result<Vec<OpenedRecord>, TunnelError> {
+        let transport = self.transport()?;
+        let checked: Vec<Result<(BytesMut, std::net::SocketAddr), TunnelError>> = datagrams
+            .into_iter()
+            .map(|(mut buf, addr)| {
+                match self.check_datagram(transport.as_deref(), &mut buf, addr) {
+                    Ok(()) => Ok((buf, addr)),
+                    Err(e) => {
+                        self.buffer_pool.put(buf);
+                        Err(e)
+                    }
+                }
+            })
+            .collect();
+
+        // NOTE: In test mode, both sides assume initiator role, so sender encrypts with the
+        // outbound keys (ir) and receiver must decrypt with the outbound keys (ir), not the
+        // inbound keys (ri). In production with proper handshake, roles would be negotiated
+        // and keys would match correctly
+        let keys = self.keys.load_full();
+        let decrypted = crypto_pool()
+            .map(checked, move |checked| -> Result<_, TunnelError> {
+                let (buf, addr) = checked?;
+                let opened = recv_record(&buf, &keys.outbound);
+                Ok((buf, addr, opened))
+            })
             .await
-            .map_err(|e| TunnelError::NetworkError(e.to_string()))?;
-        log::debug!("cryp-rq: received {} bytes from {}", len, addr);
+            .ok_or(TunnelError::DecryptionFailed)?;
 
-        // Check rate limit first
-        {
-            let mut limiter = self
-                .rate_limiter
-                .write()
-                .map_err(|e| TunnelError::LockPoisoned(e.to_string()))?;
-            limiter.check_and_consume()?;
-        }
+        Ok(decrypted
+            .into_iter()
+            .map(|decrypted| {
+                let (buf, addr, opened) = decrypted?;
+                let len = buf.len();
+                self.buffer_pool.put(buf);
+                match opened {
+                    Ok((header, payload)) => Ok(Opened {
+                        addr,
+                        len,
+                        header,
+                        payload,
+                    }),
+                    Err(e) => {
+                        log::warn!("cryp-rq: decrypt FAILED: from={} error={:?}", addr, e);
+                        Err(TunnelError::DecryptionFailed)
+                    }
+                }
+            })
+            .collect())
+    }
 
-        // Store peer address
-        {
-            *self
-                .peer_addr
-                .write()
-                .map_err(|e| TunnelError::LockPoisoned(e.to_string()))? = Some(addr);
+    /// Rate-limit, deobfuscate and parse one datagram before decryption
+    fn check_datagram(
+        &self,
+        transport: Option<&dyn PluggableTransport>,
+        buf: &mut BytesMut,
+        addr: std::net::SocketAddr,
+    ) -> Result<(), TunnelError> {
+        // Check the source's rate limit before any parsing or decryption
+        self.source_limiter
+            .read()
+            .map_err(|e| TunnelError::LockPoisoned(e.to_string()))?
+            .check(addr.ip(), buf.len())?;
+
+        if let Some(transport) = transport {
+            match transport.deobfuscate(addr, buf) {
+                Ok(datagram) => {
+                    buf.clear();
+                    buf.extend_from_slice(&datagram);
+                }
+                Err(e) => {
+                    log::debug!(
+                        "event=transport_drop transport={} peer={} error={}",
+                        transport.name(),
+                        addr,
+                        e
+                    );
+                    stats::record_drop(stats::DropReason::Transport);
+                    return Err(TunnelError::DecryptionFailed);
+                }
+            }
         }
 
         // Parse header first for logging
-        let header = match RecordHeader::from_bytes(&buf[..len.min(20)]) {
+        match RecordHeader::from_bytes(&buf[..buf.len().min(20)]) {
             Ok(h) => {
                 log::debug!(
                     "cryp-rq: header parsed: version={}, msg_type={}, epoch={}, stream_id={}, seq={}, ct_len={}",
                     h.version, h.message_type, h.epoch, h.stream_id, h.sequence_number, h.ciphertext_length
                 );
-                h
+                Ok(())
             }
             Err(e) => {
                 log::warn!("cryp-rq: failed to parse header: {}", e);
-                return Err(TunnelError::DecryptionFailed);
+                Err(TunnelError::DecryptionFailed)
             }
-        };
-
-        // Decode and decrypt record
-        // NOTE: In test mode, both sides assume initiator role, so sender encrypts with keys_outbound (ir)
-        // and receiver must decrypt with keys_outbound (ir), not keys_inbound (ri)
-        // In production with proper handshake, roles would be negotiated and keys would match correctly
-        let keys = self
-            .keys_outbound // Use outbound keys for decryption in test mode (both sides use ir)
-            .read()
-            .map_err(|e| TunnelError::LockPoisoned(e.to_string()))?
-            .clone();
+        }
+    }
 
-        // Attempt decryption
-        match recv_record(&buf[..len], &keys) {
-            Ok((header, payload)) => {
-                log::debug!(
-                    "cryp-rq: decrypt success for msg_type={} stream_id={} seq={}",
-                    header.message_type,
-                    header.stream_id,
-                    header.sequence_number
-                );
+    /// Finish a decrypted record in arrival order
+    async fn accept_opened(&self, opened: Opened) -> Result<(RecordHeader, Vec<u8>), TunnelError> {
+        let Opened {
+            addr,
+            len,
+            mut header,
+            mut payload,
+        } = opened;
+        log::debug!(
+            "cryp-rq: decrypt success for msg_type={} stream_id={} seq={}",
+            header.message_type,
+            header.stream_id,
+            header.sequence_number
+        );
 
-                // Check for replay attack using sequence number
-                {
-                    let mut window = self
-                        .replay_window
-                        .write()
-                        .map_err(|e| TunnelError::LockPoisoned(e.to_string()))?;
-                    window.check_and_update(header.sequence_number)?;
-                }
+        // Check for replay attack using sequence number
+        self.replay_window
+            .write()
+            .map_err(|e| TunnelError::LockPoisoned(e.to_string()))?
+            .check_and_update(header.sequence_number)?;
+        rate_limit::check_session(&self.session_limiter, len)?;
+        self.liveness.on_recv(Instant::now())?;
+
+        if header.flags & cryprq_core::FLAG_PADDED != 0 {
+            let len = unpad_plaintext(&payload)
+                .map_err(|e| TunnelError::UnexpectedMessage(e.to_string()))?
+                .len();
+            payload.truncate(len);
+            header.flags &= !cryprq_core::FLAG_PADDED;
+        }
 
-                // Return buffer to pool
-                self.buffer_pool.put(buf);
+        // Only an authenticated, fresh record may move the peer address.
+        // Path messages are never fragmented.
+        let path_payload = match header.flags & cryprq_core::FLAG_FRAGMENT {
+            0 => &payload[..],
+            _ => &[],
+        };
+        self.on_authenticated_record(addr, header.message_type, path_payload)
+            .await?;
 
-                Ok((header.message_type, header.stream_id, payload))
-            }
-            Err(e) => {
-                log::warn!(
-                    "cryp-rq: decrypt FAILED: msg_type={} stream_id={} seq={} error={:?}",
-                    header.message_type,
-                    header.stream_id,
-                    header.sequence_number,
-                    e
-                );
-                Err(TunnelError::DecryptionFailed)
-            }
-        }
+        Ok((header, payload))
     }
 
     /// Send VPN packet through record layer
     ///
     /// Wraps TUN packet in a CrypRQ VPN_PACKET record and sends it.
+    /// Packets rejected by the packet filter are dropped and counted.
     pub async fn send_vpn_packet(&self, packet: &[u8]) -> Result<(), TunnelError> {
+        if !self.filter_vpn_packet(Direction::Outbound, packet) {
+            return Ok(());
+        }
         self.send_record(VPN_STREAM_ID, cryprq_core::MSG_TYPE_VPN_PACKET, 0, packet)
             .await
     }
 
+    /// Send several VPN packets, batching their datagrams into as few
+    /// syscalls as possible (see `set_batch_io`)
+    ///
+    /// Each packet is filtered and sealed as by `send_vpn_packet`. While
+    /// cover traffic runs, packets are queued one by one instead.
+    pub async fn send_vpn_packets(&self, packets: &[&[u8]]) -> Result<(), TunnelError> {
+        if self.cover_queue()?.is_some() {
+            for packet in packets {
+                self.send_vpn_packet(packet).await?;
+            }
+            return Ok(());
+        }
+        let padding = self.padding_policy()?;
+        let limit = self.path_mtu.get() as usize - pmtu::RECORD_OVERHEAD;
+        let mut records = Vec::with_capacity(packets.len());
+        for packet in packets {
+            if !self.filter_vpn_packet(Direction::Outbound, packet) {
+                continue;
+            }
+            records.extend(
+                self.plain_records(
+                    VPN_STREAM_ID,
+                    cryprq_core::MSG_TYPE_VPN_PACKET,
+                    0,
+                    packet,
+                    &padding,
+                    limit,
+                )?
+                .into_iter()
+                .map(PlainRecord::into_owned),
+            );
+        }
+        let sealed = self.seal_parallel(records).await?;
+        self.send_sealed_batch(&sealed, true).await
+    }
+
+    /// Send Ethernet frame through record layer (TAP mode)
+    ///
+    /// Wraps a TAP frame in a CrypRQ ETHERNET_FRAME record and sends it.
+    pub async fn send_ethernet_frame(&self, frame: &[u8]) -> Result<(), TunnelError> {
+        self.send_record(
+            VPN_STREAM_ID,
+            cryprq_core::MSG_TYPE_ETHERNET_FRAME,
+            0,
+            frame,
+        )
+        .await
+    }
+
     /// Send file metadata through record layer
     pub async fn send_file_meta(
         &self,
@@ -536,12 +1493,17 @@ impl Tunnel {
         payload: Vec<u8>,
     ) -> Result<(), TunnelError> {
         use cryprq_core::{
-            MSG_TYPE_CONTROL, MSG_TYPE_DATA, MSG_TYPE_FILE_ACK, MSG_TYPE_FILE_CHUNK,
-            MSG_TYPE_FILE_META, MSG_TYPE_VPN_PACKET,
+            MSG_TYPE_CONTROL, MSG_TYPE_DATA, MSG_TYPE_ETHERNET_FRAME, MSG_TYPE_FILE_ACK,
+            MSG_TYPE_FILE_CHUNK, MSG_TYPE_FILE_META, MSG_TYPE_PADDING, MSG_TYPE_VPN_PACKET,
         };
 
         match msg_type {
             MSG_TYPE_VPN_PACKET => {
+                // Enforce AllowedIPs/firewall before anything reaches the TUN
+                if !self.filter_vpn_packet(Direction::Inbound, &payload) {
+                    return Ok(());
+                }
+
                 // Write VPN packet to TUN interface via channel
                 match self.tun_write_tx.read() {
                     Ok(guard) => {
@@ -569,19 +1531,44 @@ impl Tunnel {
                 }
                 Ok(())
             }
+            MSG_TYPE_ETHERNET_FRAME => {
+                // Only TAP sessions take frames, and only through their own writer
+                let guard = self
+                    .tap_write_tx
+                    .read()
+                    .map_err(|e| TunnelError::LockPoisoned(e.to_string()))?;
+                let verdict = match (guard.as_ref(), payload.len()) {
+                    (None, _) => Err(stats::DropReason::UnexpectedType),
+                    (Some(_), len) if len < ETHERNET_HEADER_LEN => {
+                        Err(stats::DropReason::Malformed)
+                    }
+                    (Some(tap), len) if len > tap.max_frame => Err(stats::DropReason::Oversized),
+                    (Some(tap), _) => Ok(tap),
+                };
+                let tap = match verdict {
+                    Ok(tap) => tap,
+                    Err(reason) => {
+                        filter::drop_packet(reason, Direction::Inbound, payload.len());
+                        return Ok(());
+                    }
+                };
+                if let Err(e) = tap.tx.send(payload) {
+                    log::error!("Failed to send Ethernet frame to TAP: {}", e);
+                }
+                Ok(())
+            }
             MSG_TYPE_FILE_META | MSG_TYPE_FILE_CHUNK | MSG_TYPE_FILE_ACK | MSG_TYPE_CONTROL => {
                 // Route to file transfer handler
                 self.handle_file_or_control(stream_id, msg_type, payload)
                     .await
             }
             MSG_TYPE_DATA => {
-                // Generic data stream
-                log::debug!(
-                    "Received data message: stream={}, {} bytes",
-                    stream_id,
-                    payload.len()
-                );
-                Ok(())
+                // Application stream data (see open_stream/accept_stream)
+                self.streams.on_data(stream_id, payload)
+            }
+            MSG_TYPE_PADDING => Ok(()),
+            _ if CustomHandlers::is_custom(msg_type) => {
+                self.handle_custom_record(msg_type, payload)
             }
             _ => {
                 log::warn!("Unknown message type: {}", msg_type);
@@ -621,8 +1608,7 @@ impl Tunnel {
     /// ```
     pub async fn send_packet(&self, pkt: &[u8]) -> Result<(), TunnelError> {
         // Use record layer for VPN packets
-        self.send_record(VPN_STREAM_ID, cryprq_core::MSG_TYPE_VPN_PACKET, 0, pkt)
-            .await
+        self.send_vpn_packet(pkt).await
     }
 
     /// Send encrypted packet to peer (legacy implementation - kept for compatibility)
@@ -689,7 +1675,7 @@ impl Tunnel {
         };
 
         if let Some(addr) = peer_addr {
-            self.socket.send_to(&packet, addr).await?;
+            self.send_datagram(&packet, addr, false).await?;
         }
 
         Ok(())
@@ -782,6 +1768,9 @@ impl Tunnel {
                     })?;
             }
             MSG_TYPE_CONTROL => {
+                if let Ok(msg) = ControlMessage::from_bytes(&payload) {
+                    return self.handle_control_message(msg).await;
+                }
                 self.file_transfer
                     .handle_control(stream_id, &payload)
                     .map_err(|e| {
@@ -799,8 +1788,15 @@ impl Tunnel {
     ///
     /// Receives a record, decrypts it, and routes it to the appropriate handler.
     pub async fn recv_and_handle_record(&self) -> Result<(), TunnelError> {
-        let (msg_type, stream_id, payload) = self.recv_record().await?;
-        self.handle_incoming_record(msg_type, stream_id, payload)
+        let (header, payload) = self.recv_record_with_header().await?;
+        if header.message_type == cryprq_core::MSG_TYPE_DATA
+            && header.flags & cryprq_core::FLAG_DATA_RELIABLE != 0
+        {
+            return self
+                .handle_stream_segment(header.stream_id, header.flags, &payload)
+                .await;
+        }
+        self.handle_incoming_record(header.message_type, header.stream_id, payload)
             .await
     }
 
@@ -811,6 +1807,62 @@ impl Tunnel {
     }
 }
 
+/// Run the keepalive schedule until the tunnel is dropped
+async fn keepalive_loop(tunnel: Weak<Tunnel>, liveness: Arc<Liveness>) {
+    loop {
+        let Ok(Some(config)) = liveness.config() else {
+            return;
+        };
+        let Some(tunnel) = tunnel.upgrade().filter(|tunnel| !tunnel.is_closed()) else {
+            return;
+        };
+        let (actions, next) = match liveness.poll(&config, Instant::now()) {
+            Ok(poll) => poll,
+            Err(e) => {
+                log::error!("event=keepalive status=stopped error={}", e);
+                return;
+            }
+        };
+        for action in actions {
+            let msg = match action {
+                KeepaliveAction::Ping(id) => ControlMessage::Ping(id),
+                KeepaliveAction::Keepalive => ControlMessage::Keepalive,
+            };
+            if let Err(e) = tunnel.send_control(&msg).await {
+                log::debug!("event=keepalive status=send_failed error={}", e);
+            }
+        }
+        drop(tunnel);
+        time::sleep_until(next.into()).await;
+    }
+}
+
+/// Send PMTU probes whenever discovery asks for one, until the tunnel is dropped
+async fn pmtu_loop(tunnel: Weak<Tunnel>, path_mtu: Arc<PathMtu>) {
+    loop {
+        let acked = path_mtu.acked.notified();
+        let Some(tunnel) = tunnel.upgrade().filter(|tunnel| !tunnel.is_closed()) else {
+            return;
+        };
+        let probe = path_mtu.update(|discovery| discovery.poll(Instant::now()));
+        if let Ok(Some(Some((probe_id, size)))) = probe {
+            if let Err(e) = tunnel.send_pmtu_probe(probe_id, size).await {
+                log::debug!("event=pmtu_probe status=failed error={}", e);
+            }
+        }
+        drop(tunnel);
+
+        let deadline = match path_mtu.update(|discovery| discovery.next_timeout()) {
+            Ok(Some(deadline)) => deadline,
+            _ => return,
+        };
+        tokio::select! {
+            _ = acked => {}
+            _ = time::sleep_until(deadline.into()) => {}
+        }
+    }
+}
+
 /// Creates a secure tunnel with peer authentication
 ///
 /// # Security
@@ -859,6 +1911,10 @@ pub async fn create_tunnel_with_output_dir(
     file_output_dir: Option<std::path::PathBuf>,
 ) -> Result<Tunnel, TunnelError> {
     let socket = UdpSocket::bind(listen_addr).await?;
+    if let Err(e) = pmtu::set_dont_fragment(&socket) {
+        log::warn!("event=pmtu_df_unavailable error={}", e);
+    }
+    let udp_batch = UdpBatch::new(&socket);
 
     // SECURITY: Verify peer identity before establishing tunnel
     verify_peer_identity(peer_pk, peer_identity_key, peer_signature)?;
@@ -883,7 +1939,7 @@ pub async fn create_tunnel_with_output_dir(
     // Derive master secret using HKDF (temporary: using X25519 secret only)
     // TODO: Replace with hybrid ML-KEM + X25519 key exchange
     let ss_kem = [0u8; 32]; // Placeholder - will be replaced with ML-KEM secret
-    let (_, master_secret) = cryprq_crypto::derive_handshake_keys(&ss_kem, &ss_x);
+    let (_, mut master_secret) = cryprq_crypto::derive_handshake_keys(&ss_kem, &ss_x);
 
     // Derive initial traffic keys for epoch 0 using epoch-scoped derivation
     // ir = initiator->responder, ri = responder->initiator
@@ -940,97 +1996,97 @@ pub async fn create_tunnel_with_output_dir(
         },
     };
 
+    let (stream_frames_tx, stream_frames_rx) =
+        tokio::sync::mpsc::channel(stream_transport::RECV_QUEUE_LEN);
     let tunnel = Tunnel {
         socket: Arc::new(socket),
         session_key: Arc::new(RwLock::new(session_key)), // Legacy
         static_iv: Arc::new(RwLock::new(static_iv)),     // Legacy
-        epoch: Arc::new(RwLock::new(Epoch::initial())),
-        keys_outbound: Arc::new(RwLock::new(keys_outbound)),
-        keys_inbound: Arc::new(RwLock::new(keys_inbound)),
-        seq_counters: Arc::new(SeqCounters::new()),
+        keys: Arc::new(ArcSwap::from_pointee(TrafficKeys::new(
+            Epoch::initial(),
+            keys_outbound,
+            keys_inbound,
+        ))),
         peer_addr: Arc::new(RwLock::new(None)),
         nonce_counter: Arc::new(RwLock::new(0)), // Legacy
         replay_window: Arc::new(RwLock::new(ReplayWindow::new())),
-        rate_limiter: Arc::new(RwLock::new(RateLimiter::new(1000, 2000))), // 1000 pps, 2000 burst
+        source_limiter: Arc::new(RwLock::new(Arc::new(SourceRateLimiter::new(
+            RateLimitConfig::default(),
+        )))),
+        session_limiter: Arc::new(Mutex::new(RateLimiter::with_limits(
+            &RateLimitConfig::default().per_session,
+        ))),
         buffer_pool: BufferPool::new(POOL_SIZE),
         tun_write_tx: Arc::new(RwLock::new(None)), // Will be set when TUN forwarding starts
+        tap_write_tx: RwLock::new(None),
         file_transfer: Arc::new(FileTransferManager::new(
             file_output_dir.unwrap_or_else(|| std::path::PathBuf::from("/tmp")),
         )),
+        peer_identity: *peer_identity_key,
+        address_pool: Arc::new(RwLock::new(None)),
+        address_lease: tokio::sync::watch::channel(None).0,
+        packet_filter: Arc::new(RwLock::new(None)),
+        path_validator: Arc::new(PathValidator::new()),
+        streams: Arc::new(StreamRegistry::new()),
+        path_mtu: Arc::new(PathMtu::new()),
+        fragment_ids: AtomicU32::new(0),
+        reassembly: Mutex::new(Reassembler::new()),
+        liveness: Arc::new(Liveness::new()),
+        closed: Arc::new(AtomicBool::new(false)),
+        master_secret: Arc::new(RwLock::new(master_secret)),
+        peer_error: Mutex::new(None),
+        custom_handlers: CustomHandlers::new(),
+        padding: RwLock::new(PaddingPolicy::None),
+        cover: RwLock::new(None),
+        transport: RwLock::new(None),
+        stream_link: RwLock::new(None),
+        stream_frames_tx,
+        stream_frames_rx: tokio::sync::Mutex::new(stream_frames_rx),
+        udp_batch,
+        opened: Mutex::new(VecDeque::new()),
     };
+    master_secret.zeroize();
 
-    // Spawn key rotation task (every 5 minutes) using epoch-scoped keys
-    let keys_outbound_clone = tunnel.keys_outbound.clone();
-    let keys_inbound_clone = tunnel.keys_inbound.clone();
-    let epoch_clone = tunnel.epoch.clone();
-    let seq_counters_clone = tunnel.seq_counters.clone();
-    let master_secret_clone = Arc::new(RwLock::new(master_secret));
+    // Spawn key rotation task (every 5 minutes) using epoch-scoped keys.
+    // It ends once the session is closed or dropped.
+    let keys_weak = Arc::downgrade(&tunnel.keys);
+    let master_secret_weak = Arc::downgrade(&tunnel.master_secret);
+    let closed_clone = tunnel.closed.clone();
     tokio::spawn(async move {
         let mut interval = time::interval(Duration::from_secs(300));
         interval.tick().await; // Skip first immediate tick
         loop {
             interval.tick().await;
-
-            // Increment epoch and derive epoch-scoped keys
-            let (new_epoch, new_keys_outbound, new_keys_inbound) = {
-                let mut epoch_guard = epoch_clone
-                    .write()
-                    .expect("Epoch lock poisoned in key rotation");
-                let old_epoch = *epoch_guard;
-                *epoch_guard = old_epoch.next();
-                let new_epoch = *epoch_guard;
-
-                // Derive epoch-scoped keys using HKDF
-                let master_secret = *master_secret_clone
-                    .read()
-                    .expect("Master secret lock poisoned in key rotation");
-                let (key_ir, iv_ir, key_ri, iv_ri) =
-                    cryprq_crypto::derive_epoch_keys(&master_secret, new_epoch.value(), 32, 12);
-
-                let new_keys_outbound = DirectionKeys {
-                    key: {
-                        let mut k = [0u8; 32];
-                        k[..key_ir.len().min(32)].copy_from_slice(&key_ir[..key_ir.len().min(32)]);
-                        k
-                    },
-                    iv: {
-                        let mut iv = [0u8; 12];
-                        iv[..iv_ir.len().min(12)].copy_from_slice(&iv_ir[..iv_ir.len().min(12)]);
-                        iv
-                    },
-                };
-                let new_keys_inbound = DirectionKeys {
-                    key: {
-                        let mut k = [0u8; 32];
-                        k[..key_ri.len().min(32)].copy_from_slice(&key_ri[..key_ri.len().min(32)]);
-                        k
-                    },
-                    iv: {
-                        let mut iv = [0u8; 12];
-                        iv[..iv_ri.len().min(12)].copy_from_slice(&iv_ri[..iv_ri.len().min(12)]);
-                        iv
-                    },
-                };
-
-                (new_epoch, new_keys_outbound, new_keys_inbound)
+            let (Some(keys), Some(master_secret_clone)) =
+                (keys_weak.upgrade(), master_secret_weak.upgrade())
+            else {
+                break;
             };
-
-            // Update directional keys
-            if let Ok(mut keys) = keys_outbound_clone.write() {
-                keys.key.zeroize();
-                keys.iv.zeroize();
-                *keys = new_keys_outbound;
+            if closed_clone.load(Ordering::Acquire) {
+                break;
             }
 
-            if let Ok(mut keys) = keys_inbound_clone.write() {
-                keys.key.zeroize();
-                keys.iv.zeroize();
-                *keys = new_keys_inbound;
+            // Derive the next epoch's keys; sequence numbers restart with them
+            let current = keys.load_full();
+            let new_epoch = current.epoch.next();
+            let mut master_secret = *master_secret_clone
+                .read()
+                .expect("Master secret lock poisoned in key rotation");
+            let (key_ir, iv_ir, key_ri, iv_ri) =
+                cryprq_crypto::derive_epoch_keys(&master_secret, new_epoch.value(), 32, 12);
+            master_secret.zeroize();
+            let next = Arc::new(TrafficKeys::new(
+                new_epoch,
+                direction_keys(&key_ir, &iv_ir),
+                direction_keys(&key_ri, &iv_ri),
+            ));
+
+            // A close in the meantime has swapped in erased keys: keep them
+            let previous = keys.compare_and_swap(&current, next);
+            if !Arc::ptr_eq(&previous, &current) {
+                break;
             }
 
-            // Reset sequence counters on key rotation (per spec, sequence numbers reset per epoch)
-            seq_counters_clone.reset();
-
             log::info!(
                 "event=key_rotation status=success epoch={} duration_ms=0 interval_secs=300",
                 new_epoch.value()
@@ -1041,6 +2097,108 @@ pub async fn create_tunnel_with_output_dir(
     Ok(tunnel)
 }
 
+/// Header fields and plaintext of a record waiting to be sealed
+struct PlainRecord<'a> {
+    stream_id: u32,
+    message_type: u8,
+    flags: u8,
+    plaintext: Cow<'a, [u8]>,
+}
+
+impl<'a> PlainRecord<'a> {
+    /// Pad `payload` to at most `limit` bytes of plaintext if the policy
+    /// asks for padding
+    fn padded(
+        stream_id: u32,
+        message_type: u8,
+        flags: u8,
+        payload: Cow<'a, [u8]>,
+        padding: &PaddingPolicy,
+        limit: usize,
+    ) -> Self {
+        if !padding.is_enabled() {
+            return Self {
+                stream_id,
+                message_type,
+                flags,
+                plaintext: payload,
+            };
+        }
+        Self {
+            stream_id,
+            message_type,
+            flags: flags | cryprq_core::FLAG_PADDED,
+            plaintext: Cow::Owned(pad_plaintext(&payload, padding, limit)),
+        }
+    }
+
+    fn into_owned(self) -> PlainRecord<'static> {
+        PlainRecord {
+            stream_id: self.stream_id,
+            message_type: self.message_type,
+            flags: self.flags,
+            plaintext: Cow::Owned(self.plaintext.into_owned()),
+        }
+    }
+}
+
+/// A received record, decrypted but not yet past the replay window
+struct Opened {
+    addr: std::net::SocketAddr,
+    len: usize,
+    header: RecordHeader,
+    payload: Vec<u8>,
+}
+
+type OpenedRecord = Result<Opened, TunnelError>;
+
+/// Next outbound sequence number under `keys`
+fn next_seq(keys: &TrafficKeys) -> Result<u64, TunnelError> {
+    // One sequence space for all message types: records share the key
+    // (so the nonce) and the peer's replay window
+    keys.seq.next_vpn().map_err(|_| TunnelError::NonceOverflow)
+}
+
+/// Encrypt a record under `keys` with sequence number `seq`
+fn seal_plain(keys: &TrafficKeys, seq: u64, record: &PlainRecord) -> Result<Vec<u8>, TunnelError> {
+    send_record(
+        keys.epoch,
+        record.stream_id,
+        seq,
+        record.message_type,
+        record.flags,
+        &record.plaintext,
+        &keys.outbound,
+    )
+    .map_err(|_| TunnelError::EncryptionFailed)
+}
+
+/// Directional keys from HKDF output (truncated or zero-padded to size)
+fn direction_keys(key: &[u8], iv: &[u8]) -> DirectionKeys {
+    let mut keys = DirectionKeys {
+        key: [0u8; 32],
+        iv: [0u8; 12],
+    };
+    keys.key[..key.len().min(32)].copy_from_slice(&key[..key.len().min(32)]);
+    keys.iv[..iv.len().min(12)].copy_from_slice(&iv[..iv.len().min(12)]);
+    keys
+}
+
+impl Drop for Tunnel {
+    fn drop(&mut self) {
+        // DirectionKeys erase themselves; clear the other secrets too
+        if let Ok(mut key) = self.session_key.write() {
+            key.zeroize();
+        }
+        if let Ok(mut iv) = self.static_iv.write() {
+            iv.zeroize();
+        }
+        if let Ok(mut secret) = self.master_secret.write() {
+            secret.zeroize();
+        }
+    }
+}
+
 // Implement PacketForwarder for Tunnel to work with TUN interface
 #[async_trait::async_trait]
 impl tun::PacketForwarder for Tunnel {
@@ -1050,6 +2208,12 @@ impl tun::PacketForwarder for Tunnel {
             .map_err(|e| anyhow::anyhow!("Failed to send VPN packet: {}", e))
     }
 
+    async fn send_packets(&self, packets: &[&[u8]]) -> anyhow::Result<()> {
+        self.send_vpn_packets(packets)
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to send VPN packets: {}", e))
+    }
+
     async fn recv_packet(&mut self) -> anyhow::Result<Vec<u8>> {
         // This is called by TUN write loop - receive records and extract VPN packets
         // The TUN write loop should actually use a separate receive loop that calls recv_and_handle_record
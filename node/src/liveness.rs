@@ -0,0 +1,376 @@
+// Copyright (c) 2025 Thor Thor
+// Author: Thor Thor (GitHub: https://github.com/codethor0)
+// Contact: codethor@gmail.com
+// LinkedIn: https://www.linkedin.com/in/thor-thor0
+// License: MIT (see LICENSE file for details)
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use tokio::sync::{oneshot, watch};
+
+use crate::TunnelError;
+
+/// PINGs awaiting their PONG; older ones are forgotten
+const MAX_OUTSTANDING_PINGS: usize = 16;
+
+/// Keepalive and dead-peer detection settings
+#[derive(Debug, Clone)]
+pub struct KeepaliveConfig {
+    /// Send a KEEPALIVE after this long without sending anything, and a
+    /// PING after this long without hearing from the peer
+    pub interval: Duration,
+    /// Declare the session down after this long without an authenticated
+    /// record from the peer
+    pub dead_peer_timeout: Duration,
+}
+
+impl Default for KeepaliveConfig {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(10),
+            dead_peer_timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+/// Whether the peer has been heard from recently
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PeerStatus {
+    /// Records arrive within the dead-peer timeout
+    Alive,
+    /// Nothing authenticated for longer than the dead-peer timeout
+    Down,
+}
+
+/// Round-trip time estimates from PING/PONG exchanges
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RttStats {
+    /// Most recent sample
+    pub latest: Duration,
+    /// Smoothed RTT (RFC 6298 gain of 1/8)
+    pub smoothed: Duration,
+    /// Interarrival jitter between consecutive samples (RFC 3550 gain of 1/16)
+    pub jitter: Duration,
+    /// Samples taken so far
+    pub samples: u64,
+}
+
+impl RttStats {
+    fn new(sample: Duration) -> Self {
+        Self {
+            latest: sample,
+            smoothed: sample,
+            jitter: Duration::ZERO,
+            samples: 1,
+        }
+    }
+
+    fn update(&mut self, sample: Duration) {
+        let delta = sample.abs_diff(self.latest);
+        self.smoothed = (self.smoothed * 7 + sample) / 8;
+        self.jitter = (self.jitter * 15 + delta) / 16;
+        self.latest = sample;
+        self.samples += 1;
+    }
+}
+
+/// PING waiting for its PONG
+struct OutstandingPing {
+    id: u64,
+    sent_at: Instant,
+    waiter: Option<oneshot::Sender<Duration>>,
+}
+
+/// Liveness bookkeeping
+struct State {
+    last_send: Instant,
+    last_recv: Instant,
+    next_ping_id: u64,
+    outstanding: VecDeque<OutstandingPing>,
+    rtt: Option<RttStats>,
+}
+
+impl State {
+    fn push_ping(&mut self, now: Instant, waiter: Option<oneshot::Sender<Duration>>) -> u64 {
+        let id = self.next_ping_id;
+        self.next_ping_id = self.next_ping_id.wrapping_add(1);
+        if self.outstanding.len() >= MAX_OUTSTANDING_PINGS {
+            self.outstanding.pop_front();
+        }
+        self.outstanding.push_back(OutstandingPing {
+            id,
+            sent_at: now,
+            waiter,
+        });
+        id
+    }
+}
+
+/// What the keepalive scheduler should send now
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum KeepaliveAction {
+    Ping(u64),
+    Keepalive,
+}
+
+/// Keepalive, RTT and dead-peer tracking for one tunnel
+///
+/// Every authenticated record counts as a sign of life, so a busy session
+/// needs no extra traffic. When the peer goes quiet for the keepalive
+/// interval it is PINGed; the PONG refreshes liveness and yields an RTT
+/// sample. When we have sent nothing for the interval, a KEEPALIVE keeps
+/// NAT bindings open.
+pub(crate) struct Liveness {
+    state: Mutex<State>,
+    status: watch::Sender<PeerStatus>,
+    /// Set once the keepalive scheduler runs
+    config: Mutex<Option<KeepaliveConfig>>,
+}
+
+impl Liveness {
+    pub(crate) fn new() -> Self {
+        let now = Instant::now();
+        Self {
+            state: Mutex::new(State {
+                last_send: now,
+                last_recv: now,
+                next_ping_id: 1,
+                outstanding: VecDeque::new(),
+                rtt: None,
+            }),
+            status: watch::channel(PeerStatus::Alive).0,
+            config: Mutex::new(None),
+        }
+    }
+
+    /// Set the scheduler's configuration; returns true the first time, when
+    /// the caller must spawn the scheduler
+    pub(crate) fn configure(&self, config: KeepaliveConfig) -> Result<bool, TunnelError> {
+        let previous = self
+            .config
+            .lock()
+            .map_err(|e| TunnelError::LockPoisoned(e.to_string()))?
+            .replace(config);
+        Ok(previous.is_none())
+    }
+
+    pub(crate) fn config(&self) -> Result<Option<KeepaliveConfig>, TunnelError> {
+        Ok(self
+            .config
+            .lock()
+            .map_err(|e| TunnelError::LockPoisoned(e.to_string()))?
+            .clone())
+    }
+
+    pub(crate) fn subscribe(&self) -> watch::Receiver<PeerStatus> {
+        self.status.subscribe()
+    }
+
+    pub(crate) fn status(&self) -> PeerStatus {
+        *self.status.borrow()
+    }
+
+    pub(crate) fn rtt(&self) -> Option<RttStats> {
+        self.lock().ok().and_then(|state| state.rtt)
+    }
+
+    /// An authenticated record arrived from the peer
+    pub(crate) fn on_recv(&self, now: Instant) -> Result<(), TunnelError> {
+        self.lock()?.last_recv = now;
+        self.status.send_if_modified(|status| {
+            if *status == PeerStatus::Alive {
+                return false;
+            }
+            log::info!("event=session_up");
+            *status = PeerStatus::Alive;
+            true
+        });
+        Ok(())
+    }
+
+    /// A record was sent to the peer
+    pub(crate) fn on_send(&self, now: Instant) -> Result<(), TunnelError> {
+        self.lock()?.last_send = now;
+        Ok(())
+    }
+
+    /// Register a PING; the receiver yields its RTT when the PONG arrives
+    pub(crate) fn new_ping(
+        &self,
+        now: Instant,
+    ) -> Result<(u64, oneshot::Receiver<Duration>), TunnelError> {
+        let (tx, rx) = oneshot::channel();
+        let id = self.lock()?.push_ping(now, Some(tx));
+        Ok((id, rx))
+    }
+
+    /// A PONG arrived; returns its RTT sample if it matches a PING
+    pub(crate) fn on_pong(&self, id: u64, now: Instant) -> Result<Option<Duration>, TunnelError> {
+        let mut state = self.lock()?;
+        let Some(index) = state.outstanding.iter().position(|p| p.id == id) else {
+            return Ok(None);
+        };
+        let Some(ping) = state.outstanding.remove(index) else {
+            return Ok(None);
+        };
+        let sample = now.saturating_duration_since(ping.sent_at);
+        match state.rtt.as_mut() {
+            Some(rtt) => rtt.update(sample),
+            None => state.rtt = Some(RttStats::new(sample)),
+        }
+        if let Some(waiter) = ping.waiter {
+            let _ = waiter.send(sample);
+        }
+        Ok(Some(sample))
+    }
+
+    /// Decide what to send and update the peer status
+    ///
+    /// Returns the actions for the scheduler and when to call again.
+    pub(crate) fn poll(
+        &self,
+        config: &KeepaliveConfig,
+        now: Instant,
+    ) -> Result<(Vec<KeepaliveAction>, Instant), TunnelError> {
+        let mut actions = Vec::new();
+        let (recv_idle, next) = {
+            let mut state = self.lock()?;
+            let recv_idle = now.saturating_duration_since(state.last_recv);
+            // One PING per interval while the peer is quiet
+            let ping_due = state
+                .outstanding
+                .back()
+                .is_none_or(|p| now.saturating_duration_since(p.sent_at) >= config.interval);
+            if recv_idle >= config.interval && ping_due {
+                actions.push(KeepaliveAction::Ping(state.push_ping(now, None)));
+            }
+            let send_idle = now.saturating_duration_since(state.last_send);
+            if send_idle >= config.interval && actions.is_empty() {
+                actions.push(KeepaliveAction::Keepalive);
+            }
+            let next = (state.last_recv + config.interval)
+                .min(state.last_send + config.interval)
+                .min(state.last_recv + config.dead_peer_timeout)
+                .max(now + config.interval / 8);
+            (recv_idle, next)
+        };
+
+        if recv_idle >= config.dead_peer_timeout {
+            self.status.send_if_modified(|status| {
+                if *status == PeerStatus::Down {
+                    return false;
+                }
+                log::warn!(
+                    "event=session_down reason=dead_peer idle_ms={}",
+                    recv_idle.as_millis()
+                );
+                *status = PeerStatus::Down;
+                true
+            });
+        }
+        Ok((actions, next))
+    }
+
+    fn lock(&self) -> Result<std::sync::MutexGuard<'_, State>, TunnelError> {
+        self.state
+            .lock()
+            .map_err(|e| TunnelError::LockPoisoned(e.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> KeepaliveConfig {
+        KeepaliveConfig {
+            interval: Duration::from_secs(10),
+            dead_peer_timeout: Duration::from_secs(30),
+        }
+    }
+
+    #[test]
+    fn test_rtt_smoothing_and_jitter() {
+        let liveness = Liveness::new();
+        let start = Instant::now();
+        assert!(liveness.rtt().is_none());
+
+        for (i, ms) in [100u64, 120, 80].into_iter().enumerate() {
+            let sent = start + Duration::from_secs(i as u64);
+            let (id, _rx) = liveness.new_ping(sent).expect("ping in test");
+            let sample = liveness
+                .on_pong(id, sent + Duration::from_millis(ms))
+                .expect("pong in test");
+            assert_eq!(sample, Some(Duration::from_millis(ms)));
+        }
+        let Some(rtt) = liveness.rtt() else {
+            return;
+        };
+        assert_eq!(rtt.samples, 3);
+        assert_eq!(rtt.latest, Duration::from_millis(80));
+        // 100 -> 102.5 -> 99.6875
+        assert!(rtt.smoothed > Duration::from_millis(99));
+        assert!(rtt.smoothed < Duration::from_millis(100));
+        // 0 -> 1.25 -> 3.67
+        assert!(rtt.jitter > Duration::from_millis(3));
+        assert!(rtt.jitter < Duration::from_millis(4));
+
+        // Unknown or repeated PONGs are ignored
+        assert_eq!(liveness.on_pong(99, start).expect("pong in test"), None);
+    }
+
+    #[test]
+    fn test_keepalive_and_ping_scheduling() {
+        let liveness = Liveness::new();
+        let start = Instant::now();
+        liveness.on_recv(start).expect("recv in test");
+        liveness.on_send(start).expect("send in test");
+
+        let (actions, next) = liveness.poll(&config(), start).expect("poll in test");
+        assert!(actions.is_empty());
+        assert_eq!(next, start + Duration::from_secs(10));
+
+        // We sent nothing, but the peer is still talking: KEEPALIVE only
+        let later = start + Duration::from_secs(10);
+        liveness.on_recv(later).expect("recv in test");
+        let (actions, _) = liveness.poll(&config(), later).expect("poll in test");
+        assert_eq!(actions, vec![KeepaliveAction::Keepalive]);
+
+        // Peer quiet: one PING per interval
+        let quiet = later + Duration::from_secs(10);
+        liveness.on_send(quiet).expect("send in test");
+        let (actions, _) = liveness.poll(&config(), quiet).expect("poll in test");
+        assert!(matches!(actions.as_slice(), [KeepaliveAction::Ping(_)]));
+        let (actions, _) = liveness
+            .poll(&config(), quiet + Duration::from_secs(1))
+            .expect("poll in test");
+        assert!(actions.is_empty());
+    }
+
+    #[test]
+    fn test_dead_peer_fires_session_down() {
+        let liveness = Liveness::new();
+        let mut status = liveness.subscribe();
+        let start = Instant::now();
+        liveness.on_recv(start).expect("recv in test");
+
+        liveness
+            .poll(&config(), start + Duration::from_secs(29))
+            .expect("poll in test");
+        assert_eq!(liveness.status(), PeerStatus::Alive);
+        liveness
+            .poll(&config(), start + Duration::from_secs(30))
+            .expect("poll in test");
+        assert_eq!(liveness.status(), PeerStatus::Down);
+        assert!(status.has_changed().expect("status channel in test"));
+        assert_eq!(*status.borrow_and_update(), PeerStatus::Down);
+
+        // Any authenticated record brings the session back up
+        liveness
+            .on_recv(start + Duration::from_secs(31))
+            .expect("recv in test");
+        assert_eq!(liveness.status(), PeerStatus::Alive);
+    }
+}
@@ -0,0 +1,327 @@
+// Copyright (c) 2025 Thor Thor
+// Author: Thor Thor (GitHub: https://github.com/codethor0)
+// Contact: codethor@gmail.com
+// LinkedIn: https://www.linkedin.com/in/thor-thor0
+// License: MIT (see LICENSE file for details)
+
+use anyhow::Result;
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::hash::Hash;
+use std::net::IpAddr;
+use std::sync::{Arc, RwLock};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+
+use crate::filter::{check_firewall, drop_packet, Direction, Firewall};
+use crate::packet::{IpHeader, IpPrefix};
+use crate::stats::DropReason;
+use crate::tun::PacketForwarder;
+
+/// Routing table shared by all peers of a mesh TUN
+///
+/// Maps destination prefixes to peers (longest prefix wins) and holds the
+/// per-peer outbound channels. Incoming packets from every peer are merged
+/// into one channel that feeds the TUN writer.
+///
+/// The table doubles as the ingress filter (cryptokey routing): a packet from
+/// a peer is only accepted if its source address routes back to that peer.
+pub struct MeshRouter<K> {
+    /// Routes sorted by prefix length, longest first
+    routes: RwLock<Vec<(IpPrefix, K)>>,
+    /// Outbound packet channel per connected peer
+    peers: RwLock<HashMap<K, UnboundedSender<Vec<u8>>>>,
+    incoming_tx: UnboundedSender<Vec<u8>>,
+    firewall: RwLock<Option<Arc<Firewall>>>,
+}
+
+impl<K> MeshRouter<K>
+where
+    K: Clone + Eq + Hash + Display,
+{
+    /// Register a connected peer and its outbound packet channel
+    pub fn add_peer(&self, peer: K, tx: UnboundedSender<Vec<u8>>) {
+        if let Ok(mut peers) = self.peers.write() {
+            peers.insert(peer, tx);
+        }
+    }
+
+    /// Remove a disconnected peer
+    ///
+    /// Its routes are kept so traffic resumes when the peer reconnects.
+    pub fn remove_peer(&self, peer: &K) {
+        if let Ok(mut peers) = self.peers.write() {
+            peers.remove(peer);
+        }
+    }
+
+    /// Route a destination prefix to a peer (replaces an existing identical prefix)
+    pub fn add_route(&self, prefix: IpPrefix, peer: K) {
+        if let Ok(mut routes) = self.routes.write() {
+            routes.retain(|(p, _)| *p != prefix);
+            routes.push((prefix, peer.clone()));
+            routes.sort_by(|a, b| b.0.len().cmp(&a.0.len()));
+            log::info!("event=mesh_route_added prefix={} peer={}", prefix, peer);
+        }
+    }
+
+    /// Number of connected peers
+    pub fn peer_count(&self) -> usize {
+        self.peers.read().map(|p| p.len()).unwrap_or(0)
+    }
+
+    /// Peer responsible for a destination address (longest prefix match)
+    pub fn lookup(&self, dst: IpAddr) -> Option<K> {
+        let routes = self.routes.read().ok()?;
+        routes
+            .iter()
+            .find(|(prefix, _)| prefix.contains(dst))
+            .map(|(_, peer)| peer.clone())
+    }
+
+    /// Set the stateful firewall applied to all mesh traffic
+    pub fn set_firewall(&self, firewall: Arc<Firewall>) {
+        if let Ok(mut guard) = self.firewall.write() {
+            *guard = Some(firewall);
+        }
+    }
+
+    /// Check a packet from `peer` against its routes and the firewall
+    pub fn check_inbound(&self, peer: &K, packet: &[u8]) -> Result<(), DropReason> {
+        let header = IpHeader::parse(packet).ok_or(DropReason::Malformed)?;
+        if self.lookup(header.src).as_ref() != Some(peer) {
+            return Err(DropReason::SourceNotAllowed);
+        }
+        self.check_firewall(Direction::Inbound, &header, packet)
+    }
+
+    /// Filter a packet from `peer` and merge it into the TUN writer
+    pub fn deliver(&self, peer: &K, packet: Vec<u8>) {
+        match self.check_inbound(peer, &packet) {
+            Ok(()) => {
+                let _ = self.incoming_tx.send(packet);
+            }
+            Err(reason) => drop_packet(reason, Direction::Inbound, packet.len()),
+        }
+    }
+
+    fn check_firewall(
+        &self,
+        direction: Direction,
+        header: &IpHeader,
+        packet: &[u8],
+    ) -> Result<(), DropReason> {
+        let firewall = self.firewall.read().ok().and_then(|f| f.clone());
+        check_firewall(firewall.as_deref(), direction, header, packet)
+    }
+
+    /// Send a TUN packet to the peer owning its destination
+    ///
+    /// Packets rejected by the firewall are dropped and counted.
+    pub fn route(&self, packet: &[u8]) -> Result<()> {
+        let header = IpHeader::parse(packet)
+            .ok_or_else(|| anyhow::anyhow!("Not an IP packet ({} bytes)", packet.len()))?;
+        if let Err(reason) = self.check_firewall(Direction::Outbound, &header, packet) {
+            drop_packet(reason, Direction::Outbound, packet.len());
+            return Ok(());
+        }
+        let peer = self
+            .lookup(header.dst)
+            .ok_or_else(|| anyhow::anyhow!("No mesh route to {}", header.dst))?;
+        let peers = self
+            .peers
+            .read()
+            .map_err(|e| anyhow::anyhow!("Mesh peer lock poisoned: {}", e))?;
+        let tx = peers.get(&peer).ok_or_else(|| {
+            anyhow::anyhow!("Mesh peer {} for {} not connected", peer, header.dst)
+        })?;
+        tx.send(packet.to_vec())
+            .map_err(|e| anyhow::anyhow!("Failed to queue packet for {}: {}", peer, e))
+    }
+}
+
+impl<K> MeshRouter<K>
+where
+    K: Clone + Eq + Hash + Display + Send + Sync + 'static,
+{
+    /// Sender used by a peer to deliver incoming packets
+    ///
+    /// Packets are filtered for that peer before reaching the TUN writer.
+    pub fn incoming_for(self: &Arc<Self>, peer: K) -> UnboundedSender<Vec<u8>> {
+        let (tx, mut rx) = unbounded_channel::<Vec<u8>>();
+        let router = self.clone();
+        tokio::spawn(async move {
+            while let Some(packet) = rx.recv().await {
+                router.deliver(&peer, packet);
+            }
+        });
+        tx
+    }
+}
+
+/// Packet forwarder serving one TUN interface for many peers
+pub struct MeshForwarder<K> {
+    router: Arc<MeshRouter<K>>,
+    incoming_rx: UnboundedReceiver<Vec<u8>>,
+}
+
+impl<K> MeshForwarder<K>
+where
+    K: Clone + Eq + Hash + Display,
+{
+    pub fn new() -> Self {
+        let (incoming_tx, incoming_rx) = unbounded_channel();
+        Self {
+            router: Arc::new(MeshRouter {
+                routes: RwLock::new(Vec::new()),
+                peers: RwLock::new(HashMap::new()),
+                incoming_tx,
+                firewall: RwLock::new(None),
+            }),
+            incoming_rx,
+        }
+    }
+
+    /// Routing table handle for adding peers and routes while forwarding runs
+    pub fn router(&self) -> Arc<MeshRouter<K>> {
+        self.router.clone()
+    }
+}
+
+impl<K> Default for MeshForwarder<K>
+where
+    K: Clone + Eq + Hash + Display,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl<K> PacketForwarder for MeshForwarder<K>
+where
+    K: Clone + Eq + Hash + Display + Send + Sync,
+{
+    async fn send_packet(&self, packet: &[u8]) -> Result<()> {
+        self.router.route(packet)
+    }
+
+    async fn recv_packet(&mut self) -> Result<Vec<u8>> {
+        // Use timeout so the TUN read side can take the forwarder lock
+        match tokio::time::timeout(
+            tokio::time::Duration::from_millis(100),
+            self.incoming_rx.recv(),
+        )
+        .await
+        {
+            Ok(Some(packet)) => Ok(packet),
+            Ok(None) => Err(anyhow::anyhow!("Channel closed")),
+            Err(_) => Err(anyhow::anyhow!("Timeout waiting for packet")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ipv4_packet(dst: [u8; 4]) -> Vec<u8> {
+        let mut packet = vec![0u8; 20];
+        packet[0] = 0x45;
+        packet[12..16].copy_from_slice(&[10, 0, 0, 1]);
+        packet[16..20].copy_from_slice(&dst);
+        packet
+    }
+
+    fn prefix(s: &str) -> IpPrefix {
+        s.parse().expect("valid prefix in test")
+    }
+
+    #[tokio::test]
+    async fn test_routes_by_longest_prefix() {
+        let forwarder: MeshForwarder<String> = MeshForwarder::new();
+        let router = forwarder.router();
+        let (tx_a, mut rx_a) = unbounded_channel();
+        let (tx_b, mut rx_b) = unbounded_channel();
+        router.add_peer("a".to_string(), tx_a);
+        router.add_peer("b".to_string(), tx_b);
+        router.add_route(prefix("10.0.0.0/24"), "a".to_string());
+        router.add_route(prefix("10.0.0.3/32"), "b".to_string());
+
+        forwarder
+            .send_packet(&ipv4_packet([10, 0, 0, 2]))
+            .await
+            .expect("route to a in test");
+        forwarder
+            .send_packet(&ipv4_packet([10, 0, 0, 3]))
+            .await
+            .expect("route to b in test");
+
+        assert_eq!(rx_a.try_recv().ok(), Some(ipv4_packet([10, 0, 0, 2])));
+        assert_eq!(rx_b.try_recv().ok(), Some(ipv4_packet([10, 0, 0, 3])));
+        assert!(forwarder
+            .send_packet(&ipv4_packet([192, 168, 0, 1]))
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_removed_peer_keeps_routes() {
+        let forwarder: MeshForwarder<String> = MeshForwarder::new();
+        let router = forwarder.router();
+        let (tx, _rx) = unbounded_channel();
+        router.add_peer("a".to_string(), tx);
+        router.add_route(prefix("10.0.0.2/32"), "a".to_string());
+        assert_eq!(router.peer_count(), 1);
+
+        router.remove_peer(&"a".to_string());
+        assert_eq!(router.peer_count(), 0);
+        assert_eq!(
+            router.lookup("10.0.0.2".parse().expect("addr")),
+            Some("a".to_string())
+        );
+        assert!(forwarder
+            .send_packet(&ipv4_packet([10, 0, 0, 2]))
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_incoming_merged_from_all_peers() {
+        let mut forwarder: MeshForwarder<String> = MeshForwarder::new();
+        let router = forwarder.router();
+        router.add_route(prefix("10.0.0.2/32"), "a".to_string());
+        router.add_route(prefix("10.0.0.3/32"), "b".to_string());
+
+        let mut from_a = ipv4_packet([10, 0, 0, 1]);
+        from_a[12..16].copy_from_slice(&[10, 0, 0, 2]);
+        let mut from_b = ipv4_packet([10, 0, 0, 1]);
+        from_b[12..16].copy_from_slice(&[10, 0, 0, 3]);
+
+        router.deliver(&"a".to_string(), from_a.clone());
+        router.deliver(&"b".to_string(), from_b.clone());
+
+        assert_eq!(forwarder.recv_packet().await.expect("recv in test"), from_a);
+        assert_eq!(forwarder.recv_packet().await.expect("recv in test"), from_b);
+    }
+
+    #[tokio::test]
+    async fn test_spoofed_source_dropped() {
+        let forwarder: MeshForwarder<String> = MeshForwarder::new();
+        let router = forwarder.router();
+        router.add_route(prefix("10.0.0.2/32"), "a".to_string());
+        router.add_route(prefix("10.0.0.3/32"), "b".to_string());
+
+        // Peer a claiming b's address is rejected
+        let mut spoofed = ipv4_packet([10, 0, 0, 1]);
+        spoofed[12..16].copy_from_slice(&[10, 0, 0, 3]);
+        assert_eq!(
+            router.check_inbound(&"a".to_string(), &spoofed),
+            Err(DropReason::SourceNotAllowed)
+        );
+        assert_eq!(
+            router.check_inbound(&"a".to_string(), &[0u8; 4]),
+            Err(DropReason::Malformed)
+        );
+    }
+}
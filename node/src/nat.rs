@@ -0,0 +1,264 @@
+// Copyright (c) 2025 Thor Thor
+// Author: Thor Thor (GitHub: https://github.com/codethor0)
+// Contact: codethor@gmail.com
+// LinkedIn: https://www.linkedin.com/in/thor-thor0
+// License: MIT (see LICENSE file for details)
+
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::net::SocketAddrV4;
+use std::time::{Duration, Instant};
+
+use crate::packet::{PROTO_ICMP, PROTO_TCP};
+use crate::stats::DropReason;
+
+/// Exit NAT limits and idle timeouts
+#[derive(Debug, Clone)]
+pub struct NatConfig {
+    /// Maximum tracked flows across all peers
+    pub max_flows: usize,
+    /// Maximum tracked flows per peer
+    pub max_flows_per_peer: usize,
+    /// Idle timeout for established TCP connections
+    pub tcp_established_timeout: Duration,
+    /// Idle timeout for TCP connections being opened or closed
+    pub tcp_transitory_timeout: Duration,
+    /// Idle timeout for UDP flows
+    pub udp_timeout: Duration,
+    /// Idle timeout for ICMP echo flows
+    pub icmp_timeout: Duration,
+    /// Allow peers to reach services on the exit host's loopback interface
+    pub allow_loopback: bool,
+    /// Allow peers to reach private, link-local (including cloud metadata
+    /// endpoints), shared (CGNAT) and documentation addresses
+    pub allow_private: bool,
+}
+
+impl Default for NatConfig {
+    fn default() -> Self {
+        Self {
+            max_flows: 65536,
+            max_flows_per_peer: 4096,
+            tcp_established_timeout: Duration::from_secs(2 * 60 * 60),
+            tcp_transitory_timeout: Duration::from_secs(4 * 60),
+            udp_timeout: Duration::from_secs(60),
+            icmp_timeout: Duration::from_secs(30),
+            allow_loopback: false,
+            allow_private: false,
+        }
+    }
+}
+
+/// Connection tracking key: one peer's inner 5-tuple
+///
+/// For ICMP echo the ports hold the echo identifier.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct FlowKey<K> {
+    pub peer: K,
+    pub protocol: u8,
+    pub src: SocketAddrV4,
+    pub dst: SocketAddrV4,
+}
+
+struct Flow<V> {
+    value: V,
+    last_seen: Instant,
+    established: bool,
+}
+
+/// Connection tracking table for the exit NAT
+///
+/// Each flow carries a value (the relay handle in `ExitNode`) and expires
+/// after a protocol-specific idle timeout.
+pub struct ConnTrack<K, V> {
+    config: NatConfig,
+    flows: HashMap<FlowKey<K>, Flow<V>>,
+    per_peer: HashMap<K, usize>,
+}
+
+impl<K, V> ConnTrack<K, V>
+where
+    K: Clone + Eq + Hash,
+{
+    pub fn new(config: NatConfig) -> Self {
+        Self {
+            config,
+            flows: HashMap::new(),
+            per_peer: HashMap::new(),
+        }
+    }
+
+    /// Track a new flow, enforcing the global and per-peer limits
+    pub fn insert(&mut self, key: FlowKey<K>, value: V, now: Instant) -> Result<(), DropReason> {
+        if let Some(flow) = self.flows.get_mut(&key) {
+            flow.value = value;
+            flow.last_seen = now;
+            return Ok(());
+        }
+        let peer_flows = self.per_peer.get(&key.peer).copied().unwrap_or(0);
+        if self.flows.len() >= self.config.max_flows || peer_flows >= self.config.max_flows_per_peer
+        {
+            return Err(DropReason::NatLimit);
+        }
+        *self.per_peer.entry(key.peer.clone()).or_insert(0) += 1;
+        self.flows.insert(
+            key,
+            Flow {
+                value,
+                last_seen: now,
+                established: false,
+            },
+        );
+        Ok(())
+    }
+
+    /// Look up a live flow and refresh its idle timer
+    pub fn touch(&mut self, key: &FlowKey<K>, now: Instant) -> Option<&V> {
+        let flow = self.flows.get_mut(key)?;
+        flow.last_seen = now;
+        Some(&flow.value)
+    }
+
+    /// Mark a flow as established (TCP handshake complete, or reply seen)
+    pub fn set_established(&mut self, key: &FlowKey<K>, established: bool) {
+        if let Some(flow) = self.flows.get_mut(key) {
+            flow.established = established;
+        }
+    }
+
+    /// Stop tracking a flow
+    pub fn remove(&mut self, key: &FlowKey<K>) -> Option<V> {
+        let flow = self.flows.remove(key)?;
+        self.release_peer_slot(&key.peer);
+        Some(flow.value)
+    }
+
+    /// Remove all flows idle past their timeout
+    pub fn expire(&mut self, now: Instant) -> Vec<(FlowKey<K>, V)> {
+        let expired: Vec<FlowKey<K>> = self
+            .flows
+            .iter()
+            .filter(|(key, flow)| {
+                now.duration_since(flow.last_seen) >= self.timeout(key.protocol, flow.established)
+            })
+            .map(|(key, _)| key.clone())
+            .collect();
+        expired
+            .into_iter()
+            .filter_map(|key| self.remove(&key).map(|value| (key, value)))
+            .collect()
+    }
+
+    /// Remove every flow of a disconnected peer
+    pub fn remove_peer(&mut self, peer: &K) -> Vec<(FlowKey<K>, V)> {
+        let keys: Vec<FlowKey<K>> = self
+            .flows
+            .keys()
+            .filter(|key| key.peer == *peer)
+            .cloned()
+            .collect();
+        keys.into_iter()
+            .filter_map(|key| self.remove(&key).map(|value| (key, value)))
+            .collect()
+    }
+
+    /// Number of tracked flows
+    pub fn len(&self) -> usize {
+        self.flows.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.flows.is_empty()
+    }
+
+    /// Number of tracked flows belonging to a peer
+    pub fn peer_flows(&self, peer: &K) -> usize {
+        self.per_peer.get(peer).copied().unwrap_or(0)
+    }
+
+    /// Idle timeout for a flow
+    pub fn timeout(&self, protocol: u8, established: bool) -> Duration {
+        match protocol {
+            PROTO_TCP if established => self.config.tcp_established_timeout,
+            PROTO_TCP => self.config.tcp_transitory_timeout,
+            PROTO_ICMP => self.config.icmp_timeout,
+            _ => self.config.udp_timeout,
+        }
+    }
+
+    fn release_peer_slot(&mut self, peer: &K) {
+        if let Some(count) = self.per_peer.get_mut(peer) {
+            *count -= 1;
+            if *count == 0 {
+                self.per_peer.remove(peer);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::PROTO_UDP;
+
+    fn key(peer: &str, sport: u16) -> FlowKey<String> {
+        FlowKey {
+            peer: peer.to_string(),
+            protocol: PROTO_UDP,
+            src: SocketAddrV4::new([10, 0, 0, 2].into(), sport),
+            dst: SocketAddrV4::new([192, 0, 2, 1].into(), 53),
+        }
+    }
+
+    #[test]
+    fn test_per_peer_limit() {
+        let config = NatConfig {
+            max_flows_per_peer: 2,
+            ..NatConfig::default()
+        };
+        let mut table = ConnTrack::new(config);
+        let now = Instant::now();
+        assert!(table.insert(key("a", 1), (), now).is_ok());
+        assert!(table.insert(key("a", 2), (), now).is_ok());
+        assert_eq!(
+            table.insert(key("a", 3), (), now),
+            Err(DropReason::NatLimit)
+        );
+        // Other peers have their own budget
+        assert!(table.insert(key("b", 1), (), now).is_ok());
+        assert_eq!(table.peer_flows(&"a".to_string()), 2);
+
+        table.remove(&key("a", 1));
+        assert!(table.insert(key("a", 3), (), now).is_ok());
+        assert_eq!(table.remove_peer(&"a".to_string()).len(), 2);
+        assert_eq!(table.len(), 1);
+    }
+
+    #[test]
+    fn test_idle_flows_expire() {
+        let mut table = ConnTrack::new(NatConfig::default());
+        let start = Instant::now();
+        table
+            .insert(key("a", 1), (), start)
+            .expect("insert in test");
+        table
+            .insert(key("a", 2), (), start)
+            .expect("insert in test");
+
+        // Traffic on flow 2 keeps it alive past the UDP timeout
+        let later = start + Duration::from_secs(45);
+        assert!(table.touch(&key("a", 2), later).is_some());
+        let expired = table.expire(start + Duration::from_secs(61));
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].0, key("a", 1));
+        assert!(table.touch(&key("a", 1), later).is_none());
+        assert_eq!(table.peer_flows(&"a".to_string()), 1);
+    }
+
+    #[test]
+    fn test_tcp_timeout_depends_on_state() {
+        let table: ConnTrack<String, ()> = ConnTrack::new(NatConfig::default());
+        assert!(table.timeout(PROTO_TCP, true) > table.timeout(PROTO_TCP, false));
+        assert_eq!(table.timeout(PROTO_ICMP, false), Duration::from_secs(30));
+    }
+}
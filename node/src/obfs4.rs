@@ -0,0 +1,850 @@
+// Copyright (c) 2025 Thor Thor
+// Author: Thor Thor (GitHub: https://github.com/codethor0)
+// Contact: codethor@gmail.com
+// LinkedIn: https://www.linkedin.com/in/thor-thor0
+// License: MIT (see LICENSE file for details)
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Nonce};
+use curve25519_dalek::constants::EIGHT_TORSION;
+use curve25519_dalek::{EdwardsPoint, MontgomeryPoint};
+use num_bigint::BigUint;
+use rand::{Rng, RngCore};
+use zeroize::Zeroize;
+
+use crate::rate_limit::RateLimiter;
+use crate::transport::PluggableTransport;
+
+/// Elligator2 representative of the client's ephemeral key
+const REPRESENTATIVE_SIZE: usize = 32;
+const NONCE_SIZE: usize = 12;
+const TAG_SIZE: usize = 16;
+
+/// Clients the server keeps keys for; the least recently heard from is
+/// evicted first
+const MAX_SERVER_SESSIONS: usize = 64;
+/// Source addresses remembered per client, which may roam
+const MAX_SESSION_ADDRS: usize = 4;
+/// Nonces remembered per client to drop replayed datagrams
+const MAX_SEEN_NONCES: usize = 1024;
+
+/// New representatives the server decodes per second (each costs an
+/// Elligator2 decode and an X25519 multiplication)
+const HANDSHAKES_PER_SECOND: u32 = 50;
+const HANDSHAKE_BURST: u32 = 100;
+
+/// Replies the client fails to open before it sends its representative
+/// again
+const MAX_FAILED_REPLIES: u32 = 8;
+/// Silence from the server after which the client sends its representative
+/// again (the server may have evicted its session)
+const REPLY_TIMEOUT: Duration = Duration::from_secs(10);
+
+const CLIENT_TO_SERVER_CONTEXT: &str = "cryprq obfs4 v1 client to server";
+const SERVER_TO_CLIENT_CONTEXT: &str = "cryprq obfs4 v1 server to client";
+
+/// Curve25519 Montgomery coefficient
+const MONTGOMERY_A: u32 = 486662;
+
+/// Arithmetic modulo 2^255 - 19 for the Elligator2 map
+///
+/// Only public values (public keys and their representatives) pass through
+/// here, so the variable-time big integers do not leak secrets.
+struct Field {
+    p: BigUint,
+}
+
+impl Field {
+    fn new() -> Self {
+        Self {
+            p: (BigUint::from(1u32) << 255u32) - 19u32,
+        }
+    }
+
+    fn decode(&self, bytes: &[u8; 32]) -> BigUint {
+        BigUint::from_bytes_le(bytes) % &self.p
+    }
+
+    fn encode(x: &BigUint) -> [u8; 32] {
+        let mut bytes = [0u8; 32];
+        let le = x.to_bytes_le();
+        bytes[..le.len()].copy_from_slice(&le);
+        bytes
+    }
+
+    fn add(&self, a: &BigUint, b: &BigUint) -> BigUint {
+        (a + b) % &self.p
+    }
+
+    fn mul(&self, a: &BigUint, b: &BigUint) -> BigUint {
+        (a * b) % &self.p
+    }
+
+    fn neg(&self, a: &BigUint) -> BigUint {
+        (&self.p - a % &self.p) % &self.p
+    }
+
+    fn inv(&self, a: &BigUint) -> BigUint {
+        a.modpow(&(&self.p - 2u32), &self.p)
+    }
+
+    fn is_zero(a: &BigUint) -> bool {
+        a.bits() == 0
+    }
+
+    /// Zero counts as a square
+    fn is_square(&self, a: &BigUint) -> bool {
+        let exp = (&self.p - 1u32) >> 1u32;
+        a.modpow(&exp, &self.p) != &self.p - 1u32
+    }
+
+    /// The square root in [0, (p - 1) / 2], if `a` is a square
+    fn sqrt(&self, a: &BigUint) -> Option<BigUint> {
+        // p = 5 (mod 8): a^((p + 3) / 8) is a root of a or of -a
+        let mut root = a.modpow(&((&self.p + 3u32) >> 3u32), &self.p);
+        if self.mul(&root, &root) != *a {
+            let sqrt_minus_one = BigUint::from(2u32).modpow(&((&self.p - 1u32) >> 2u32), &self.p);
+            root = self.mul(&root, &sqrt_minus_one);
+            if self.mul(&root, &root) != *a {
+                return None;
+            }
+        }
+        let negated = self.neg(&root);
+        Some(root.min(negated))
+    }
+}
+
+/// Map a representative to the u-coordinate of a Curve25519 point
+///
+/// The two high bits are random filler and are ignored.
+fn elligator2_point(representative: &[u8; 32]) -> [u8; 32] {
+    let field = Field::new();
+    let mut bytes = *representative;
+    bytes[31] &= 0x3F;
+    let r = field.decode(&bytes);
+    let a = BigUint::from(MONTGOMERY_A);
+    let one = BigUint::from(1u32);
+
+    // w = -A / (1 + 2r^2); u = w if w^3 + Aw^2 + w is a square, else -w - A
+    let r2 = field.mul(&r, &r);
+    let denominator = field.add(&one, &field.add(&r2, &r2));
+    let w = field.mul(&field.neg(&a), &field.inv(&denominator));
+    let w2 = field.mul(&w, &w);
+    let curve = field.add(&field.add(&field.mul(&w2, &w), &field.mul(&a, &w2)), &w);
+    let u = if field.is_square(&curve) {
+        w
+    } else {
+        field.neg(&field.add(&w, &a))
+    };
+    Field::encode(&u)
+}
+
+/// Representative of a point's u-coordinate, if the point has one
+///
+/// About half of all points are representable. The returned value is below
+/// 2^254; callers fill the two high bits with randomness.
+fn elligator2_representative(point: &[u8; 32]) -> Option<[u8; 32]> {
+    let field = Field::new();
+    let u = field.decode(point);
+    let u_plus_a = field.add(&u, &BigUint::from(MONTGOMERY_A));
+    if Field::is_zero(&u) || Field::is_zero(&u_plus_a) {
+        return None;
+    }
+    // r = sqrt(-u / (2(u + A)))
+    let denominator = field.add(&u_plus_a, &u_plus_a);
+    let r = field.sqrt(&field.mul(&field.neg(&u), &field.inv(&denominator)))?;
+    Some(Field::encode(&r))
+}
+
+/// Ephemeral keypair whose public key has an Elligator2 representative
+///
+/// The public key includes a random low-order component, so it is not
+/// confined to the prime-order subgroup (which would tell it apart from
+/// random bytes). Clamping makes the component vanish from the shared secret.
+fn generate_representable() -> ([u8; 32], [u8; 32]) {
+    let mut rng = rand::thread_rng();
+    loop {
+        let mut secret = [0u8; 32];
+        rng.fill_bytes(&mut secret);
+        let torsion = EIGHT_TORSION[rng.gen_range(0..EIGHT_TORSION.len())];
+        let public = (EdwardsPoint::mul_base_clamped(secret) + torsion).to_montgomery();
+        if let Some(mut representative) = elligator2_representative(&public.to_bytes()) {
+            representative[31] |= rng.gen::<u8>() & 0xC0;
+            return (secret, representative);
+        }
+        secret.zeroize();
+    }
+}
+
+/// Directional ciphers of one client's obfuscation session
+struct ObfsKeys {
+    client_to_server: ChaCha20Poly1305,
+    server_to_client: ChaCha20Poly1305,
+}
+
+impl ObfsKeys {
+    fn derive(shared: &[u8; 32], representative: &[u8; 32], server_public: &[u8; 32]) -> Self {
+        let mut material = [0u8; 96];
+        material[..32].copy_from_slice(shared);
+        material[32..64].copy_from_slice(representative);
+        material[64..].copy_from_slice(server_public);
+        let mut c2s = blake3::derive_key(CLIENT_TO_SERVER_CONTEXT, &material);
+        let mut s2c = blake3::derive_key(SERVER_TO_CLIENT_CONTEXT, &material);
+        let keys = Self {
+            client_to_server: ChaCha20Poly1305::new(&c2s.into()),
+            server_to_client: ChaCha20Poly1305::new(&s2c.into()),
+        };
+        material.zeroize();
+        c2s.zeroize();
+        s2c.zeroize();
+        keys
+    }
+}
+
+/// Encrypt under a random nonce: nonce || ciphertext || tag
+fn seal(cipher: &ChaCha20Poly1305, aad: &[u8], datagram: &[u8]) -> io::Result<Vec<u8>> {
+    let mut nonce = [0u8; NONCE_SIZE];
+    rand::thread_rng().fill_bytes(&mut nonce);
+    let ciphertext = cipher
+        .encrypt(&Nonce::from(nonce), Payload { msg: datagram, aad })
+        .map_err(|_| io::Error::new(io::ErrorKind::Other, "obfs4 encryption failed"))?;
+    let mut out = Vec::with_capacity(NONCE_SIZE + ciphertext.len());
+    out.extend_from_slice(&nonce);
+    out.extend_from_slice(&ciphertext);
+    Ok(out)
+}
+
+fn open(cipher: &ChaCha20Poly1305, aad: &[u8], sealed: &[u8]) -> Option<Vec<u8>> {
+    if sealed.len() < NONCE_SIZE + TAG_SIZE {
+        return None;
+    }
+    let (nonce, ciphertext) = sealed.split_at(NONCE_SIZE);
+    let mut nonce_bytes = [0u8; NONCE_SIZE];
+    nonce_bytes.copy_from_slice(nonce);
+    cipher
+        .decrypt(
+            &Nonce::from(nonce_bytes),
+            Payload {
+                msg: ciphertext,
+                aad,
+            },
+        )
+        .ok()
+}
+
+fn not_ours() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "not an obfs4 datagram")
+}
+
+/// Bridge key of an obfs4 server
+///
+/// The public half is handed to clients out of band; a client without it
+/// cannot produce a datagram the server answers.
+pub struct Obfs4ServerKey {
+    secret: [u8; 32],
+}
+
+impl Obfs4ServerKey {
+    pub fn generate() -> Self {
+        let mut secret = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut secret);
+        Self { secret }
+    }
+
+    pub fn from_bytes(secret: [u8; 32]) -> Self {
+        Self { secret }
+    }
+
+    pub fn public_key(&self) -> [u8; 32] {
+        MontgomeryPoint::mul_base_clamped(self.secret).to_bytes()
+    }
+}
+
+impl Drop for Obfs4ServerKey {
+    fn drop(&mut self) {
+        self.secret.zeroize();
+    }
+}
+
+struct ClientState {
+    representative: [u8; 32],
+    keys: ObfsKeys,
+    /// The server answered, so it holds our keys
+    confirmed: AtomicBool,
+    /// Replies that failed to open since the last one that did
+    failed_replies: AtomicU32,
+    last_reply: Mutex<Instant>,
+}
+
+impl ClientState {
+    /// Whether the server still holds our keys, as far as we can tell
+    fn is_confirmed(&self) -> bool {
+        if !self.confirmed.load(Ordering::Acquire) {
+            return false;
+        }
+        let silent = self
+            .last_reply
+            .lock()
+            .map(|last| last.elapsed() >= REPLY_TIMEOUT)
+            .unwrap_or(true);
+        if silent {
+            self.rehandshake("timeout");
+        }
+        !silent
+    }
+
+    fn rehandshake(&self, reason: &str) {
+        if self.confirmed.swap(false, Ordering::AcqRel) {
+            log::debug!("event=obfs4_rehandshake reason={}", reason);
+        }
+        self.failed_replies.store(0, Ordering::Relaxed);
+    }
+
+    fn deobfuscate(&self, datagram: &[u8]) -> io::Result<Vec<u8>> {
+        let Some(plaintext) = open(&self.keys.server_to_client, &[], datagram) else {
+            if self.failed_replies.fetch_add(1, Ordering::Relaxed) + 1 >= MAX_FAILED_REPLIES {
+                self.rehandshake("undecryptable_replies");
+            }
+            return Err(not_ours());
+        };
+        self.failed_replies.store(0, Ordering::Relaxed);
+        if let Ok(mut last) = self.last_reply.lock() {
+            *last = Instant::now();
+        }
+        self.confirmed.store(true, Ordering::Release);
+        Ok(plaintext)
+    }
+}
+
+/// One client's keys, identified by its representative
+struct ServerSession {
+    keys: Arc<ObfsKeys>,
+    /// Addresses the client sent from, least recent first
+    addrs: VecDeque<SocketAddr>,
+    seen_nonces: HashSet<[u8; NONCE_SIZE]>,
+    nonce_order: VecDeque<[u8; NONCE_SIZE]>,
+    last_seen: Instant,
+}
+
+/// Client sessions, keyed by representative rather than address so a
+/// datagram from a new address can never displace another client
+#[derive(Default)]
+struct Sessions {
+    by_representative: HashMap<[u8; 32], ServerSession>,
+    by_addr: HashMap<SocketAddr, [u8; 32]>,
+}
+
+impl Sessions {
+    fn keys_for(&self, addr: SocketAddr) -> Option<Arc<ObfsKeys>> {
+        let representative = self.by_addr.get(&addr)?;
+        Some(self.by_representative.get(representative)?.keys.clone())
+    }
+
+    /// Sessions to try for a datagram from `addr`: the address's own first,
+    /// then the others in case the client moved
+    fn candidates(&self, addr: SocketAddr) -> Vec<([u8; 32], Arc<ObfsKeys>)> {
+        let own = self.by_addr.get(&addr);
+        let mut candidates: Vec<_> = self
+            .by_representative
+            .iter()
+            .map(|(representative, session)| (*representative, session.keys.clone()))
+            .collect();
+        candidates.sort_by_key(|(representative, _)| Some(representative) != own);
+        candidates
+    }
+
+    /// Record an authenticated datagram from `addr`, creating the session
+    /// if needed; false if its nonce was seen before (a replay)
+    fn accept(
+        &mut self,
+        representative: [u8; 32],
+        keys: Arc<ObfsKeys>,
+        addr: SocketAddr,
+        nonce: [u8; NONCE_SIZE],
+        now: Instant,
+    ) -> bool {
+        if !self.by_representative.contains_key(&representative) {
+            if self.by_representative.len() >= MAX_SERVER_SESSIONS {
+                self.evict_least_recent();
+            }
+            self.by_representative.insert(
+                representative,
+                ServerSession {
+                    keys,
+                    addrs: VecDeque::new(),
+                    seen_nonces: HashSet::new(),
+                    nonce_order: VecDeque::new(),
+                    last_seen: now,
+                },
+            );
+        }
+        let Some(session) = self.by_representative.get_mut(&representative) else {
+            return false;
+        };
+        if !session.seen_nonces.insert(nonce) {
+            return false;
+        }
+        session.nonce_order.push_back(nonce);
+        if session.nonce_order.len() > MAX_SEEN_NONCES {
+            if let Some(oldest) = session.nonce_order.pop_front() {
+                session.seen_nonces.remove(&oldest);
+            }
+        }
+        session.last_seen = now;
+
+        session.addrs.retain(|known| *known != addr);
+        session.addrs.push_back(addr);
+        let dropped = match session.addrs.len() > MAX_SESSION_ADDRS {
+            true => session.addrs.pop_front(),
+            false => None,
+        };
+        if let Some(dropped) = dropped {
+            self.by_addr.remove(&dropped);
+        }
+        if let Some(previous) = self.by_addr.insert(addr, representative) {
+            // The address now belongs to this client; the other keeps its session
+            if previous != representative {
+                if let Some(other) = self.by_representative.get_mut(&previous) {
+                    other.addrs.retain(|known| *known != addr);
+                }
+            }
+        }
+        true
+    }
+
+    fn evict_least_recent(&mut self) {
+        let oldest = self
+            .by_representative
+            .iter()
+            .min_by_key(|(_, session)| session.last_seen)
+            .map(|(representative, _)| *representative);
+        if let Some(session) = oldest.and_then(|r| self.by_representative.remove(&r)) {
+            for addr in session.addrs {
+                self.by_addr.remove(&addr);
+            }
+            log::debug!("event=obfs4_session_evicted");
+        }
+    }
+}
+
+struct ServerState {
+    key: Obfs4ServerKey,
+    public: [u8; 32],
+    sessions: Mutex<Sessions>,
+    handshakes: Mutex<RateLimiter>,
+}
+
+enum Role {
+    Client(ClientState),
+    Server(ServerState),
+}
+
+/// obfs4-style transport: every datagram is indistinguishable from random bytes
+///
+/// The client derives keys from an ephemeral X25519 key and the server's
+/// bridge key, and sends its ephemeral public key Elligator2-encoded in
+/// front of each datagram until the server has answered (and again if the
+/// server goes quiet or its replies stop opening). All datagrams are
+/// ChaCha20-Poly1305 sealed under a random nonce, so neither the tunnel's
+/// record header nor the key exchange shows on the wire. The server never
+/// speaks first and stays silent towards anyone who does not know its
+/// bridge key.
+pub struct Obfs4Transport {
+    role: Role,
+}
+
+impl Obfs4Transport {
+    /// Client side, given the server's bridge public key
+    pub fn client(server_public: [u8; 32]) -> Self {
+        let (mut secret, representative) = generate_representable();
+        let shared = MontgomeryPoint(server_public).mul_clamped(secret);
+        secret.zeroize();
+        let mut canonical = representative;
+        canonical[31] &= 0x3F;
+        let keys = ObfsKeys::derive(shared.as_bytes(), &canonical, &server_public);
+        Self {
+            role: Role::Client(ClientState {
+                representative,
+                keys,
+                confirmed: AtomicBool::new(false),
+                failed_replies: AtomicU32::new(0),
+                last_reply: Mutex::new(Instant::now()),
+            }),
+        }
+    }
+
+    /// Server side, holding the bridge key
+    pub fn server(key: Obfs4ServerKey) -> Self {
+        let public = key.public_key();
+        Self {
+            role: Role::Server(ServerState {
+                key,
+                public,
+                sessions: Mutex::new(Sessions::default()),
+                handshakes: Mutex::new(RateLimiter::new(HANDSHAKES_PER_SECOND, HANDSHAKE_BURST)),
+            }),
+        }
+    }
+}
+
+impl ServerState {
+    fn lock_sessions(&self) -> io::Result<MutexGuard<'_, Sessions>> {
+        self.sessions
+            .lock()
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))
+    }
+
+    fn keys_for(&self, addr: SocketAddr) -> io::Result<Option<Arc<ObfsKeys>>> {
+        Ok(self.lock_sessions()?.keys_for(addr))
+    }
+
+    fn accept(
+        &self,
+        representative: [u8; 32],
+        keys: Arc<ObfsKeys>,
+        addr: SocketAddr,
+        sealed: &[u8],
+    ) -> io::Result<()> {
+        let mut nonce = [0u8; NONCE_SIZE];
+        nonce.copy_from_slice(&sealed[..NONCE_SIZE]);
+        match self
+            .lock_sessions()?
+            .accept(representative, keys, addr, nonce, Instant::now())
+        {
+            true => Ok(()),
+            false => Err(not_ours()),
+        }
+    }
+
+    /// Spend a token for decoding a new representative
+    fn allow_handshake(&self) -> io::Result<()> {
+        self.handshakes
+            .lock()
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?
+            .check_packet(0)
+            .map_err(|_| io::Error::new(io::ErrorKind::WouldBlock, "obfs4 handshake rate exceeded"))
+    }
+
+    fn deobfuscate(&self, addr: SocketAddr, datagram: &[u8]) -> io::Result<Vec<u8>> {
+        let candidates = self.lock_sessions()?.candidates(addr);
+        for (representative, keys) in &candidates {
+            if let Some(plaintext) = open(&keys.client_to_server, &[], datagram) {
+                self.accept(*representative, keys.clone(), addr, datagram)?;
+                return Ok(plaintext);
+            }
+        }
+
+        // Handshake form: representative || nonce || ciphertext
+        if datagram.len() < REPRESENTATIVE_SIZE + NONCE_SIZE {
+            return Err(not_ours());
+        }
+        let (raw_representative, sealed) = datagram.split_at(REPRESENTATIVE_SIZE);
+        let mut representative = [0u8; 32];
+        representative.copy_from_slice(raw_representative);
+        representative[31] &= 0x3F;
+
+        let known = candidates
+            .iter()
+            .find(|(known, _)| *known == representative)
+            .map(|(_, keys)| keys.clone());
+        let keys = match known {
+            Some(keys) => keys,
+            None => {
+                self.allow_handshake()?;
+                let client_public = elligator2_point(&representative);
+                let shared = MontgomeryPoint(client_public).mul_clamped(self.key.secret);
+                if shared.as_bytes().iter().all(|&b| b == 0) {
+                    return Err(not_ours());
+                }
+                Arc::new(ObfsKeys::derive(
+                    shared.as_bytes(),
+                    &representative,
+                    &self.public,
+                ))
+            }
+        };
+        let plaintext =
+            open(&keys.client_to_server, raw_representative, sealed).ok_or_else(not_ours)?;
+        self.accept(representative, keys, addr, sealed)?;
+        Ok(plaintext)
+    }
+}
+
+impl PluggableTransport for Obfs4Transport {
+    fn name(&self) -> &'static str {
+        "obfs4"
+    }
+
+    fn obfuscate(&self, peer: SocketAddr, datagram: &[u8]) -> io::Result<Vec<u8>> {
+        match &self.role {
+            Role::Client(client) => {
+                if client.is_confirmed() {
+                    return seal(&client.keys.client_to_server, &[], datagram);
+                }
+                let sealed = seal(
+                    &client.keys.client_to_server,
+                    &client.representative,
+                    datagram,
+                )?;
+                let mut out = Vec::with_capacity(REPRESENTATIVE_SIZE + sealed.len());
+                out.extend_from_slice(&client.representative);
+                out.extend_from_slice(&sealed);
+                Ok(out)
+            }
+            Role::Server(server) => {
+                let keys = server.keys_for(peer)?.ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::NotConnected,
+                        "obfs4 client has not sent a handshake from this address",
+                    )
+                })?;
+                seal(&keys.server_to_client, &[], datagram)
+            }
+        }
+    }
+
+    fn deobfuscate(&self, peer: SocketAddr, datagram: &[u8]) -> io::Result<Vec<u8>> {
+        match &self.role {
+            Role::Client(client) => client.deobfuscate(datagram),
+            Role::Server(server) => server.deobfuscate(peer, datagram),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_elligator2_map() {
+        // Same input and output as curve25519-dalek's own Elligator2 test
+        let input: [u8; 32] = std::array::from_fn(|i| i as u8);
+        let expected = [
+            0x5f, 0x35, 0x20, 0x00, 0x1c, 0x6c, 0x99, 0x36, 0xa3, 0x12, 0x06, 0xaf, 0xe7, 0xc7,
+            0xac, 0x22, 0x4e, 0x88, 0x61, 0x61, 0x9b, 0xf9, 0x88, 0x72, 0x44, 0x49, 0x15, 0x89,
+            0x9d, 0x95, 0xf4, 0x6e,
+        ];
+        assert_eq!(elligator2_point(&input), expected);
+        assert_eq!(elligator2_point(&[0; 32]), [0; 32]);
+
+        for _ in 0..8 {
+            let (secret, representative) = generate_representable();
+            let point = elligator2_point(&representative);
+            // Same shared secret as the prime-order public key
+            let server = Obfs4ServerKey::generate();
+            let server_public = MontgomeryPoint(server.public_key());
+            assert_eq!(
+                MontgomeryPoint(point).mul_clamped(server.secret),
+                server_public.mul_clamped(secret)
+            );
+        }
+
+        // Points on the prime-order subgroup round-trip when representable
+        let mut representable = 0;
+        for seed in 0u8..32 {
+            let point = MontgomeryPoint::mul_base_clamped([seed; 32]).to_bytes();
+            if let Some(representative) = elligator2_representative(&point) {
+                assert_eq!(elligator2_point(&representative), point);
+                representable += 1;
+            }
+        }
+        assert!(representable > 0 && representable < 32);
+    }
+
+    #[test]
+    fn test_obfs4_roundtrip_and_probe_resistance() {
+        let client_addr: SocketAddr = "192.0.2.1:4000".parse().expect("addr in test");
+        let roamed_addr: SocketAddr = "192.0.2.1:4001".parse().expect("addr in test");
+        let key = Obfs4ServerKey::generate();
+        let client = Obfs4Transport::client(key.public_key());
+        let server = Obfs4Transport::server(key);
+        let server_addr: SocketAddr = "198.51.100.1:443".parse().expect("addr in test");
+
+        // The server cannot speak first
+        assert!(server.obfuscate(client_addr, b"hello").is_err());
+
+        let first = client
+            .obfuscate(server_addr, b"record one")
+            .expect("obfuscate in test");
+        assert_eq!(
+            first.len(),
+            REPRESENTATIVE_SIZE + NONCE_SIZE + 10 + TAG_SIZE
+        );
+        assert!(!first.windows(10).any(|w| w == b"record one"));
+        assert_eq!(
+            server
+                .deobfuscate(client_addr, &first)
+                .expect("deobfuscate in test"),
+            b"record one"
+        );
+
+        // Until the server answers, the client repeats its representative
+        let second = client
+            .obfuscate(server_addr, b"record two")
+            .expect("obfuscate in test");
+        assert_eq!(first[..REPRESENTATIVE_SIZE], second[..REPRESENTATIVE_SIZE]);
+        let reply = server
+            .obfuscate(client_addr, b"reply")
+            .expect("obfuscate in test");
+        assert_eq!(
+            client
+                .deobfuscate(server_addr, &reply)
+                .expect("deobfuscate in test"),
+            b"reply"
+        );
+        let third = client
+            .obfuscate(server_addr, b"record three")
+            .expect("obfuscate in test");
+        assert_eq!(third.len(), NONCE_SIZE + 12 + TAG_SIZE);
+
+        // A client that moved keeps its session
+        assert_eq!(
+            server
+                .deobfuscate(roamed_addr, &third)
+                .expect("deobfuscate in test"),
+            b"record three"
+        );
+        assert!(server.obfuscate(roamed_addr, b"reply").is_ok());
+
+        // Probes without the bridge key, and tampered datagrams, are dropped
+        let stranger = Obfs4Transport::client(Obfs4ServerKey::generate().public_key());
+        let probe = stranger
+            .obfuscate(server_addr, b"probe")
+            .expect("obfuscate in test");
+        assert!(server.deobfuscate(client_addr, &probe).is_err());
+        let mut tampered = third.clone();
+        tampered[NONCE_SIZE] ^= 1;
+        assert!(server.deobfuscate(client_addr, &tampered).is_err());
+        assert!(server.deobfuscate(client_addr, &[0u8; 8]).is_err());
+    }
+
+    /// Handshake, answer and confirm one client from `addr`
+    fn connect(server: &Obfs4Transport, client: &Obfs4Transport, addr: SocketAddr) {
+        let server_addr: SocketAddr = "198.51.100.1:443".parse().expect("addr in test");
+        let hello = client
+            .obfuscate(server_addr, b"hello")
+            .expect("obfuscate in test");
+        server
+            .deobfuscate(addr, &hello)
+            .expect("deobfuscate in test");
+        let reply = server.obfuscate(addr, b"reply").expect("obfuscate in test");
+        client
+            .deobfuscate(server_addr, &reply)
+            .expect("deobfuscate in test");
+    }
+
+    #[test]
+    fn test_obfs4_sessions_survive_replay_and_eviction() {
+        let key = Obfs4ServerKey::generate();
+        let public = key.public_key();
+        let server = Obfs4Transport::server(key);
+        let server_addr: SocketAddr = "198.51.100.1:443".parse().expect("addr in test");
+        let addr: SocketAddr = "192.0.2.1:4000".parse().expect("addr in test");
+        let client = Obfs4Transport::client(public);
+
+        let hello = client
+            .obfuscate(server_addr, b"hello")
+            .expect("obfuscate in test");
+        server
+            .deobfuscate(addr, &hello)
+            .expect("deobfuscate in test");
+        let reply = server.obfuscate(addr, b"reply").expect("obfuscate in test");
+        client
+            .deobfuscate(server_addr, &reply)
+            .expect("deobfuscate in test");
+        let established = client
+            .obfuscate(server_addr, b"data")
+            .expect("obfuscate in test");
+        server
+            .deobfuscate(addr, &established)
+            .expect("deobfuscate in test");
+
+        // Replays from spoofed sources neither open nor take over the session
+        for port in 0..(MAX_SERVER_SESSIONS as u16 * 2) {
+            let spoofed = SocketAddr::from(([203, 0, 113, 1], 5000 + port));
+            assert!(server.deobfuscate(spoofed, &hello).is_err());
+            assert!(server.deobfuscate(spoofed, &established).is_err());
+            assert!(server.obfuscate(spoofed, b"reply").is_err());
+        }
+        assert!(server.obfuscate(addr, b"reply").is_ok());
+
+        // New clients evict the least recently heard from
+        for port in 0..MAX_SERVER_SESSIONS as u16 {
+            let other = Obfs4Transport::client(public);
+            connect(
+                &server,
+                &other,
+                SocketAddr::from(([192, 0, 2, 2], 6000 + port)),
+            );
+        }
+        assert!(server.obfuscate(addr, b"reply").is_err());
+        let lost = client
+            .obfuscate(server_addr, b"lost")
+            .expect("obfuscate in test");
+        assert_eq!(lost.len(), NONCE_SIZE + 4 + TAG_SIZE);
+        assert!(server.deobfuscate(addr, &lost).is_err());
+
+        // Replies that stop opening make the client send its representative again
+        for _ in 0..MAX_FAILED_REPLIES {
+            assert!(client.deobfuscate(server_addr, &[0u8; 40]).is_err());
+        }
+        let retry = client
+            .obfuscate(server_addr, b"retry")
+            .expect("obfuscate in test");
+        assert_eq!(retry.len(), REPRESENTATIVE_SIZE + NONCE_SIZE + 5 + TAG_SIZE);
+        assert_eq!(
+            server
+                .deobfuscate(addr, &retry)
+                .expect("deobfuscate in test"),
+            b"retry"
+        );
+        assert!(server.obfuscate(addr, b"reply").is_ok());
+    }
+
+    #[test]
+    fn test_obfs4_client_rehandshakes_after_silence() {
+        let key = Obfs4ServerKey::generate();
+        let client = Obfs4Transport::client(key.public_key());
+        let server = Obfs4Transport::server(key);
+        let server_addr: SocketAddr = "198.51.100.1:443".parse().expect("addr in test");
+        connect(
+            &server,
+            &client,
+            "192.0.2.1:4000".parse().expect("addr in test"),
+        );
+
+        let confirmed = client
+            .obfuscate(server_addr, b"data")
+            .expect("obfuscate in test");
+        assert_eq!(confirmed.len(), NONCE_SIZE + 4 + TAG_SIZE);
+
+        if let Role::Client(state) = &client.role {
+            let long_ago = Instant::now()
+                .checked_sub(REPLY_TIMEOUT)
+                .expect("instant in test");
+            *state.last_reply.lock().expect("lock in test") = long_ago;
+        }
+        let retry = client
+            .obfuscate(server_addr, b"data")
+            .expect("obfuscate in test");
+        assert_eq!(retry.len(), REPRESENTATIVE_SIZE + NONCE_SIZE + 4 + TAG_SIZE);
+    }
+
+    #[test]
+    fn test_obfs4_handshake_attempts_are_rate_limited() {
+        let server = Obfs4Transport::server(Obfs4ServerKey::generate());
+        let addr: SocketAddr = "192.0.2.1:4000".parse().expect("addr in test");
+        let limited = (0..HANDSHAKE_BURST * 4)
+            .filter_map(|_| server.deobfuscate(addr, &[7u8; 64]).err())
+            .filter(|e| e.kind() == io::ErrorKind::WouldBlock)
+            .count();
+        assert!(limited > 0);
+    }
+}
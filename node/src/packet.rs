@@ -0,0 +1,310 @@
+// Copyright (c) 2025 Thor Thor
+// Author: Thor Thor (GitHub: https://github.com/codethor0)
+// Contact: codethor@gmail.com
+// LinkedIn: https://www.linkedin.com/in/thor-thor0
+// License: MIT (see LICENSE file for details)
+
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU16, Ordering};
+
+pub(crate) const PROTO_ICMP: u8 = 1;
+pub(crate) const PROTO_TCP: u8 = 6;
+pub(crate) const PROTO_UDP: u8 = 17;
+pub(crate) const PROTO_ICMPV6: u8 = 58;
+
+/// IPv4 identification counter for generated packets
+static NEXT_IPV4_ID: AtomicU16 = AtomicU16::new(1);
+
+/// Minimal view of an IPv4/IPv6 packet header read from TUN
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IpHeader {
+    pub src: IpAddr,
+    pub dst: IpAddr,
+    /// IPv4 protocol / IPv6 next header
+    pub protocol: u8,
+    /// Offset of the transport header
+    pub header_len: usize,
+}
+
+impl IpHeader {
+    /// Parse the IP header of a raw packet
+    ///
+    /// Returns None for truncated packets and non-IP frames.
+    pub fn parse(packet: &[u8]) -> Option<Self> {
+        match packet.first()? >> 4 {
+            4 => {
+                let header_len = ((packet[0] & 0x0F) as usize) * 4;
+                if header_len < 20 || packet.len() < header_len {
+                    return None;
+                }
+                Some(Self {
+                    src: IpAddr::V4(Ipv4Addr::new(
+                        packet[12], packet[13], packet[14], packet[15],
+                    )),
+                    dst: IpAddr::V4(Ipv4Addr::new(
+                        packet[16], packet[17], packet[18], packet[19],
+                    )),
+                    protocol: packet[9],
+                    header_len,
+                })
+            }
+            6 => {
+                if packet.len() < 40 {
+                    return None;
+                }
+                let mut src = [0u8; 16];
+                let mut dst = [0u8; 16];
+                src.copy_from_slice(&packet[8..24]);
+                dst.copy_from_slice(&packet[24..40]);
+                Some(Self {
+                    src: IpAddr::V6(Ipv6Addr::from(src)),
+                    dst: IpAddr::V6(Ipv6Addr::from(dst)),
+                    protocol: packet[6],
+                    header_len: 40,
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+/// IP network prefix (`10.0.0.0/24`, `fd00::/64`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct IpPrefix {
+    addr: IpAddr,
+    len: u8,
+}
+
+impl IpPrefix {
+    /// Create a prefix; host bits of `addr` are cleared
+    pub fn new(addr: IpAddr, len: u8) -> Option<Self> {
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        if len > max {
+            return None;
+        }
+        let addr = match addr {
+            IpAddr::V4(a) => IpAddr::V4(Ipv4Addr::from(u32::from(a) & v4_mask(len))),
+            IpAddr::V6(a) => IpAddr::V6(Ipv6Addr::from(u128::from(a) & v6_mask(len))),
+        };
+        Some(Self { addr, len })
+    }
+
+    /// Prefix covering exactly one address
+    pub fn host(addr: IpAddr) -> Self {
+        let len = if addr.is_ipv4() { 32 } else { 128 };
+        Self { addr, len }
+    }
+
+    pub fn addr(&self) -> IpAddr {
+        self.addr
+    }
+
+    pub fn len(&self) -> u8 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns true if `addr` falls inside this prefix
+    pub fn contains(&self, addr: IpAddr) -> bool {
+        match (self.addr, addr) {
+            (IpAddr::V4(net), IpAddr::V4(a)) => u32::from(a) & v4_mask(self.len) == u32::from(net),
+            (IpAddr::V6(net), IpAddr::V6(a)) => {
+                u128::from(a) & v6_mask(self.len) == u128::from(net)
+            }
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for IpPrefix {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.len)
+    }
+}
+
+impl FromStr for IpPrefix {
+    type Err = String;
+
+    /// Parses `addr/len`; a bare address is a host prefix
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, len) = match s.split_once('/') {
+            Some((addr, len)) => (addr, Some(len)),
+            None => (s, None),
+        };
+        let addr: IpAddr = addr
+            .parse()
+            .map_err(|_| format!("invalid address in prefix {}", s))?;
+        match len {
+            Some(len) => {
+                let len: u8 = len
+                    .parse()
+                    .map_err(|_| format!("invalid length in prefix {}", s))?;
+                Self::new(addr, len).ok_or_else(|| format!("prefix length out of range in {}", s))
+            }
+            None => Ok(Self::host(addr)),
+        }
+    }
+}
+
+/// Add `data` to a running Internet checksum (RFC 1071)
+pub(crate) fn checksum_add(mut sum: u32, data: &[u8]) -> u32 {
+    let mut chunks = data.chunks_exact(2);
+    for chunk in &mut chunks {
+        sum += u16::from_be_bytes([chunk[0], chunk[1]]) as u32;
+    }
+    if let [last] = chunks.remainder() {
+        sum += (*last as u32) << 8;
+    }
+    sum
+}
+
+/// Fold a running checksum into its final one's complement form
+pub(crate) fn checksum_finish(mut sum: u32) -> u16 {
+    while sum >> 16 != 0 {
+        sum = (sum & 0xFFFF) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+/// Build an IPv4 packet around a transport segment
+///
+/// The segment's checksum field must be zero; it is filled in here for TCP,
+/// UDP and ICMP along with the IP header checksum.
+pub(crate) fn build_ipv4_packet(
+    src: Ipv4Addr,
+    dst: Ipv4Addr,
+    protocol: u8,
+    mut segment: Vec<u8>,
+) -> Vec<u8> {
+    let checksum_offset = match protocol {
+        PROTO_TCP => Some(16),
+        PROTO_UDP => Some(6),
+        PROTO_ICMP => Some(2),
+        _ => None,
+    };
+    if let Some(offset) = checksum_offset.filter(|o| segment.len() >= o + 2) {
+        let mut sum = 0;
+        if protocol != PROTO_ICMP {
+            sum = checksum_add(sum, &src.octets());
+            sum = checksum_add(sum, &dst.octets());
+            sum += protocol as u32 + segment.len() as u32;
+        }
+        let mut checksum = checksum_finish(checksum_add(sum, &segment));
+        if protocol == PROTO_UDP && checksum == 0 {
+            checksum = 0xFFFF;
+        }
+        segment[offset..offset + 2].copy_from_slice(&checksum.to_be_bytes());
+    }
+
+    let total_len = (20 + segment.len()) as u16;
+    let id = NEXT_IPV4_ID.fetch_add(1, Ordering::Relaxed);
+    let mut packet = Vec::with_capacity(total_len as usize);
+    packet.extend_from_slice(&[0x45, 0]);
+    packet.extend_from_slice(&total_len.to_be_bytes());
+    packet.extend_from_slice(&id.to_be_bytes());
+    packet.extend_from_slice(&[0x40, 0, 64, protocol, 0, 0]);
+    packet.extend_from_slice(&src.octets());
+    packet.extend_from_slice(&dst.octets());
+    let checksum = checksum_finish(checksum_add(0, &packet));
+    packet[10..12].copy_from_slice(&checksum.to_be_bytes());
+    packet.extend_from_slice(&segment);
+    packet
+}
+
+fn v4_mask(len: u8) -> u32 {
+    u32::MAX.checked_shl(32 - len as u32).unwrap_or(0)
+}
+
+fn v6_mask(len: u8) -> u128 {
+    u128::MAX.checked_shl(128 - len as u32).unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ipv4_packet(src: [u8; 4], dst: [u8; 4], protocol: u8) -> Vec<u8> {
+        let mut packet = vec![0u8; 28];
+        packet[0] = 0x45;
+        packet[9] = protocol;
+        packet[12..16].copy_from_slice(&src);
+        packet[16..20].copy_from_slice(&dst);
+        packet
+    }
+
+    #[test]
+    fn test_parse_ipv4_header() {
+        let packet = ipv4_packet([10, 0, 0, 2], [10, 0, 0, 3], 17);
+        let header = IpHeader::parse(&packet).expect("valid IPv4 header in test");
+        assert_eq!(header.src, "10.0.0.2".parse::<IpAddr>().expect("addr"));
+        assert_eq!(header.dst, "10.0.0.3".parse::<IpAddr>().expect("addr"));
+        assert_eq!(header.protocol, 17);
+        assert_eq!(header.header_len, 20);
+    }
+
+    #[test]
+    fn test_parse_ipv6_header() {
+        let mut packet = vec![0u8; 48];
+        packet[0] = 0x60;
+        packet[6] = 58;
+        packet[23] = 1;
+        packet[39] = 2;
+        let header = IpHeader::parse(&packet).expect("valid IPv6 header in test");
+        assert_eq!(header.src, "::1".parse::<IpAddr>().expect("addr"));
+        assert_eq!(header.dst, "::2".parse::<IpAddr>().expect("addr"));
+        assert_eq!(header.protocol, 58);
+    }
+
+    #[test]
+    fn test_parse_rejects_garbage() {
+        assert!(IpHeader::parse(&[]).is_none());
+        assert!(IpHeader::parse(&[0x45, 0, 0]).is_none());
+        assert!(IpHeader::parse(&[0x10; 40]).is_none());
+    }
+
+    #[test]
+    fn test_build_ipv4_packet_checksums() {
+        let src = Ipv4Addr::new(192, 0, 2, 1);
+        let dst = Ipv4Addr::new(10, 0, 0, 2);
+        let mut udp = vec![0u8; 8];
+        udp[0..2].copy_from_slice(&53u16.to_be_bytes());
+        udp[2..4].copy_from_slice(&4000u16.to_be_bytes());
+        udp[4..6].copy_from_slice(&9u16.to_be_bytes());
+        udp.push(0xAB);
+        let packet = build_ipv4_packet(src, dst, PROTO_UDP, udp);
+
+        let header = IpHeader::parse(&packet).expect("valid IPv4 header in test");
+        assert_eq!(header.src, IpAddr::V4(src));
+        assert_eq!(header.protocol, PROTO_UDP);
+        // A correct checksum sums to zero when verified
+        assert_eq!(checksum_finish(checksum_add(0, &packet[..20])), 0);
+        let mut sum = checksum_add(0, &src.octets());
+        sum = checksum_add(sum, &dst.octets());
+        sum += PROTO_UDP as u32 + 9;
+        assert_eq!(checksum_finish(checksum_add(sum, &packet[20..])), 0);
+    }
+
+    #[test]
+    fn test_prefix_contains() {
+        let net: IpPrefix = "10.0.0.77/24".parse().expect("valid prefix in test");
+        assert_eq!(net.to_string(), "10.0.0.0/24");
+        assert!(net.contains("10.0.0.200".parse().expect("addr")));
+        assert!(!net.contains("10.0.1.1".parse().expect("addr")));
+        assert!(!net.contains("::1".parse().expect("addr")));
+
+        let any: IpPrefix = "0.0.0.0/0".parse().expect("valid prefix in test");
+        assert!(any.contains("192.168.1.1".parse().expect("addr")));
+
+        let v6: IpPrefix = "fd00::/64".parse().expect("valid prefix in test");
+        assert!(v6.contains("fd00::1234".parse().expect("addr")));
+
+        let host: IpPrefix = "10.0.0.5".parse().expect("valid prefix in test");
+        assert_eq!(host.len(), 32);
+        assert!("10.0.0.0/33".parse::<IpPrefix>().is_err());
+    }
+}
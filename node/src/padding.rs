@@ -4,9 +4,92 @@
 // LinkedIn: https://www.linkedin.com/in/thor-thor0
 // License: MIT (see LICENSE file for details)
 
+use std::io;
+
 use rand::Rng;
 
+use crate::CoverTrafficConfig;
+
+/// Trailer ending a padded plaintext: the padding length (2 bytes, big-endian)
+pub(crate) const PADDING_TRAILER_SIZE: usize = 2;
+
+/// How a session pads its records inside the AEAD (Section 6.5)
+///
+/// Sizes count the whole padded plaintext: payload, padding and trailer.
+/// Padding never makes a record larger than the path allows, so a payload
+/// close to the limit gets only the trailer.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum PaddingPolicy {
+    /// Records are sent unpadded
+    #[default]
+    None,
+    /// Pad to the smallest bucket that fits; beyond the largest, to the path limit
+    Buckets(Vec<usize>),
+    /// Pad every record to the largest plaintext the path MTU allows
+    Mtu,
+    /// Add between 0 and `max` bytes of padding, chosen uniformly
+    Random { max: usize },
+}
+
+impl PaddingPolicy {
+    /// Buckets suited to VPN traffic below the base path MTU
+    pub fn default_buckets() -> Self {
+        PaddingPolicy::Buckets(vec![128, 256, 512, 1024])
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        *self != PaddingPolicy::None
+    }
+
+    /// Padded plaintext size for `len` payload bytes when `limit` bytes fit
+    fn padded_len(&self, len: usize, limit: usize) -> usize {
+        let min = len + PADDING_TRAILER_SIZE;
+        let target = match self {
+            PaddingPolicy::None => min,
+            PaddingPolicy::Buckets(buckets) => buckets
+                .iter()
+                .copied()
+                .filter(|&bucket| bucket >= min)
+                .min()
+                .unwrap_or(limit),
+            PaddingPolicy::Mtu => limit,
+            PaddingPolicy::Random { max } => min + rand::thread_rng().gen_range(0..=*max),
+        };
+        target.min(limit).max(min)
+    }
+}
+
+/// Pad a record payload according to `policy`
+///
+/// The result is the plaintext of a `FLAG_PADDED` record: payload, zero
+/// padding, then the padding length. `limit` is the largest plaintext the
+/// record may carry.
+pub fn pad_plaintext(payload: &[u8], policy: &PaddingPolicy, limit: usize) -> Vec<u8> {
+    let padded_len = policy.padded_len(payload.len(), limit);
+    let padding = (padded_len - payload.len() - PADDING_TRAILER_SIZE).min(u16::MAX as usize);
+    let mut plaintext = Vec::with_capacity(padded_len);
+    plaintext.extend_from_slice(payload);
+    plaintext.resize(payload.len() + padding, 0);
+    plaintext.extend_from_slice(&(padding as u16).to_be_bytes());
+    plaintext
+}
+
+/// Strip the padding from the plaintext of a `FLAG_PADDED` record
+pub fn unpad_plaintext(plaintext: &[u8]) -> io::Result<&[u8]> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "malformed record padding");
+    let body_len = plaintext
+        .len()
+        .checked_sub(PADDING_TRAILER_SIZE)
+        .ok_or_else(invalid)?;
+    let padding = u16::from_be_bytes([plaintext[body_len], plaintext[body_len + 1]]) as usize;
+    let payload_len = body_len.checked_sub(padding).ok_or_else(invalid)?;
+    Ok(&plaintext[..payload_len])
+}
+
 /// Padding configuration for traffic analysis resistance
+#[deprecated(
+    note = "records are padded inside the AEAD: use `PaddingPolicy` with `Tunnel::set_padding`, and `CoverTrafficConfig` for constant-rate traffic"
+)]
 #[derive(Debug, Clone)]
 pub struct PaddingConfig {
     /// Minimum packet size (bytes)
@@ -21,6 +104,7 @@ pub struct PaddingConfig {
     pub constant_rate_interval_ms: u64,
 }
 
+#[allow(deprecated)]
 impl Default for PaddingConfig {
     fn default() -> Self {
         Self {
@@ -33,10 +117,31 @@ impl Default for PaddingConfig {
     }
 }
 
+#[allow(deprecated)]
+impl PaddingConfig {
+    /// Record padding equivalent to padding every packet to the target size
+    pub fn policy(&self) -> PaddingPolicy {
+        PaddingPolicy::Buckets(vec![self.target_packet_size])
+    }
+
+    /// Cover traffic equivalent to `constant_rate`, if enabled
+    pub fn cover_traffic(&self) -> Option<CoverTrafficConfig> {
+        self.constant_rate.then(|| CoverTrafficConfig {
+            packets_per_second: 1000.0 / self.constant_rate_interval_ms.max(1) as f64,
+            record_size: self.target_packet_size,
+            ..CoverTrafficConfig::default()
+        })
+    }
+}
+
 /// Add padding to a packet to resist traffic analysis
 ///
 /// Pads packets to a target size using random padding to prevent
 /// packet size fingerprinting attacks.
+#[deprecated(
+    note = "the padding length is not recorded; use `pad_plaintext`, or `Tunnel::set_padding` to pad every record"
+)]
+#[allow(deprecated)]
 pub fn pad_packet(data: &[u8], config: &PaddingConfig) -> Vec<u8> {
     let current_size = data.len();
 
@@ -70,6 +175,7 @@ pub fn pad_packet(data: &[u8], config: &PaddingConfig) -> Vec<u8> {
 /// In practice, padding removal depends on the protocol.
 /// This is a simple implementation that assumes the original
 /// data length is encoded in the packet header.
+#[deprecated(note = "use `unpad_plaintext`, which reads the padding length from the record")]
 pub fn unpad_packet(padded: &[u8], original_len: usize) -> Vec<u8> {
     if original_len > padded.len() {
         return padded.to_vec(); // Return as-is if invalid
@@ -82,6 +188,52 @@ mod tests {
     use super::*;
 
     #[test]
+    fn test_pad_roundtrip() {
+        let policies = [
+            PaddingPolicy::default_buckets(),
+            PaddingPolicy::Mtu,
+            PaddingPolicy::Random { max: 64 },
+        ];
+        for policy in &policies {
+            for len in [0usize, 1, 125, 126, 700, 1100, 1198] {
+                let payload: Vec<u8> = (0..len).map(|i| i as u8).collect();
+                let padded = pad_plaintext(&payload, policy, 1200);
+                assert!(padded.len() <= 1200);
+                assert_eq!(
+                    unpad_plaintext(&padded).expect("unpad in test"),
+                    &payload[..]
+                );
+            }
+        }
+
+        // A payload at the limit still gets its trailer
+        let padded = pad_plaintext(&[1; 1200], &PaddingPolicy::Mtu, 1200);
+        assert_eq!(padded.len(), 1202);
+        assert!(unpad_plaintext(&[0]).is_err());
+        assert!(unpad_plaintext(&[0, 0, 5]).is_err());
+    }
+
+    #[test]
+    fn test_bucket_and_mtu_sizes() {
+        let buckets = PaddingPolicy::default_buckets();
+        assert_eq!(pad_plaintext(&[0; 10], &buckets, 1200).len(), 128);
+        assert_eq!(pad_plaintext(&[0; 126], &buckets, 1200).len(), 128);
+        assert_eq!(pad_plaintext(&[0; 127], &buckets, 1200).len(), 256);
+        assert_eq!(pad_plaintext(&[0; 1023], &buckets, 1200).len(), 1200);
+        assert_eq!(
+            pad_plaintext(&[0; 10], &PaddingPolicy::Mtu, 1200).len(),
+            1200
+        );
+
+        let random = PaddingPolicy::Random { max: 16 };
+        for _ in 0..32 {
+            let len = pad_plaintext(&[0; 10], &random, 1200).len();
+            assert!((12..=28).contains(&len));
+        }
+    }
+
+    #[test]
+    #[allow(deprecated)]
     fn test_pad_packet() {
         let config = PaddingConfig::default();
         let data = vec![1u8; 100];
@@ -89,9 +241,15 @@ mod tests {
 
         assert_eq!(padded.len(), config.target_packet_size);
         assert_eq!(&padded[..100], &data);
+
+        // Migration: the same target as a record padding policy
+        let record = pad_plaintext(&data, &config.policy(), 1200);
+        assert_eq!(record.len(), config.target_packet_size);
+        assert!(config.cover_traffic().is_none());
     }
 
     #[test]
+    #[allow(deprecated)]
     fn test_unpad_packet() {
         let padded = vec![1u8, 2u8, 3u8, 4u8, 5u8];
         let unpadded = unpad_packet(&padded, 3);
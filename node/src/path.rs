@@ -0,0 +1,164 @@
+// Copyright (c) 2025 Thor Thor
+// Author: Thor Thor (GitHub: https://github.com/codethor0)
+// Contact: codethor@gmail.com
+// LinkedIn: https://www.linkedin.com/in/thor-thor0
+// License: MIT (see LICENSE file for details)
+
+use std::net::SocketAddr;
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+use rand::rngs::OsRng;
+use rand::RngCore;
+
+use crate::{ControlMessage, TunnelError};
+
+/// How long a path challenge stays valid before a new one may be sent
+const PATH_CHALLENGE_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Candidate peer address waiting for its PATH_RESPONSE
+struct PendingPath {
+    addr: SocketAddr,
+    token: [u8; 8],
+    sent_at: Instant,
+}
+
+/// Peer address validation (Section 9.6)
+///
+/// The first authenticated record fixes the peer address. Records from
+/// another address are still processed, but that address only becomes the
+/// send target after it echoes a PATH_CHALLENGE token.
+pub(crate) struct PathValidator {
+    pending: Mutex<Option<PendingPath>>,
+}
+
+impl PathValidator {
+    pub(crate) fn new() -> Self {
+        Self {
+            pending: Mutex::new(None),
+        }
+    }
+
+    /// Record a fresh challenge for `addr` and return its token
+    pub(crate) fn challenge(&self, addr: SocketAddr) -> Result<[u8; 8], TunnelError> {
+        let mut token = [0u8; 8];
+        OsRng.fill_bytes(&mut token);
+        *self
+            .pending
+            .lock()
+            .map_err(|e| TunnelError::LockPoisoned(e.to_string()))? = Some(PendingPath {
+            addr,
+            token,
+            sent_at: Instant::now(),
+        });
+        Ok(token)
+    }
+
+    /// Track the peer address after a record from `addr` authenticated
+    ///
+    /// Returns the CONTROL messages to send back to `addr`: a challenge for
+    /// an unvalidated address and/or the answer to the peer's challenge.
+    pub(crate) fn on_authenticated(
+        &self,
+        peer: &RwLock<Option<SocketAddr>>,
+        addr: SocketAddr,
+        msg_type: u8,
+        payload: &[u8],
+    ) -> Result<Vec<ControlMessage>, TunnelError> {
+        let path_msg = match msg_type {
+            cryprq_core::MSG_TYPE_CONTROL => ControlMessage::from_bytes(payload).ok(),
+            _ => None,
+        };
+        if let Some(ControlMessage::PathResponse(token)) = path_msg {
+            self.complete(peer, addr, token)?;
+            return Ok(Vec::new());
+        }
+
+        let mut replies = Vec::new();
+        if self.needs_challenge(peer, addr)? {
+            log::info!("event=path_challenge peer={} reason=new_address", addr);
+            replies.push(ControlMessage::PathChallenge(self.challenge(addr)?));
+        }
+        if let Some(ControlMessage::PathChallenge(token)) = path_msg {
+            // Answer on the path the challenge arrived on
+            replies.push(ControlMessage::PathResponse(token));
+        }
+        Ok(replies)
+    }
+
+    /// Adopt the first peer address; report whether `addr` must be challenged
+    fn needs_challenge(
+        &self,
+        peer: &RwLock<Option<SocketAddr>>,
+        addr: SocketAddr,
+    ) -> Result<bool, TunnelError> {
+        {
+            let mut peer = peer
+                .write()
+                .map_err(|e| TunnelError::LockPoisoned(e.to_string()))?;
+            match *peer {
+                None => {
+                    *peer = Some(addr);
+                    return Ok(false);
+                }
+                Some(current) if current == addr => return Ok(false),
+                Some(_) => {}
+            }
+        }
+
+        let pending = self
+            .pending
+            .lock()
+            .map_err(|e| TunnelError::LockPoisoned(e.to_string()))?;
+        Ok(!matches!(
+            pending.as_ref(),
+            Some(p) if p.addr == addr && p.sent_at.elapsed() < PATH_CHALLENGE_TIMEOUT
+        ))
+    }
+
+    /// Switch the send target once a candidate address answers its challenge
+    fn complete(
+        &self,
+        peer: &RwLock<Option<SocketAddr>>,
+        addr: SocketAddr,
+        token: [u8; 8],
+    ) -> Result<(), TunnelError> {
+        {
+            let mut pending = self
+                .pending
+                .lock()
+                .map_err(|e| TunnelError::LockPoisoned(e.to_string()))?;
+            let valid = matches!(
+                pending.as_ref(),
+                Some(p) if p.addr == addr
+                    && p.token == token
+                    && p.sent_at.elapsed() < PATH_CHALLENGE_TIMEOUT
+            );
+            if !valid {
+                log::debug!("event=path_response status=ignored peer={}", addr);
+                return Ok(());
+            }
+            *pending = None;
+        }
+
+        let previous = peer
+            .write()
+            .map_err(|e| TunnelError::LockPoisoned(e.to_string()))?
+            .replace(addr);
+        match previous {
+            Some(previous) if previous != addr => {
+                log::info!("event=path_migrated from={} to={}", previous, addr)
+            }
+            _ => log::debug!("event=path_validated peer={}", addr),
+        }
+        Ok(())
+    }
+}
+
+/// Whether a CONTROL payload belongs to path validation
+pub(crate) fn is_path_message(payload: &[u8]) -> bool {
+    matches!(
+        ControlMessage::from_bytes(payload),
+        Ok(ControlMessage::PathChallenge(_) | ControlMessage::PathResponse(_))
+    )
+}
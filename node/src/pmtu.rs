@@ -0,0 +1,384 @@
+// Copyright (c) 2025 Thor Thor
+// Author: Thor Thor (GitHub: https://github.com/codethor0)
+// Contact: codethor@gmail.com
+// LinkedIn: https://www.linkedin.com/in/thor-thor0
+// License: MIT (see LICENSE file for details)
+
+use std::net::SocketAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use tokio::sync::{watch, Notify};
+
+use crate::TunnelError;
+
+/// Bytes a record adds to its payload: header plus Poly1305 tag
+pub(crate) const RECORD_OVERHEAD: usize = cryprq_core::RECORD_HEADER_SIZE + 16;
+
+/// Datagram size assumed before any probe succeeds (RFC 8899 BASE_PLPMTU
+/// for UDP over IPv6: 1280 minus IPv6 and UDP headers)
+pub(crate) const BASE_PLPMTU: u16 = 1232;
+
+/// Largest datagram probed over IPv4: a 1500-byte Ethernet MTU minus IPv4
+/// and UDP headers
+const MAX_PLPMTU_V4: u16 = 1472;
+
+/// Largest datagram probed over IPv6
+const MAX_PLPMTU_V6: u16 = 1452;
+
+/// The TUN MTU never drops below the IPv6 minimum link MTU; bigger packets
+/// are fragmented by the record layer
+const MIN_TUN_MTU: u16 = 1280;
+
+/// How long to wait for a probe's acknowledgement
+const PROBE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Sends of one probe size before it is considered too large (MAX_PROBES)
+const MAX_PROBES: u8 = 3;
+
+/// Search stops once the unconfirmed range is smaller than this
+const SEARCH_GRANULARITY: u16 = 16;
+
+/// How often the current PMTU is re-confirmed to detect black holes
+const CONFIRM_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How often to search above the current PMTU again (RFC 8899 PMTU_RAISE_TIMER)
+const RAISE_INTERVAL: Duration = Duration::from_secs(600);
+
+/// Outstanding probe
+#[derive(Debug, Clone, Copy)]
+struct Probe {
+    id: u32,
+    size: u16,
+    sent_at: Instant,
+    attempts: u8,
+    /// Re-confirming the current PMTU rather than searching above it
+    confirm: bool,
+}
+
+/// Datagram packetization layer PMTU discovery (RFC 8899)
+///
+/// Starts at `BASE_PLPMTU` and binary-searches up to the largest probe size
+/// with padded PMTU_PROBE records, one probe at a time. A size is confirmed
+/// by its acknowledgement and ruled out after `MAX_PROBES` unanswered sends.
+/// Once the search settles, the PMTU is re-probed periodically: if it stops
+/// getting through, the path is treated as a black hole and the PMTU drops
+/// back to the base before searching again.
+#[derive(Debug)]
+pub(crate) struct PmtuDiscovery {
+    plpmtu: u16,
+    max: u16,
+    /// Largest size not yet ruled out
+    search_high: u16,
+    probe: Option<Probe>,
+    next_id: u32,
+    confirm_at: Instant,
+    raise_at: Instant,
+}
+
+impl PmtuDiscovery {
+    pub(crate) fn new(max: u16, now: Instant) -> Self {
+        let max = max.max(BASE_PLPMTU);
+        Self {
+            plpmtu: BASE_PLPMTU,
+            max,
+            search_high: max,
+            probe: None,
+            next_id: 1,
+            confirm_at: now + CONFIRM_INTERVAL,
+            raise_at: now + RAISE_INTERVAL,
+        }
+    }
+
+    /// Largest datagram known to reach the peer
+    pub(crate) fn plpmtu(&self) -> u16 {
+        self.plpmtu
+    }
+
+    /// Probe to send now, as (probe ID, datagram size), if any
+    pub(crate) fn poll(&mut self, now: Instant) -> Option<(u32, u16)> {
+        if let Some(probe) = self.probe.as_mut() {
+            if now < probe.sent_at + PROBE_TIMEOUT {
+                return None;
+            }
+            if probe.attempts < MAX_PROBES {
+                probe.attempts += 1;
+                probe.sent_at = now;
+                return Some((probe.id, probe.size));
+            }
+            let probe = *probe;
+            self.on_probe_failed(probe, now);
+        }
+
+        if now >= self.raise_at {
+            self.search_high = self.max;
+            self.raise_at = now + RAISE_INTERVAL;
+        }
+        let (size, confirm) = if self.searching() {
+            let gap = self.search_high - self.plpmtu;
+            let step = gap.div_ceil(2).max(SEARCH_GRANULARITY).min(gap);
+            (self.plpmtu + step, false)
+        } else if now >= self.confirm_at && self.plpmtu > BASE_PLPMTU {
+            self.confirm_at = now + CONFIRM_INTERVAL;
+            (self.plpmtu, true)
+        } else {
+            return None;
+        };
+
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+        self.probe = Some(Probe {
+            id,
+            size,
+            sent_at: now,
+            attempts: 1,
+            confirm,
+        });
+        Some((id, size))
+    }
+
+    /// A probe was acknowledged; returns true if the PMTU changed
+    pub(crate) fn on_ack(&mut self, probe_id: u32, now: Instant) -> bool {
+        let Some(probe) = self.probe.filter(|p| p.id == probe_id) else {
+            return false;
+        };
+        self.probe = None;
+        if probe.confirm || probe.size <= self.plpmtu {
+            return false;
+        }
+        self.plpmtu = probe.size;
+        self.confirm_at = now + CONFIRM_INTERVAL;
+        true
+    }
+
+    /// The probe could not be sent at all (e.g. larger than the local
+    /// interface MTU); rule its size out without waiting
+    pub(crate) fn on_send_error(&mut self, probe_id: u32, now: Instant) {
+        if let Some(probe) = self.probe.filter(|p| p.id == probe_id) {
+            self.on_probe_failed(probe, now);
+        }
+    }
+
+    /// When `poll` next has something to do
+    pub(crate) fn next_timeout(&self) -> Instant {
+        match self.probe {
+            Some(probe) => probe.sent_at + PROBE_TIMEOUT,
+            None if self.searching() => Instant::now(),
+            None => self.confirm_at.min(self.raise_at),
+        }
+    }
+
+    /// Whether sizes above the PMTU are still worth probing: the search
+    /// stops within `SEARCH_GRANULARITY` of a failed size, but always tries
+    /// the largest probe size itself
+    fn searching(&self) -> bool {
+        let gap = self.search_high.saturating_sub(self.plpmtu);
+        gap >= SEARCH_GRANULARITY || (gap > 0 && self.search_high == self.max)
+    }
+
+    fn on_probe_failed(&mut self, probe: Probe, now: Instant) {
+        self.probe = None;
+        if probe.confirm {
+            log::warn!(
+                "event=pmtu_black_hole plpmtu={} fallback={}",
+                self.plpmtu,
+                BASE_PLPMTU
+            );
+            self.plpmtu = BASE_PLPMTU;
+            self.confirm_at = now + CONFIRM_INTERVAL;
+        }
+        self.search_high = probe.size.saturating_sub(1).max(self.plpmtu);
+    }
+}
+
+/// Path MTU state shared between the tunnel and its discovery task
+pub(crate) struct PathMtu {
+    discovery: Mutex<Option<PmtuDiscovery>>,
+    current: watch::Sender<u16>,
+    pub(crate) acked: Notify,
+}
+
+impl PathMtu {
+    pub(crate) fn new() -> Self {
+        Self {
+            discovery: Mutex::new(None),
+            current: watch::channel(BASE_PLPMTU).0,
+            acked: Notify::new(),
+        }
+    }
+
+    /// Largest datagram known to reach the peer
+    pub(crate) fn get(&self) -> u16 {
+        *self.current.borrow()
+    }
+
+    pub(crate) fn subscribe(&self) -> watch::Receiver<u16> {
+        self.current.subscribe()
+    }
+
+    /// Start (or restart) discovery for a peer address
+    ///
+    /// Returns true if discovery was not running before, i.e. the caller
+    /// must spawn the probe task.
+    pub(crate) fn start(&self, peer: SocketAddr) -> Result<bool, TunnelError> {
+        let max = match peer {
+            SocketAddr::V4(_) => MAX_PLPMTU_V4,
+            SocketAddr::V6(_) => MAX_PLPMTU_V6,
+        };
+        let previous = self
+            .lock()?
+            .replace(PmtuDiscovery::new(max, Instant::now()));
+        self.current.send_replace(BASE_PLPMTU);
+        self.acked.notify_one();
+        Ok(previous.is_none())
+    }
+
+    /// Run `f` on the discovery state and publish any PMTU change
+    pub(crate) fn update<T>(
+        &self,
+        f: impl FnOnce(&mut PmtuDiscovery) -> T,
+    ) -> Result<Option<T>, TunnelError> {
+        let mut guard = self.lock()?;
+        let Some(discovery) = guard.as_mut() else {
+            return Ok(None);
+        };
+        let out = f(discovery);
+        let plpmtu = discovery.plpmtu();
+        drop(guard);
+        self.current.send_if_modified(|current| {
+            if *current == plpmtu {
+                return false;
+            }
+            log::info!("event=pmtu_update from={} to={}", current, plpmtu);
+            *current = plpmtu;
+            true
+        });
+        Ok(Some(out))
+    }
+
+    fn lock(&self) -> Result<std::sync::MutexGuard<'_, Option<PmtuDiscovery>>, TunnelError> {
+        self.discovery
+            .lock()
+            .map_err(|e| TunnelError::LockPoisoned(e.to_string()))
+    }
+}
+
+/// TUN MTU that keeps VPN packets inside one datagram of `plpmtu` bytes
+pub fn tun_mtu_for_path(plpmtu: u16) -> u16 {
+    plpmtu
+        .saturating_sub(RECORD_OVERHEAD as u16)
+        .max(MIN_TUN_MTU)
+}
+
+/// Set the don't-fragment bit on outgoing datagrams
+///
+/// Probes are only meaningful if routers drop oversized datagrams instead
+/// of fragmenting them. `IP_PMTUDISC_PROBE` sets DF without letting the
+/// kernel's own PMTU estimate cap what we send.
+#[cfg(target_os = "linux")]
+pub(crate) fn set_dont_fragment(socket: &tokio::net::UdpSocket) -> std::io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    let (level, name, value) = match socket.local_addr()? {
+        SocketAddr::V4(_) => (
+            libc::IPPROTO_IP,
+            libc::IP_MTU_DISCOVER,
+            libc::IP_PMTUDISC_PROBE,
+        ),
+        SocketAddr::V6(_) => (
+            libc::IPPROTO_IPV6,
+            libc::IPV6_MTU_DISCOVER,
+            libc::IPV6_PMTUDISC_PROBE,
+        ),
+    };
+    // SAFETY: the fd is a live socket owned by `socket`, and the option
+    // value is a c_int whose size is passed alongside it
+    let rc = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            level,
+            name,
+            &value as *const libc::c_int as *const libc::c_void,
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if rc != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Set the don't-fragment bit on outgoing datagrams (not supported here;
+/// probes may then be fragmented by the sending host)
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn set_dont_fragment(_socket: &tokio::net::UdpSocket) -> std::io::Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Drive discovery against a path that carries datagrams up to `mtu`
+    fn settle(discovery: &mut PmtuDiscovery, mtu: u16, mut now: Instant) -> Instant {
+        for _ in 0..64 {
+            match discovery.poll(now) {
+                Some((id, size)) if size <= mtu => {
+                    discovery.on_ack(id, now);
+                }
+                Some(_) => {}
+                None if discovery.probe.is_some() => now += PROBE_TIMEOUT,
+                None => break,
+            }
+        }
+        now
+    }
+
+    #[test]
+    fn test_search_converges_on_path_mtu() {
+        let start = Instant::now();
+        let mut discovery = PmtuDiscovery::new(MAX_PLPMTU_V4, start);
+        assert_eq!(discovery.plpmtu(), BASE_PLPMTU);
+
+        settle(&mut discovery, 1400, start);
+        assert!(discovery.plpmtu() <= 1400);
+        assert!(discovery.plpmtu() > 1400 - SEARCH_GRANULARITY);
+
+        let mut discovery = PmtuDiscovery::new(MAX_PLPMTU_V4, start);
+        settle(&mut discovery, 9000, start);
+        assert_eq!(discovery.plpmtu(), MAX_PLPMTU_V4);
+    }
+
+    #[test]
+    fn test_black_hole_falls_back_to_base() {
+        let start = Instant::now();
+        let mut discovery = PmtuDiscovery::new(MAX_PLPMTU_V4, start);
+        let now = settle(&mut discovery, MAX_PLPMTU_V4, start);
+        assert_eq!(discovery.plpmtu(), MAX_PLPMTU_V4);
+
+        // The path shrinks; the next confirmation probe goes unanswered
+        let later = now + CONFIRM_INTERVAL;
+        assert!(matches!(discovery.poll(later), Some((_, MAX_PLPMTU_V4))));
+        settle(&mut discovery, 1300, later);
+        assert!(discovery.plpmtu() <= 1300);
+        assert!(discovery.plpmtu() > 1300 - SEARCH_GRANULARITY);
+    }
+
+    #[test]
+    fn test_send_error_rules_out_size() {
+        let start = Instant::now();
+        let mut discovery = PmtuDiscovery::new(MAX_PLPMTU_V4, start);
+        let Some((id, size)) = discovery.poll(start) else {
+            return;
+        };
+        discovery.on_send_error(id, start);
+        assert_eq!(discovery.search_high, size - 1);
+        assert!(matches!(discovery.poll(start), Some((_, next)) if next < size));
+    }
+
+    #[test]
+    fn test_tun_mtu_for_path() {
+        assert_eq!(tun_mtu_for_path(1472), 1436);
+        assert_eq!(tun_mtu_for_path(BASE_PLPMTU), MIN_TUN_MTU);
+    }
+}
@@ -0,0 +1,320 @@
+// Copyright (c) 2025 Thor Thor
+// Author: Thor Thor (GitHub: https://github.com/codethor0)
+// Contact: codethor@gmail.com
+// LinkedIn: https://www.linkedin.com/in/thor-thor0
+// License: MIT (see LICENSE file for details)
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::stats::{record_drop, DropReason};
+use crate::TunnelError;
+
+/// Packet and byte rates for one token-bucket pair
+#[derive(Debug, Clone, Copy)]
+pub struct BucketLimits {
+    /// Sustained packets per second
+    pub packets_per_second: u32,
+    /// Packet burst capacity
+    pub packet_burst: u32,
+    /// Sustained bytes per second (0: unlimited)
+    pub bytes_per_second: u64,
+    /// Byte burst capacity; raised to `bytes_per_second` if smaller, so a
+    /// second's worth of traffic always fits
+    pub byte_burst: u64,
+}
+
+/// Ingress rate limits: per source IP before authentication, per session after
+#[derive(Debug, Clone)]
+pub struct RateLimitConfig {
+    /// Applied to every datagram, keyed by source IP
+    pub per_source: BucketLimits,
+    /// Applied to authenticated records, one bucket per session
+    pub per_session: BucketLimits,
+    /// Source buckets unused for this long are discarded
+    pub idle_timeout: Duration,
+    /// Maximum source IPs tracked at once; while the table is full, new
+    /// sources share one overflow bucket with `per_source` limits
+    pub max_sources: usize,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            per_source: BucketLimits {
+                packets_per_second: 1000,
+                packet_burst: 2000,
+                bytes_per_second: 8 * 1024 * 1024,
+                byte_burst: 16 * 1024 * 1024,
+            },
+            per_session: BucketLimits {
+                packets_per_second: 20_000,
+                packet_burst: 40_000,
+                bytes_per_second: 64 * 1024 * 1024,
+                byte_burst: 128 * 1024 * 1024,
+            },
+            idle_timeout: Duration::from_secs(60),
+            max_sources: 65536,
+        }
+    }
+}
+
+/// Single token bucket
+struct TokenBucket {
+    /// Maximum tokens (burst capacity)
+    capacity: f64,
+    /// Current token count
+    tokens: f64,
+    /// Tokens added per second (steady rate)
+    refill_rate: f64,
+    /// Last refill timestamp
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    fn new(rate: f64, capacity: f64) -> Self {
+        Self {
+            capacity,
+            tokens: capacity,
+            refill_rate: rate,
+            last_refill: Instant::now(),
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.refill_rate).min(self.capacity);
+        self.last_refill = now;
+    }
+}
+
+/// Token bucket rate limiter
+///
+/// Implements a token bucket algorithm to limit incoming packet rate, with
+/// an optional second bucket limiting bytes. A packet is accepted only if
+/// both buckets have room, so large records cost more than small ones.
+pub(crate) struct RateLimiter {
+    packets: TokenBucket,
+    bytes: Option<TokenBucket>,
+}
+
+impl RateLimiter {
+    /// Create new packet-only rate limiter
+    ///
+    /// # Arguments
+    /// * `packets_per_second` - Sustained rate limit
+    /// * `burst_size` - Maximum burst capacity
+    pub(crate) fn new(packets_per_second: u32, burst_size: u32) -> Self {
+        Self {
+            packets: TokenBucket::new(packets_per_second as f64, burst_size as f64),
+            bytes: None,
+        }
+    }
+
+    /// Create a rate limiter with packet and byte buckets
+    pub(crate) fn with_limits(limits: &BucketLimits) -> Self {
+        let mut limiter = Self::new(limits.packets_per_second, limits.packet_burst);
+        if limits.bytes_per_second > 0 {
+            limiter.bytes = Some(TokenBucket::new(
+                limits.bytes_per_second as f64,
+                limits.byte_burst.max(limits.bytes_per_second) as f64,
+            ));
+        }
+        limiter
+    }
+
+    /// Check if packet should be accepted
+    /// Returns Ok(()) if within limit, Err if rate exceeded
+    #[cfg(test)]
+    pub(crate) fn check_and_consume(&mut self) -> Result<(), TunnelError> {
+        self.check_packet(0)
+    }
+
+    /// Check and consume one packet of `len` bytes
+    pub(crate) fn check_packet(&mut self, len: usize) -> Result<(), TunnelError> {
+        let now = Instant::now();
+        self.packets.refill(now);
+        if self.packets.tokens < 1.0 {
+            return Err(TunnelError::RateLimitExceeded);
+        }
+        if let Some(bytes) = self.bytes.as_mut() {
+            bytes.refill(now);
+            if bytes.tokens < len as f64 {
+                return Err(TunnelError::RateLimitExceeded);
+            }
+            bytes.tokens -= len as f64;
+        }
+        self.packets.tokens -= 1.0;
+        Ok(())
+    }
+
+    /// Whether the buckets have been full (unused) for at least `idle`
+    fn is_idle(&self, idle: Duration) -> bool {
+        self.packets.last_refill.elapsed() >= idle
+    }
+}
+
+/// Per-source-IP ingress limiter applied before any decryption
+pub(crate) struct SourceRateLimiter {
+    config: RateLimitConfig,
+    sources: Mutex<SourceTable>,
+}
+
+struct SourceTable {
+    buckets: HashMap<IpAddr, RateLimiter>,
+    /// Shared by sources that arrive while `buckets` is full, so spoofing
+    /// `max_sources` addresses cannot lock out every newcomer
+    overflow: RateLimiter,
+    last_sweep: Instant,
+}
+
+impl SourceRateLimiter {
+    pub(crate) fn new(config: RateLimitConfig) -> Self {
+        Self {
+            sources: Mutex::new(SourceTable {
+                buckets: HashMap::new(),
+                overflow: RateLimiter::with_limits(&config.per_source),
+                last_sweep: Instant::now(),
+            }),
+            config,
+        }
+    }
+
+    /// Fresh per-session limiter with this configuration's session limits
+    pub(crate) fn session_limiter(&self) -> RateLimiter {
+        RateLimiter::with_limits(&self.config.per_session)
+    }
+
+    /// Charge a datagram of `len` bytes to `source`, counting drops
+    pub(crate) fn check(&self, source: IpAddr, len: usize) -> Result<(), TunnelError> {
+        let mut table = self
+            .sources
+            .lock()
+            .map_err(|e| TunnelError::LockPoisoned(e.to_string()))?;
+        if table.last_sweep.elapsed() >= self.config.idle_timeout / 2 {
+            let idle = self.config.idle_timeout;
+            table.buckets.retain(|_, bucket| !bucket.is_idle(idle));
+            table.last_sweep = Instant::now();
+        }
+        let full = table.buckets.len() >= self.config.max_sources;
+        let SourceTable {
+            buckets, overflow, ..
+        } = &mut *table;
+        let bucket = match buckets.get_mut(&source) {
+            Some(bucket) => bucket,
+            None if full => overflow,
+            None => buckets
+                .entry(source)
+                .or_insert_with(|| RateLimiter::with_limits(&self.config.per_source)),
+        };
+        let result = bucket.check_packet(len);
+        if result.is_err() {
+            record_drop(DropReason::RateLimitSource);
+        }
+        result
+    }
+
+    /// Number of tracked source IPs
+    #[cfg(test)]
+    fn tracked_sources(&self) -> usize {
+        self.sources.lock().map(|t| t.buckets.len()).unwrap_or(0)
+    }
+}
+
+/// Charge an authenticated record to its session's limiter, counting drops
+pub(crate) fn check_session(limiter: &Mutex<RateLimiter>, len: usize) -> Result<(), TunnelError> {
+    let result = limiter
+        .lock()
+        .map_err(|e| TunnelError::LockPoisoned(e.to_string()))?
+        .check_packet(len);
+    if result.is_err() {
+        record_drop(DropReason::RateLimitSession);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stats::dropped_packets;
+
+    fn limits(pps: u32, bytes: u64) -> BucketLimits {
+        BucketLimits {
+            packets_per_second: pps,
+            packet_burst: pps,
+            bytes_per_second: bytes,
+            byte_burst: bytes,
+        }
+    }
+
+    #[test]
+    fn test_byte_budget_limits_large_packets() {
+        let mut limiter = RateLimiter::with_limits(&limits(100, 3000));
+        assert!(limiter.check_packet(1400).is_ok());
+        assert!(limiter.check_packet(1400).is_ok());
+        // Packet tokens remain, but the byte budget is spent
+        assert!(matches!(
+            limiter.check_packet(1400),
+            Err(TunnelError::RateLimitExceeded)
+        ));
+        assert!(limiter.check_packet(100).is_ok());
+    }
+
+    #[test]
+    fn test_sources_limited_independently() {
+        let limiter = SourceRateLimiter::new(RateLimitConfig {
+            per_source: limits(5, 0),
+            ..RateLimitConfig::default()
+        });
+        let noisy: IpAddr = "192.0.2.1".parse().expect("ip in test");
+        let quiet: IpAddr = "192.0.2.2".parse().expect("ip in test");
+        let drops_before = dropped_packets(DropReason::RateLimitSource);
+
+        for _ in 0..5 {
+            assert!(limiter.check(noisy, 100).is_ok());
+        }
+        assert!(limiter.check(noisy, 100).is_err());
+        assert!(limiter.check(quiet, 100).is_ok());
+        assert!(dropped_packets(DropReason::RateLimitSource) > drops_before);
+    }
+
+    #[test]
+    fn test_source_table_bounded_and_expired() {
+        let limiter = SourceRateLimiter::new(RateLimitConfig {
+            idle_timeout: Duration::from_millis(50),
+            max_sources: 2,
+            ..RateLimitConfig::default()
+        });
+        let ip = |n: u8| IpAddr::from([198, 51, 100, n]);
+        assert!(limiter.check(ip(1), 10).is_ok());
+        assert!(limiter.check(ip(2), 10).is_ok());
+        // Newcomers to a full table share the overflow bucket
+        assert!(limiter.check(ip(3), 10).is_ok());
+        assert_eq!(limiter.tracked_sources(), 2);
+
+        std::thread::sleep(Duration::from_millis(60));
+        assert!(limiter.check(ip(3), 10).is_ok());
+        assert_eq!(limiter.tracked_sources(), 1);
+    }
+
+    #[test]
+    fn test_full_table_shares_overflow_bucket() {
+        let limiter = SourceRateLimiter::new(RateLimitConfig {
+            per_source: limits(3, 0),
+            max_sources: 1,
+            ..RateLimitConfig::default()
+        });
+        let ip = |n: u8| IpAddr::from([198, 51, 100, n]);
+        assert!(limiter.check(ip(1), 10).is_ok());
+
+        // Sources beyond the table are limited together, not refused outright
+        for n in 2..5 {
+            assert!(limiter.check(ip(n), 10).is_ok());
+        }
+        assert!(limiter.check(ip(5), 10).is_err());
+        // A tracked source keeps its own budget
+        assert!(limiter.check(ip(1), 10).is_ok());
+    }
+}
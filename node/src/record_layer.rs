@@ -8,7 +8,7 @@ use cryprq_core::{Record, RecordHeader, PROTOCOL_VERSION};
 use std::io;
 use zeroize::Zeroize;
 
-use crate::Epoch;
+use crate::{Epoch, SeqCounters};
 
 /// Directional keys for encryption/decryption
 #[derive(Clone, Debug)]
@@ -30,6 +30,62 @@ impl Drop for DirectionKeys {
     }
 }
 
+/// Keys and sequence numbers of one epoch, published as a single snapshot
+///
+/// Tunnels swap in a new snapshot on rotation instead of updating keys in
+/// place, so sealing and opening never take a lock. Sequence numbers come
+/// from the snapshot whose key seals the record, so no (key, nonce) pair
+/// repeats across a rotation.
+#[derive(Debug)]
+pub(crate) struct TrafficKeys {
+    pub(crate) epoch: Epoch,
+    pub(crate) outbound: DirectionKeys,
+    // Unused until roles are negotiated: test mode opens with `outbound`
+    #[allow(dead_code)]
+    pub(crate) inbound: DirectionKeys,
+    pub(crate) seq: SeqCounters,
+}
+
+impl TrafficKeys {
+    pub(crate) fn new(epoch: Epoch, outbound: DirectionKeys, inbound: DirectionKeys) -> Self {
+        Self {
+            epoch,
+            outbound,
+            inbound,
+            seq: SeqCounters::new(),
+        }
+    }
+
+    /// All-zero keys, installed when a session closes
+    pub(crate) fn erased(epoch: Epoch) -> Self {
+        let erased = DirectionKeys {
+            key: [0; 32],
+            iv: [0; 12],
+        };
+        Self::new(epoch, erased.clone(), erased)
+    }
+}
+
+/// Header protection keys for a session (Section 6.6)
+#[derive(Clone, Debug)]
+pub struct HeaderProtectionKeys {
+    pub outbound: [u8; 32],
+    pub inbound: [u8; 32],
+}
+
+impl zeroize::Zeroize for HeaderProtectionKeys {
+    fn zeroize(&mut self) {
+        self.outbound.zeroize();
+        self.inbound.zeroize();
+    }
+}
+
+impl Drop for HeaderProtectionKeys {
+    fn drop(&mut self) {
+        self.zeroize();
+    }
+}
+
 /// Sends a CrypRQ record over the transport
 ///
 /// As specified in Section 6.1-6.2:
@@ -101,6 +157,9 @@ pub fn recv_record(buf: &[u8], keys: &DirectionKeys) -> io::Result<(RecordHeader
     Ok((record.header, plaintext))
 }
 
+/// Stream ID for session-level CONTROL messages
+pub const CONTROL_STREAM_ID: u32 = 0;
+
 /// Stream ID for VPN/TUN traffic
 pub const VPN_STREAM_ID: u32 = 1;
 
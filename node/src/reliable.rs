@@ -0,0 +1,733 @@
+// Copyright (c) 2025 Thor Thor
+// Author: Thor Thor (GitHub: https://github.com/codethor0)
+// Contact: codethor@gmail.com
+// LinkedIn: https://www.linkedin.com/in/thor-thor0
+// License: MIT (see LICENSE file for details)
+
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use cryprq_core::{StreamAck, FLAG_DATA_FIN, FLAG_DATA_RELIABLE, MAX_ACK_RANGES};
+use tokio::sync::Notify;
+
+use crate::congestion::{NewReno, Pacer};
+use crate::{TunnelError, MAX_STREAM_CHUNK};
+
+/// Retransmission timeout before the first RTT sample (RFC 6298)
+const INITIAL_RTO: Duration = Duration::from_secs(1);
+
+/// Lower bound on the retransmission timeout
+const MIN_RTO: Duration = Duration::from_millis(200);
+
+/// Upper bound on the retransmission timeout
+const MAX_RTO: Duration = Duration::from_secs(60);
+
+/// Retransmissions of one segment before the stream is reset
+const MAX_RETRANSMITS: u32 = 8;
+
+/// Later segments SACKed before an unacknowledged one is deemed lost and
+/// retransmitted without waiting for its timer (RFC 6675 DupThresh)
+const DUP_THRESHOLD: usize = 3;
+
+/// Lower bound on the tail loss probe timeout (RFC 8985 uses 2 * SRTT)
+const MIN_PTO: Duration = Duration::from_millis(10);
+
+/// Unacknowledged stream bytes a sender may have outstanding
+pub(crate) const SEND_WINDOW: u64 = 256 * 1024;
+
+/// Out-of-order bytes a receiver buffers; the sender window keeps well below
+const REORDER_LIMIT: usize = 2 * SEND_WINDOW as usize;
+
+/// Size of the stream offset that prefixes a reliable DATA payload
+pub(crate) const OFFSET_SIZE: usize = 8;
+
+/// One reliable DATA record's worth of stream data
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Segment {
+    pub(crate) offset: u64,
+    pub(crate) data: Vec<u8>,
+    pub(crate) fin: bool,
+}
+
+impl Segment {
+    /// Offset just past this segment; a FIN occupies one offset
+    fn end(&self) -> u64 {
+        self.offset + self.data.len() as u64 + u64::from(self.fin)
+    }
+
+    /// Record flags and payload carrying this segment
+    pub(crate) fn encode(&self) -> (u8, Vec<u8>) {
+        let mut flags = FLAG_DATA_RELIABLE;
+        if self.fin {
+            flags |= FLAG_DATA_FIN;
+        }
+        let mut payload = Vec::with_capacity(OFFSET_SIZE + self.data.len());
+        payload.extend_from_slice(&self.offset.to_be_bytes());
+        payload.extend_from_slice(&self.data);
+        (flags, payload)
+    }
+
+    /// Parse a reliable DATA payload
+    pub(crate) fn decode(flags: u8, payload: &[u8]) -> Option<Self> {
+        let (offset, data) = payload.split_first_chunk::<OFFSET_SIZE>()?;
+        Some(Self {
+            offset: u64::from_be_bytes(*offset),
+            data: data.to_vec(),
+            fin: flags & FLAG_DATA_FIN != 0,
+        })
+    }
+}
+
+/// Smoothed RTT and retransmission timeout (RFC 6298)
+pub(crate) struct RttEstimator {
+    srtt: Option<Duration>,
+    rttvar: Duration,
+    rto: Duration,
+}
+
+impl RttEstimator {
+    pub(crate) fn new() -> Self {
+        Self {
+            srtt: None,
+            rttvar: Duration::ZERO,
+            rto: INITIAL_RTO,
+        }
+    }
+
+    /// Fold in an RTT measured on a segment that was never retransmitted
+    pub(crate) fn on_sample(&mut self, rtt: Duration) {
+        match self.srtt {
+            None => {
+                self.srtt = Some(rtt);
+                self.rttvar = rtt / 2;
+            }
+            Some(srtt) => {
+                let delta = if srtt > rtt { srtt - rtt } else { rtt - srtt };
+                self.rttvar = (self.rttvar * 3 + delta) / 4;
+                self.srtt = Some((srtt * 7 + rtt) / 8);
+            }
+        }
+        let srtt = self.srtt.unwrap_or(rtt);
+        self.rto = (srtt + self.rttvar * 4).clamp(MIN_RTO, MAX_RTO);
+    }
+
+    /// Back off after a retransmission timeout
+    pub(crate) fn on_timeout(&mut self) {
+        self.rto = (self.rto * 2).min(MAX_RTO);
+    }
+
+    pub(crate) fn rto(&self) -> Duration {
+        self.rto
+    }
+
+    pub(crate) fn srtt(&self) -> Option<Duration> {
+        self.srtt
+    }
+}
+
+struct InFlight {
+    segment: Segment,
+    sent_at: Instant,
+    retransmits: u32,
+    /// Covered by a SACK range: buffered by the peer, not yet delivered
+    sacked: bool,
+    /// Deemed lost from SACK information; resent on the next timer pass
+    lost: bool,
+}
+
+/// Send side of a reliable stream: numbering, retransmission, and window
+pub(crate) struct ReliableSender {
+    next_offset: u64,
+    /// Peer's cumulative acknowledgement
+    acked: u64,
+    /// Unacknowledged segments keyed by end offset (the empty opening
+    /// segment ends at 0, so keys stay unique)
+    in_flight: BTreeMap<u64, InFlight>,
+    rtt: RttEstimator,
+    cc: NewReno,
+    pacer: Pacer,
+    fin_queued: bool,
+    /// Send time and offset of the latest-sent segment acknowledged so far
+    /// (RACK, RFC 8985)
+    rack: Option<(Instant, u64)>,
+    /// A tail loss probe may be sent: cleared by a probe or a timeout,
+    /// re-armed when an acknowledgement makes progress
+    probe_armed: bool,
+}
+
+impl ReliableSender {
+    pub(crate) fn new() -> Self {
+        Self {
+            next_offset: 0,
+            acked: 0,
+            in_flight: BTreeMap::new(),
+            rtt: RttEstimator::new(),
+            cc: NewReno::new(MAX_STREAM_CHUNK as u64),
+            pacer: Pacer::new(MAX_STREAM_CHUNK as u64),
+            fin_queued: false,
+            rack: None,
+            probe_armed: true,
+        }
+    }
+
+    /// Whether both the peer's window and the congestion window have room
+    pub(crate) fn can_send(&self) -> bool {
+        self.next_offset.saturating_sub(self.acked) < SEND_WINDOW
+            && self.cc.can_send(self.bytes_in_flight())
+    }
+
+    /// Take pacing budget for a `len`-byte segment, or say how long to wait
+    pub(crate) fn pace(&mut self, len: usize, now: Instant) -> Result<(), Duration> {
+        self.pacer.try_send(len, now)
+    }
+
+    /// Data bytes sent and neither acknowledged nor SACKed
+    fn bytes_in_flight(&self) -> u64 {
+        self.in_flight
+            .values()
+            .filter(|entry| !entry.sacked)
+            .map(|entry| entry.segment.data.len() as u64)
+            .sum()
+    }
+
+    /// Number the next segment and track it until acknowledged
+    ///
+    /// An empty non-FIN segment at offset 0 opens the stream on the peer.
+    pub(crate) fn queue(&mut self, data: Vec<u8>, fin: bool, now: Instant) -> Segment {
+        let segment = Segment {
+            offset: self.next_offset,
+            data,
+            fin,
+        };
+        self.next_offset = segment.end();
+        self.fin_queued |= fin;
+        self.in_flight.insert(
+            segment.end(),
+            InFlight {
+                segment: segment.clone(),
+                sent_at: now,
+                retransmits: 0,
+                sacked: false,
+                lost: false,
+            },
+        );
+        segment
+    }
+
+    /// Apply a STREAM_ACK; returns whether it acknowledged anything new
+    pub(crate) fn on_ack(&mut self, ack: &StreamAck, now: Instant) -> bool {
+        if ack.cumulative > self.next_offset {
+            log::debug!(
+                "event=stream_ack status=ignored stream={} cumulative={} sent={}",
+                ack.stream_id,
+                ack.cumulative,
+                self.next_offset
+            );
+            return false;
+        }
+        let mut newest_sample = None;
+        let mut newest_sent = None;
+        let mut newly_acked = 0;
+        let mut take_sample = |entry: &InFlight| {
+            if entry.sacked {
+                return;
+            }
+            newly_acked += entry.segment.data.len() as u64;
+            newest_sent = newest_sent.max(Some((entry.sent_at, entry.segment.offset)));
+            // Karn: only never-retransmitted segments give unambiguous samples
+            if entry.retransmits == 0 {
+                newest_sample = Some(now.duration_since(entry.sent_at));
+            }
+        };
+
+        let still_in_flight = self.in_flight.split_off(&(ack.cumulative + 1));
+        for entry in std::mem::replace(&mut self.in_flight, still_in_flight).values() {
+            take_sample(entry);
+        }
+        for (start, end) in &ack.ranges {
+            for entry in self.in_flight.range_mut(..=*end).map(|(_, e)| e) {
+                if entry.segment.offset >= *start && !entry.sacked {
+                    take_sample(entry);
+                    entry.sacked = true;
+                }
+            }
+        }
+        if let Some(rtt) = newest_sample {
+            self.rtt.on_sample(rtt);
+        }
+        let highest_acked = ack
+            .ranges
+            .iter()
+            .map(|(_, end)| *end)
+            .fold(ack.cumulative, u64::max);
+        self.cc.on_ack(newly_acked, highest_acked);
+
+        if newest_sent.is_some() {
+            self.rack = self.rack.max(newest_sent);
+            self.probe_armed = true;
+        }
+        if let Some(offset) = self.detect_losses(now) {
+            self.cc
+                .on_loss(offset, self.bytes_in_flight(), self.next_offset);
+        }
+        if let Some(srtt) = self.rtt.srtt() {
+            self.pacer.set_rate(self.cc.window(), srtt);
+        }
+
+        let advanced = newly_acked > 0 || ack.cumulative > self.acked;
+        self.acked = self.acked.max(ack.cumulative);
+        advanced
+    }
+
+    /// Mark segments lost from acknowledgements of later sends; returns
+    /// the lowest offset newly marked
+    ///
+    /// A segment is lost once `DUP_THRESHOLD` segments sent after it were
+    /// SACKed (RFC 6675), or once a later send was acknowledged and the
+    /// reordering window has passed since it went out (RACK, RFC 8985).
+    /// Ordering by send time rather than offset also catches a lost
+    /// retransmission.
+    fn detect_losses(&mut self, now: Instant) -> Option<u64> {
+        let mut sacked_sends: Vec<(Instant, u64)> = self
+            .in_flight
+            .values()
+            .filter(|entry| entry.sacked)
+            .map(|entry| (entry.sent_at, entry.segment.offset))
+            .collect();
+        sacked_sends.sort_unstable();
+        let reorder_window = self.reorder_window();
+        let rack = self.rack;
+        let mut first_lost = None;
+        for entry in self.in_flight.values_mut().rev() {
+            if entry.sacked || entry.lost {
+                continue;
+            }
+            let sent = (entry.sent_at, entry.segment.offset);
+            let sacked_later =
+                sacked_sends.len() - sacked_sends.partition_point(|later| *later <= sent);
+            let overtaken = rack.is_some_and(|rack| rack > sent)
+                && reorder_window.is_some_and(|window| entry.sent_at + window <= now);
+            if sacked_later >= DUP_THRESHOLD || overtaken {
+                entry.lost = true;
+                first_lost = Some(entry.segment.offset);
+            }
+        }
+        first_lost
+    }
+
+    /// How long a segment overtaken by a later acknowledged one may still
+    /// arrive: a quarter round trip past the smoothed RTT
+    fn reorder_window(&self) -> Option<Duration> {
+        self.rtt.srtt().map(|srtt| srtt + srtt / 4)
+    }
+
+    /// When to probe the tail of the flight for a loss no acknowledgement
+    /// can reveal (RFC 8985 TLP): two round trips after the newest send
+    fn probe_deadline(&self) -> Option<Instant> {
+        if !self.probe_armed {
+            return None;
+        }
+        let pto = (self.rtt.srtt()? * 2).clamp(MIN_PTO, self.rtt.rto());
+        self.in_flight
+            .values()
+            .filter(|entry| !entry.sacked)
+            .map(|entry| entry.sent_at)
+            .max()
+            .map(|newest| newest + pto)
+    }
+
+    /// Segments whose retransmission timer expired, marked as resent now
+    ///
+    /// Includes segments deemed lost and, if nothing else is due, a tail
+    /// loss probe resending the newest segment. Neither backs off the timer
+    /// or collapses the congestion window; an expired timer does both.
+    pub(crate) fn take_expired(&mut self, now: Instant) -> Result<Vec<Segment>, TunnelError> {
+        let rto = self.rtt.rto();
+        let in_flight = self.bytes_in_flight();
+        if let Some(offset) = self.detect_losses(now) {
+            self.cc.on_loss(offset, in_flight, self.next_offset);
+        }
+        let mut expired = Vec::new();
+        let mut timed_out = false;
+        for entry in self.in_flight.values_mut() {
+            if entry.sacked || !(entry.lost || entry.sent_at + rto <= now) {
+                continue;
+            }
+            timed_out |= !entry.lost;
+            entry.lost = false;
+            if entry.retransmits >= MAX_RETRANSMITS {
+                return Err(TunnelError::NetworkError(format!(
+                    "stream segment at offset {} unacknowledged after {} retransmissions",
+                    entry.segment.offset, MAX_RETRANSMITS
+                )));
+            }
+            entry.retransmits += 1;
+            entry.sent_at = now;
+            expired.push(entry.segment.clone());
+        }
+        if timed_out {
+            self.rtt.on_timeout();
+            self.cc.on_timeout(in_flight, self.next_offset);
+            self.probe_armed = false;
+        } else if expired.is_empty() && self.probe_deadline().is_some_and(|probe| probe <= now) {
+            self.probe_armed = false;
+            let newest = self
+                .in_flight
+                .values_mut()
+                .rev()
+                .find(|entry| !entry.sacked && entry.retransmits < MAX_RETRANSMITS);
+            if let Some(entry) = newest {
+                entry.retransmits += 1;
+                entry.sent_at = now;
+                expired.push(entry.segment.clone());
+            }
+        }
+        Ok(expired)
+    }
+
+    /// When the earliest retransmission timer fires (now, for segments
+    /// already deemed lost)
+    pub(crate) fn next_deadline(&self) -> Option<Instant> {
+        let rto = self.rtt.rto();
+        let reorder_window = self.reorder_window();
+        self.in_flight
+            .values()
+            .filter(|entry| !entry.sacked)
+            .map(|entry| {
+                let overtaken = self
+                    .rack
+                    .is_some_and(|rack| rack > (entry.sent_at, entry.segment.offset));
+                match reorder_window {
+                    _ if entry.lost => entry.sent_at,
+                    Some(window) if overtaken => entry.sent_at + window,
+                    _ => entry.sent_at + rto,
+                }
+            })
+            .chain(self.probe_deadline())
+            .min()
+    }
+
+    /// FIN sent and everything acknowledged
+    pub(crate) fn is_finished(&self) -> bool {
+        self.fin_queued && self.in_flight.is_empty()
+    }
+
+    /// Current smoothed RTT estimate
+    #[cfg(test)]
+    fn srtt(&self) -> Option<Duration> {
+        self.rtt.srtt()
+    }
+}
+
+/// Send state shared by a stream's writer, the ACK handler, and its
+/// retransmission task
+pub(crate) struct SendState {
+    pub(crate) sender: Mutex<ReliableSender>,
+    /// Woken when acknowledgements open the window (or the stream fails)
+    pub(crate) window: Notify,
+    /// Woken when a new segment needs a retransmission timer
+    pub(crate) timer: Notify,
+    failed: AtomicBool,
+}
+
+impl SendState {
+    pub(crate) fn new() -> Self {
+        Self {
+            sender: Mutex::new(ReliableSender::new()),
+            window: Notify::new(),
+            timer: Notify::new(),
+            failed: AtomicBool::new(false),
+        }
+    }
+
+    pub(crate) fn fail(&self) {
+        self.failed.store(true, Ordering::Release);
+        self.window.notify_waiters();
+    }
+
+    pub(crate) fn is_failed(&self) -> bool {
+        self.failed.load(Ordering::Acquire)
+    }
+}
+
+/// Receive side of a reliable stream: reorder buffer and acknowledgements
+pub(crate) struct ReliableReceiver {
+    /// Every offset below this has been handed to the reader
+    delivered: u64,
+    /// Received segments not yet delivered, keyed by offset
+    pending: BTreeMap<u64, Segment>,
+    pending_bytes: usize,
+    finished: bool,
+}
+
+impl ReliableReceiver {
+    pub(crate) fn new() -> Self {
+        Self {
+            delivered: 0,
+            pending: BTreeMap::new(),
+            pending_bytes: 0,
+            finished: false,
+        }
+    }
+
+    /// Buffer a received segment; duplicates and overflow are discarded
+    pub(crate) fn on_segment(&mut self, segment: Segment) {
+        if segment.end() <= self.delivered || self.pending.contains_key(&segment.offset) {
+            return;
+        }
+        if segment.data.is_empty() && !segment.fin {
+            // Opening segment: nothing to deliver
+            return;
+        }
+        if self.pending_bytes + segment.data.len() > REORDER_LIMIT {
+            log::warn!(
+                "event=stream_reorder_overflow offset={} pending_bytes={}",
+                segment.offset,
+                self.pending_bytes
+            );
+            return;
+        }
+        self.pending_bytes += segment.data.len();
+        self.pending.insert(segment.offset, segment);
+    }
+
+    /// Hand in-order data to `push` until a gap or until `push` refuses
+    /// (returning the data); returns whether the FIN was reached
+    pub(crate) fn deliver(&mut self, mut push: impl FnMut(Vec<u8>) -> Result<(), Vec<u8>>) -> bool {
+        while let Some(entry) = self.pending.first_entry() {
+            if *entry.key() != self.delivered {
+                break;
+            }
+            let mut segment = entry.remove();
+            let len = segment.data.len();
+            if len > 0 {
+                if let Err(data) = push(std::mem::take(&mut segment.data)) {
+                    segment.data = data;
+                    self.pending.insert(segment.offset, segment);
+                    break;
+                }
+            }
+            self.pending_bytes -= len;
+            self.delivered += len as u64;
+            if segment.fin {
+                self.delivered += 1;
+                self.finished = true;
+            }
+        }
+        self.finished
+    }
+
+    /// Acknowledgement point: offsets below it reached the reader
+    pub(crate) fn delivered(&self) -> u64 {
+        self.delivered
+    }
+
+    /// Acknowledgement describing what has been delivered and buffered
+    pub(crate) fn ack(&self, stream_id: u32) -> StreamAck {
+        let mut ranges: Vec<(u64, u64)> = Vec::new();
+        for segment in self.pending.values() {
+            if let Some((_, end)) = ranges.last_mut().filter(|(_, end)| *end == segment.offset) {
+                *end = segment.end();
+            } else if ranges.len() == MAX_ACK_RANGES {
+                break;
+            } else {
+                ranges.push((segment.offset, segment.end()));
+            }
+        }
+        StreamAck {
+            stream_id,
+            cumulative: self.delivered,
+            ranges,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ack(cumulative: u64, ranges: Vec<(u64, u64)>) -> StreamAck {
+        StreamAck {
+            stream_id: 1,
+            cumulative,
+            ranges,
+        }
+    }
+
+    #[test]
+    fn test_segment_roundtrip() {
+        let segment = Segment {
+            offset: 4800,
+            data: b"tail".to_vec(),
+            fin: true,
+        };
+        let (flags, payload) = segment.encode();
+        assert_eq!(flags, FLAG_DATA_RELIABLE | FLAG_DATA_FIN);
+        assert_eq!(Segment::decode(flags, &payload), Some(segment));
+        assert_eq!(Segment::decode(flags, &payload[..7]), None);
+    }
+
+    #[test]
+    fn test_rto_follows_rfc6298() {
+        let mut rtt = RttEstimator::new();
+        assert_eq!(rtt.rto(), INITIAL_RTO);
+        rtt.on_sample(Duration::from_millis(100));
+        // srtt = 100ms, rttvar = 50ms, rto = 300ms
+        assert_eq!(rtt.rto(), Duration::from_millis(300));
+        rtt.on_sample(Duration::from_millis(100));
+        assert!(rtt.rto() < Duration::from_millis(300));
+        rtt.on_timeout();
+        rtt.on_timeout();
+        assert!(rtt.rto() >= Duration::from_millis(800));
+    }
+
+    #[test]
+    fn test_receiver_reorders_and_sacks() {
+        let mut receiver = ReliableReceiver::new();
+        let seg = |offset: u64, data: &[u8], fin: bool| Segment {
+            offset,
+            data: data.to_vec(),
+            fin,
+        };
+        receiver.on_segment(seg(4, b"efgh", false));
+        receiver.on_segment(seg(12, b"", true));
+        let mut out = Vec::new();
+        assert!(!receiver.deliver(|d| {
+            out.extend(d);
+            Ok(())
+        }));
+        assert!(out.is_empty());
+        assert_eq!(receiver.ack(1), ack(0, vec![(4, 8), (12, 13)]));
+
+        receiver.on_segment(seg(0, b"abcd", false));
+        receiver.on_segment(seg(8, b"ijkl", false));
+        // A full reader keeps data buffered (and unacknowledged)
+        assert!(!receiver.deliver(Err));
+        assert_eq!(receiver.ack(1).cumulative, 0);
+
+        assert!(receiver.deliver(|d| {
+            out.extend(d);
+            Ok(())
+        }));
+        assert_eq!(out, b"abcdefghijkl");
+        assert_eq!(receiver.ack(1), ack(13, vec![]));
+
+        // Retransmitted duplicates are ignored
+        receiver.on_segment(seg(4, b"efgh", false));
+        assert_eq!(receiver.ack(1), ack(13, vec![]));
+    }
+
+    #[test]
+    fn test_sender_retransmits_until_acked() {
+        let start = Instant::now();
+        let mut sender = ReliableSender::new();
+        let first = sender.queue(vec![0; 100], false, start);
+        let second = sender.queue(vec![1; 100], false, start);
+        let fin = sender.queue(Vec::new(), true, start);
+        assert_eq!((first.offset, second.offset, fin.offset), (0, 100, 200));
+
+        // The second segment arrived out of order; only it is SACKed
+        let sacked_at = start + Duration::from_millis(100);
+        assert!(sender.on_ack(&ack(0, vec![(100, 200)]), sacked_at));
+        let later = start + INITIAL_RTO;
+        let resent = sender.take_expired(later).expect("retransmit in test");
+        assert_eq!(resent, vec![first, fin]);
+        // RTO was 300ms after the sample and doubled on timeout
+        assert_eq!(
+            sender.next_deadline(),
+            Some(later + Duration::from_millis(600))
+        );
+
+        assert!(sender.on_ack(&ack(201, vec![]), later + Duration::from_secs(5)));
+        assert!(sender.is_finished());
+        assert!(sender.can_send());
+        // Retransmitted segments give no RTT sample (Karn)
+        assert_eq!(sender.srtt(), Some(Duration::from_millis(100)));
+    }
+
+    #[test]
+    fn test_sack_triggers_fast_retransmit() {
+        let start = Instant::now();
+        let mut sender = ReliableSender::new();
+        let segments: Vec<Segment> = (0..5)
+            .map(|_| sender.queue(vec![0; 100], false, start))
+            .collect();
+
+        // Segment 1 missing; two later segments SACKed is not yet enough
+        let now = start + Duration::from_millis(20);
+        sender.on_ack(&ack(100, vec![(200, 400)]), now);
+        assert!(sender
+            .take_expired(now)
+            .expect("no retransmit in test")
+            .is_empty());
+
+        sender.on_ack(&ack(100, vec![(200, 500)]), now);
+        let resent = sender.take_expired(now).expect("fast retransmit in test");
+        assert_eq!(resent, vec![segments[1].clone()]);
+        // No timeout happened, so the RTO is not backed off
+        assert_eq!(sender.rtt.rto(), MIN_RTO);
+    }
+
+    #[test]
+    fn test_tail_loss_probe_reveals_loss() {
+        let start = Instant::now();
+        let mut sender = ReliableSender::new();
+        let segments: Vec<Segment> = (0..3)
+            .map(|_| sender.queue(vec![0; 100], false, start))
+            .collect();
+
+        // The last two segments are lost: no SACK can reveal it
+        let acked_at = start + Duration::from_millis(10);
+        sender.on_ack(&ack(100, vec![]), acked_at);
+        assert!(sender
+            .take_expired(acked_at)
+            .expect("nothing due in test")
+            .is_empty());
+
+        // Two round trips later the newest segment is probed, well before
+        // the retransmission timer
+        let probe_at = start + Duration::from_millis(20);
+        assert_eq!(sender.next_deadline(), Some(probe_at));
+        let probe = sender.take_expired(probe_at).expect("probe in test");
+        assert_eq!(probe, vec![segments[2].clone()]);
+
+        // Its SACK shows the earlier segment overtaken: resent as a loss,
+        // halving the window instead of collapsing it
+        let sacked_at = probe_at + Duration::from_millis(10);
+        sender.on_ack(&ack(100, vec![(200, 300)]), sacked_at);
+        let resent = sender.take_expired(sacked_at).expect("retransmit in test");
+        assert_eq!(resent, vec![segments[1].clone()]);
+        assert_eq!(sender.rtt.rto(), MIN_RTO);
+        assert_eq!(sender.cc.window(), 2 * MAX_STREAM_CHUNK as u64);
+    }
+
+    #[test]
+    fn test_sender_window_and_give_up() {
+        let start = Instant::now();
+        let mut sender = ReliableSender::new();
+        // The initial congestion window holds ten full segments
+        while sender.can_send() {
+            sender.queue(vec![0; MAX_STREAM_CHUNK], false, start);
+        }
+        assert_eq!(sender.next_offset, 10 * MAX_STREAM_CHUNK as u64);
+
+        // SACKed data leaves the congestion window but holds the peer's
+        // window until it is delivered
+        while sender.next_offset < SEND_WINDOW {
+            sender.queue(vec![0; MAX_STREAM_CHUNK], false, start);
+        }
+        sender.on_ack(&ack(0, vec![(1, sender.next_offset)]), start);
+        assert_eq!(sender.bytes_in_flight(), MAX_STREAM_CHUNK as u64);
+        assert!(!sender.can_send());
+
+        let mut now = start;
+        for _ in 0..MAX_RETRANSMITS {
+            now += MAX_RTO;
+            assert!(sender.take_expired(now).is_ok());
+        }
+        now += MAX_RTO;
+        assert!(sender.take_expired(now).is_err());
+    }
+}
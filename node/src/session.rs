@@ -0,0 +1,1330 @@
+// Copyright (c) 2025 Thor Thor
+// Author: Thor Thor (GitHub: https://github.com/codethor0)
+// Contact: codethor@gmail.com
+// LinkedIn: https://www.linkedin.com/in/thor-thor0
+// License: MIT (see LICENSE file for details)
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock, Weak};
+use std::time::{Duration, Instant};
+
+use cryprq_core::{
+    protect_header, unprotect_header, ControlMessage, ErrorCode, HandshakeMessage, Record,
+    RecordHeader, EXT_COOKIE, FLAG_PADDED, FLAG_PROTECTED, HS_CLIENT_FINISH, HS_CLIENT_HELLO,
+    HS_COOKIE, HS_SERVER_HELLO, MSG_TYPE_CONTROL, MSG_TYPE_HANDSHAKE, MSG_TYPE_PADDING,
+    MSG_TYPE_VPN_PACKET, SESSION_ID_SIZE, SUPPORTED_VERSIONS,
+};
+use rand::rngs::OsRng;
+use rand::RngCore;
+use tokio::net::UdpSocket;
+use tokio::sync::{mpsc, Notify};
+use zeroize::Zeroize;
+
+use crate::cookie::CookieJar;
+use crate::handshake::{HandshakeConfig, Initiator, Responder, SessionKeys};
+use crate::kem_pool::KemPool;
+use crate::padding::{pad_plaintext, unpad_plaintext, PaddingPolicy};
+use crate::path::{is_path_message, PathValidator};
+use crate::pmtu;
+use crate::rate_limit::{self, RateLimitConfig, RateLimiter, SourceRateLimiter};
+use crate::record_layer::{
+    recv_record, DirectionKeys, HeaderProtectionKeys, CONTROL_STREAM_ID, VPN_STREAM_ID,
+};
+use crate::{ReplayWindow, TunnelError, BUFFER_SIZE, MAX_NONCE_VALUE};
+
+/// How often an unanswered handshake message is resent
+const HANDSHAKE_RETRY: Duration = Duration::from_secs(1);
+
+/// Largest padded plaintext: session records are not fragmented, so
+/// padding stays within the base PLPMTU
+const MAX_PADDED_PLAINTEXT: usize =
+    pmtu::BASE_PLPMTU as usize - pmtu::RECORD_OVERHEAD - SESSION_ID_SIZE;
+
+/// Decrypted record: (message_type, stream_id, payload)
+type Incoming = (u8, u32, Vec<u8>);
+
+/// Limits and timeouts for a multi-session `TunnelServer`
+#[derive(Clone)]
+pub struct TunnelServerConfig {
+    /// Identity and accepted client identities
+    pub handshake: HandshakeConfig,
+    /// Sessions with no authenticated traffic for this long are closed
+    pub idle_timeout: Duration,
+    /// Handshakes not finished within this time are dropped
+    pub handshake_timeout: Duration,
+    /// Maximum established sessions
+    pub max_sessions: usize,
+    /// Maximum handshakes waiting for CLIENT_FINISH
+    pub max_pending_handshakes: usize,
+    /// Pending handshakes at which CLIENT_HELLO must echo a cookie (0: always)
+    pub cookie_threshold: usize,
+    /// Pre-generated ephemeral key pairs kept ready for new handshakes
+    pub kem_pool_size: usize,
+    /// Per-source and per-session ingress rate limits
+    pub rate_limits: RateLimitConfig,
+}
+
+impl Default for TunnelServerConfig {
+    fn default() -> Self {
+        Self {
+            handshake: HandshakeConfig::default(),
+            idle_timeout: Duration::from_secs(180),
+            handshake_timeout: Duration::from_secs(10),
+            max_sessions: 4096,
+            max_pending_handshakes: 1024,
+            cookie_threshold: 64,
+            kem_pool_size: 16,
+            rate_limits: RateLimitConfig::default(),
+        }
+    }
+}
+
+/// One authenticated session: its own keys, sequence space and replay window
+///
+/// Records carry the session ID, so many sessions can share one UDP socket.
+pub struct Session {
+    id: u64,
+    version: u8,
+    socket: Arc<UdpSocket>,
+    peer_addr: RwLock<Option<SocketAddr>>,
+    peer_identity: Option<[u8; 32]>,
+    keys_outbound: RwLock<DirectionKeys>,
+    keys_inbound: RwLock<DirectionKeys>,
+    header_protection: RwLock<Option<HeaderProtectionKeys>>,
+    next_seq: AtomicU64,
+    replay_window: Mutex<ReplayWindow>,
+    rate_limiter: Mutex<RateLimiter>,
+    path_validator: PathValidator,
+    last_seen: Mutex<Instant>,
+    established: Notify,
+    incoming_tx: Mutex<Option<mpsc::UnboundedSender<Incoming>>>,
+    incoming_rx: tokio::sync::Mutex<mpsc::UnboundedReceiver<Incoming>>,
+    peer_error: Mutex<Option<(ErrorCode, String)>>,
+    padding: RwLock<PaddingPolicy>,
+}
+
+impl Session {
+    fn new(
+        keys: SessionKeys,
+        socket: Arc<UdpSocket>,
+        peer_addr: SocketAddr,
+        rate_limiter: RateLimiter,
+    ) -> Arc<Self> {
+        let (incoming_tx, incoming_rx) = mpsc::unbounded_channel();
+        Arc::new(Self {
+            id: keys.session_id,
+            version: keys.version,
+            socket,
+            peer_addr: RwLock::new(Some(peer_addr)),
+            peer_identity: keys.peer_identity,
+            keys_outbound: RwLock::new(keys.outbound),
+            keys_inbound: RwLock::new(keys.inbound),
+            header_protection: RwLock::new(keys.header_protection),
+            next_seq: AtomicU64::new(0),
+            replay_window: Mutex::new(ReplayWindow::new()),
+            rate_limiter: Mutex::new(rate_limiter),
+            path_validator: PathValidator::new(),
+            last_seen: Mutex::new(Instant::now()),
+            established: Notify::new(),
+            incoming_tx: Mutex::new(Some(incoming_tx)),
+            incoming_rx: tokio::sync::Mutex::new(incoming_rx),
+            peer_error: Mutex::new(None),
+            padding: RwLock::new(PaddingPolicy::None),
+        })
+    }
+
+    /// Session ID carried in every record of this session
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Protocol version negotiated in the handshake
+    pub fn version(&self) -> u8 {
+        self.version
+    }
+
+    /// Current (validated) peer address
+    pub fn peer_addr(&self) -> Option<SocketAddr> {
+        self.peer_addr.read().ok().and_then(|addr| *addr)
+    }
+
+    /// Ed25519 identity the peer proved during the handshake, if any
+    pub fn peer_identity(&self) -> Option<[u8; 32]> {
+        self.peer_identity
+    }
+
+    /// Whether record headers are masked on the wire (Section 6.6)
+    pub fn header_protection(&self) -> bool {
+        self.header_protection
+            .read()
+            .map(|keys| keys.is_some())
+            .unwrap_or(false)
+    }
+
+    /// Time since the last authenticated record from the peer
+    pub fn idle_time(&self) -> Duration {
+        self.last_seen
+            .lock()
+            .map(|last| last.elapsed())
+            .unwrap_or_default()
+    }
+
+    /// Pad outgoing records inside the AEAD (Section 6.5)
+    ///
+    /// Padded records from the peer are accepted either way.
+    pub fn set_padding(&self, policy: PaddingPolicy) {
+        if let Ok(mut guard) = self.padding.write() {
+            *guard = policy;
+        }
+    }
+
+    /// Whether the session has been closed (expired or shut down)
+    pub fn is_closed(&self) -> bool {
+        self.incoming_tx
+            .lock()
+            .map(|tx| tx.is_none())
+            .unwrap_or(true)
+    }
+
+    /// Stop delivering records and erase the session keys
+    ///
+    /// Pending `recv_record` calls return an error. Does not tell the peer;
+    /// see `shutdown`.
+    pub fn close(&self) {
+        if let Ok(mut tx) = self.incoming_tx.lock() {
+            tx.take();
+        }
+        for keys in [&self.keys_outbound, &self.keys_inbound] {
+            if let Ok(mut keys) = keys.write() {
+                keys.zeroize();
+            }
+        }
+        if let Ok(mut keys) = self.header_protection.write() {
+            keys.take();
+        }
+    }
+
+    /// Send CLOSE to the peer, then close the session
+    pub async fn shutdown(&self) -> Result<(), TunnelError> {
+        if self.is_closed() {
+            return Ok(());
+        }
+        let sent = self
+            .send_control(&ControlMessage::Close { stream_id: None })
+            .await;
+        self.close();
+        sent
+    }
+
+    /// Report `err` to the peer in an ERROR message, then close the session
+    ///
+    /// Errors without a wire code get a plain CLOSE.
+    pub async fn close_with_error(&self, err: &TunnelError) -> Result<(), TunnelError> {
+        let Some(code) = err.error_code() else {
+            return self.shutdown().await;
+        };
+        if self.is_closed() {
+            return Ok(());
+        }
+        let sent = self
+            .send_control(&ControlMessage::Error {
+                code,
+                reason: err.to_string(),
+            })
+            .await;
+        self.close();
+        sent
+    }
+
+    async fn send_control(&self, msg: &ControlMessage) -> Result<(), TunnelError> {
+        self.send_record(CONTROL_STREAM_ID, MSG_TYPE_CONTROL, 0, &msg.to_bytes())
+            .await
+    }
+
+    /// Session-level CLOSE or fatal ERROR from the peer: close the session
+    ///
+    /// Returns false for anything else, which is delivered as usual.
+    fn handle_termination(&self, payload: &[u8]) -> bool {
+        match ControlMessage::from_bytes(payload) {
+            Ok(ControlMessage::Close { stream_id: None }) => {
+                log::info!(
+                    "event=session_closed session={:016x} reason=peer_close",
+                    self.id
+                );
+                self.close();
+                true
+            }
+            Ok(ControlMessage::Error { code, reason }) if code.is_fatal() => {
+                log::warn!(
+                    "event=session_closed session={:016x} reason=peer_error code={} message={:?}",
+                    self.id,
+                    code,
+                    reason
+                );
+                if let Ok(mut slot) = self.peer_error.lock() {
+                    *slot = Some((code, reason));
+                }
+                self.close();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Error returned once the session is closed
+    fn closed_error(&self) -> TunnelError {
+        match self.peer_error.lock().ok().and_then(|slot| slot.clone()) {
+            Some((code, reason)) => TunnelError::PeerError { code, reason },
+            None => TunnelError::SessionClosed,
+        }
+    }
+
+    /// Send a record on this session
+    pub async fn send_record(
+        &self,
+        stream_id: u32,
+        message_type: u8,
+        flags: u8,
+        payload: &[u8],
+    ) -> Result<(), TunnelError> {
+        let Some(addr) = self.peer_addr() else {
+            return Ok(());
+        };
+        self.send_to(addr, stream_id, message_type, flags, payload)
+            .await
+    }
+
+    /// Send an IP packet as a VPN_PACKET record
+    pub async fn send_vpn_packet(&self, packet: &[u8]) -> Result<(), TunnelError> {
+        self.send_record(VPN_STREAM_ID, MSG_TYPE_VPN_PACKET, 0, packet)
+            .await
+    }
+
+    /// Receive the next decrypted record: (message_type, stream_id, payload)
+    pub async fn recv_record(&self) -> Result<Incoming, TunnelError> {
+        self.incoming_rx
+            .lock()
+            .await
+            .recv()
+            .await
+            .ok_or_else(|| self.closed_error())
+    }
+
+    async fn send_to(
+        &self,
+        addr: SocketAddr,
+        stream_id: u32,
+        message_type: u8,
+        flags: u8,
+        payload: &[u8],
+    ) -> Result<(), TunnelError> {
+        let padding = self
+            .padding
+            .read()
+            .map_err(|e| TunnelError::LockPoisoned(e.to_string()))?
+            .clone();
+        let (flags, payload) = match padding.is_enabled() {
+            true => (
+                flags | FLAG_PADDED,
+                Cow::Owned(pad_plaintext(payload, &padding, MAX_PADDED_PLAINTEXT)),
+            ),
+            false => (flags, Cow::Borrowed(payload)),
+        };
+        let datagram = {
+            let keys = self
+                .keys_outbound
+                .read()
+                .map_err(|e| TunnelError::LockPoisoned(e.to_string()))?;
+            let hp_keys = self
+                .header_protection
+                .read()
+                .map_err(|e| TunnelError::LockPoisoned(e.to_string()))?;
+            // Checked under the key lock: close() erases the keys after this
+            if self.is_closed() {
+                return Err(self.closed_error());
+            }
+            let seq = self.next_seq.fetch_add(1, Ordering::Relaxed);
+            if seq >= MAX_NONCE_VALUE {
+                return Err(TunnelError::NonceOverflow);
+            }
+            let flags = match hp_keys.is_some() {
+                true => flags | FLAG_PROTECTED,
+                false => flags,
+            };
+            let mut datagram = Record::encrypt_for_session(
+                self.version,
+                Some(self.id),
+                message_type,
+                flags,
+                0,
+                stream_id,
+                seq,
+                &payload,
+                &keys.key,
+                &keys.iv,
+            )
+            .map_err(|_| TunnelError::EncryptionFailed)?
+            .to_bytes();
+            // Masked after sealing: the AAD is the unmasked header
+            if let Some(hp_keys) = hp_keys.as_ref() {
+                protect_header(&hp_keys.outbound, &mut datagram)
+                    .map_err(|_| TunnelError::EncryptionFailed)?;
+            }
+            datagram
+        };
+        self.socket
+            .send_to(&datagram, addr)
+            .await
+            .map_err(|e| TunnelError::NetworkError(e.to_string()))?;
+        Ok(())
+    }
+
+    /// Remove header protection so the header can be read and authenticated
+    ///
+    /// Records must be protected exactly when the session negotiated it.
+    fn unprotect<'a>(&self, buf: &'a [u8]) -> Result<Cow<'a, [u8]>, TunnelError> {
+        let hp_keys = self
+            .header_protection
+            .read()
+            .map_err(|e| TunnelError::LockPoisoned(e.to_string()))?;
+        let protected = buf.get(2).is_some_and(|flags| flags & FLAG_PROTECTED != 0);
+        match (hp_keys.as_ref(), protected) {
+            (Some(hp_keys), true) => {
+                let mut record = buf.to_vec();
+                unprotect_header(&hp_keys.inbound, &mut record)
+                    .map_err(|_| TunnelError::DecryptionFailed)?;
+                Ok(Cow::Owned(record))
+            }
+            (None, false) => Ok(Cow::Borrowed(buf)),
+            _ => Err(TunnelError::DecryptionFailed),
+        }
+    }
+
+    /// Decrypt a datagram addressed to this session and deliver it
+    async fn handle_datagram(&self, buf: &[u8], addr: SocketAddr) -> Result<(), TunnelError> {
+        if self.is_closed() {
+            return Err(self.closed_error());
+        }
+        let record = self.unprotect(buf)?;
+        let (mut header, mut payload) = {
+            let keys = self
+                .keys_inbound
+                .read()
+                .map_err(|e| TunnelError::LockPoisoned(e.to_string()))?;
+            recv_record(&record, &keys).map_err(|_| TunnelError::DecryptionFailed)?
+        };
+        if header.version != self.version {
+            return Err(TunnelError::UnsupportedVersion(header.version));
+        }
+        self.replay_window
+            .lock()
+            .map_err(|e| TunnelError::LockPoisoned(e.to_string()))?
+            .check_and_update(header.sequence_number)?;
+        rate_limit::check_session(&self.rate_limiter, buf.len())?;
+        if let Ok(mut last) = self.last_seen.lock() {
+            *last = Instant::now();
+        }
+        if header.flags & FLAG_PADDED != 0 {
+            let len = unpad_plaintext(&payload)
+                .map_err(|e| TunnelError::UnexpectedMessage(e.to_string()))?
+                .len();
+            payload.truncate(len);
+            header.flags &= !FLAG_PADDED;
+        }
+
+        let replies = self.path_validator.on_authenticated(
+            &self.peer_addr,
+            addr,
+            header.message_type,
+            &payload,
+        )?;
+        for reply in replies {
+            self.send_to(
+                addr,
+                CONTROL_STREAM_ID,
+                MSG_TYPE_CONTROL,
+                0,
+                &reply.to_bytes(),
+            )
+            .await?;
+        }
+
+        match header.message_type {
+            MSG_TYPE_HANDSHAKE => {
+                if let Ok(HandshakeMessage::HandshakeDone) = HandshakeMessage::from_bytes(&payload)
+                {
+                    self.established.notify_one();
+                }
+            }
+            MSG_TYPE_CONTROL if is_path_message(&payload) => {}
+            MSG_TYPE_CONTROL if self.handle_termination(&payload) => {}
+            MSG_TYPE_PADDING => {}
+            message_type => {
+                if let Ok(guard) = self.incoming_tx.lock() {
+                    if let Some(tx) = guard.as_ref() {
+                        let _ = tx.send((message_type, header.stream_id, payload));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Responder state for a handshake waiting for CLIENT_FINISH
+struct PendingHandshake {
+    responder: Responder,
+    addr: SocketAddr,
+    client_hello: Vec<u8>,
+    started: Instant,
+}
+
+/// Multi-session UDP server: many peers on one socket
+///
+/// Runs the responder side of the handshake for every client, assigns each
+/// a session ID, and routes records to sessions by the ID they carry. Each
+/// session keeps its own keys and replay window; idle sessions expire.
+pub struct TunnelServer {
+    socket: Arc<UdpSocket>,
+    config: TunnelServerConfig,
+    sessions: RwLock<HashMap<u64, Arc<Session>>>,
+    pending: Mutex<HashMap<u64, PendingHandshake>>,
+    cookies: CookieJar,
+    source_limiter: SourceRateLimiter,
+    kem_pool: Arc<KemPool>,
+    accept_tx: mpsc::UnboundedSender<Arc<Session>>,
+    accept_rx: tokio::sync::Mutex<mpsc::UnboundedReceiver<Arc<Session>>>,
+}
+
+impl TunnelServer {
+    /// Bind the server socket and start the receive and expiry tasks
+    pub async fn bind(
+        listen_addr: &str,
+        config: TunnelServerConfig,
+    ) -> Result<Arc<Self>, TunnelError> {
+        let socket = Arc::new(UdpSocket::bind(listen_addr).await?);
+        let (accept_tx, accept_rx) = mpsc::unbounded_channel();
+        let kem_pool = Arc::new(KemPool::new(config.kem_pool_size));
+        let server = Arc::new(Self {
+            socket: socket.clone(),
+            sessions: RwLock::new(HashMap::new()),
+            pending: Mutex::new(HashMap::new()),
+            cookies: CookieJar::new(),
+            source_limiter: SourceRateLimiter::new(config.rate_limits.clone()),
+            kem_pool: kem_pool.clone(),
+            config,
+            accept_tx,
+            accept_rx: tokio::sync::Mutex::new(accept_rx),
+        });
+
+        tokio::spawn(Self::receive_loop(Arc::downgrade(&server), socket));
+        tokio::spawn(Self::refill_loop(Arc::downgrade(&server), kem_pool));
+        let sweep_every = server
+            .config
+            .idle_timeout
+            .min(server.config.handshake_timeout)
+            .div_f32(4.0)
+            .max(Duration::from_millis(100));
+        let weak = Arc::downgrade(&server);
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(sweep_every);
+            loop {
+                ticker.tick().await;
+                let Some(server) = weak.upgrade() else {
+                    break;
+                };
+                server.expire();
+            }
+        });
+
+        log::info!("event=tunnel_server_started addr={}", server.local_addr()?);
+        Ok(server)
+    }
+
+    /// Local address of the server socket
+    pub fn local_addr(&self) -> Result<SocketAddr, TunnelError> {
+        Ok(self.socket.local_addr()?)
+    }
+
+    /// Wait for the next established session
+    pub async fn accept(&self) -> Option<Arc<Session>> {
+        self.accept_rx.lock().await.recv().await
+    }
+
+    /// Look up an established session by ID
+    pub fn session(&self, id: u64) -> Option<Arc<Session>> {
+        self.sessions.read().ok()?.get(&id).cloned()
+    }
+
+    /// Number of established sessions
+    pub fn session_count(&self) -> usize {
+        self.sessions.read().map(|s| s.len()).unwrap_or(0)
+    }
+
+    async fn receive_loop(server: Weak<Self>, socket: Arc<UdpSocket>) {
+        let mut buf = vec![0u8; BUFFER_SIZE];
+        loop {
+            let (len, addr) = match socket.recv_from(&mut buf).await {
+                Ok(received) => received,
+                Err(e) => {
+                    log::debug!("event=tunnel_server_recv_error error={}", e);
+                    continue;
+                }
+            };
+            let Some(server) = server.upgrade() else {
+                break;
+            };
+            if let Err(e) = server.handle_datagram(&buf[..len], addr).await {
+                log::debug!("event=tunnel_server_drop peer={} error={}", addr, e);
+            }
+        }
+    }
+
+    /// Keep the ephemeral key pool full without blocking the receive loop
+    async fn refill_loop(server: Weak<Self>, pool: Arc<KemPool>) {
+        loop {
+            let refill = pool.clone();
+            if tokio::task::spawn_blocking(move || refill.refill())
+                .await
+                .is_err()
+            {
+                break;
+            }
+            // Re-check periodically so the task ends with the server
+            let _ = tokio::time::timeout(Duration::from_secs(1), pool.wait_for_refill()).await;
+            if server.strong_count() == 0 {
+                break;
+            }
+        }
+    }
+
+    async fn handle_datagram(&self, buf: &[u8], addr: SocketAddr) -> Result<(), TunnelError> {
+        self.source_limiter.check(addr.ip(), buf.len())?;
+        let header = RecordHeader::from_bytes(buf)?;
+        let session_id = Record::peek_session_id(buf);
+        // Protected records carry a masked message type
+        if header.message_type == MSG_TYPE_HANDSHAKE && header.flags & FLAG_PROTECTED == 0 {
+            if let Ok(record) = Record::from_bytes(buf) {
+                match record.ciphertext.first() {
+                    Some(&HS_CLIENT_HELLO) if session_id.is_none() => {
+                        return self.handle_client_hello(&record.ciphertext, addr).await;
+                    }
+                    Some(&HS_CLIENT_FINISH) => {
+                        return self
+                            .handle_client_finish(session_id, &record.ciphertext, addr)
+                            .await;
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        let session = session_id
+            .and_then(|id| self.session(id))
+            .ok_or(TunnelError::DecryptionFailed)?;
+        session.handle_datagram(buf, addr).await
+    }
+
+    async fn handle_client_hello(&self, hello: &[u8], addr: SocketAddr) -> Result<(), TunnelError> {
+        // A retransmitted CLIENT_HELLO gets the same SERVER_HELLO again
+        let (session_id, server_hello) = match self.resend_server_hello(hello, addr)? {
+            Some(previous) => previous,
+            None => {
+                let pending_count = self
+                    .pending
+                    .lock()
+                    .map_err(|e| TunnelError::LockPoisoned(e.to_string()))?
+                    .len();
+                if pending_count >= self.config.max_pending_handshakes {
+                    log::warn!(
+                        "event=handshake_rejected peer={} reason=pending_limit",
+                        addr
+                    );
+                    return Ok(());
+                }
+                // Under load, allocate nothing until the client proves its address
+                if pending_count >= self.config.cookie_threshold
+                    && !self.has_valid_cookie(hello, addr)
+                {
+                    return self.send_cookie(addr).await;
+                }
+
+                let session_id = self.new_session_id()?;
+                let responder = match Responder::accept(
+                    &self.config.handshake,
+                    hello,
+                    session_id,
+                    self.kem_pool.take(),
+                ) {
+                    Ok(responder) => responder,
+                    Err(e) => {
+                        let err = TunnelError::from(e);
+                        if let TunnelError::UnsupportedVersion(_) = err {
+                            self.reject_version(&err, addr).await?;
+                        }
+                        return Err(err);
+                    }
+                };
+                let server_hello = responder.server_hello().to_vec();
+                self.pending
+                    .lock()
+                    .map_err(|e| TunnelError::LockPoisoned(e.to_string()))?
+                    .insert(
+                        session_id,
+                        PendingHandshake {
+                            responder,
+                            addr,
+                            client_hello: hello.to_vec(),
+                            started: Instant::now(),
+                        },
+                    );
+                (session_id, server_hello)
+            }
+        };
+
+        let record =
+            Record::new(MSG_TYPE_HANDSHAKE, 0, 0, 0, 0, server_hello).with_session_id(session_id);
+        self.socket.send_to(&record.to_bytes(), addr).await?;
+        Ok(())
+    }
+
+    /// Tell a client that speaks another protocol version why it gets no
+    /// SERVER_HELLO
+    ///
+    /// No keys exist yet, so the ERROR goes out in a plaintext CONTROL
+    /// record. Clients honour it only while waiting for SERVER_HELLO.
+    async fn reject_version(&self, err: &TunnelError, addr: SocketAddr) -> Result<(), TunnelError> {
+        log::warn!(
+            "event=handshake_rejected peer={} reason=unsupported_version error={}",
+            addr,
+            err
+        );
+        let error = ControlMessage::Error {
+            code: ErrorCode::UnsupportedVersion,
+            reason: format!("server speaks versions {:?}", SUPPORTED_VERSIONS),
+        };
+        let record = Record::new(MSG_TYPE_CONTROL, 0, 0, 0, 0, error.to_bytes());
+        self.socket.send_to(&record.to_bytes(), addr).await?;
+        Ok(())
+    }
+
+    fn has_valid_cookie(&self, hello: &[u8], addr: SocketAddr) -> bool {
+        match HandshakeMessage::from_bytes(hello) {
+            Ok(HandshakeMessage::ClientHello(hello)) => hello
+                .extension(EXT_COOKIE)
+                .is_some_and(|cookie| self.cookies.verify(addr, cookie)),
+            _ => false,
+        }
+    }
+
+    async fn send_cookie(&self, addr: SocketAddr) -> Result<(), TunnelError> {
+        log::debug!("event=cookie_challenge peer={}", addr);
+        let cookie = HandshakeMessage::Cookie(self.cookies.issue(addr));
+        let record = Record::new(MSG_TYPE_HANDSHAKE, 0, 0, 0, 0, cookie.to_bytes());
+        self.socket.send_to(&record.to_bytes(), addr).await?;
+        Ok(())
+    }
+
+    fn resend_server_hello(
+        &self,
+        hello: &[u8],
+        addr: SocketAddr,
+    ) -> Result<Option<(u64, Vec<u8>)>, TunnelError> {
+        let pending = self
+            .pending
+            .lock()
+            .map_err(|e| TunnelError::LockPoisoned(e.to_string()))?;
+        Ok(pending
+            .iter()
+            .find(|(_, p)| p.addr == addr && p.client_hello == hello)
+            .map(|(id, p)| (*id, p.responder.server_hello().to_vec())))
+    }
+
+    async fn handle_client_finish(
+        &self,
+        session_id: Option<u64>,
+        finish: &[u8],
+        addr: SocketAddr,
+    ) -> Result<(), TunnelError> {
+        let session_id = session_id.ok_or(TunnelError::DecryptionFailed)?;
+        let session = match self.complete_handshake(session_id, finish, addr)? {
+            Some(session) => session,
+            None => {
+                // Lost HANDSHAKE_DONE: confirm again, but only to the client's address
+                match self.session(session_id) {
+                    Some(session) if session.peer_addr() == Some(addr) => session,
+                    _ => return Ok(()),
+                }
+            }
+        };
+        session
+            .send_record(
+                CONTROL_STREAM_ID,
+                MSG_TYPE_HANDSHAKE,
+                0,
+                &HandshakeMessage::HandshakeDone.to_bytes(),
+            )
+            .await
+    }
+
+    /// Verify CLIENT_FINISH and register the new session
+    ///
+    /// Returns `None` if no handshake is pending for `session_id`.
+    fn complete_handshake(
+        &self,
+        session_id: u64,
+        finish: &[u8],
+        addr: SocketAddr,
+    ) -> Result<Option<Arc<Session>>, TunnelError> {
+        let Some(pending) = self
+            .pending
+            .lock()
+            .map_err(|e| TunnelError::LockPoisoned(e.to_string()))?
+            .remove(&session_id)
+        else {
+            return Ok(None);
+        };
+
+        let keys = match pending.responder.finish(finish) {
+            Ok(keys) => keys,
+            Err(e) => {
+                log::warn!("event=handshake_failed peer={} error={}", addr, e);
+                return Err(e.into());
+            }
+        };
+
+        let mut sessions = self
+            .sessions
+            .write()
+            .map_err(|e| TunnelError::LockPoisoned(e.to_string()))?;
+        if sessions.len() >= self.config.max_sessions {
+            log::warn!(
+                "event=handshake_rejected peer={} reason=session_limit",
+                addr
+            );
+            return Ok(None);
+        }
+        let session = Session::new(
+            keys,
+            self.socket.clone(),
+            pending.addr,
+            self.source_limiter.session_limiter(),
+        );
+        sessions.insert(session_id, session.clone());
+        drop(sessions);
+
+        log::info!(
+            "event=session_established session={:016x} peer={} identity={}",
+            session_id,
+            pending.addr,
+            session
+                .peer_identity()
+                .map(hex::encode)
+                .unwrap_or_else(|| "anonymous".to_string())
+        );
+        let _ = self.accept_tx.send(session.clone());
+        Ok(Some(session))
+    }
+
+    /// Random non-zero session ID not in use by a session or handshake
+    fn new_session_id(&self) -> Result<u64, TunnelError> {
+        let sessions = self
+            .sessions
+            .read()
+            .map_err(|e| TunnelError::LockPoisoned(e.to_string()))?;
+        let pending = self
+            .pending
+            .lock()
+            .map_err(|e| TunnelError::LockPoisoned(e.to_string()))?;
+        loop {
+            let id = OsRng.next_u64();
+            if id != 0 && !sessions.contains_key(&id) && !pending.contains_key(&id) {
+                return Ok(id);
+            }
+        }
+    }
+
+    /// Close idle sessions and drop stale handshakes
+    fn expire(&self) {
+        if let Ok(mut pending) = self.pending.lock() {
+            pending.retain(|_, p| p.started.elapsed() < self.config.handshake_timeout);
+        }
+        let Ok(mut sessions) = self.sessions.write() else {
+            return;
+        };
+        sessions.retain(|id, session| {
+            let alive = !session.is_closed() && session.idle_time() < self.config.idle_timeout;
+            if !alive {
+                session.close();
+                log::info!("event=session_expired session={:016x}", id);
+            }
+            alive
+        });
+    }
+}
+
+/// Run the initiator handshake against a `TunnelServer` and open a session
+///
+/// Binds `bind_addr`, resends CLIENT_HELLO and CLIENT_FINISH once per second
+/// until the server answers, and gives up after `timeout`.
+pub async fn connect_session(
+    bind_addr: &str,
+    server_addr: SocketAddr,
+    config: &HandshakeConfig,
+    timeout: Duration,
+) -> Result<Arc<Session>, TunnelError> {
+    let socket = Arc::new(UdpSocket::bind(bind_addr).await?);
+    let deadline = tokio::time::Instant::now() + timeout;
+    let mut initiator = Initiator::new(config);
+
+    // CLIENT_HELLO until SERVER_HELLO; a COOKIE reply restarts it with the cookie
+    let server_hello = loop {
+        let hello = Record::new(
+            MSG_TYPE_HANDSHAKE,
+            0,
+            0,
+            0,
+            0,
+            initiator.client_hello().to_vec(),
+        );
+        socket.send_to(&hello.to_bytes(), server_addr).await?;
+        let retry = (tokio::time::Instant::now() + HANDSHAKE_RETRY).min(deadline);
+        if let Ok(Some(reply)) =
+            tokio::time::timeout_at(retry, recv_server_hello(&socket, server_addr)).await
+        {
+            let reply = reply?;
+            match HandshakeMessage::from_bytes(&reply) {
+                Ok(HandshakeMessage::Cookie(cookie)) => {
+                    log::debug!("event=cookie_received peer={}", server_addr);
+                    initiator.set_cookie(&cookie);
+                    continue;
+                }
+                _ => break reply,
+            }
+        }
+        if tokio::time::Instant::now() >= deadline {
+            return Err(TunnelError::Timeout("handshake".to_string()));
+        }
+    };
+
+    let (keys, finish) = initiator.finish(&server_hello)?;
+    let session_id = keys.session_id;
+    let session = Session::new(
+        keys,
+        socket.clone(),
+        server_addr,
+        RateLimiter::with_limits(&RateLimitConfig::default().per_session),
+    );
+    tokio::spawn(client_receive_loop(
+        Arc::downgrade(&session),
+        socket.clone(),
+    ));
+
+    // CLIENT_FINISH until HANDSHAKE_DONE
+    let finish = Record::new(MSG_TYPE_HANDSHAKE, 0, 0, 0, 0, finish).with_session_id(session_id);
+    loop {
+        socket.send_to(&finish.to_bytes(), server_addr).await?;
+        let retry = (tokio::time::Instant::now() + HANDSHAKE_RETRY).min(deadline);
+        if tokio::time::timeout_at(retry, session.established.notified())
+            .await
+            .is_ok()
+        {
+            log::info!(
+                "event=session_established session={:016x} peer={}",
+                session_id,
+                server_addr
+            );
+            return Ok(session);
+        }
+        if tokio::time::Instant::now() >= deadline {
+            session.close();
+            return Err(TunnelError::Timeout("handshake".to_string()));
+        }
+    }
+}
+
+/// Wait for a SERVER_HELLO or COOKIE from the server, ignoring anything else
+///
+/// An UNSUPPORTED_VERSION error from the server ends the wait with
+/// `TunnelError::PeerError`.
+async fn recv_server_hello(
+    socket: &UdpSocket,
+    server_addr: SocketAddr,
+) -> Option<Result<Vec<u8>, TunnelError>> {
+    let mut buf = vec![0u8; BUFFER_SIZE];
+    loop {
+        let (len, addr) = socket.recv_from(&mut buf).await.ok()?;
+        if addr != server_addr {
+            continue;
+        }
+        match Record::from_bytes(&buf[..len]) {
+            Ok(record)
+                if record.header.message_type == MSG_TYPE_HANDSHAKE
+                    && matches!(
+                        record.ciphertext.first(),
+                        Some(&HS_SERVER_HELLO | &HS_COOKIE)
+                    ) =>
+            {
+                return Some(Ok(record.ciphertext))
+            }
+            Ok(record) if record.header.message_type == MSG_TYPE_CONTROL => {
+                if let Ok(ControlMessage::Error {
+                    code: code @ ErrorCode::UnsupportedVersion,
+                    reason,
+                }) = ControlMessage::from_bytes(&record.ciphertext)
+                {
+                    return Some(Err(TunnelError::PeerError { code, reason }));
+                }
+            }
+            _ => continue,
+        }
+    }
+}
+
+async fn client_receive_loop(session: Weak<Session>, socket: Arc<UdpSocket>) {
+    let mut buf = vec![0u8; BUFFER_SIZE];
+    loop {
+        let Ok((len, addr)) = socket.recv_from(&mut buf).await else {
+            continue;
+        };
+        let Some(session) = session.upgrade() else {
+            break;
+        };
+        if session.is_closed() {
+            break;
+        }
+        if Record::peek_session_id(&buf[..len]) != Some(session.id()) {
+            continue;
+        }
+        if let Err(e) = session.handle_datagram(&buf[..len], addr).await {
+            log::debug!("event=session_drop peer={} error={}", addr, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cryprq_core::{
+        ClientHello, CIPHER_SUITE_CHACHA20_POLY1305, MSG_TYPE_DATA, PROTOCOL_VERSION,
+    };
+
+    async fn recv(session: &Session) -> Incoming {
+        tokio::time::timeout(Duration::from_secs(5), session.recv_record())
+            .await
+            .expect("record in time in test")
+            .expect("session open in test")
+    }
+
+    #[tokio::test]
+    async fn test_server_demultiplexes_sessions() {
+        let server = TunnelServer::bind("127.0.0.1:0", TunnelServerConfig::default())
+            .await
+            .expect("bind server in test");
+        let server_addr = server.local_addr().expect("server addr in test");
+        let config = HandshakeConfig::default();
+
+        let (a, b) = tokio::join!(
+            connect_session("127.0.0.1:0", server_addr, &config, Duration::from_secs(5)),
+            connect_session("127.0.0.1:0", server_addr, &config, Duration::from_secs(5)),
+        );
+        let (a, b) = (a.expect("client a in test"), b.expect("client b in test"));
+        assert_ne!(a.id(), b.id());
+        assert_eq!(server.session_count(), 2);
+
+        a.send_record(5, MSG_TYPE_DATA, 0, b"from a")
+            .await
+            .expect("send a in test");
+        b.send_record(5, MSG_TYPE_DATA, 0, b"from b")
+            .await
+            .expect("send b in test");
+
+        for client in [&a, &b] {
+            let session = server.session(client.id()).expect("server session in test");
+            let (msg_type, stream_id, payload) = recv(&session).await;
+            assert_eq!((msg_type, stream_id), (MSG_TYPE_DATA, 5));
+            let expected: &[u8] = if client.id() == a.id() {
+                b"from a"
+            } else {
+                b"from b"
+            };
+            assert_eq!(payload, expected);
+
+            // Replies reach only the session they were sent on
+            session
+                .send_record(5, MSG_TYPE_DATA, 0, &payload)
+                .await
+                .expect("reply in test");
+            assert_eq!(recv(client).await.2, expected);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_header_protection_masks_wire_header() {
+        let handshake = HandshakeConfig {
+            header_protection: true,
+            ..HandshakeConfig::default()
+        };
+        let server = TunnelServer::bind(
+            "127.0.0.1:0",
+            TunnelServerConfig {
+                handshake: handshake.clone(),
+                ..TunnelServerConfig::default()
+            },
+        )
+        .await
+        .expect("bind server in test");
+        let server_addr = server.local_addr().expect("server addr in test");
+        let client = connect_session(
+            "127.0.0.1:0",
+            server_addr,
+            &handshake,
+            Duration::from_secs(5),
+        )
+        .await
+        .expect("connect in test");
+        let session = server.accept().await.expect("accepted session in test");
+        assert!(client.header_protection());
+        assert!(session.header_protection());
+
+        client
+            .send_record(5, MSG_TYPE_DATA, 0, b"masked")
+            .await
+            .expect("send in test");
+        assert_eq!(recv(&session).await, (MSG_TYPE_DATA, 5, b"masked".to_vec()));
+
+        // Capture a record off the wire: type, stream ID and sequence are hidden
+        let observer = UdpSocket::bind("127.0.0.1:0")
+            .await
+            .expect("bind observer in test");
+        let observer_addr = observer.local_addr().expect("observer addr in test");
+        session
+            .send_to(observer_addr, 7, MSG_TYPE_DATA, 0, b"hidden")
+            .await
+            .expect("send to observer in test");
+        let mut buf = vec![0u8; BUFFER_SIZE];
+        let (len, _) = tokio::time::timeout(Duration::from_secs(5), observer.recv_from(&mut buf))
+            .await
+            .expect("datagram in time in test")
+            .expect("datagram in test");
+        let wire = &buf[..len];
+        let header = RecordHeader::from_bytes(wire).expect("header in test");
+        assert_ne!(header.flags & FLAG_PROTECTED, 0);
+        assert_eq!(Record::peek_session_id(wire), Some(client.id()));
+        assert_ne!(
+            (header.stream_id, header.sequence_number),
+            (7, session.next_seq.load(Ordering::Relaxed) - 1)
+        );
+
+        // The client unmasks it; a replay of the same record is refused
+        client
+            .handle_datagram(wire, server_addr)
+            .await
+            .expect("unmask in test");
+        assert_eq!(recv(&client).await, (MSG_TYPE_DATA, 7, b"hidden".to_vec()));
+        assert!(matches!(
+            client.handle_datagram(wire, server_addr).await,
+            Err(TunnelError::ReplayDetected)
+        ));
+
+        // A record without protection is rejected by a protected session
+        let mut stripped = wire.to_vec();
+        stripped[2] &= !FLAG_PROTECTED;
+        assert!(matches!(
+            client.handle_datagram(&stripped, server_addr).await,
+            Err(TunnelError::DecryptionFailed)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_idle_sessions_expire() {
+        let config = TunnelServerConfig {
+            idle_timeout: Duration::from_millis(300),
+            ..TunnelServerConfig::default()
+        };
+        let server = TunnelServer::bind("127.0.0.1:0", config)
+            .await
+            .expect("bind server in test");
+        let client = connect_session(
+            "127.0.0.1:0",
+            server.local_addr().expect("server addr in test"),
+            &HandshakeConfig::default(),
+            Duration::from_secs(5),
+        )
+        .await
+        .expect("connect in test");
+        let session = server.accept().await.expect("accepted session in test");
+        assert_eq!(session.id(), client.id());
+
+        tokio::time::sleep(Duration::from_millis(800)).await;
+        assert_eq!(server.session_count(), 0);
+        assert!(session.is_closed());
+        assert!(session.recv_record().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_closes_peer_and_erases_keys() {
+        let server = TunnelServer::bind("127.0.0.1:0", TunnelServerConfig::default())
+            .await
+            .expect("bind server in test");
+        let client = connect_session(
+            "127.0.0.1:0",
+            server.local_addr().expect("server addr in test"),
+            &HandshakeConfig::default(),
+            Duration::from_secs(5),
+        )
+        .await
+        .expect("connect in test");
+        let session = server.accept().await.expect("accepted session in test");
+
+        client.shutdown().await.expect("shutdown in test");
+        assert!(matches!(
+            client.send_vpn_packet(b"late").await,
+            Err(TunnelError::SessionClosed)
+        ));
+        assert!(matches!(
+            tokio::time::timeout(Duration::from_secs(5), session.recv_record())
+                .await
+                .expect("close in time in test"),
+            Err(TunnelError::SessionClosed)
+        ));
+        for closed in [&client, &session] {
+            let keys = closed.keys_outbound.read().expect("keys lock in test");
+            assert_eq!(keys.key, [0u8; 32]);
+            assert_eq!(keys.iv, [0u8; 12]);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fatal_error_closes_session() {
+        let server = TunnelServer::bind("127.0.0.1:0", TunnelServerConfig::default())
+            .await
+            .expect("bind server in test");
+        let client = connect_session(
+            "127.0.0.1:0",
+            server.local_addr().expect("server addr in test"),
+            &HandshakeConfig::default(),
+            Duration::from_secs(5),
+        )
+        .await
+        .expect("connect in test");
+        let session = server.accept().await.expect("accepted session in test");
+
+        session
+            .close_with_error(&TunnelError::ReplayDetected)
+            .await
+            .expect("close with error in test");
+        let err = tokio::time::timeout(Duration::from_secs(5), client.recv_record())
+            .await
+            .expect("error in time in test");
+        assert!(matches!(
+            err,
+            Err(TunnelError::PeerError {
+                code: ErrorCode::CryptoError,
+                ..
+            })
+        ));
+        assert!(client.is_closed());
+    }
+
+    #[tokio::test]
+    async fn test_unsupported_version_reported() {
+        let server = TunnelServer::bind("127.0.0.1:0", TunnelServerConfig::default())
+            .await
+            .expect("bind server in test");
+        let server_addr = server.local_addr().expect("server addr in test");
+        let socket = UdpSocket::bind("127.0.0.1:0")
+            .await
+            .expect("bind client in test");
+
+        // A CLIENT_HELLO for a version this server does not speak
+        let hello = HandshakeMessage::ClientHello(ClientHello {
+            version: PROTOCOL_VERSION + 1,
+            random: [0; 32],
+            cipher_suites: vec![CIPHER_SUITE_CHACHA20_POLY1305],
+            extensions: vec![],
+        })
+        .to_bytes();
+        let record = Record::new(MSG_TYPE_HANDSHAKE, 0, 0, 0, 0, hello);
+        socket
+            .send_to(&record.to_bytes(), server_addr)
+            .await
+            .expect("send hello in test");
+        let reply = tokio::time::timeout(
+            Duration::from_secs(5),
+            recv_server_hello(&socket, server_addr),
+        )
+        .await
+        .expect("reply in time in test")
+        .expect("reply in test");
+        assert!(matches!(
+            reply,
+            Err(TunnelError::PeerError {
+                code: ErrorCode::UnsupportedVersion,
+                ..
+            })
+        ));
+        assert_eq!(server.session_count(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_cookie_required_under_load() {
+        let config = TunnelServerConfig {
+            cookie_threshold: 0,
+            ..TunnelServerConfig::default()
+        };
+        let server = TunnelServer::bind("127.0.0.1:0", config)
+            .await
+            .expect("bind server in test");
+        let server_addr = server.local_addr().expect("server addr in test");
+
+        // A bare CLIENT_HELLO gets a COOKIE and leaves no handshake state
+        let socket = UdpSocket::bind("127.0.0.1:0")
+            .await
+            .expect("bind client in test");
+        let initiator = Initiator::new(&HandshakeConfig::default());
+        let hello = Record::new(
+            MSG_TYPE_HANDSHAKE,
+            0,
+            0,
+            0,
+            0,
+            initiator.client_hello().to_vec(),
+        );
+        socket
+            .send_to(&hello.to_bytes(), server_addr)
+            .await
+            .expect("send hello in test");
+        let reply = tokio::time::timeout(
+            Duration::from_secs(5),
+            recv_server_hello(&socket, server_addr),
+        )
+        .await
+        .expect("reply in time in test")
+        .expect("reply in test")
+        .expect("no error reply in test");
+        assert!(matches!(
+            HandshakeMessage::from_bytes(&reply),
+            Ok(HandshakeMessage::Cookie(_))
+        ));
+        assert!(server
+            .pending
+            .lock()
+            .expect("pending lock in test")
+            .is_empty());
+
+        // The full client echoes the cookie and connects
+        let client = connect_session(
+            "127.0.0.1:0",
+            server_addr,
+            &HandshakeConfig::default(),
+            Duration::from_secs(5),
+        )
+        .await
+        .expect("connect with cookie in test");
+        assert!(server.session(client.id()).is_some());
+    }
+}
@@ -0,0 +1,159 @@
+// Copyright (c) 2025 Thor Thor
+// Author: Thor Thor (GitHub: https://github.com/codethor0)
+// Contact: codethor@gmail.com
+// LinkedIn: https://www.linkedin.com/in/thor-thor0
+// License: MIT (see LICENSE file for details)
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Reason a packet was dropped before reaching TUN or the peer
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DropReason {
+    /// Not a parseable IPv4/IPv6 packet
+    Malformed,
+    /// Source address outside the peer's allowed prefixes
+    SourceNotAllowed,
+    /// Rejected by a firewall rule
+    Firewall,
+    /// Exit NAT flow limit reached for the peer or the node
+    NatLimit,
+    /// Exit NAT cannot translate the packet (protocol, family or destination)
+    NatUnsupported,
+    /// Exit NAT flow's upstream fell behind and its queue is full
+    NatBackpressure,
+    /// Frame or packet larger than the configured MTU
+    Oversized,
+    /// Source IP over its packet or byte rate (before authentication)
+    RateLimitSource,
+    /// Session over its packet or byte rate (after authentication)
+    RateLimitSession,
+    /// Datagram the pluggable transport could not deobfuscate
+    Transport,
+    /// VPN packet dropped because the stream transport fell behind
+    StreamBackpressure,
+    /// Datagram larger than the receive buffer, cut short by the kernel
+    Truncated,
+    /// Record type the session was not opened for (e.g. an Ethernet frame
+    /// on a layer-3 TUN)
+    UnexpectedType,
+    /// Custom message dropped because its handler fell behind
+    HandlerBackpressure,
+}
+
+impl DropReason {
+    /// All drop reasons, in metric export order
+    pub const ALL: [DropReason; 14] = [
+        DropReason::Malformed,
+        DropReason::SourceNotAllowed,
+        DropReason::Firewall,
+        DropReason::NatLimit,
+        DropReason::NatUnsupported,
+        DropReason::NatBackpressure,
+        DropReason::Oversized,
+        DropReason::RateLimitSource,
+        DropReason::RateLimitSession,
+        DropReason::Transport,
+        DropReason::StreamBackpressure,
+        DropReason::Truncated,
+        DropReason::UnexpectedType,
+        DropReason::HandlerBackpressure,
+    ];
+
+    /// Label used in logs and metrics
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DropReason::Malformed => "malformed",
+            DropReason::SourceNotAllowed => "source_not_allowed",
+            DropReason::Firewall => "firewall",
+            DropReason::NatLimit => "nat_limit",
+            DropReason::NatUnsupported => "nat_unsupported",
+            DropReason::NatBackpressure => "nat_backpressure",
+            DropReason::Oversized => "oversized",
+            DropReason::RateLimitSource => "rate_limit_source",
+            DropReason::RateLimitSession => "rate_limit_session",
+            DropReason::Transport => "transport",
+            DropReason::StreamBackpressure => "stream_backpressure",
+            DropReason::Truncated => "truncated",
+            DropReason::UnexpectedType => "unexpected_type",
+            DropReason::HandlerBackpressure => "handler_backpressure",
+        }
+    }
+
+    fn index(&self) -> usize {
+        *self as usize
+    }
+}
+
+/// Process-wide dropped packet counters, indexed by `DropReason`
+static PACKETS_DROPPED: [AtomicU64; DropReason::ALL.len()] =
+    [const { AtomicU64::new(0) }; DropReason::ALL.len()];
+
+/// Count a dropped packet
+pub fn record_drop(reason: DropReason) {
+    PACKETS_DROPPED[reason.index()].fetch_add(1, Ordering::Relaxed);
+}
+
+/// Total packets dropped for a reason since startup
+pub fn dropped_packets(reason: DropReason) -> u64 {
+    PACKETS_DROPPED[reason.index()].load(Ordering::Relaxed)
+}
+
+/// Bytes sent in cover traffic slots, by what filled them
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CoverBytes {
+    /// Application payload carried in a slot
+    Data,
+    /// Padding and record overhead around that payload
+    Padding,
+    /// Whole PADDING records sent in empty slots
+    Dummy,
+}
+
+impl CoverBytes {
+    /// All kinds, in metric export order
+    pub const ALL: [CoverBytes; 3] = [CoverBytes::Data, CoverBytes::Padding, CoverBytes::Dummy];
+
+    /// Label used in logs and metrics
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CoverBytes::Data => "data",
+            CoverBytes::Padding => "padding",
+            CoverBytes::Dummy => "dummy",
+        }
+    }
+
+    fn index(&self) -> usize {
+        *self as usize
+    }
+}
+
+/// Process-wide cover traffic byte counters, indexed by `CoverBytes`
+static COVER_BYTES: [AtomicU64; CoverBytes::ALL.len()] =
+    [const { AtomicU64::new(0) }; CoverBytes::ALL.len()];
+
+/// Count bytes sent in cover traffic slots
+pub fn record_cover_bytes(kind: CoverBytes, bytes: u64) {
+    COVER_BYTES[kind.index()].fetch_add(bytes, Ordering::Relaxed);
+}
+
+/// Total cover traffic bytes of a kind since startup
+pub fn cover_bytes(kind: CoverBytes) -> u64 {
+    COVER_BYTES[kind.index()].load(Ordering::Relaxed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_drop_counts_per_reason() {
+        let before = dropped_packets(DropReason::Firewall);
+        let other = dropped_packets(DropReason::Malformed);
+        record_drop(DropReason::Firewall);
+        record_drop(DropReason::Firewall);
+        assert!(dropped_packets(DropReason::Firewall) >= before + 2);
+        assert_eq!(DropReason::Firewall.as_str(), "firewall");
+        // Other reasons are unaffected by this test's drops
+        assert!(dropped_packets(DropReason::Malformed) >= other);
+    }
+}
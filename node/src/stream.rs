@@ -0,0 +1,1069 @@
+// Copyright (c) 2025 Thor Thor
+// Author: Thor Thor (GitHub: https://github.com/codethor0)
+// Contact: codethor@gmail.com
+// LinkedIn: https://www.linkedin.com/in/thor-thor0
+// License: MIT (see LICENSE file for details)
+
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, Weak};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use cryprq_core::{StreamAck, MSG_TYPE_DATA};
+use rand::rngs::OsRng;
+use rand::Rng;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::sync::mpsc;
+use tokio::sync::mpsc::error::TrySendError;
+
+use crate::padding;
+use crate::pmtu;
+use crate::reliable::{self, ReliableReceiver, Segment, SendState};
+use crate::{Tunnel, TunnelError};
+
+/// Largest DATA payload sent in one record
+///
+/// Sized so a reliable segment fits the base path MTU unfragmented.
+pub const MAX_STREAM_CHUNK: usize = pmtu::BASE_PLPMTU as usize
+    - pmtu::RECORD_OVERHEAD
+    - padding::PADDING_TRAILER_SIZE
+    - reliable::OFFSET_SIZE;
+
+/// DATA records buffered per stream before new ones are dropped (unreliable
+/// streams) or left unacknowledged (reliable streams)
+const STREAM_RECV_QUEUE: usize = 256;
+
+/// Application streams use the upper half of the stream ID space; file
+/// transfers count up from 2 in the lower half
+const APP_STREAM_BASE: u32 = 0x8000_0000;
+
+/// How long a fully closed reliable stream is remembered, so late
+/// retransmissions are acknowledged instead of reopening it
+const CLOSED_STREAM_LINGER: Duration = Duration::from_secs(300);
+
+/// Streams open at once; further opens from the peer are reset
+const MAX_CONCURRENT_STREAMS: usize = 1024;
+
+/// Peer-opened streams waiting for `accept_stream`; opens beyond this are
+/// reset
+const ACCEPT_QUEUE_LEN: usize = 64;
+
+type PendingSend = Pin<Box<dyn Future<Output = Result<(), TunnelError>> + Send>>;
+type IncomingStream = (u32, mpsc::Receiver<Vec<u8>>, Option<Arc<SendState>>);
+
+struct StreamEntry {
+    /// Receive buffer sender; None once the peer closed its side
+    tx: Option<mpsc::Sender<Vec<u8>>>,
+    /// Our side sent STREAM_CLOSE or queued its FIN
+    local_closed: bool,
+    /// Acknowledgement and retransmission state (reliable streams only)
+    reliable: Option<ReliableEntry>,
+}
+
+struct ReliableEntry {
+    send: Arc<SendState>,
+    receiver: ReliableReceiver,
+}
+
+impl StreamEntry {
+    fn new(reliable: bool) -> (Self, mpsc::Receiver<Vec<u8>>) {
+        let (tx, rx) = mpsc::channel(STREAM_RECV_QUEUE);
+        let entry = Self {
+            tx: Some(tx),
+            local_closed: false,
+            reliable: reliable.then(|| ReliableEntry {
+                send: Arc::new(SendState::new()),
+                receiver: ReliableReceiver::new(),
+            }),
+        };
+        (entry, rx)
+    }
+
+    fn send_state(&self) -> Option<Arc<SendState>> {
+        self.reliable.as_ref().map(|reliable| reliable.send.clone())
+    }
+
+    /// Move in-order reliable data into the receive buffer; returns the
+    /// acknowledgement point
+    fn deliver(&mut self) -> u64 {
+        let StreamEntry { tx, reliable, .. } = self;
+        let Some(reliable) = reliable.as_mut() else {
+            return 0;
+        };
+        let finished = reliable.receiver.deliver(|data| match tx.as_ref() {
+            Some(tx) => match tx.try_send(data) {
+                Ok(()) => Ok(()),
+                Err(TrySendError::Full(data)) => Err(data),
+                // Reader gone: consume the data so the sender can finish
+                Err(TrySendError::Closed(_)) => Ok(()),
+            },
+            None => Ok(()),
+        });
+        if finished {
+            *tx = None;
+        }
+        reliable.receiver.delivered()
+    }
+
+    /// Both directions done: FIN delivered, and our FIN acknowledged
+    fn is_finished(&self) -> bool {
+        let Some(reliable) = self.reliable.as_ref() else {
+            return false;
+        };
+        self.local_closed
+            && self.tx.is_none()
+            && reliable
+                .send
+                .sender
+                .lock()
+                .map(|sender| sender.is_finished())
+                .unwrap_or(false)
+    }
+}
+
+/// What closing our side of a stream requires
+enum LocalClose {
+    /// Already closed (or unknown)
+    Done,
+    /// Unreliable stream: send STREAM_CLOSE
+    Control,
+    /// Reliable stream: queue a FIN segment
+    Fin(Arc<SendState>),
+}
+
+/// Open streams of one tunnel and the queue of peer-opened streams
+pub(crate) struct StreamRegistry {
+    streams: Mutex<HashMap<u32, StreamEntry>>,
+    /// Final acknowledgement point of recently finished reliable streams
+    closed: Mutex<HashMap<u32, (u64, Instant)>>,
+    /// None once the session closed: `accept` returns None
+    incoming_tx: Mutex<Option<mpsc::Sender<IncomingStream>>>,
+    incoming_rx: tokio::sync::Mutex<mpsc::Receiver<IncomingStream>>,
+}
+
+impl StreamRegistry {
+    pub(crate) fn new() -> Self {
+        let (incoming_tx, incoming_rx) = mpsc::channel(ACCEPT_QUEUE_LEN);
+        Self {
+            streams: Mutex::new(HashMap::new()),
+            closed: Mutex::new(HashMap::new()),
+            incoming_tx: Mutex::new(Some(incoming_tx)),
+            incoming_rx: tokio::sync::Mutex::new(incoming_rx),
+        }
+    }
+
+    /// Register a locally opened stream under a random unused ID
+    fn open_local(&self, reliable: bool) -> Result<IncomingStream, TunnelError> {
+        let mut streams = self
+            .streams
+            .lock()
+            .map_err(|e| TunnelError::LockPoisoned(e.to_string()))?;
+        let closed = self
+            .closed
+            .lock()
+            .map_err(|e| TunnelError::LockPoisoned(e.to_string()))?;
+        let stream_id = loop {
+            let id = OsRng.gen_range(APP_STREAM_BASE..u32::MAX);
+            if !streams.contains_key(&id) && !closed.contains_key(&id) {
+                break id;
+            }
+        };
+        let (entry, rx) = StreamEntry::new(reliable);
+        let send = entry.send_state();
+        streams.insert(stream_id, entry);
+        Ok((stream_id, rx, send))
+    }
+
+    /// Peer sent STREAM_OPEN: queue the stream for `accept_stream`
+    ///
+    /// Fails with `StreamClosed` if the stream cannot be admitted; the
+    /// caller resets it.
+    pub(crate) fn on_open(&self, stream_id: u32) -> Result<(), TunnelError> {
+        let mut streams = self
+            .streams
+            .lock()
+            .map_err(|e| TunnelError::LockPoisoned(e.to_string()))?;
+        if streams.contains_key(&stream_id) {
+            log::warn!("event=stream_open status=duplicate stream={}", stream_id);
+            return Ok(());
+        }
+        let (entry, rx) = StreamEntry::new(false);
+        self.admit(streams.len(), (stream_id, rx, None))?;
+        streams.insert(stream_id, entry);
+        log::debug!("event=stream_open status=accepted stream={}", stream_id);
+        Ok(())
+    }
+
+    /// DATA record for a stream: append to its receive buffer
+    pub(crate) fn on_data(&self, stream_id: u32, payload: Vec<u8>) -> Result<(), TunnelError> {
+        let streams = self
+            .streams
+            .lock()
+            .map_err(|e| TunnelError::LockPoisoned(e.to_string()))?;
+        match streams.get(&stream_id).and_then(|entry| entry.tx.as_ref()) {
+            Some(tx) => {
+                if tx.try_send(payload).is_err() {
+                    log::warn!("event=stream_data_dropped stream={}", stream_id);
+                }
+            }
+            None => log::debug!(
+                "Ignoring data for unknown stream: stream={}, {} bytes",
+                stream_id,
+                payload.len()
+            ),
+        }
+        Ok(())
+    }
+
+    /// Reliable DATA segment: reorder, deliver, and return the ACK to send
+    ///
+    /// The first segment of an unknown stream opens it, failing with
+    /// `StreamClosed` if it cannot be admitted.
+    pub(crate) fn on_segment(
+        &self,
+        stream_id: u32,
+        segment: Segment,
+    ) -> Result<Option<StreamAck>, TunnelError> {
+        let mut streams = self
+            .streams
+            .lock()
+            .map_err(|e| TunnelError::LockPoisoned(e.to_string()))?;
+        let open = streams.len();
+        let entry = match streams.entry(stream_id) {
+            Entry::Occupied(occupied) => occupied.into_mut(),
+            Entry::Vacant(vacant) => {
+                let closed = self
+                    .closed
+                    .lock()
+                    .map_err(|e| TunnelError::LockPoisoned(e.to_string()))?;
+                if let Some((cumulative, _)) = closed.get(&stream_id) {
+                    return Ok(Some(StreamAck {
+                        stream_id,
+                        cumulative: *cumulative,
+                        ranges: Vec::new(),
+                    }));
+                }
+                let (entry, rx) = StreamEntry::new(true);
+                self.admit(open, (stream_id, rx, entry.send_state()))?;
+                log::debug!("event=stream_open status=accepted stream={}", stream_id);
+                vacant.insert(entry)
+            }
+        };
+        let Some(reliable) = entry.reliable.as_mut() else {
+            log::warn!(
+                "event=stream_segment status=unexpected stream={}",
+                stream_id
+            );
+            return Ok(None);
+        };
+        reliable.receiver.on_segment(segment);
+        entry.deliver();
+        let ack = entry
+            .reliable
+            .as_ref()
+            .map(|reliable| reliable.receiver.ack(stream_id));
+        self.remove_if_finished(&mut streams, stream_id)?;
+        Ok(ack)
+    }
+
+    /// Reader made room: deliver buffered data; returns an ACK if that
+    /// moved the acknowledgement point
+    fn resume(&self, stream_id: u32) -> Result<Option<StreamAck>, TunnelError> {
+        let mut streams = self
+            .streams
+            .lock()
+            .map_err(|e| TunnelError::LockPoisoned(e.to_string()))?;
+        let Some(entry) = streams.get_mut(&stream_id) else {
+            return Ok(None);
+        };
+        let Some(before) = entry
+            .reliable
+            .as_ref()
+            .map(|reliable| reliable.receiver.delivered())
+        else {
+            return Ok(None);
+        };
+        if entry.deliver() == before {
+            return Ok(None);
+        }
+        let ack = entry
+            .reliable
+            .as_ref()
+            .map(|reliable| reliable.receiver.ack(stream_id));
+        self.remove_if_finished(&mut streams, stream_id)?;
+        Ok(ack)
+    }
+
+    /// Peer acknowledged reliable data
+    pub(crate) fn on_ack(&self, ack: &StreamAck) -> Result<(), TunnelError> {
+        let mut streams = self
+            .streams
+            .lock()
+            .map_err(|e| TunnelError::LockPoisoned(e.to_string()))?;
+        let Some(send) = streams
+            .get(&ack.stream_id)
+            .and_then(|entry| entry.send_state())
+        else {
+            return Ok(());
+        };
+        let advanced = send
+            .sender
+            .lock()
+            .map_err(|e| TunnelError::LockPoisoned(e.to_string()))?
+            .on_ack(ack, Instant::now());
+        if advanced {
+            send.window.notify_waiters();
+        }
+        send.timer.notify_one();
+        self.remove_if_finished(&mut streams, ack.stream_id)
+    }
+
+    /// Peer sent STREAM_CLOSE: end of its data (reader sees EOF)
+    pub(crate) fn on_close(&self, stream_id: u32) -> Result<(), TunnelError> {
+        let mut streams = self
+            .streams
+            .lock()
+            .map_err(|e| TunnelError::LockPoisoned(e.to_string()))?;
+        if let Some(entry) = streams.get_mut(&stream_id) {
+            if entry.reliable.is_some() {
+                // Reliable streams end with a FIN segment
+                return Ok(());
+            }
+            entry.tx = None;
+            if entry.local_closed {
+                streams.remove(&stream_id);
+            }
+        }
+        Ok(())
+    }
+
+    /// Mark our side closed and say how to tell the peer
+    fn close_local(&self, stream_id: u32) -> Result<LocalClose, TunnelError> {
+        let mut streams = self
+            .streams
+            .lock()
+            .map_err(|e| TunnelError::LockPoisoned(e.to_string()))?;
+        let Some(entry) = streams.get_mut(&stream_id) else {
+            return Ok(LocalClose::Done);
+        };
+        if entry.local_closed {
+            return Ok(LocalClose::Done);
+        }
+        entry.local_closed = true;
+        if let Some(send) = entry.send_state() {
+            return Ok(LocalClose::Fin(send));
+        }
+        if entry.tx.is_none() {
+            streams.remove(&stream_id);
+        }
+        Ok(LocalClose::Control)
+    }
+
+    /// Drop a reliable stream whose peer stopped acknowledging
+    fn reset(&self, stream_id: u32) -> Result<(), TunnelError> {
+        let mut streams = self
+            .streams
+            .lock()
+            .map_err(|e| TunnelError::LockPoisoned(e.to_string()))?;
+        if let Some(mut entry) = streams.remove(&stream_id) {
+            let cumulative = entry.deliver();
+            self.remember_closed(stream_id, cumulative)?;
+        }
+        Ok(())
+    }
+
+    /// Peer sent CLOSE for a stream: end both directions now
+    ///
+    /// The reader sees EOF after data already delivered; writes fail.
+    pub(crate) fn abort(&self, stream_id: u32) -> Result<(), TunnelError> {
+        let entry = self
+            .streams
+            .lock()
+            .map_err(|e| TunnelError::LockPoisoned(e.to_string()))?
+            .remove(&stream_id);
+        if let Some(entry) = entry {
+            log::debug!("event=stream_closed stream={}", stream_id);
+            self.discard(stream_id, entry)?;
+        }
+        Ok(())
+    }
+
+    /// Session closed: abort every stream and stop accepting new ones
+    pub(crate) fn abort_all(&self) -> Result<(), TunnelError> {
+        self.incoming_tx
+            .lock()
+            .map_err(|e| TunnelError::LockPoisoned(e.to_string()))?
+            .take();
+        let entries: Vec<(u32, StreamEntry)> = self
+            .streams
+            .lock()
+            .map_err(|e| TunnelError::LockPoisoned(e.to_string()))?
+            .drain()
+            .collect();
+        for (stream_id, entry) in entries {
+            self.discard(stream_id, entry)?;
+        }
+        Ok(())
+    }
+
+    /// Fail pending writes of a removed stream and remember it as closed
+    fn discard(&self, stream_id: u32, mut entry: StreamEntry) -> Result<(), TunnelError> {
+        if let Some(send) = entry.send_state() {
+            send.fail();
+            send.timer.notify_one();
+        }
+        let cumulative = entry.deliver();
+        self.remember_closed(stream_id, cumulative)
+    }
+
+    /// Queue a peer-opened stream for `accept`, given `open` streams
+    ///
+    /// Refused while `MAX_CONCURRENT_STREAMS` are open, the accept queue is
+    /// full, or the session is closing.
+    fn admit(&self, open: usize, stream: IncomingStream) -> Result<(), TunnelError> {
+        let stream_id = stream.0;
+        let queued = open < MAX_CONCURRENT_STREAMS
+            && self
+                .incoming_tx
+                .lock()
+                .map_err(|e| TunnelError::LockPoisoned(e.to_string()))?
+                .as_ref()
+                .is_some_and(|tx| tx.try_send(stream).is_ok());
+        if !queued {
+            log::warn!(
+                "event=stream_open status=refused stream={} open={}",
+                stream_id,
+                open
+            );
+            return Err(TunnelError::StreamClosed(stream_id));
+        }
+        Ok(())
+    }
+
+    fn remove_if_finished(
+        &self,
+        streams: &mut HashMap<u32, StreamEntry>,
+        stream_id: u32,
+    ) -> Result<(), TunnelError> {
+        if !streams
+            .get(&stream_id)
+            .is_some_and(StreamEntry::is_finished)
+        {
+            return Ok(());
+        }
+        if let Some(mut entry) = streams.remove(&stream_id) {
+            let cumulative = entry.deliver();
+            self.remember_closed(stream_id, cumulative)?;
+            log::debug!("event=stream_finished stream={}", stream_id);
+        }
+        Ok(())
+    }
+
+    fn remember_closed(&self, stream_id: u32, cumulative: u64) -> Result<(), TunnelError> {
+        let mut closed = self
+            .closed
+            .lock()
+            .map_err(|e| TunnelError::LockPoisoned(e.to_string()))?;
+        closed.retain(|_, (_, at)| at.elapsed() < CLOSED_STREAM_LINGER);
+        closed.insert(stream_id, (cumulative, Instant::now()));
+        Ok(())
+    }
+
+    async fn accept(&self) -> Option<IncomingStream> {
+        self.incoming_rx.lock().await.recv().await
+    }
+
+    #[cfg(test)]
+    fn len(&self) -> usize {
+        self.streams.lock().map(|s| s.len()).unwrap_or(0)
+    }
+}
+
+impl Tunnel {
+    /// Open a reliable byte stream to the peer
+    ///
+    /// The peer receives it from `accept_stream`. Data is carried in
+    /// reliable DATA records that are acknowledged, retransmitted on loss,
+    /// and delivered in order; `shutdown` sends a FIN.
+    pub async fn open_stream(self: &Arc<Self>) -> Result<TunnelStream, TunnelError> {
+        let (stream_id, rx, send) = self.streams.open_local(true)?;
+        if let Some(send) = send.as_ref() {
+            // The empty opening segment is retransmitted like data
+            let segment = send
+                .sender
+                .lock()
+                .map_err(|e| TunnelError::LockPoisoned(e.to_string()))?
+                .queue(Vec::new(), false, Instant::now());
+            self.send_segment(stream_id, &segment).await?;
+        }
+        log::debug!("event=stream_open status=opened stream={}", stream_id);
+        Ok(TunnelStream::new(stream_id, self.clone(), rx, send))
+    }
+
+    /// Open a best-effort byte stream to the peer
+    ///
+    /// DATA records are sent once, as on the VPN path: lost records leave
+    /// gaps. `shutdown` sends STREAM_CLOSE.
+    pub async fn open_unreliable_stream(self: &Arc<Self>) -> Result<TunnelStream, TunnelError> {
+        let (stream_id, rx, _) = self.streams.open_local(false)?;
+        self.send_control(&crate::ControlMessage::StreamOpen { stream_id })
+            .await?;
+        log::debug!("event=stream_open status=opened stream={}", stream_id);
+        Ok(TunnelStream::new(stream_id, self.clone(), rx, None))
+    }
+
+    /// Wait for the peer to open a stream
+    pub async fn accept_stream(self: &Arc<Self>) -> Result<TunnelStream, TunnelError> {
+        let (stream_id, rx, send) = self
+            .streams
+            .accept()
+            .await
+            .ok_or(TunnelError::SessionClosed)?;
+        Ok(TunnelStream::new(stream_id, self.clone(), rx, send))
+    }
+
+    /// Reliable DATA record: deliver it and acknowledge
+    pub(crate) async fn handle_stream_segment(
+        &self,
+        stream_id: u32,
+        flags: u8,
+        payload: &[u8],
+    ) -> Result<(), TunnelError> {
+        let Some(segment) = Segment::decode(flags, payload) else {
+            log::warn!("event=stream_segment status=malformed stream={}", stream_id);
+            return Ok(());
+        };
+        let ack = match self.streams.on_segment(stream_id, segment) {
+            Err(TunnelError::StreamClosed(_)) => return self.refuse_stream(stream_id).await,
+            result => result?,
+        };
+        if let Some(ack) = ack {
+            self.send_control(&crate::ControlMessage::StreamAck(ack))
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Peer opened a stream we cannot take: reset it with CLOSE
+    pub(crate) async fn refuse_stream(&self, stream_id: u32) -> Result<(), TunnelError> {
+        self.send_control(&crate::ControlMessage::Close {
+            stream_id: Some(stream_id),
+        })
+        .await
+    }
+
+    async fn send_segment(&self, stream_id: u32, segment: &Segment) -> Result<(), TunnelError> {
+        let (flags, payload) = segment.encode();
+        self.send_record(stream_id, MSG_TYPE_DATA, flags, &payload)
+            .await
+    }
+
+    /// Close a stream in both directions and tell the peer with CLOSE
+    async fn abort_stream(&self, stream_id: u32) -> Result<(), TunnelError> {
+        self.streams.abort(stream_id)?;
+        self.send_control(&crate::ControlMessage::Close {
+            stream_id: Some(stream_id),
+        })
+        .await
+    }
+
+    /// Close our side of a stream, telling the peer once
+    async fn close_stream(&self, stream_id: u32) -> Result<(), TunnelError> {
+        match self.streams.close_local(stream_id)? {
+            LocalClose::Done => {}
+            LocalClose::Control => {
+                self.send_control(&crate::ControlMessage::StreamClose { stream_id })
+                    .await?
+            }
+            LocalClose::Fin(send) => {
+                if send.is_failed() {
+                    return Ok(());
+                }
+                let segment = send
+                    .sender
+                    .lock()
+                    .map_err(|e| TunnelError::LockPoisoned(e.to_string()))?
+                    .queue(Vec::new(), true, Instant::now());
+                send.timer.notify_one();
+                self.send_segment(stream_id, &segment).await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Retransmit a reliable stream's unacknowledged segments until its FIN is
+/// acknowledged, resetting the stream if the peer stops answering
+async fn retransmit_loop(tunnel: Weak<Tunnel>, stream_id: u32, send: Arc<SendState>) {
+    loop {
+        let timer = send.timer.notified();
+        if send.is_failed() {
+            return;
+        }
+        let expired = match send.sender.lock() {
+            Ok(mut sender) if !sender.is_finished() => sender
+                .take_expired(Instant::now())
+                .map(|expired| (expired, sender.next_deadline())),
+            _ => return,
+        };
+        let (expired, deadline) = match expired {
+            Ok(expired) => expired,
+            Err(e) => {
+                log::warn!("event=stream_reset stream={} error={}", stream_id, e);
+                send.fail();
+                if let Some(tunnel) = tunnel.upgrade() {
+                    let _ = tunnel.streams.reset(stream_id);
+                }
+                return;
+            }
+        };
+        if !expired.is_empty() {
+            let Some(tunnel) = tunnel.upgrade() else {
+                return;
+            };
+            log::debug!(
+                "event=stream_retransmit stream={} segments={}",
+                stream_id,
+                expired.len()
+            );
+            for segment in &expired {
+                if let Err(e) = tunnel.send_segment(stream_id, segment).await {
+                    log::debug!(
+                        "event=stream_retransmit status=failed stream={} error={}",
+                        stream_id,
+                        e
+                    );
+                }
+            }
+        }
+        match deadline {
+            Some(deadline) => {
+                tokio::select! {
+                    _ = tokio::time::sleep_until(deadline.into()) => {}
+                    _ = timer => {}
+                }
+            }
+            None => timer.await,
+        }
+    }
+}
+
+/// Byte stream multiplexed over a tunnel
+///
+/// Implements tokio `AsyncRead`/`AsyncWrite`. Writes are split into DATA
+/// records of at most `MAX_STREAM_CHUNK` bytes; on reliable streams a write
+/// waits while the flow or congestion window is full, and is paced across
+/// the round trip. Reads return EOF after the peer
+/// shuts down its side. Dropping the stream closes it.
+pub struct TunnelStream {
+    stream_id: u32,
+    tunnel: Arc<Tunnel>,
+    rx: mpsc::Receiver<Vec<u8>>,
+    send: Option<Arc<SendState>>,
+    read_buf: Vec<u8>,
+    read_pos: usize,
+    pending_write: Option<(usize, PendingSend)>,
+    pending_close: Option<PendingSend>,
+    closed: bool,
+}
+
+impl TunnelStream {
+    fn new(
+        stream_id: u32,
+        tunnel: Arc<Tunnel>,
+        rx: mpsc::Receiver<Vec<u8>>,
+        send: Option<Arc<SendState>>,
+    ) -> Self {
+        if let Some(send) = send.as_ref() {
+            tokio::spawn(retransmit_loop(
+                Arc::downgrade(&tunnel),
+                stream_id,
+                send.clone(),
+            ));
+        }
+        Self {
+            stream_id,
+            tunnel,
+            rx,
+            send,
+            read_buf: Vec::new(),
+            read_pos: 0,
+            pending_write: None,
+            pending_close: None,
+            closed: false,
+        }
+    }
+
+    /// Stream ID carried in this stream's DATA records
+    pub fn id(&self) -> u32 {
+        self.stream_id
+    }
+
+    /// Shut down our side and wait until the peer acknowledged all of it
+    ///
+    /// On best-effort streams this is the same as `shutdown`.
+    pub async fn finish(&mut self) -> io::Result<()> {
+        tokio::io::AsyncWriteExt::shutdown(self).await?;
+        let Some(send) = self.send.clone() else {
+            return Ok(());
+        };
+        loop {
+            let acked = send.window.notified();
+            if send.is_failed() {
+                return Err(io::ErrorKind::ConnectionReset.into());
+            }
+            let finished = send
+                .sender
+                .lock()
+                .map(|sender| sender.is_finished())
+                .map_err(|e| io::Error::other(e.to_string()))?;
+            if finished {
+                return Ok(());
+            }
+            acked.await;
+        }
+    }
+
+    /// Close both directions now, without waiting for unacknowledged data
+    ///
+    /// The peer is sent CLOSE: its reader sees EOF and its writes fail.
+    /// Use `finish` for a graceful end of our data instead.
+    pub async fn close(&mut self) -> io::Result<()> {
+        self.pending_write = None;
+        self.pending_close = None;
+        self.closed = true;
+        self.tunnel
+            .abort_stream(self.stream_id)
+            .await
+            .map_err(to_io_error)
+    }
+
+    /// Whether data on this stream is acknowledged and retransmitted
+    pub fn is_reliable(&self) -> bool {
+        self.send.is_some()
+    }
+
+    /// Reliable streams: the reader freed buffer space, so data the
+    /// receiver held back can be delivered and acknowledged
+    fn resume_delivery(&self) {
+        if self.send.is_none() {
+            return;
+        }
+        if let Ok(Some(ack)) = self.tunnel.streams.resume(self.stream_id) {
+            let tunnel = self.tunnel.clone();
+            tokio::spawn(async move {
+                let _ = tunnel
+                    .send_control(&crate::ControlMessage::StreamAck(ack))
+                    .await;
+            });
+        }
+    }
+
+    fn poll_pending_write(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<usize>> {
+        let Some((len, send)) = self.pending_write.as_mut() else {
+            return Poll::Ready(Ok(0));
+        };
+        let len = *len;
+        match send.as_mut().poll(cx) {
+            Poll::Pending => Poll::Pending,
+            Poll::Ready(result) => {
+                self.pending_write = None;
+                Poll::Ready(result.map(|()| len).map_err(to_io_error))
+            }
+        }
+    }
+}
+
+impl AsyncRead for TunnelStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        if self.read_pos >= self.read_buf.len() {
+            match self.rx.poll_recv(cx) {
+                Poll::Pending => return Poll::Pending,
+                // Peer closed its side
+                Poll::Ready(None) => return Poll::Ready(Ok(())),
+                Poll::Ready(Some(chunk)) => {
+                    self.read_buf = chunk;
+                    self.read_pos = 0;
+                    self.resume_delivery();
+                }
+            }
+        }
+        let start = self.read_pos;
+        let len = buf.remaining().min(self.read_buf.len() - start);
+        buf.put_slice(&self.read_buf[start..start + len]);
+        self.read_pos += len;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncWrite for TunnelStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        if self.pending_write.is_none() {
+            if self.closed || self.pending_close.is_some() {
+                return Poll::Ready(Err(io::ErrorKind::BrokenPipe.into()));
+            }
+            if buf.is_empty() {
+                return Poll::Ready(Ok(0));
+            }
+            let len = buf.len().min(MAX_STREAM_CHUNK);
+            let chunk = buf[..len].to_vec();
+            let tunnel = self.tunnel.clone();
+            let stream_id = self.stream_id;
+            let send: PendingSend = match self.send.clone() {
+                Some(send) => Box::pin(async move {
+                    let segment = loop {
+                        let window_open = send.window.notified();
+                        if send.is_failed() {
+                            return Err(TunnelError::StreamClosed(stream_id));
+                        }
+                        let pacing_delay = {
+                            let mut sender = send
+                                .sender
+                                .lock()
+                                .map_err(|e| TunnelError::LockPoisoned(e.to_string()))?;
+                            if !sender.can_send() {
+                                None
+                            } else {
+                                let now = Instant::now();
+                                match sender.pace(chunk.len(), now) {
+                                    Ok(()) => break sender.queue(chunk, false, now),
+                                    Err(delay) => Some(delay),
+                                }
+                            }
+                        };
+                        match pacing_delay {
+                            Some(delay) => tokio::time::sleep(delay).await,
+                            None => window_open.await,
+                        }
+                    };
+                    send.timer.notify_one();
+                    tunnel.send_segment(stream_id, &segment).await
+                }),
+                None => Box::pin(async move {
+                    tunnel
+                        .send_record(stream_id, MSG_TYPE_DATA, 0, &chunk)
+                        .await
+                }),
+            };
+            self.pending_write = Some((len, send));
+        }
+        self.poll_pending_write(cx)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.poll_pending_write(cx).map_ok(|_| ())
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        if self.poll_pending_write(cx)?.is_pending() {
+            return Poll::Pending;
+        }
+        if self.closed {
+            return Poll::Ready(Ok(()));
+        }
+        if self.pending_close.is_none() {
+            let tunnel = self.tunnel.clone();
+            let stream_id = self.stream_id;
+            self.pending_close = Some(Box::pin(
+                async move { tunnel.close_stream(stream_id).await },
+            ));
+        }
+        let Some(close) = self.pending_close.as_mut() else {
+            return Poll::Ready(Ok(()));
+        };
+        match close.as_mut().poll(cx) {
+            Poll::Pending => Poll::Pending,
+            Poll::Ready(result) => {
+                self.pending_close = None;
+                self.closed = true;
+                Poll::Ready(result.map_err(to_io_error))
+            }
+        }
+    }
+}
+
+impl Drop for TunnelStream {
+    fn drop(&mut self) {
+        if self.closed {
+            return;
+        }
+        if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+            let tunnel = self.tunnel.clone();
+            let stream_id = self.stream_id;
+            runtime.spawn(async move {
+                if let Err(e) = tunnel.close_stream(stream_id).await {
+                    log::debug!(
+                        "event=stream_close status=failed stream={} error={}",
+                        stream_id,
+                        e
+                    );
+                }
+            });
+        }
+    }
+}
+
+fn to_io_error(err: TunnelError) -> io::Error {
+    io::Error::other(err.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_registry_open_data_close() {
+        let registry = StreamRegistry::new();
+        registry.on_open(0x8000_0005).expect("open in test");
+        // Duplicate opens are ignored
+        registry
+            .on_open(0x8000_0005)
+            .expect("duplicate open in test");
+
+        let (stream_id, mut rx, send) = registry.accept().await.expect("accept in test");
+        assert_eq!(stream_id, 0x8000_0005);
+        assert!(send.is_none());
+        registry
+            .on_data(stream_id, b"hello".to_vec())
+            .expect("data in test");
+        assert_eq!(rx.recv().await, Some(b"hello".to_vec()));
+
+        // Peer half-close ends the receive buffer; our close removes the stream
+        registry.on_close(stream_id).expect("close in test");
+        assert_eq!(rx.recv().await, None);
+        assert_eq!(registry.len(), 1);
+        assert!(matches!(
+            registry
+                .close_local(stream_id)
+                .expect("local close in test"),
+            LocalClose::Control
+        ));
+        assert!(matches!(
+            registry
+                .close_local(stream_id)
+                .expect("second close in test"),
+            LocalClose::Done
+        ));
+        assert_eq!(registry.len(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_registry_reliable_segments() {
+        let registry = StreamRegistry::new();
+        let stream_id = 0x8000_0009;
+        let seg = |offset: u64, data: &[u8], fin: bool| Segment {
+            offset,
+            data: data.to_vec(),
+            fin,
+        };
+
+        // A segment past a gap opens the stream but is only SACKed
+        let ack = registry
+            .on_segment(stream_id, seg(3, b"def", true))
+            .expect("segment in test")
+            .expect("ack in test");
+        assert_eq!((ack.cumulative, ack.ranges), (0, vec![(3, 7)]));
+        let (accepted, mut rx, send) = registry.accept().await.expect("accept in test");
+        assert_eq!(accepted, stream_id);
+        assert!(send.is_some());
+        let Some(send) = send else {
+            return;
+        };
+
+        let ack = registry
+            .on_segment(stream_id, seg(0, b"abc", false))
+            .expect("segment in test")
+            .expect("ack in test");
+        assert_eq!(ack.cumulative, 7);
+        assert_eq!(rx.recv().await, Some(b"abc".to_vec()));
+        assert_eq!(rx.recv().await, Some(b"def".to_vec()));
+        assert_eq!(rx.recv().await, None);
+
+        // Our FIN acknowledged: the stream is finished and remembered
+        assert!(matches!(
+            registry.close_local(stream_id),
+            Ok(LocalClose::Fin(_))
+        ));
+        send.sender
+            .lock()
+            .expect("sender lock in test")
+            .queue(Vec::new(), true, Instant::now());
+        registry
+            .on_ack(&StreamAck {
+                stream_id,
+                cumulative: 1,
+                ranges: Vec::new(),
+            })
+            .expect("ack in test");
+        assert_eq!(registry.len(), 0);
+
+        // A late retransmission is acknowledged, not reopened
+        let ack = registry
+            .on_segment(stream_id, seg(0, b"abc", false))
+            .expect("segment in test")
+            .expect("ack in test");
+        assert_eq!(ack.cumulative, 7);
+        assert_eq!(registry.len(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_peer_opens_bounded() {
+        let registry = StreamRegistry::new();
+        for n in 0..ACCEPT_QUEUE_LEN as u32 {
+            registry.on_open(n).expect("open in test");
+        }
+        // Nobody is accepting: further opens are refused, not queued
+        assert!(matches!(
+            registry.on_open(0x100),
+            Err(TunnelError::StreamClosed(0x100))
+        ));
+        let segment = Segment {
+            offset: 0,
+            data: Vec::new(),
+            fin: false,
+        };
+        assert!(matches!(
+            registry.on_segment(0x101, segment.clone()),
+            Err(TunnelError::StreamClosed(0x101))
+        ));
+        assert_eq!(registry.len(), ACCEPT_QUEUE_LEN);
+
+        assert!(registry.accept().await.is_some());
+        registry.on_open(0x100).expect("open after accept in test");
+
+        // At the concurrency limit even an accepted slot does not help
+        while registry.len() < MAX_CONCURRENT_STREAMS {
+            registry.open_local(true).expect("local open in test");
+        }
+        assert!(registry.accept().await.is_some());
+        assert!(matches!(
+            registry.on_segment(0x101, segment),
+            Err(TunnelError::StreamClosed(0x101))
+        ));
+    }
+
+    #[test]
+    fn test_local_stream_ids_in_app_range() {
+        let registry = StreamRegistry::new();
+        for reliable in [false, true] {
+            for _ in 0..8 {
+                let (stream_id, _rx, send) = registry.open_local(reliable).expect("open in test");
+                assert!(stream_id >= APP_STREAM_BASE);
+                assert_eq!(send.is_some(), reliable);
+            }
+        }
+        assert_eq!(registry.len(), 16);
+    }
+}
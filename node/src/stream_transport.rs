@@ -0,0 +1,428 @@
+// Copyright (c) 2025 Thor Thor
+// Author: Thor Thor (GitHub: https://github.com/codethor0)
+// Contact: codethor@gmail.com
+// LinkedIn: https://www.linkedin.com/in/thor-thor0
+// License: MIT (see LICENSE file for details)
+
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use cryprq_core::{MSG_TYPE_ETHERNET_FRAME, MSG_TYPE_PADDING, MSG_TYPE_VPN_PACKET};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+
+use crate::stats::{record_drop, DropReason};
+use crate::{TlsClient, TlsConfig, TlsError, TlsServer, Tunnel, TunnelError};
+
+/// Length prefix in front of every record on a stream
+const FRAME_HEADER_SIZE: usize = 2;
+
+/// Records waiting for the stream writer
+const SEND_QUEUE_LEN: usize = 256;
+
+/// Records read from the stream but not yet received by the tunnel
+pub(crate) const RECV_QUEUE_LEN: usize = 256;
+
+/// Time between UDP probes while deciding on a transport
+const UDP_PROBE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Pause after a failed TCP accept (e.g. out of file descriptors)
+const ACCEPT_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// Record frames read from any attached stream, with the stream's peer
+pub(crate) type StreamFrame = (Vec<u8>, SocketAddr);
+
+/// Where to go when UDP does not get through (Section 3.3)
+#[derive(Debug, Clone)]
+pub struct StreamFallback {
+    /// How long UDP PINGs may go unanswered before falling back
+    pub udp_timeout: Duration,
+    /// TCP address of the peer's stream endpoint, e.g. "vpn.example.com:443"
+    pub tcp_addr: String,
+    /// Wrap the TCP connection in TLS (`node::tls`)
+    pub tls: Option<TlsConfig>,
+}
+
+impl Default for StreamFallback {
+    fn default() -> Self {
+        Self {
+            udp_timeout: Duration::from_secs(3),
+            tcp_addr: String::new(),
+            tls: None,
+        }
+    }
+}
+
+/// Transport a tunnel is sending records over
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransportKind {
+    Udp,
+    /// TCP or TLS byte stream with length-prefixed records
+    Stream,
+}
+
+/// Byte stream carrying one record per length-prefixed frame
+///
+/// A writer task drains a bounded queue so senders never wait on the
+/// socket for VPN traffic; see `send`.
+pub(crate) struct StreamLink {
+    peer: SocketAddr,
+    tx: mpsc::Sender<Vec<u8>>,
+    closed: Arc<AtomicBool>,
+    reader: JoinHandle<()>,
+}
+
+impl StreamLink {
+    fn new<S>(stream: S, peer: SocketAddr, frames: mpsc::Sender<StreamFrame>) -> Self
+    where
+        S: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+    {
+        let (reader, writer) = tokio::io::split(stream);
+        let (tx, rx) = mpsc::channel(SEND_QUEUE_LEN);
+        let closed = Arc::new(AtomicBool::new(false));
+        tokio::spawn(write_loop(writer, rx, peer, closed.clone()));
+        let reader = tokio::spawn(read_loop(reader, peer, frames, closed.clone()));
+        Self {
+            peer,
+            tx,
+            closed,
+            reader,
+        }
+    }
+
+    pub(crate) fn peer(&self) -> SocketAddr {
+        self.peer
+    }
+
+    /// Queue one record for the stream
+    ///
+    /// TCP delivers in order, so a record stuck behind a full buffer
+    /// delays everything after it. VPN packets are dropped instead of
+    /// queued once the writer falls behind: the tunneled connections see
+    /// loss and back off, rather than a growing delay. Other records wait
+    /// for queue space. Callers pass `droppable` from the record's message
+    /// type (`is_droppable`), as the sealed frame does not reveal it.
+    pub(crate) async fn send(&self, frame: Vec<u8>, droppable: bool) -> Result<(), TunnelError> {
+        if self.closed.load(Ordering::Acquire) {
+            return Err(stream_closed());
+        }
+        if !droppable {
+            return self.tx.send(frame).await.map_err(|_| stream_closed());
+        }
+        match self.tx.try_send(frame) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(_)) => {
+                record_drop(DropReason::StreamBackpressure);
+                Ok(())
+            }
+            Err(TrySendError::Closed(_)) => Err(stream_closed()),
+        }
+    }
+}
+
+impl Drop for StreamLink {
+    fn drop(&mut self) {
+        self.reader.abort();
+    }
+}
+
+/// Message types whose records may be lost without breaking the session
+pub(crate) fn is_droppable(message_type: u8) -> bool {
+    matches!(
+        message_type,
+        MSG_TYPE_VPN_PACKET | MSG_TYPE_ETHERNET_FRAME | MSG_TYPE_PADDING
+    )
+}
+
+fn stream_closed() -> TunnelError {
+    TunnelError::NetworkError("stream transport closed".to_string())
+}
+
+async fn write_loop<W: AsyncWrite + Unpin>(
+    mut writer: W,
+    mut rx: mpsc::Receiver<Vec<u8>>,
+    peer: SocketAddr,
+    closed: Arc<AtomicBool>,
+) {
+    let mut buf = Vec::new();
+    while let Some(frame) = rx.recv().await {
+        // Coalesce whatever is queued into one write
+        buf.clear();
+        push_frame(&mut buf, &frame);
+        while let Ok(frame) = rx.try_recv() {
+            push_frame(&mut buf, &frame);
+        }
+        let written = async {
+            writer.write_all(&buf).await?;
+            writer.flush().await
+        };
+        if let Err(e) = written.await {
+            log::info!("event=stream_closed peer={} error={}", peer, e);
+            break;
+        }
+    }
+    closed.store(true, Ordering::Release);
+    let _ = writer.shutdown().await;
+}
+
+fn push_frame(buf: &mut Vec<u8>, frame: &[u8]) {
+    buf.extend_from_slice(&(frame.len() as u16).to_be_bytes());
+    buf.extend_from_slice(frame);
+}
+
+async fn read_loop<R: AsyncRead + Unpin>(
+    mut reader: R,
+    peer: SocketAddr,
+    frames: mpsc::Sender<StreamFrame>,
+    closed: Arc<AtomicBool>,
+) {
+    let reason = loop {
+        let mut len = [0u8; FRAME_HEADER_SIZE];
+        if let Err(e) = reader.read_exact(&mut len).await {
+            break e.to_string();
+        }
+        let len = u16::from_be_bytes(len) as usize;
+        if len == 0 {
+            break "empty frame".to_string();
+        }
+        let mut frame = vec![0u8; len];
+        if let Err(e) = reader.read_exact(&mut frame).await {
+            break e.to_string();
+        }
+        if frames.send((frame, peer)).await.is_err() {
+            break "tunnel dropped".to_string();
+        }
+    };
+    closed.store(true, Ordering::Release);
+    log::info!("event=stream_closed peer={} reason={}", peer, reason);
+}
+
+impl Tunnel {
+    /// Carry this tunnel's records over a byte stream (TCP or `TlsStream`)
+    ///
+    /// Each record travels in a frame with a 2-byte length prefix. Outgoing
+    /// records go to the stream from now on and `peer` becomes the peer
+    /// address; UDP datagrams are still received. Replaces an attached
+    /// stream.
+    pub fn attach_stream<S>(&self, stream: S, peer: SocketAddr) -> Result<(), TunnelError>
+    where
+        S: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+    {
+        let link = StreamLink::new(stream, peer, self.stream_frames_tx.clone());
+        *self
+            .stream_link
+            .write()
+            .map_err(|e| TunnelError::LockPoisoned(e.to_string()))? = Some(Arc::new(link));
+        *self
+            .peer_addr
+            .write()
+            .map_err(|e| TunnelError::LockPoisoned(e.to_string()))? = Some(peer);
+        log::info!("event=stream_attached peer={}", peer);
+        Ok(())
+    }
+
+    /// Accept peers that fall back to a stream, on a listening `TlsServer`
+    ///
+    /// The server side of `connect_with_fallback`. Each connection's TLS
+    /// handshake runs in its own task under the server's handshake timeout,
+    /// so a stalled client holds up nobody; a completed one is attached
+    /// with `attach_stream`, replacing any earlier stream. Runs until the
+    /// tunnel is closed or dropped.
+    ///
+    /// A client that completes the handshake becomes the peer, so the
+    /// server must have `require_client_auth` set; otherwise this fails
+    /// with `InvalidConfig` rather than let any TCP client take over the
+    /// tunnel's traffic.
+    pub fn serve_stream_fallback(
+        self: &Arc<Self>,
+        server: TlsServer,
+    ) -> Result<JoinHandle<()>, TunnelError> {
+        if !server.requires_client_auth() {
+            return Err(TunnelError::InvalidConfig(
+                "stream fallback needs a TLS server with require_client_auth".to_string(),
+            ));
+        }
+        let tunnel = Arc::downgrade(self);
+        Ok(tokio::spawn(async move {
+            loop {
+                let handshake = match server.accept().await {
+                    Ok(handshake) => handshake,
+                    Err(TlsError::NotListening) => return,
+                    Err(e) => {
+                        log::warn!("event=stream_accept_failed error={}", e);
+                        tokio::time::sleep(ACCEPT_RETRY_DELAY).await;
+                        continue;
+                    }
+                };
+                if tunnel.upgrade().is_none_or(|tunnel| tunnel.is_closed()) {
+                    return;
+                }
+                let tunnel = tunnel.clone();
+                tokio::spawn(async move {
+                    let peer = handshake.peer_addr();
+                    let stream = match handshake.await {
+                        Ok(stream) => stream,
+                        Err(e) => {
+                            log::debug!("event=stream_rejected peer={} error={}", peer, e);
+                            return;
+                        }
+                    };
+                    if let Some(tunnel) = tunnel.upgrade() {
+                        if let Err(e) = tunnel.attach_stream(stream, peer) {
+                            log::warn!("event=stream_attach_failed peer={} error={}", peer, e);
+                        }
+                    }
+                });
+            }
+        }))
+    }
+
+    /// Transport outgoing records currently use
+    pub fn transport_kind(&self) -> TransportKind {
+        match self.stream_link() {
+            Ok(Some(_)) => TransportKind::Stream,
+            _ => TransportKind::Udp,
+        }
+    }
+
+    pub(crate) fn stream_link(&self) -> Result<Option<Arc<StreamLink>>, TunnelError> {
+        Ok(self
+            .stream_link
+            .read()
+            .map_err(|e| TunnelError::LockPoisoned(e.to_string()))?
+            .clone())
+    }
+
+    /// Next record frame from an attached stream
+    pub(crate) async fn recv_stream_frame(&self) -> Option<StreamFrame> {
+        self.stream_frames_rx.lock().await.recv().await
+    }
+
+    /// Reach `udp_peer` over UDP, or fall back to a stream if UDP is blocked
+    ///
+    /// PINGs the peer over UDP for up to `fallback.udp_timeout`; if none is
+    /// answered, connects to `fallback.tcp_addr` (with TLS if configured)
+    /// and attaches the stream. Incoming records must be processed
+    /// concurrently via `recv_and_handle_record`, as for `ping`.
+    pub async fn connect_with_fallback(
+        &self,
+        udp_peer: SocketAddr,
+        fallback: &StreamFallback,
+    ) -> Result<TransportKind, TunnelError> {
+        *self
+            .peer_addr
+            .write()
+            .map_err(|e| TunnelError::LockPoisoned(e.to_string()))? = Some(udp_peer);
+        let deadline = Instant::now() + fallback.udp_timeout;
+        loop {
+            let wait = deadline
+                .saturating_duration_since(Instant::now())
+                .min(UDP_PROBE_INTERVAL);
+            if wait.is_zero() {
+                break;
+            }
+            if self.ping(wait).await.is_ok() {
+                log::info!("event=transport_selected kind=udp peer={}", udp_peer);
+                return Ok(TransportKind::Udp);
+            }
+        }
+
+        log::info!(
+            "event=udp_fallback peer={} stream={} tls={}",
+            udp_peer,
+            fallback.tcp_addr,
+            fallback.tls.is_some()
+        );
+        match &fallback.tls {
+            Some(config) => {
+                let stream = TlsClient::new(config.clone())
+                    .connect(&fallback.tcp_addr)
+                    .await
+                    .map_err(|e| TunnelError::NetworkError(e.to_string()))?;
+                let peer = stream
+                    .peer_addr()
+                    .map_err(|e| TunnelError::NetworkError(e.to_string()))?;
+                self.attach_stream(stream, peer)?;
+            }
+            None => {
+                let stream = TcpStream::connect(&fallback.tcp_addr).await?;
+                stream.set_nodelay(true)?;
+                let peer = stream.peer_addr()?;
+                self.attach_stream(stream, peer)?;
+            }
+        }
+        Ok(TransportKind::Stream)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stats::dropped_packets;
+    use cryprq_core::MSG_TYPE_CONTROL;
+
+    #[tokio::test]
+    async fn test_vpn_records_dropped_when_stream_backs_up() {
+        // Nobody reads the far end, so the writer stalls once the pipe fills
+        let (near, _far) = tokio::io::duplex(64);
+        let (frames, _rx) = mpsc::channel(RECV_QUEUE_LEN);
+        let peer: SocketAddr = "192.0.2.1:443".parse().expect("addr in test");
+        let link = StreamLink::new(near, peer, frames);
+
+        let dropped = dropped_packets(DropReason::StreamBackpressure);
+        for _ in 0..2 {
+            for _ in 0..SEND_QUEUE_LEN * 2 {
+                tokio::time::timeout(
+                    Duration::from_secs(1),
+                    link.send(vec![0; 32], is_droppable(MSG_TYPE_VPN_PACKET)),
+                )
+                .await
+                .expect("VPN records never wait in test")
+                .expect("send in test");
+            }
+            // Let the writer take a batch and block on the full pipe
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert!(dropped_packets(DropReason::StreamBackpressure) > dropped);
+
+        // Control records wait for space instead of being dropped
+        assert!(tokio::time::timeout(
+            Duration::from_millis(100),
+            link.send(vec![0; 32], is_droppable(MSG_TYPE_CONTROL)),
+        )
+        .await
+        .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_frames_split_and_coalesced() {
+        let (near, mut far) = tokio::io::duplex(4096);
+        let (frames, mut rx) = mpsc::channel(RECV_QUEUE_LEN);
+        let peer: SocketAddr = "192.0.2.1:443".parse().expect("addr in test");
+        let link = StreamLink::new(near, peer, frames);
+
+        link.send(vec![7; 3], false).await.expect("send in test");
+        let mut wire = [0u8; 5];
+        far.read_exact(&mut wire).await.expect("read in test");
+        assert_eq!(wire, [0, 3, 7, 7, 7]);
+
+        // Two frames in one write, the second split across writes
+        far.write_all(&[0, 2, 1, 2, 0, 3, 4])
+            .await
+            .expect("write in test");
+        far.write_all(&[5, 6]).await.expect("write in test");
+        assert_eq!(rx.recv().await, Some((vec![1, 2], peer)));
+        assert_eq!(rx.recv().await, Some((vec![4, 5, 6], peer)));
+
+        // An empty frame ends the stream
+        far.write_all(&[0, 0]).await.expect("write in test");
+        assert_eq!(rx.recv().await, None);
+        assert!(link.send(vec![1], false).await.is_err());
+    }
+}
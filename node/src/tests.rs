@@ -6,7 +6,84 @@
 
 #[cfg(test)]
 mod tunnel_tests {
-    use crate::{create_tunnel, generate_handshake_auth, TunnelError, MAX_NONCE_VALUE};
+    use crate::{
+        create_tunnel, create_tunnel_with_output_dir, generate_handshake_auth, Tunnel, TunnelError,
+        MAX_NONCE_VALUE,
+    };
+    use std::net::SocketAddr;
+    use std::sync::Arc;
+    use tokio::task::JoinHandle;
+
+    // Test-mode keys: both sides derive the same traffic keys
+    const TEST_PK: [u8; 32] = [1u8; 32];
+    const TEST_ID: [u8; 32] = [1u8; 32];
+    const TEST_SIG: [u8; 64] = [1u8; 64];
+
+    /// Tunnel with the test-mode keys on an ephemeral loopback port
+    async fn test_tunnel() -> Tunnel {
+        create_tunnel(&TEST_PK, &TEST_PK, &TEST_ID, &TEST_SIG, "127.0.0.1:0")
+            .await
+            .expect("tunnel in test")
+    }
+
+    /// Listener and dialer, with the dialer sending to the listener
+    async fn tunnel_pair() -> (Arc<Tunnel>, Arc<Tunnel>) {
+        let listener = Arc::new(test_tunnel().await);
+        let dialer = Arc::new(test_tunnel().await);
+        *dialer.peer_addr().write().expect("peer addr lock in test") =
+            Some(listener.local_addr().expect("listener addr in test"));
+        (listener, dialer)
+    }
+
+    /// Run each tunnel's receive loop until the returned task is aborted
+    fn spawn_receivers(tunnels: &[&Arc<Tunnel>]) -> Vec<JoinHandle<()>> {
+        tunnels
+            .iter()
+            .map(|&tunnel| {
+                let tunnel = tunnel.clone();
+                tokio::spawn(async move {
+                    loop {
+                        let _ = tunnel.recv_and_handle_record().await;
+                    }
+                })
+            })
+            .collect()
+    }
+
+    /// Relay datagrams between the pair and point the dialer at the relay
+    ///
+    /// `drop(n, len)` is asked about the n-th datagram (from 1, either
+    /// direction) and discards it when it returns true.
+    async fn spawn_relay(
+        listener: &Tunnel,
+        dialer: &Tunnel,
+        mut drop: impl FnMut(u64, usize) -> bool + Send + 'static,
+    ) -> SocketAddr {
+        let listener_addr = listener.local_addr().expect("listener addr in test");
+        let dialer_addr = dialer.local_addr().expect("dialer addr in test");
+        let relay = tokio::net::UdpSocket::bind("127.0.0.1:0")
+            .await
+            .expect("relay socket in test");
+        let relay_addr = relay.local_addr().expect("relay addr in test");
+        tokio::spawn(async move {
+            let mut buf = vec![0u8; 65535];
+            let mut forwarded = 0u64;
+            while let Ok((len, from)) = relay.recv_from(&mut buf).await {
+                forwarded += 1;
+                if drop(forwarded, len) {
+                    continue;
+                }
+                let to = if from == listener_addr {
+                    dialer_addr
+                } else {
+                    listener_addr
+                };
+                let _ = relay.send_to(&buf[..len], to).await;
+            }
+        });
+        *dialer.peer_addr().write().expect("peer addr lock in test") = Some(relay_addr);
+        relay_addr
+    }
 
     #[tokio::test]
     async fn test_tunnel_creation() {
@@ -69,8 +146,8 @@ mod tunnel_tests {
         .await
         {
             // Set VPN sequence counter to MAX_NONCE_VALUE (at the limit)
-            // send_packet uses send_record which uses seq_counters.next_vpn()
-            tunnel.seq_counters.set_vpn_for_test(MAX_NONCE_VALUE);
+            // send_packet uses send_record which takes the current keys' seq.next_vpn()
+            tunnel.keys.load().seq.set_vpn_for_test(MAX_NONCE_VALUE);
 
             // This send should trigger nonce overflow error
             let result = tunnel.send_packet(b"test").await;
@@ -162,23 +239,12 @@ mod tunnel_tests {
             )
             .await,
         ) {
-            // Check actual traffic keys (keys_outbound) instead of legacy session_key
-            if let (Ok(key1_read), Ok(key2_read)) = (
-                tunnel1
-                    .keys_outbound
-                    .read()
-                    .map_err(|e| TunnelError::LockPoisoned(e.to_string())),
-                tunnel2
-                    .keys_outbound
-                    .read()
-                    .map_err(|e| TunnelError::LockPoisoned(e.to_string())),
-            ) {
-                assert_ne!(
-                    &key1_read.key[..],
-                    &key2_read.key[..],
-                    "Different tunnels should have different keys"
-                );
-            }
+            // Check actual traffic keys instead of legacy session_key
+            assert_ne!(
+                &tunnel1.keys.load().outbound.key[..],
+                &tunnel2.keys.load().outbound.key[..],
+                "Different tunnels should have different keys"
+            );
         }
     }
 
@@ -240,6 +306,1347 @@ mod tunnel_tests {
         );
     }
 
+    #[tokio::test]
+    async fn test_packet_filter_drops_spoofed_source() {
+        use crate::stats::{dropped_packets, DropReason};
+        use crate::PacketFilter;
+        use std::time::Duration;
+
+        let (receiver, sender) = tunnel_pair().await;
+        let (tun_tx, mut tun_rx) = tokio::sync::mpsc::unbounded_channel();
+        receiver.set_tun_writer(tun_tx);
+        receiver.set_packet_filter(PacketFilter::new(vec!["10.0.0.2/32"
+            .parse()
+            .expect("valid prefix in test")]));
+        spawn_receivers(&[&receiver]);
+
+        let ipv4_from = |src: [u8; 4]| {
+            let mut packet = vec![0u8; 20];
+            packet[0] = 0x45;
+            packet[12..16].copy_from_slice(&src);
+            packet[16..20].copy_from_slice(&[10, 0, 0, 1]);
+            packet
+        };
+        let drops_before = dropped_packets(DropReason::SourceNotAllowed);
+
+        sender
+            .send_vpn_packet(&ipv4_from([192, 168, 1, 50]))
+            .await
+            .expect("send spoofed in test");
+        sender
+            .send_vpn_packet(&ipv4_from([10, 0, 0, 2]))
+            .await
+            .expect("send allowed in test");
+
+        let delivered = tokio::time::timeout(Duration::from_secs(5), tun_rx.recv())
+            .await
+            .expect("packet delivered in test")
+            .expect("tun channel open in test");
+        assert_eq!(&delivered[12..16], &[10, 0, 0, 2]);
+        assert!(tun_rx.try_recv().is_err());
+        assert!(dropped_packets(DropReason::SourceNotAllowed) > drops_before);
+    }
+
+    #[tokio::test]
+    async fn test_ethernet_frame_round_trip() {
+        use std::time::Duration;
+
+        let (receiver, sender) = tunnel_pair().await;
+        let (tap_tx, mut tap_rx) = tokio::sync::mpsc::unbounded_channel();
+        receiver.set_tap_writer(tap_tx, 1500);
+        spawn_receivers(&[&receiver]);
+
+        // ARP broadcast: not an IP packet, must still cross the tunnel
+        let mut frame = vec![0u8; 42];
+        frame[0..6].copy_from_slice(&[0xff; 6]);
+        frame[6..12].copy_from_slice(&[0x02, 0, 0, 0, 0, 1]);
+        frame[12..14].copy_from_slice(&0x0806u16.to_be_bytes());
+        sender
+            .send_ethernet_frame(&frame)
+            .await
+            .expect("send frame in test");
+
+        let delivered = tokio::time::timeout(Duration::from_secs(5), tap_rx.recv())
+            .await
+            .expect("frame delivered in test")
+            .expect("tap channel open in test");
+        assert_eq!(delivered, frame);
+    }
+
+    #[tokio::test]
+    async fn test_ethernet_frames_need_tap_mode_and_fit_the_mtu() {
+        use crate::stats::{dropped_packets, DropReason};
+        use crate::PacketFilter;
+        use std::time::Duration;
+
+        let (receiver, sender) = tunnel_pair().await;
+
+        // Layer-3 session restricted to 10.0.0.2
+        let (tun_tx, mut tun_rx) = tokio::sync::mpsc::unbounded_channel();
+        receiver.set_tun_writer(tun_tx);
+        receiver.set_packet_filter(PacketFilter::new(vec!["10.0.0.2/32"
+            .parse()
+            .expect("valid prefix in test")]));
+        spawn_receivers(&[&receiver]);
+
+        // A spoofed IP packet relabelled as an Ethernet frame is dropped
+        let mut spoofed = vec![0u8; 20];
+        spoofed[0] = 0x45;
+        spoofed[12..16].copy_from_slice(&[192, 168, 1, 50]);
+        spoofed[16..20].copy_from_slice(&[10, 0, 0, 1]);
+        let unexpected = dropped_packets(DropReason::UnexpectedType);
+        sender
+            .send_ethernet_frame(&spoofed)
+            .await
+            .expect("send relabelled packet in test");
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert!(tun_rx.try_recv().is_err());
+        assert!(dropped_packets(DropReason::UnexpectedType) > unexpected);
+
+        // In TAP mode, frames over MTU + header + VLAN tag are dropped
+        let (tap_tx, mut tap_rx) = tokio::sync::mpsc::unbounded_channel();
+        receiver.set_tap_writer(tap_tx, 1000);
+        let oversized = dropped_packets(DropReason::Oversized);
+        sender
+            .send_ethernet_frame(&[0u8; 1000 + 14 + 4 + 1])
+            .await
+            .expect("send oversized frame in test");
+        sender
+            .send_ethernet_frame(&[0u8; 1000 + 14 + 4])
+            .await
+            .expect("send full frame in test");
+        let delivered = tokio::time::timeout(Duration::from_secs(5), tap_rx.recv())
+            .await
+            .expect("frame delivered in test")
+            .expect("tap channel open in test");
+        assert_eq!(delivered.len(), 1000 + 14 + 4);
+        assert!(dropped_packets(DropReason::Oversized) > oversized);
+        assert!(tun_rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_unauthenticated_datagram_does_not_move_peer() {
+        let (receiver, sender) = tunnel_pair().await;
+        let receiver_addr = receiver.local_addr().expect("receiver addr in test");
+        let sender_addr = sender.local_addr().expect("sender addr in test");
+
+        sender
+            .send_record(
+                crate::VPN_STREAM_ID,
+                cryprq_core::MSG_TYPE_DATA,
+                0,
+                b"hello",
+            )
+            .await
+            .expect("send record in test");
+        receiver.recv_record().await.expect("recv record in test");
+        assert_eq!(
+            *receiver.peer_addr().read().expect("peer addr lock in test"),
+            Some(sender_addr)
+        );
+
+        // A forged record from another address fails authentication
+        let spoofer = tokio::net::UdpSocket::bind("127.0.0.1:0")
+            .await
+            .expect("spoofer socket in test");
+        let mut forged = vec![0u8; 64];
+        forged[0] = cryprq_core::PROTOCOL_VERSION;
+        forged[1] = cryprq_core::MSG_TYPE_DATA;
+        spoofer
+            .send_to(&forged, receiver_addr)
+            .await
+            .expect("send forged in test");
+        assert!(matches!(
+            receiver.recv_record().await,
+            Err(TunnelError::DecryptionFailed)
+        ));
+        assert_eq!(
+            *receiver.peer_addr().read().expect("peer addr lock in test"),
+            Some(sender_addr)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_peer_migrates_after_path_validation() {
+        use std::time::Duration;
+
+        let (server, client) = tunnel_pair().await;
+
+        // Each relay stands in for one NAT mapping of the client
+        let paths = [
+            spawn_relay(&server, &client, |_, _| false).await,
+            spawn_relay(&server, &client, |_, _| false).await,
+        ];
+        spawn_receivers(&[&server, &client]);
+
+        let wait_for_peer = |expected: SocketAddr| {
+            let server = server.clone();
+            async move {
+                let deadline = tokio::time::Instant::now() + Duration::from_secs(5);
+                while *server.peer_addr().read().expect("peer addr lock in test") != Some(expected)
+                {
+                    assert!(
+                        tokio::time::Instant::now() < deadline,
+                        "server never moved to {}",
+                        expected
+                    );
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                }
+            }
+        };
+
+        *client.peer_addr().write().expect("peer addr lock in test") = Some(paths[0]);
+        client
+            .revalidate_path()
+            .await
+            .expect("probe first path in test");
+        wait_for_peer(paths[0]).await;
+
+        // Client roams: its traffic now arrives from the second mapping
+        *client.peer_addr().write().expect("peer addr lock in test") = Some(paths[1]);
+        client
+            .revalidate_path()
+            .await
+            .expect("revalidate path in test");
+        wait_for_peer(paths[1]).await;
+    }
+
+    #[tokio::test]
+    async fn test_address_lease_over_control() {
+        use crate::{AddressPool, AddressPoolConfig};
+        use std::sync::Mutex;
+        use std::time::Duration;
+
+        let (listener, dialer) = tunnel_pair().await;
+        let pool = AddressPool::new(
+            AddressPoolConfig::from_cidr("10.8.0.0/24").expect("valid cidr in test"),
+        )
+        .expect("pool creation in test");
+        listener.set_address_pool(Arc::new(Mutex::new(pool)));
+        spawn_receivers(&[&listener, &dialer]);
+
+        let lease = dialer
+            .request_address_lease(Duration::from_secs(5))
+            .await
+            .expect("address lease in test");
+        assert_eq!(lease.address, std::net::Ipv4Addr::new(10, 8, 0, 2));
+        assert_eq!(lease.prefix_len, 24);
+        assert_eq!(dialer.address_lease(), Some(lease.clone()));
+
+        // Renewal returns the same address
+        let renewed = dialer
+            .request_address_lease(Duration::from_secs(5))
+            .await
+            .expect("lease renewal in test");
+        assert_eq!(renewed.address, lease.address);
+    }
+
+    #[tokio::test]
+    async fn test_stream_round_trip() {
+        use std::time::Duration;
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let (listener, dialer) = tunnel_pair().await;
+        spawn_receivers(&[&listener, &dialer]);
+
+        // Several DATA records, then a half-close
+        let request: Vec<u8> = (0..5000u32).map(|i| i as u8).collect();
+        let mut outbound = dialer.open_stream().await.expect("open stream in test");
+        outbound
+            .write_all(&request)
+            .await
+            .expect("write stream in test");
+        outbound.shutdown().await.expect("shutdown in test");
+
+        let mut inbound = tokio::time::timeout(Duration::from_secs(5), listener.accept_stream())
+            .await
+            .expect("accept in time in test")
+            .expect("accept stream in test");
+        assert_eq!(inbound.id(), outbound.id());
+        let mut received = Vec::new();
+        tokio::time::timeout(Duration::from_secs(5), inbound.read_to_end(&mut received))
+            .await
+            .expect("read in time in test")
+            .expect("read stream in test");
+        assert_eq!(received, request);
+
+        // The other direction stays open after the peer's half-close
+        inbound.write_all(b"reply").await.expect("reply in test");
+        inbound.shutdown().await.expect("reply shutdown in test");
+        let mut reply = Vec::new();
+        tokio::time::timeout(Duration::from_secs(5), outbound.read_to_end(&mut reply))
+            .await
+            .expect("reply in time in test")
+            .expect("read reply in test");
+        assert_eq!(reply, b"reply");
+    }
+
+    #[tokio::test]
+    async fn test_reliable_stream_survives_loss() {
+        use std::time::Duration;
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let (listener, dialer) = tunnel_pair().await;
+        // Relay dropping every fourth datagram in either direction
+        spawn_relay(&listener, &dialer, |n, _| n % 4 == 0).await;
+        spawn_receivers(&[&listener, &dialer]);
+
+        let payload: Vec<u8> = (0..64 * 1024u32).map(|i| (i % 251) as u8).collect();
+        let mut outbound = dialer.open_stream().await.expect("open stream in test");
+        assert!(outbound.is_reliable());
+        let writer = tokio::spawn({
+            let payload = payload.clone();
+            async move {
+                outbound
+                    .write_all(&payload)
+                    .await
+                    .expect("write stream in test");
+                outbound.shutdown().await.expect("shutdown in test");
+                outbound
+            }
+        });
+
+        let mut inbound = tokio::time::timeout(Duration::from_secs(10), listener.accept_stream())
+            .await
+            .expect("accept in time in test")
+            .expect("accept stream in test");
+        let mut received = Vec::new();
+        tokio::time::timeout(Duration::from_secs(30), inbound.read_to_end(&mut received))
+            .await
+            .expect("read in time in test")
+            .expect("read stream in test");
+        assert_eq!(received.len(), payload.len());
+        assert!(received == payload, "stream data reordered or corrupted");
+        writer.await.expect("writer task in test");
+    }
+
+    #[tokio::test]
+    async fn test_bulk_stream_leaves_room_for_vpn_traffic() {
+        use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+        use std::time::Duration;
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        use tokio::net::UdpSocket;
+
+        let (listener, dialer) = tunnel_pair().await;
+        let listener_addr = listener.local_addr().expect("listener addr in test");
+        let dialer_addr = dialer.local_addr().expect("dialer addr in test");
+
+        // Bottleneck towards the listener: one datagram per millisecond
+        // behind a 32-datagram drop-tail queue
+        let relay = Arc::new(
+            UdpSocket::bind("127.0.0.1:0")
+                .await
+                .expect("relay socket in test"),
+        );
+        let relay_addr = relay.local_addr().expect("relay addr in test");
+        let (queue_tx, mut queue_rx) = tokio::sync::mpsc::channel::<Vec<u8>>(32);
+        tokio::spawn({
+            let relay = relay.clone();
+            async move {
+                let mut tick = tokio::time::interval(Duration::from_millis(1));
+                while let Some(datagram) = queue_rx.recv().await {
+                    tick.tick().await;
+                    let _ = relay.send_to(&datagram, listener_addr).await;
+                }
+            }
+        });
+        tokio::spawn(async move {
+            let mut buf = vec![0u8; 65535];
+            while let Ok((len, from)) = relay.recv_from(&mut buf).await {
+                if from == listener_addr {
+                    let _ = relay.send_to(&buf[..len], dialer_addr).await;
+                } else {
+                    let _ = queue_tx.try_send(buf[..len].to_vec());
+                }
+            }
+        });
+        *dialer.peer_addr().write().expect("peer addr lock in test") = Some(relay_addr);
+
+        let (tun_tx, mut tun_rx) = tokio::sync::mpsc::unbounded_channel();
+        listener.set_tun_writer(tun_tx);
+        spawn_receivers(&[&listener, &dialer]);
+
+        // VPN traffic at a steady 100 packets per second alongside the transfer
+        let bulk_done = Arc::new(AtomicBool::new(false));
+        let vpn_sent = Arc::new(AtomicUsize::new(0));
+        let vpn_task = tokio::spawn({
+            let dialer = dialer.clone();
+            let bulk_done = bulk_done.clone();
+            let vpn_sent = vpn_sent.clone();
+            async move {
+                let mut packet = vec![0u8; 200];
+                packet[0] = 0x45;
+                packet[12..16].copy_from_slice(&[10, 0, 0, 2]);
+                packet[16..20].copy_from_slice(&[10, 0, 0, 1]);
+                while !bulk_done.load(Ordering::Acquire) {
+                    if dialer.send_vpn_packet(&packet).await.is_ok() {
+                        vpn_sent.fetch_add(1, Ordering::Relaxed);
+                    }
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                }
+            }
+        });
+
+        let payload = vec![0x5au8; 1024 * 1024];
+        let mut outbound = dialer.open_stream().await.expect("open stream in test");
+        let writer = tokio::spawn({
+            let payload = payload.clone();
+            async move {
+                outbound
+                    .write_all(&payload)
+                    .await
+                    .expect("write stream in test");
+                outbound.shutdown().await.expect("shutdown in test");
+                outbound
+            }
+        });
+        let mut inbound = tokio::time::timeout(Duration::from_secs(10), listener.accept_stream())
+            .await
+            .expect("accept in time in test")
+            .expect("accept stream in test");
+        let mut received = Vec::new();
+        // Without congestion control the transfer collapses into repeated
+        // timeouts at the bottleneck and takes far longer
+        tokio::time::timeout(Duration::from_secs(10), inbound.read_to_end(&mut received))
+            .await
+            .expect("bulk transfer in time in test")
+            .expect("read stream in test");
+        assert!(received == payload, "bulk data corrupted");
+        bulk_done.store(true, Ordering::Release);
+        vpn_task.await.expect("vpn task in test");
+        writer.await.expect("writer task in test");
+
+        // Let queued VPN packets drain, then compare
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let mut vpn_delivered = 0;
+        while tun_rx.try_recv().is_ok() {
+            vpn_delivered += 1;
+        }
+        let vpn_sent = vpn_sent.load(Ordering::Relaxed);
+        assert!(vpn_sent >= 20, "transfer too short to measure");
+        assert!(
+            vpn_delivered * 10 >= vpn_sent * 9,
+            "VPN traffic starved: {} of {} packets delivered",
+            vpn_delivered,
+            vpn_sent
+        );
+    }
+
+    #[tokio::test]
+    async fn test_path_mtu_discovery_and_fragmentation() {
+        use std::time::Duration;
+
+        const PATH_MTU: usize = 1400;
+
+        let (listener, dialer) = tunnel_pair().await;
+        // Relay standing in for a path that drops datagrams over PATH_MTU
+        spawn_relay(&listener, &dialer, |_, len| len > PATH_MTU).await;
+
+        let (tun_tx, mut tun_rx) = tokio::sync::mpsc::unbounded_channel();
+        listener.set_tun_writer(tun_tx);
+        spawn_receivers(&[&listener, &dialer]);
+
+        // Before discovery the base size is used; a large packet still
+        // arrives as fragments
+        assert_eq!(dialer.path_mtu(), 1232);
+        let packet: Vec<u8> = (0..20_000u32).map(|i| (i % 249) as u8).collect();
+        dialer
+            .send_vpn_packet(&packet)
+            .await
+            .expect("send large packet in test");
+        let delivered = tokio::time::timeout(Duration::from_secs(5), tun_rx.recv())
+            .await
+            .expect("fragmented packet delivered in test")
+            .expect("tun channel open in test");
+        assert_eq!(delivered, packet);
+
+        // Discovery settles just under the relay's limit
+        let mut mtu = dialer.subscribe_path_mtu();
+        dialer
+            .start_path_mtu_discovery()
+            .expect("start discovery in test");
+        let settled = tokio::time::timeout(Duration::from_secs(15), async {
+            while *mtu.borrow_and_update() <= PATH_MTU as u16 - 16 {
+                if mtu.changed().await.is_err() {
+                    break;
+                }
+            }
+        })
+        .await;
+        assert!(settled.is_ok());
+        assert!(dialer.path_mtu() as usize <= PATH_MTU);
+        assert_eq!(dialer.tun_mtu(), dialer.path_mtu() - 36);
+
+        // A full-size TUN packet now fits one datagram
+        let packet = vec![0x45; dialer.tun_mtu() as usize];
+        dialer
+            .send_vpn_packet(&packet)
+            .await
+            .expect("send tun-sized packet in test");
+        let delivered = tokio::time::timeout(Duration::from_secs(5), tun_rx.recv())
+            .await
+            .expect("packet delivered in test")
+            .expect("tun channel open in test");
+        assert_eq!(delivered, packet);
+    }
+
+    #[tokio::test]
+    async fn test_send_file_over_stream() {
+        use std::time::Duration;
+
+        let dir = std::env::temp_dir().join(format!("cryprq-send-file-{}", std::process::id()));
+        let out_dir = dir.join("received");
+        std::fs::create_dir_all(&out_dir).expect("create dirs in test");
+        let source = dir.join("payload.bin");
+        let payload: Vec<u8> = (0..200_000u32).map(|i| (i % 253) as u8).collect();
+        std::fs::write(&source, &payload).expect("write source in test");
+
+        let listener = Arc::new(
+            create_tunnel_with_output_dir(
+                &TEST_PK,
+                &TEST_PK,
+                &TEST_ID,
+                &TEST_SIG,
+                "127.0.0.1:0",
+                Some(out_dir.clone()),
+            )
+            .await
+            .expect("listener tunnel in test"),
+        );
+        let dialer = Arc::new(test_tunnel().await);
+        *dialer.peer_addr().write().expect("peer addr lock in test") =
+            Some(listener.local_addr().expect("listener addr in test"));
+        spawn_receivers(&[&listener, &dialer]);
+
+        let receiver = tokio::spawn({
+            let listener = listener.clone();
+            async move {
+                let stream = listener.accept_stream().await?;
+                listener.receive_file(stream).await
+            }
+        });
+        let meta = tokio::time::timeout(Duration::from_secs(10), dialer.send_file(&source))
+            .await
+            .expect("send_file timed out in test")
+            .expect("send_file in test");
+        assert_eq!(meta.filename, "payload.bin");
+        assert_eq!(meta.size, payload.len() as u64);
+
+        let path = tokio::time::timeout(Duration::from_secs(10), receiver)
+            .await
+            .expect("receive_file timed out in test")
+            .expect("receiver task in test")
+            .expect("receive_file in test");
+        assert_eq!(path, out_dir.join("payload.bin"));
+        assert_eq!(std::fs::read(&path).expect("read output in test"), payload);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_ping_rtt_and_dead_peer() {
+        use crate::{KeepaliveConfig, PeerStatus};
+        use std::time::Duration;
+
+        let (listener, dialer) = tunnel_pair().await;
+        let receivers = spawn_receivers(&[&listener, &dialer]);
+
+        assert!(dialer.rtt_stats().is_none());
+        for _ in 0..3 {
+            let rtt = dialer
+                .ping(Duration::from_secs(2))
+                .await
+                .expect("pong in test");
+            assert!(rtt < Duration::from_secs(2));
+        }
+        let stats = dialer.rtt_stats().expect("rtt stats in test");
+        assert_eq!(stats.samples, 3);
+        assert!(stats.smoothed > Duration::ZERO);
+
+        dialer
+            .start_keepalive(KeepaliveConfig {
+                interval: Duration::from_millis(100),
+                dead_peer_timeout: Duration::from_millis(500),
+            })
+            .expect("start keepalive in test");
+        let mut status = dialer.subscribe_peer_status();
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert_eq!(dialer.peer_status(), PeerStatus::Alive);
+
+        // The listener stops answering: the dialer declares it dead
+        receivers[0].abort();
+        tokio::time::timeout(
+            Duration::from_secs(3),
+            status.wait_for(|s| *s == PeerStatus::Down),
+        )
+        .await
+        .expect("session down in time in test")
+        .expect("status channel in test");
+        assert!(matches!(
+            dialer.ping(Duration::from_millis(200)).await,
+            Err(TunnelError::Timeout(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_close_ends_streams_and_session() {
+        use crate::ErrorCode;
+        use std::time::Duration;
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let (listener, dialer) = tunnel_pair().await;
+        spawn_receivers(&[&dialer]);
+        let (result_tx, mut result_rx) = tokio::sync::mpsc::unbounded_channel();
+        tokio::spawn({
+            let listener = listener.clone();
+            async move {
+                loop {
+                    if let Err(e) = listener.recv_and_handle_record().await {
+                        let _ = result_tx.send(e);
+                    }
+                }
+            }
+        });
+
+        // CLOSE on one stream ends it in both directions
+        let mut stream = dialer.open_stream().await.expect("open in test");
+        stream.write_all(b"hello").await.expect("write in test");
+        let mut accepted = tokio::time::timeout(Duration::from_secs(5), listener.accept_stream())
+            .await
+            .expect("accept in time in test")
+            .expect("accept in test");
+        let mut buf = [0u8; 5];
+        accepted.read_exact(&mut buf).await.expect("read in test");
+        stream.close().await.expect("close stream in test");
+        let mut rest = Vec::new();
+        tokio::time::timeout(Duration::from_secs(5), accepted.read_to_end(&mut rest))
+            .await
+            .expect("EOF in time in test")
+            .expect("EOF in test");
+        assert!(accepted.write_all(b"late").await.is_err());
+
+        // CLOSE on the session: the listener stops and erases its keys
+        let _other = dialer.open_stream().await.expect("open in test");
+        let mut other = tokio::time::timeout(Duration::from_secs(5), listener.accept_stream())
+            .await
+            .expect("accept in time in test")
+            .expect("accept in test");
+        dialer.close().await.expect("close in test");
+        assert!(dialer.is_closed());
+        assert!(matches!(
+            dialer.send_vpn_packet(b"late").await,
+            Err(TunnelError::SessionClosed)
+        ));
+        let err = tokio::time::timeout(Duration::from_secs(5), result_rx.recv())
+            .await
+            .expect("close in time in test")
+            .expect("receive loop error in test");
+        assert!(matches!(err, TunnelError::SessionClosed));
+        assert!(listener.is_closed());
+        assert_eq!(listener.keys.load().outbound.key, [0u8; 32]);
+        assert_eq!(
+            *listener.master_secret.read().expect("secret lock in test"),
+            [0u8; 32]
+        );
+        assert!(other.write_all(b"late").await.is_err());
+        assert!(matches!(
+            listener.accept_stream().await,
+            Err(TunnelError::SessionClosed)
+        ));
+
+        // Wire codes for errors the peer caused; none for local ones
+        assert_eq!(
+            TunnelError::DecryptionFailed.error_code(),
+            Some(ErrorCode::CryptoError)
+        );
+        assert_eq!(
+            TunnelError::UnsupportedVersion(2).error_code(),
+            Some(ErrorCode::UnsupportedVersion)
+        );
+        assert_eq!(TunnelError::RateLimitExceeded.error_code(), None);
+    }
+
+    #[tokio::test]
+    async fn test_padding_hides_length_within_bucket() {
+        use crate::PaddingPolicy;
+        use std::time::Duration;
+        use tokio::net::UdpSocket;
+
+        let (listener, dialer) = tunnel_pair().await;
+        dialer.set_padding(PaddingPolicy::Buckets(vec![256, 512]));
+
+        // Observe what goes on the wire
+        let observer = UdpSocket::bind("127.0.0.1:0")
+            .await
+            .expect("bind observer in test");
+        *dialer.peer_addr().write().expect("peer addr lock in test") =
+            Some(observer.local_addr().expect("observer addr in test"));
+        let mut buf = vec![0u8; 2048];
+        let mut sizes = Vec::new();
+        for len in [1usize, 40, 200, 254] {
+            dialer
+                .send_vpn_packet(&vec![0x45; len])
+                .await
+                .expect("send in test");
+            let (size, _) =
+                tokio::time::timeout(Duration::from_secs(5), observer.recv_from(&mut buf))
+                    .await
+                    .expect("datagram in time in test")
+                    .expect("recv in test");
+            sizes.push(size);
+        }
+        assert!(sizes.windows(2).all(|w| w[0] == w[1]), "sizes {:?}", sizes);
+        dialer
+            .send_vpn_packet(&[0x45; 255])
+            .await
+            .expect("send in test");
+        let (size, _) = observer.recv_from(&mut buf).await.expect("recv in test");
+        assert_eq!(size, sizes[0] + 256);
+
+        // The receiver strips the padding
+        *dialer.peer_addr().write().expect("peer addr lock in test") =
+            Some(listener.local_addr().expect("listener addr in test"));
+        for len in [1usize, 300] {
+            dialer
+                .send_vpn_packet(&vec![0x45; len])
+                .await
+                .expect("send in test");
+            let (msg_type, _, payload) =
+                tokio::time::timeout(Duration::from_secs(5), listener.recv_record())
+                    .await
+                    .expect("record in time in test")
+                    .expect("record in test");
+            assert_eq!(msg_type, cryprq_core::MSG_TYPE_VPN_PACKET);
+            assert_eq!(payload, vec![0x45; len]);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_cover_traffic_constant_size_and_rate() {
+        use crate::stats::{cover_bytes, CoverBytes};
+        use crate::CoverTrafficConfig;
+        use std::time::{Duration, Instant};
+        use tokio::net::UdpSocket;
+
+        let (listener, dialer) = tunnel_pair().await;
+        assert!(matches!(
+            dialer.start_cover_traffic(CoverTrafficConfig {
+                record_size: 4,
+                ..CoverTrafficConfig::default()
+            }),
+            Err(TunnelError::InvalidConfig(_))
+        ));
+
+        let observer = UdpSocket::bind("127.0.0.1:0")
+            .await
+            .expect("bind observer in test");
+        *dialer.peer_addr().write().expect("peer addr lock in test") =
+            Some(observer.local_addr().expect("observer addr in test"));
+        let dummy_before = cover_bytes(CoverBytes::Dummy);
+        dialer
+            .start_cover_traffic(CoverTrafficConfig {
+                packets_per_second: 50.0,
+                record_size: 256,
+                max_jitter_ms: 2,
+                queue_len: 16,
+            })
+            .expect("start cover in test");
+
+        // Idle and busy slots look the same on the wire
+        let mut buf = vec![0u8; 2048];
+        let mut sizes = Vec::new();
+        let started = Instant::now();
+        for i in 0..20 {
+            if i == 5 {
+                dialer
+                    .send_vpn_packet(&[0x45; 600])
+                    .await
+                    .expect("send in test");
+            }
+            let (size, _) =
+                tokio::time::timeout(Duration::from_secs(2), observer.recv_from(&mut buf))
+                    .await
+                    .expect("slot in time in test")
+                    .expect("recv in test");
+            sizes.push(size);
+        }
+        let elapsed = started.elapsed();
+        assert!(
+            sizes.iter().all(|&size| size == sizes[0]),
+            "sizes {:?}",
+            sizes
+        );
+        assert!(
+            elapsed >= Duration::from_millis(300),
+            "elapsed {:?}",
+            elapsed
+        );
+        assert!(cover_bytes(CoverBytes::Dummy) > dummy_before);
+
+        // The peer gets the data and drops the filler
+        let (tun_tx, mut tun_rx) = tokio::sync::mpsc::unbounded_channel();
+        listener.set_tun_writer(tun_tx);
+        let receivers = spawn_receivers(&[&listener]);
+        *dialer.peer_addr().write().expect("peer addr lock in test") =
+            Some(listener.local_addr().expect("listener addr in test"));
+        for len in [10usize, 600] {
+            dialer
+                .send_vpn_packet(&vec![0x45; len])
+                .await
+                .expect("send in test");
+            let packet = tokio::time::timeout(Duration::from_secs(2), tun_rx.recv())
+                .await
+                .expect("packet in time in test");
+            assert_eq!(packet, Some(vec![0x45; len]));
+        }
+        dialer.stop_cover_traffic().expect("stop cover in test");
+        for receiver in receivers {
+            receiver.abort();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_obfuscation_transports() {
+        use crate::stats::{dropped_packets, DropReason};
+        use crate::{DtlsTransport, Obfs4ServerKey, Obfs4Transport};
+        use std::time::Duration;
+        use tokio::net::UdpSocket;
+
+        let (listener, dialer) = tunnel_pair().await;
+        let key = Obfs4ServerKey::generate();
+        dialer.set_transport(Arc::new(Obfs4Transport::client(key.public_key())));
+        listener.set_transport(Arc::new(Obfs4Transport::server(key)));
+        let listener_addr = listener.local_addr().expect("listener addr in test");
+        *listener
+            .peer_addr()
+            .write()
+            .expect("peer addr lock in test") =
+            Some(dialer.local_addr().expect("dialer addr in test"));
+
+        let observer = UdpSocket::bind("127.0.0.1:0")
+            .await
+            .expect("bind observer in test");
+        let packet = [0x45u8; 100];
+
+        // Send one packet to the observer instead of the listener
+        async fn observe(
+            dialer: &Tunnel,
+            observer: &UdpSocket,
+            listener_addr: SocketAddr,
+            packet: &[u8],
+        ) -> Vec<u8> {
+            *dialer.peer_addr().write().expect("peer addr lock in test") =
+                Some(observer.local_addr().expect("observer addr in test"));
+            dialer.send_vpn_packet(packet).await.expect("send in test");
+            *dialer.peer_addr().write().expect("peer addr lock in test") = Some(listener_addr);
+            let mut buf = vec![0u8; 2048];
+            let (len, _) =
+                tokio::time::timeout(Duration::from_secs(5), observer.recv_from(&mut buf))
+                    .await
+                    .expect("datagram in time in test")
+                    .expect("recv in test");
+            buf.truncate(len);
+            buf
+        }
+        async fn recv(tunnel: &Tunnel) -> Result<Vec<u8>, TunnelError> {
+            let (_, _, payload) =
+                tokio::time::timeout(Duration::from_secs(5), tunnel.recv_record())
+                    .await
+                    .expect("record in time in test")?;
+            Ok(payload)
+        }
+
+        // Until the server answers, datagrams carry the client's representative
+        let first = observe(&dialer, &observer, listener_addr, &packet).await;
+        assert!(!first.windows(packet.len()).any(|w| w == packet));
+        dialer.send_vpn_packet(&packet).await.expect("send in test");
+        assert_eq!(recv(&listener).await.expect("record in test"), packet);
+
+        listener
+            .send_vpn_packet(&packet)
+            .await
+            .expect("reply in test");
+        assert_eq!(recv(&dialer).await.expect("reply in test"), packet);
+        let second = observe(&dialer, &observer, listener_addr, &packet).await;
+        assert_eq!(second.len(), first.len() - 32);
+
+        // A peer without the transport is dropped before the record layer
+        let plain = test_tunnel().await;
+        *plain.peer_addr().write().expect("peer addr lock in test") = Some(listener_addr);
+        let dropped = dropped_packets(DropReason::Transport);
+        plain.send_vpn_packet(&packet).await.expect("send in test");
+        assert!(matches!(
+            recv(&listener).await,
+            Err(TunnelError::DecryptionFailed)
+        ));
+        assert!(dropped_packets(DropReason::Transport) > dropped);
+
+        // DTLS framing
+        dialer.set_transport(Arc::new(DtlsTransport::client(&[9; 32])));
+        listener.set_transport(Arc::new(DtlsTransport::server(&[9; 32])));
+        let framed = observe(&dialer, &observer, listener_addr, &packet).await;
+        assert_eq!(framed[..3], [0x17, 0xFE, 0xFD]);
+        dialer.send_vpn_packet(&packet).await.expect("send in test");
+        assert_eq!(recv(&listener).await.expect("record in test"), packet);
+    }
+
+    #[tokio::test]
+    async fn test_stream_transport_fallback() {
+        use crate::{StreamFallback, TransportKind};
+        use std::time::Duration;
+        use tokio::net::{TcpListener, UdpSocket};
+
+        let (listener, dialer) = tunnel_pair().await;
+        *listener
+            .peer_addr()
+            .write()
+            .expect("peer addr lock in test") =
+            Some(dialer.local_addr().expect("dialer addr in test"));
+        spawn_receivers(&[&listener, &dialer]);
+        let tcp = TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("bind tcp in test");
+        let fallback = StreamFallback {
+            udp_timeout: Duration::from_millis(500),
+            tcp_addr: tcp.local_addr().expect("tcp addr in test").to_string(),
+            tls: None,
+        };
+
+        // UDP is used while it gets through
+        let kind = dialer
+            .connect_with_fallback(
+                listener.local_addr().expect("listener addr in test"),
+                &fallback,
+            )
+            .await
+            .expect("connect in test");
+        assert_eq!(kind, TransportKind::Udp);
+
+        // A peer that never answers over UDP is reached over TCP instead
+        let blackhole = UdpSocket::bind("127.0.0.1:0")
+            .await
+            .expect("bind blackhole in test");
+        let accept = {
+            let listener = listener.clone();
+            tokio::spawn(async move {
+                let (stream, peer) = tcp.accept().await.expect("accept in test");
+                listener
+                    .attach_stream(stream, peer)
+                    .expect("attach in test");
+            })
+        };
+        let kind = dialer
+            .connect_with_fallback(
+                blackhole.local_addr().expect("blackhole addr in test"),
+                &fallback,
+            )
+            .await
+            .expect("fallback in test");
+        assert_eq!(kind, TransportKind::Stream);
+        assert_eq!(dialer.transport_kind(), TransportKind::Stream);
+        accept.await.expect("accept task in test");
+
+        // Both directions now travel over the stream
+        dialer
+            .ping(Duration::from_secs(2))
+            .await
+            .expect("pong over stream in test");
+    }
+
+    #[tokio::test]
+    async fn test_stream_fallback_over_tls_listener() {
+        use crate::{StreamFallback, TlsConfig, TlsServer, TransportKind};
+        use rcgen::{BasicConstraints, CertificateParams, IsCa, KeyPair};
+        use std::time::Duration;
+        use tokio::net::{TcpStream, UdpSocket};
+
+        let dir = std::env::temp_dir().join(format!("cryprq-fallback-tls-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).expect("temp dir in test");
+        let mut ca_params = CertificateParams::new(Vec::new()).expect("ca params in test");
+        ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let ca_key = KeyPair::generate().expect("ca key in test");
+        let ca = ca_params.self_signed(&ca_key).expect("ca cert in test");
+        let write = |name: &str, subject: &str| {
+            let key = KeyPair::generate().expect("key in test");
+            let cert = CertificateParams::new(vec![subject.to_string()])
+                .expect("params in test")
+                .signed_by(&key, &ca, &ca_key)
+                .expect("cert in test");
+            let cert_path = dir.join(format!("{}.crt", name));
+            let key_path = dir.join(format!("{}.key", name));
+            std::fs::write(&cert_path, cert.pem()).expect("write cert in test");
+            std::fs::write(&key_path, key.serialize_pem()).expect("write key in test");
+            (
+                Some(cert_path.to_string_lossy().into_owned()),
+                Some(key_path.to_string_lossy().into_owned()),
+            )
+        };
+        let ca_path = dir.join("ca.crt");
+        std::fs::write(&ca_path, ca.pem()).expect("write ca in test");
+        let ca_path = Some(ca_path.to_string_lossy().into_owned());
+        let (server_cert, server_key) = write("server", "localhost");
+        let (client_cert, client_key) = write("client", "client");
+
+        let (listener, dialer) = tunnel_pair().await;
+        spawn_receivers(&[&listener, &dialer]);
+
+        let mut server = TlsServer::new(TlsConfig {
+            cert_path: server_cert.clone(),
+            key_path: server_key.clone(),
+            ca_cert_path: ca_path.clone(),
+            require_client_auth: true,
+            handshake_timeout: Duration::from_millis(500),
+            ..TlsConfig::default()
+        });
+        server.listen("127.0.0.1:0").await.expect("listen in test");
+        let tcp_addr = server.local_addr().expect("tls addr in test").to_string();
+        let _serving = listener
+            .serve_stream_fallback(server)
+            .expect("serve stream fallback in test");
+
+        // A server that lets any client in cannot take over the tunnel
+        let mut open_server = TlsServer::new(TlsConfig {
+            cert_path: server_cert,
+            key_path: server_key,
+            ..TlsConfig::default()
+        });
+        open_server
+            .listen("127.0.0.1:0")
+            .await
+            .expect("listen in test");
+        assert!(matches!(
+            listener.serve_stream_fallback(open_server),
+            Err(TunnelError::InvalidConfig(_))
+        ));
+
+        // A client that never finishes its handshake does not block the next
+        let _stalled = TcpStream::connect(&tcp_addr)
+            .await
+            .expect("connect in test");
+
+        let blackhole = UdpSocket::bind("127.0.0.1:0")
+            .await
+            .expect("bind blackhole in test");
+        let fallback = StreamFallback {
+            udp_timeout: Duration::from_millis(300),
+            tcp_addr,
+            tls: Some(TlsConfig {
+                cert_path: client_cert,
+                key_path: client_key,
+                ca_cert_path: ca_path,
+                server_name: Some("localhost".to_string()),
+                ..TlsConfig::default()
+            }),
+        };
+        let kind = dialer
+            .connect_with_fallback(
+                blackhole.local_addr().expect("blackhole addr in test"),
+                &fallback,
+            )
+            .await
+            .expect("fallback in test");
+        assert_eq!(kind, TransportKind::Stream);
+
+        // The listener attached the stream and answers over it
+        dialer
+            .ping(Duration::from_secs(2))
+            .await
+            .expect("pong over TLS stream in test");
+        assert_eq!(listener.transport_kind(), TransportKind::Stream);
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[tokio::test]
+    async fn test_send_vpn_packets_batched() {
+        use std::time::Duration;
+
+        let (listener, dialer) = tunnel_pair().await;
+
+        // Equal sizes share GSO sends; the large packet is fragmented
+        let mut packets: Vec<Vec<u8>> = (0..40u8).map(|i| vec![i; 1000]).collect();
+        packets.insert(20, vec![0xEE; 4000]);
+        packets.push(vec![0xFF; 10]);
+        for batched in [true, false] {
+            dialer.set_batch_io(batched);
+            listener.set_batch_io(batched);
+            let refs: Vec<&[u8]> = packets.iter().map(|p| p.as_slice()).collect();
+            dialer
+                .send_vpn_packets(&refs)
+                .await
+                .expect("send batch in test");
+            for packet in &packets {
+                let (_, _, payload) =
+                    tokio::time::timeout(Duration::from_secs(5), listener.recv_record())
+                        .await
+                        .expect("record in time in test")
+                        .expect("record in test");
+                assert_eq!(&payload, packet);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_replayed_and_forged_datagrams() {
+        use std::time::Duration;
+        use tokio::net::UdpSocket;
+
+        let (listener, dialer) = tunnel_pair().await;
+        let observer = UdpSocket::bind("127.0.0.1:0")
+            .await
+            .expect("bind observer in test");
+        *dialer.peer_addr().write().expect("peer addr lock in test") =
+            Some(observer.local_addr().expect("observer addr in test"));
+
+        // Capture two sealed records
+        let mut sealed = Vec::new();
+        let mut buf = vec![0u8; 2048];
+        for packet in [[0x45; 32], [0x46; 32]] {
+            dialer.send_vpn_packet(&packet).await.expect("send in test");
+            let (size, _) =
+                tokio::time::timeout(Duration::from_secs(5), observer.recv_from(&mut buf))
+                    .await
+                    .expect("datagram in time in test")
+                    .expect("recv in test");
+            sealed.push(buf[..size].to_vec());
+        }
+        let mut forged = sealed[1].clone();
+        *forged.last_mut().expect("tag byte in test") ^= 0x01;
+
+        // A forgery must not take its sequence number from the genuine record,
+        // and of two copies decrypted together only the first is accepted
+        let listener_addr = listener.local_addr().expect("listener addr in test");
+        for datagram in [&forged, &sealed[0], &sealed[0], &sealed[1]] {
+            observer
+                .send_to(datagram, listener_addr)
+                .await
+                .expect("send in test");
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let mut results = Vec::new();
+        for _ in 0..4 {
+            results.push(
+                tokio::time::timeout(Duration::from_secs(5), listener.recv_record())
+                    .await
+                    .expect("record in time in test"),
+            );
+        }
+        assert!(matches!(results[0], Err(TunnelError::DecryptionFailed)));
+        assert!(matches!(&results[1], Ok((_, _, payload)) if payload == &[0x45; 32]));
+        assert!(matches!(results[2], Err(TunnelError::ReplayDetected)));
+        assert!(matches!(&results[3], Ok((_, _, payload)) if payload == &[0x46; 32]));
+
+        // Later replays are rejected too
+        observer
+            .send_to(&sealed[1], listener_addr)
+            .await
+            .expect("send in test");
+        let replayed = tokio::time::timeout(Duration::from_secs(5), listener.recv_record())
+            .await
+            .expect("record in time in test");
+        assert!(matches!(replayed, Err(TunnelError::ReplayDetected)));
+    }
+
+    #[tokio::test]
+    async fn test_custom_message_handlers() {
+        use crate::stats::{dropped_packets, DropReason};
+        use crate::{CustomMessage, MessageHandler};
+        use std::time::Duration;
+        use tokio::sync::mpsc;
+
+        /// Telemetry report: sequence number, then opaque samples
+        struct Telemetry {
+            seq: u32,
+            samples: Vec<u8>,
+        }
+
+        impl CustomMessage for Telemetry {
+            const MESSAGE_TYPE: u8 = 0x90;
+
+            fn to_bytes(&self) -> Vec<u8> {
+                [&self.seq.to_be_bytes()[..], &self.samples].concat()
+            }
+
+            fn from_bytes(payload: &[u8]) -> std::io::Result<Self> {
+                let seq = payload
+                    .get(..4)
+                    .and_then(|b| b.try_into().ok())
+                    .map(u32::from_be_bytes)
+                    .ok_or_else(|| std::io::Error::other("short telemetry"))?;
+                Ok(Self {
+                    seq,
+                    samples: payload[4..].to_vec(),
+                })
+            }
+        }
+
+        struct TelemetryAck(u32);
+
+        impl CustomMessage for TelemetryAck {
+            const MESSAGE_TYPE: u8 = 0x91;
+
+            fn to_bytes(&self) -> Vec<u8> {
+                self.0.to_be_bytes().to_vec()
+            }
+
+            fn from_bytes(payload: &[u8]) -> std::io::Result<Self> {
+                let seq = payload
+                    .try_into()
+                    .map_err(|_| std::io::Error::other("bad ack"))?;
+                Ok(Self(u32::from_be_bytes(seq)))
+            }
+        }
+
+        /// Acknowledges each report over the same session
+        struct Collector(mpsc::UnboundedSender<(u32, usize)>);
+
+        #[async_trait::async_trait]
+        impl MessageHandler<Telemetry> for Collector {
+            async fn handle(
+                &self,
+                tunnel: Arc<Tunnel>,
+                report: Telemetry,
+            ) -> Result<(), TunnelError> {
+                let _ = self.0.send((report.seq, report.samples.len()));
+                tunnel.send_message(&TelemetryAck(report.seq)).await
+            }
+        }
+
+        struct AckWaiter(mpsc::UnboundedSender<u32>);
+
+        #[async_trait::async_trait]
+        impl MessageHandler<TelemetryAck> for AckWaiter {
+            async fn handle(&self, _: Arc<Tunnel>, ack: TelemetryAck) -> Result<(), TunnelError> {
+                let _ = self.0.send(ack.0);
+                Ok(())
+            }
+        }
+
+        /// Tries to claim a built-in record type
+        struct Hijack;
+
+        impl CustomMessage for Hijack {
+            const MESSAGE_TYPE: u8 = cryprq_core::MSG_TYPE_CONTROL;
+
+            fn to_bytes(&self) -> Vec<u8> {
+                Vec::new()
+            }
+
+            fn from_bytes(_: &[u8]) -> std::io::Result<Self> {
+                Ok(Self)
+            }
+        }
+
+        struct Ignore;
+
+        #[async_trait::async_trait]
+        impl MessageHandler<Hijack> for Ignore {
+            async fn handle(&self, _: Arc<Tunnel>, _: Hijack) -> Result<(), TunnelError> {
+                Ok(())
+            }
+        }
+
+        /// Never finishes handling its first message
+        struct Stall;
+
+        impl CustomMessage for Stall {
+            const MESSAGE_TYPE: u8 = 0x92;
+
+            fn to_bytes(&self) -> Vec<u8> {
+                Vec::new()
+            }
+
+            fn from_bytes(_: &[u8]) -> std::io::Result<Self> {
+                Ok(Self)
+            }
+        }
+
+        struct Stuck;
+
+        #[async_trait::async_trait]
+        impl MessageHandler<Stall> for Stuck {
+            async fn handle(&self, _: Arc<Tunnel>, _: Stall) -> Result<(), TunnelError> {
+                std::future::pending().await
+            }
+        }
+
+        let (listener, dialer) = tunnel_pair().await;
+        let (report_tx, mut reports) = mpsc::unbounded_channel();
+        let (ack_tx, mut acks) = mpsc::unbounded_channel();
+        listener
+            .register_handler(Collector(report_tx))
+            .expect("register collector in test");
+        dialer
+            .register_handler(AckWaiter(ack_tx))
+            .expect("register ack waiter in test");
+        listener
+            .register_handler(Stuck)
+            .expect("register stuck handler in test");
+        assert!(matches!(
+            listener.register_handler(Ignore),
+            Err(TunnelError::InvalidMessageType(_))
+        ));
+        assert!(dialer.send_message(&Hijack).await.is_err());
+
+        let receivers = spawn_receivers(&[&listener, &dialer]);
+
+        // Small and fragmented reports both reach the handler
+        for (seq, len) in [(1u32, 16usize), (2, 6000)] {
+            let report = Telemetry {
+                seq,
+                samples: vec![7; len],
+            };
+            dialer.send_message(&report).await.expect("send in test");
+            let received = tokio::time::timeout(Duration::from_secs(5), reports.recv())
+                .await
+                .expect("report in time in test");
+            assert_eq!(received, Some((seq, len)));
+            let ack = tokio::time::timeout(Duration::from_secs(5), acks.recv())
+                .await
+                .expect("ack in time in test");
+            assert_eq!(ack, Some(seq));
+        }
+
+        // A stuck handler holds up only its own type; its backlog is dropped
+        let dropped = dropped_packets(DropReason::HandlerBackpressure);
+        for _ in 0..80 {
+            dialer
+                .send_message(&Stall)
+                .await
+                .expect("send stall in test");
+        }
+        dialer
+            .send_message(&Telemetry {
+                seq: 3,
+                samples: Vec::new(),
+            })
+            .await
+            .expect("send in test");
+        let ack = tokio::time::timeout(Duration::from_secs(5), acks.recv())
+            .await
+            .expect("ack past stuck handler in test");
+        assert_eq!(ack, Some(3));
+        assert_eq!(reports.recv().await, Some((3, 0)));
+        assert!(dropped_packets(DropReason::HandlerBackpressure) > dropped);
+
+        // Without a handler the type is dropped
+        assert!(listener
+            .unregister_handler(Telemetry::MESSAGE_TYPE)
+            .expect("unregister in test"));
+        dialer
+            .send_message(&Telemetry {
+                seq: 4,
+                samples: Vec::new(),
+            })
+            .await
+            .expect("send in test");
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert!(reports.try_recv().is_err());
+        assert!(acks.try_recv().is_err());
+
+        for receiver in receivers {
+            receiver.abort();
+        }
+    }
+
     #[test]
     fn test_replay_window_sequential() {
         use crate::ReplayWindow;
@@ -4,41 +4,71 @@
 // LinkedIn: https://www.linkedin.com/in/thor-thor0
 // License: MIT (see LICENSE file for details)
 
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use rustls::crypto::CryptoProvider;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+use rustls::server::WebPkiClientVerifier;
+use rustls::{ClientConfig, NamedGroup, RootCertStore, ServerConfig};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf};
 use tokio::net::TcpListener;
 use tokio::net::TcpStream;
+use tokio::task::JoinHandle;
+use tokio_rustls::{TlsAcceptor, TlsConnector};
+
+use crate::tls_kx::X25519_MLKEM768;
 
 /// TLS 1.3 configuration for control plane
+///
+/// Key exchange prefers the X25519MLKEM768 hybrid group. Certificates and
+/// keys are PEM files. The CA in `ca_cert_path` is the only trust anchor:
+/// clients accept just the servers it issued, and with
+/// `require_client_auth` servers accept just the clients it issued.
 #[derive(Debug, Clone)]
 pub struct TlsConfig {
-    /// Enable TLS 1.3
-    pub enabled: bool,
-    /// Certificate path (for server mode)
+    /// Certificate chain: the server's, or the client's for mutual TLS
     pub cert_path: Option<String>,
-    /// Private key path (for server mode)
+    /// Private key for `cert_path`
     pub key_path: Option<String>,
-    /// CA certificate path (for client verification)
+    /// CA certificate pinned for verifying the peer
     pub ca_cert_path: Option<String>,
     /// Require client authentication
     pub require_client_auth: bool,
+    /// Name to verify in the server certificate (client mode); defaults to
+    /// the host part of the address passed to `connect`
+    pub server_name: Option<String>,
+    /// Refuse peers that cannot do the hybrid post-quantum key exchange
+    pub require_post_quantum: bool,
+    /// Time an accepted connection gets to finish its handshake
+    pub handshake_timeout: Duration,
 }
 
 impl Default for TlsConfig {
     fn default() -> Self {
         Self {
-            enabled: true,
             cert_path: None,
             key_path: None,
             ca_cert_path: None,
             require_client_auth: false,
+            server_name: None,
+            require_post_quantum: false,
+            handshake_timeout: Duration::from_secs(10),
         }
     }
 }
 
 /// TLS 1.3 server for control plane
 pub struct TlsServer {
-    #[allow(dead_code)]
     config: TlsConfig,
     listener: Option<TcpListener>,
+    acceptor: Option<TlsAcceptor>,
 }
 
 impl TlsServer {
@@ -47,36 +77,106 @@ impl TlsServer {
         Self {
             config,
             listener: None,
+            acceptor: None,
         }
     }
 
-    /// Start listening on the specified address
+    /// Load the certificate and key, then start listening on `addr`
     pub async fn listen(&mut self, addr: &str) -> Result<(), TlsError> {
+        let acceptor = TlsAcceptor::from(Arc::new(server_config(&self.config)?));
         let listener = TcpListener::bind(addr)
             .await
             .map_err(|e| TlsError::NetworkError(e.to_string()))?;
         self.listener = Some(listener);
+        self.acceptor = Some(acceptor);
         Ok(())
     }
 
-    /// Accept a new TLS connection
-    pub async fn accept(&self) -> Result<TlsStream, TlsError> {
-        let listener = self.listener.as_ref().ok_or(TlsError::NotListening)?;
+    /// Whether clients must present a certificate issued by the pinned CA
+    pub fn requires_client_auth(&self) -> bool {
+        self.config.require_client_auth
+    }
+
+    /// Address the server is listening on
+    pub fn local_addr(&self) -> Result<SocketAddr, TlsError> {
+        self.listener
+            .as_ref()
+            .ok_or(TlsError::NotListening)?
+            .local_addr()
+            .map_err(|e| TlsError::NetworkError(e.to_string()))
+    }
+
+    /// Accept a new TCP connection and start its TLS handshake
+    ///
+    /// Returns once TCP accepts; the handshake runs in its own task under
+    /// `handshake_timeout`, so a peer that stalls mid-handshake cannot hold
+    /// up the accept loop. Await the returned `TlsHandshake` for the stream.
+    pub async fn accept(&self) -> Result<TlsHandshake, TlsError> {
+        let (Some(listener), Some(acceptor)) = (&self.listener, &self.acceptor) else {
+            return Err(TlsError::NotListening);
+        };
 
-        let (stream, _) = listener
+        let (stream, peer) = listener
             .accept()
             .await
             .map_err(|e| TlsError::NetworkError(e.to_string()))?;
+        stream
+            .set_nodelay(true)
+            .map_err(|e| TlsError::NetworkError(e.to_string()))?;
+        let acceptor = acceptor.clone();
+        let timeout = self.config.handshake_timeout;
+        let task = tokio::spawn(async move {
+            let stream = tokio::time::timeout(timeout, acceptor.accept(stream))
+                .await
+                .map_err(|_| {
+                    log::debug!("event=tls_handshake_timeout peer={}", peer);
+                    TlsError::HandshakeError("handshake timed out".to_string())
+                })?
+                .map_err(|e| {
+                    log::debug!("event=tls_handshake_failed peer={} error={}", peer, e);
+                    TlsError::HandshakeError(e.to_string())
+                })?;
+            Ok(TlsStream {
+                inner: stream.into(),
+            })
+        });
+        Ok(TlsHandshake { peer, task })
+    }
+}
+
+/// Server handshake in progress; resolves to the established stream
+///
+/// Dropping it aborts the handshake.
+pub struct TlsHandshake {
+    peer: SocketAddr,
+    task: JoinHandle<Result<TlsStream, TlsError>>,
+}
 
-        // TODO: Wrap stream with rustls or native-tls
-        // For now, return raw TCP stream wrapper
-        Ok(TlsStream { inner: stream })
+impl TlsHandshake {
+    /// Address of the connecting peer
+    pub fn peer_addr(&self) -> SocketAddr {
+        self.peer
+    }
+}
+
+impl Future for TlsHandshake {
+    type Output = Result<TlsStream, TlsError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut self.task)
+            .poll(cx)
+            .map(|joined| joined.unwrap_or_else(|e| Err(TlsError::HandshakeError(e.to_string()))))
+    }
+}
+
+impl Drop for TlsHandshake {
+    fn drop(&mut self) {
+        self.task.abort();
     }
 }
 
 /// TLS 1.3 client connection
 pub struct TlsClient {
-    #[allow(dead_code)]
     config: TlsConfig,
 }
 
@@ -86,47 +186,120 @@ impl TlsClient {
         Self { config }
     }
 
-    /// Connect to a TLS server
+    /// Connect to a TLS server and verify it against the pinned CA
     pub async fn connect(&self, addr: &str) -> Result<TlsStream, TlsError> {
+        let connector = TlsConnector::from(Arc::new(client_config(&self.config)?));
+        let name = match &self.config.server_name {
+            Some(name) => name.as_str(),
+            None => host_of(addr),
+        };
+        let server_name = ServerName::try_from(name.to_string())
+            .map_err(|_| TlsError::HandshakeError(format!("invalid server name: {}", name)))?;
+
         let stream = TcpStream::connect(addr)
             .await
             .map_err(|e| TlsError::NetworkError(e.to_string()))?;
-
-        // TODO: Wrap stream with rustls or native-tls
-        // For now, return raw TCP stream wrapper
-        Ok(TlsStream { inner: stream })
+        stream
+            .set_nodelay(true)
+            .map_err(|e| TlsError::NetworkError(e.to_string()))?;
+        let stream = connector
+            .connect(server_name, stream)
+            .await
+            .map_err(|e| TlsError::HandshakeError(e.to_string()))?;
+        Ok(TlsStream {
+            inner: stream.into(),
+        })
     }
 }
 
 /// TLS stream wrapper
+///
+/// Also usable as a tokio byte stream through `AsyncRead`/`AsyncWrite`.
 pub struct TlsStream {
-    inner: TcpStream,
+    inner: tokio_rustls::TlsStream<TcpStream>,
 }
 
 impl TlsStream {
     /// Read data from TLS stream
-    pub async fn read(&self, buf: &mut [u8]) -> Result<usize, TlsError> {
+    pub async fn read(&mut self, buf: &mut [u8]) -> Result<usize, TlsError> {
         self.inner
-            .readable()
+            .read(buf)
             .await
-            .map_err(|e| TlsError::NetworkError(e.to_string()))?;
-        self.inner
-            .try_read(buf)
             .map_err(|e| TlsError::NetworkError(e.to_string()))
     }
 
     /// Write data to TLS stream
-    pub async fn write(&self, buf: &[u8]) -> Result<usize, TlsError> {
+    pub async fn write(&mut self, buf: &[u8]) -> Result<usize, TlsError> {
+        let written = self
+            .inner
+            .write(buf)
+            .await
+            .map_err(|e| TlsError::NetworkError(e.to_string()))?;
         self.inner
-            .writable()
+            .flush()
             .await
             .map_err(|e| TlsError::NetworkError(e.to_string()))?;
+        Ok(written)
+    }
+
+    /// Whether the session keys came from the hybrid post-quantum exchange
+    pub fn is_post_quantum(&self) -> bool {
+        self.inner
+            .get_ref()
+            .1
+            .negotiated_key_exchange_group()
+            .map(|group| group.name())
+            == Some(NamedGroup::X25519MLKEM768)
+    }
+
+    /// DER certificate the peer authenticated with, if it presented one
+    pub fn peer_certificate(&self) -> Option<&[u8]> {
+        self.inner
+            .get_ref()
+            .1
+            .peer_certificates()
+            .and_then(|certs| certs.first())
+            .map(|cert| cert.as_ref())
+    }
+
+    /// Address of the other end of the TCP connection
+    pub fn peer_addr(&self) -> Result<SocketAddr, TlsError> {
         self.inner
-            .try_write(buf)
+            .get_ref()
+            .0
+            .peer_addr()
             .map_err(|e| TlsError::NetworkError(e.to_string()))
     }
 }
 
+impl AsyncRead for TlsStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for TlsStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum TlsError {
     #[error("Network error: {0}")]
@@ -138,3 +311,252 @@ pub enum TlsError {
     #[error("Server not listening")]
     NotListening,
 }
+
+/// ring provider with the hybrid group first; TLS 1.3 only
+fn provider(config: &TlsConfig) -> Arc<CryptoProvider> {
+    let mut provider = rustls::crypto::ring::default_provider();
+    provider.kx_groups = match config.require_post_quantum {
+        true => vec![X25519_MLKEM768],
+        false => vec![X25519_MLKEM768, rustls::crypto::ring::kx_group::X25519],
+    };
+    Arc::new(provider)
+}
+
+fn server_config(config: &TlsConfig) -> Result<ServerConfig, TlsError> {
+    let (Some(cert_path), Some(key_path)) = (&config.cert_path, &config.key_path) else {
+        return Err(TlsError::CertificateError(
+            "server needs cert_path and key_path".to_string(),
+        ));
+    };
+    let provider = provider(config);
+    let builder = ServerConfig::builder_with_provider(provider.clone())
+        .with_protocol_versions(&[&rustls::version::TLS13])
+        .map_err(|e| TlsError::HandshakeError(e.to_string()))?;
+    let builder = if config.require_client_auth {
+        let ca_path = config.ca_cert_path.as_ref().ok_or_else(|| {
+            TlsError::CertificateError("client authentication needs ca_cert_path".to_string())
+        })?;
+        let verifier =
+            WebPkiClientVerifier::builder_with_provider(Arc::new(load_roots(ca_path)?), provider)
+                .build()
+                .map_err(|e| TlsError::CertificateError(e.to_string()))?;
+        builder.with_client_cert_verifier(verifier)
+    } else {
+        builder.with_no_client_auth()
+    };
+    builder
+        .with_single_cert(load_certs(cert_path)?, load_key(key_path)?)
+        .map_err(|e| TlsError::CertificateError(e.to_string()))
+}
+
+fn client_config(config: &TlsConfig) -> Result<ClientConfig, TlsError> {
+    let ca_path = config.ca_cert_path.as_ref().ok_or_else(|| {
+        TlsError::CertificateError("client needs ca_cert_path to verify the server".to_string())
+    })?;
+    let builder = ClientConfig::builder_with_provider(provider(config))
+        .with_protocol_versions(&[&rustls::version::TLS13])
+        .map_err(|e| TlsError::HandshakeError(e.to_string()))?
+        .with_root_certificates(load_roots(ca_path)?);
+    match (&config.cert_path, &config.key_path) {
+        (Some(cert_path), Some(key_path)) => builder
+            .with_client_auth_cert(load_certs(cert_path)?, load_key(key_path)?)
+            .map_err(|e| TlsError::CertificateError(e.to_string())),
+        (None, None) => Ok(builder.with_no_client_auth()),
+        _ => Err(TlsError::CertificateError(
+            "cert_path and key_path must be set together".to_string(),
+        )),
+    }
+}
+
+fn load_certs(path: &str) -> Result<Vec<CertificateDer<'static>>, TlsError> {
+    let certs = CertificateDer::pem_file_iter(path)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| TlsError::CertificateError(format!("{}: {}", path, e)))?;
+    if certs.is_empty() {
+        return Err(TlsError::CertificateError(format!(
+            "{}: no certificates found",
+            path
+        )));
+    }
+    Ok(certs)
+}
+
+fn load_key(path: &str) -> Result<PrivateKeyDer<'static>, TlsError> {
+    PrivateKeyDer::from_pem_file(path)
+        .map_err(|e| TlsError::CertificateError(format!("{}: {}", path, e)))
+}
+
+fn load_roots(path: &str) -> Result<RootCertStore, TlsError> {
+    let mut roots = RootCertStore::empty();
+    for cert in load_certs(path)? {
+        roots
+            .add(cert)
+            .map_err(|e| TlsError::CertificateError(format!("{}: {}", path, e)))?;
+    }
+    Ok(roots)
+}
+
+/// Host part of `host:port` or `[v6]:port`
+fn host_of(addr: &str) -> &str {
+    addr.rsplit_once(':')
+        .map_or(addr, |(host, _)| host)
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rcgen::{BasicConstraints, CertificateParams, IsCa, KeyPair};
+    use std::path::Path;
+
+    struct Issued {
+        cert: rcgen::Certificate,
+        key: KeyPair,
+    }
+
+    fn ca() -> Issued {
+        let mut params = CertificateParams::new(Vec::new()).expect("ca params in test");
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let key = KeyPair::generate().expect("ca key in test");
+        let cert = params.self_signed(&key).expect("ca cert in test");
+        Issued { cert, key }
+    }
+
+    fn issue(ca: &Issued, name: &str) -> Issued {
+        let params = CertificateParams::new(vec![name.to_string()]).expect("params in test");
+        let key = KeyPair::generate().expect("key in test");
+        let cert = params
+            .signed_by(&key, &ca.cert, &ca.key)
+            .expect("cert in test");
+        Issued { cert, key }
+    }
+
+    /// Write PEM files for the test into a fresh directory
+    fn write_pem(dir: &Path, name: &str, issued: &Issued) -> (String, String) {
+        let cert = dir.join(format!("{}.crt", name));
+        let key = dir.join(format!("{}.key", name));
+        std::fs::write(&cert, issued.cert.pem()).expect("write cert in test");
+        std::fs::write(&key, issued.key.serialize_pem()).expect("write key in test");
+        (
+            cert.to_string_lossy().into_owned(),
+            key.to_string_lossy().into_owned(),
+        )
+    }
+
+    async fn accept(server: &TlsServer) -> Result<TlsStream, TlsError> {
+        server.accept().await?.await
+    }
+
+    #[tokio::test]
+    async fn test_mutual_tls_with_hybrid_key_exchange() {
+        let dir = std::env::temp_dir().join(format!("cryprq-tls-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).expect("temp dir in test");
+        let authority = ca();
+        let (ca_path, _) = write_pem(&dir, "ca", &authority);
+        let (server_cert, server_key) = write_pem(&dir, "server", &issue(&authority, "localhost"));
+        let (client_cert, client_key) = write_pem(&dir, "client", &issue(&authority, "client"));
+        let (other_ca_path, _) = write_pem(&dir, "other-ca", &ca());
+
+        let mut server = TlsServer::new(TlsConfig {
+            cert_path: Some(server_cert),
+            key_path: Some(server_key),
+            ca_cert_path: Some(ca_path.clone()),
+            require_client_auth: true,
+            ..TlsConfig::default()
+        });
+        server.listen("127.0.0.1:0").await.expect("listen in test");
+        let addr = server
+            .local_addr()
+            .expect("server addr in test")
+            .to_string();
+        let client_config = TlsConfig {
+            cert_path: Some(client_cert),
+            key_path: Some(client_key),
+            ca_cert_path: Some(ca_path),
+            server_name: Some("localhost".to_string()),
+            ..TlsConfig::default()
+        };
+
+        let client = TlsClient::new(client_config.clone());
+        let (accepted, connected) = tokio::join!(accept(&server), client.connect(&addr));
+        let (mut accepted, mut connected) = (
+            accepted.expect("accept in test"),
+            connected.expect("connect in test"),
+        );
+        assert!(accepted.is_post_quantum());
+        assert!(connected.is_post_quantum());
+        assert!(accepted.peer_certificate().is_some());
+
+        connected.write(b"ping").await.expect("write in test");
+        let mut buf = [0u8; 16];
+        let n = accepted.read(&mut buf).await.expect("read in test");
+        assert_eq!(&buf[..n], b"ping");
+
+        // A client without a certificate is refused
+        let anonymous = TlsClient::new(TlsConfig {
+            cert_path: None,
+            key_path: None,
+            ..client_config.clone()
+        });
+        let (accepted, connected) = tokio::join!(accept(&server), async {
+            let mut stream = anonymous.connect(&addr).await?;
+            // TLS 1.3 reports client authentication failures on first read
+            stream.read(&mut [0u8; 1]).await
+        });
+        assert!(accepted.is_err());
+        assert!(connected.is_err());
+
+        // A client pinned to another CA refuses the server
+        let pinned_elsewhere = TlsClient::new(TlsConfig {
+            ca_cert_path: Some(other_ca_path),
+            ..client_config
+        });
+        let (accepted, connected) = tokio::join!(accept(&server), pinned_elsewhere.connect(&addr));
+        assert!(accepted.is_err());
+        assert!(matches!(connected, Err(TlsError::HandshakeError(_))));
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[tokio::test]
+    async fn test_stalled_handshake_does_not_block_accept() {
+        let dir = std::env::temp_dir().join(format!("cryprq-tls-stall-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).expect("temp dir in test");
+        let authority = ca();
+        let (ca_path, _) = write_pem(&dir, "ca", &authority);
+        let (server_cert, server_key) = write_pem(&dir, "server", &issue(&authority, "localhost"));
+
+        let mut server = TlsServer::new(TlsConfig {
+            cert_path: Some(server_cert),
+            key_path: Some(server_key),
+            handshake_timeout: Duration::from_millis(200),
+            ..TlsConfig::default()
+        });
+        server.listen("127.0.0.1:0").await.expect("listen in test");
+        let addr = server
+            .local_addr()
+            .expect("server addr in test")
+            .to_string();
+
+        // A peer that connects and never speaks
+        let _silent = TcpStream::connect(&addr).await.expect("connect in test");
+        let stalled = tokio::time::timeout(Duration::from_secs(1), server.accept())
+            .await
+            .expect("accept returns before the handshake in test")
+            .expect("accept in test");
+
+        // The next client is served while the first is still stalled
+        let client = TlsClient::new(TlsConfig {
+            ca_cert_path: Some(ca_path),
+            server_name: Some("localhost".to_string()),
+            ..TlsConfig::default()
+        });
+        let (accepted, connected) = tokio::join!(accept(&server), client.connect(&addr));
+        assert!(accepted.is_ok());
+        assert!(connected.is_ok());
+
+        assert!(matches!(stalled.await, Err(TlsError::HandshakeError(_))));
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -0,0 +1,180 @@
+// Copyright (c) 2025 Thor Thor
+// Author: Thor Thor (GitHub: https://github.com/codethor0)
+// Contact: codethor@gmail.com
+// LinkedIn: https://www.linkedin.com/in/thor-thor0
+// License: MIT (see LICENSE file for details)
+
+use cryprq_crypto::{kyber_encapsulate, HybridHandshake};
+use rustls::crypto::{ActiveKeyExchange, CompletedKeyExchange, SharedSecret, SupportedKxGroup};
+use rustls::{Error, NamedGroup, PeerMisbehaved, ProtocolVersion};
+use x25519_dalek::{EphemeralSecret, PublicKey};
+use zeroize::Zeroize;
+
+const MLKEM768_ENCAPSULATION_KEY_SIZE: usize = 1184;
+const MLKEM768_CIPHERTEXT_SIZE: usize = 1088;
+const X25519_KEY_SIZE: usize = 32;
+
+/// X25519MLKEM768 hybrid key exchange for TLS 1.3
+///
+/// Key shares are the ML-KEM-768 part followed by the X25519 part, and the
+/// shared secret is the ML-KEM secret followed by the X25519 secret, as in
+/// draft-ietf-tls-ecdhe-mlkem. The session stays confidential as long as
+/// either component holds.
+#[derive(Debug)]
+pub(crate) struct X25519MlKem768;
+
+pub(crate) static X25519_MLKEM768: &dyn SupportedKxGroup = &X25519MlKem768;
+
+impl SupportedKxGroup for X25519MlKem768 {
+    fn start(&self) -> Result<Box<dyn ActiveKeyExchange>, Error> {
+        let keys = HybridHandshake::new();
+        let mut pub_key = keys.kyber_public_key_bytes().to_vec();
+        pub_key.extend_from_slice(PublicKey::from(keys.x25519_secret()).as_bytes());
+        Ok(Box::new(ClientKeyExchange { keys, pub_key }))
+    }
+
+    /// Server side: encapsulate to the client's share in one step
+    fn start_and_complete(&self, client_share: &[u8]) -> Result<CompletedKeyExchange, Error> {
+        if client_share.len() != MLKEM768_ENCAPSULATION_KEY_SIZE + X25519_KEY_SIZE {
+            return Err(invalid_share());
+        }
+        let (encapsulation_key, x25519_share) =
+            client_share.split_at(MLKEM768_ENCAPSULATION_KEY_SIZE);
+        let (mut pub_key, mut ss_kem) =
+            kyber_encapsulate(encapsulation_key).ok_or_else(invalid_share)?;
+
+        let x_secret = EphemeralSecret::random_from_rng(rand_core::OsRng);
+        pub_key.extend_from_slice(PublicKey::from(&x_secret).as_bytes());
+        let ss_x = x_secret.diffie_hellman(&peer_x25519(x25519_share)?);
+        if !ss_x.was_contributory() {
+            ss_kem.zeroize();
+            return Err(invalid_share());
+        }
+
+        Ok(CompletedKeyExchange {
+            group: NamedGroup::X25519MLKEM768,
+            pub_key,
+            secret: combine(ss_kem, ss_x.as_bytes()),
+        })
+    }
+
+    fn name(&self) -> NamedGroup {
+        NamedGroup::X25519MLKEM768
+    }
+
+    fn usable_for_version(&self, version: ProtocolVersion) -> bool {
+        version == ProtocolVersion::TLSv1_3
+    }
+}
+
+/// Client side: ephemeral ML-KEM and X25519 keys awaiting the server's share
+struct ClientKeyExchange {
+    keys: HybridHandshake,
+    pub_key: Vec<u8>,
+}
+
+impl ActiveKeyExchange for ClientKeyExchange {
+    fn complete(self: Box<Self>, server_share: &[u8]) -> Result<SharedSecret, Error> {
+        if server_share.len() != MLKEM768_CIPHERTEXT_SIZE + X25519_KEY_SIZE {
+            return Err(invalid_share());
+        }
+        let (ciphertext, x25519_share) = server_share.split_at(MLKEM768_CIPHERTEXT_SIZE);
+        let mut ss_kem = self
+            .keys
+            .decapsulate(ciphertext)
+            .ok_or_else(invalid_share)?;
+        let ss_x = self
+            .keys
+            .x25519_secret()
+            .diffie_hellman(&peer_x25519(x25519_share)?);
+        if !ss_x.was_contributory() {
+            ss_kem.zeroize();
+            return Err(invalid_share());
+        }
+        Ok(combine(ss_kem, ss_x.as_bytes()))
+    }
+
+    /// Our X25519 share, also offered on its own so servers without the
+    /// hybrid group can answer without a HelloRetryRequest
+    fn hybrid_component(&self) -> Option<(NamedGroup, &[u8])> {
+        Some((
+            NamedGroup::X25519,
+            &self.pub_key[MLKEM768_ENCAPSULATION_KEY_SIZE..],
+        ))
+    }
+
+    fn complete_hybrid_component(
+        self: Box<Self>,
+        server_share: &[u8],
+    ) -> Result<SharedSecret, Error> {
+        let ss_x = self
+            .keys
+            .x25519_secret()
+            .diffie_hellman(&peer_x25519(server_share)?);
+        if !ss_x.was_contributory() {
+            return Err(invalid_share());
+        }
+        Ok(SharedSecret::from(&ss_x.as_bytes()[..]))
+    }
+
+    fn pub_key(&self) -> &[u8] {
+        &self.pub_key
+    }
+
+    fn group(&self) -> NamedGroup {
+        NamedGroup::X25519MLKEM768
+    }
+}
+
+fn peer_x25519(share: &[u8]) -> Result<PublicKey, Error> {
+    let share: [u8; X25519_KEY_SIZE] = share.try_into().map_err(|_| invalid_share())?;
+    Ok(PublicKey::from(share))
+}
+
+/// ML-KEM secret followed by the X25519 secret
+fn combine(mut ss_kem: [u8; 32], ss_x: &[u8; 32]) -> SharedSecret {
+    let mut secret = Vec::with_capacity(64);
+    secret.extend_from_slice(&ss_kem);
+    secret.extend_from_slice(ss_x);
+    ss_kem.zeroize();
+    SharedSecret::from(secret)
+}
+
+fn invalid_share() -> Error {
+    Error::PeerMisbehaved(PeerMisbehaved::InvalidKeyShare)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hybrid_key_exchange_agrees() {
+        let client = X25519_MLKEM768.start().expect("start in test");
+        assert_eq!(
+            client.pub_key().len(),
+            MLKEM768_ENCAPSULATION_KEY_SIZE + X25519_KEY_SIZE
+        );
+        let server = X25519_MLKEM768
+            .start_and_complete(client.pub_key())
+            .expect("server complete in test");
+        assert_eq!(
+            server.pub_key.len(),
+            MLKEM768_CIPHERTEXT_SIZE + X25519_KEY_SIZE
+        );
+
+        let client_secret = client.complete(&server.pub_key).expect("complete in test");
+        assert_eq!(client_secret.secret_bytes().len(), 64);
+        assert_eq!(client_secret.secret_bytes(), server.secret.secret_bytes());
+
+        // Truncated shares and all-zero X25519 keys are refused
+        let client = X25519_MLKEM768.start().expect("start in test");
+        assert!(X25519_MLKEM768
+            .start_and_complete(&client.pub_key()[1..])
+            .is_err());
+        let mut low_order = client.pub_key().to_vec();
+        low_order[MLKEM768_ENCAPSULATION_KEY_SIZE..].fill(0);
+        assert!(X25519_MLKEM768.start_and_complete(&low_order).is_err());
+        assert!(client.complete(&server.pub_key[..100]).is_err());
+    }
+}
@@ -4,9 +4,80 @@
 // LinkedIn: https://www.linkedin.com/in/thor-thor0
 // License: MIT (see LICENSE file for details)
 
+use std::sync::{Arc, Weak};
 use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+use tokio::sync::mpsc::error::TryRecvError;
 use tokio::time::{interval, MissedTickBehavior};
 
+use cryprq_core::MSG_TYPE_PADDING;
+
+use crate::padding::PaddingPolicy;
+use crate::pmtu;
+use crate::record_layer::CONTROL_STREAM_ID;
+use crate::stats::{record_cover_bytes, CoverBytes};
+use crate::stream_transport::is_droppable;
+use crate::{Tunnel, TunnelError};
+
+/// Smallest cover record plaintext: a fragment header, one byte and the trailer
+const MIN_COVER_RECORD_SIZE: usize = 16;
+
+/// Constant-rate cover traffic settings (opt-in, Section 9.3)
+#[derive(Debug, Clone)]
+pub struct CoverTrafficConfig {
+    /// Records sent per second, whether or not there is data
+    pub packets_per_second: f64,
+    /// Plaintext size of every record; larger payloads are fragmented
+    pub record_size: usize,
+    /// Random delay added to each slot (milliseconds, below the slot interval)
+    pub max_jitter_ms: u64,
+    /// Records waiting for a slot before senders are held back
+    pub queue_len: usize,
+}
+
+impl Default for CoverTrafficConfig {
+    fn default() -> Self {
+        Self {
+            packets_per_second: 50.0,
+            record_size: pmtu::BASE_PLPMTU as usize - pmtu::RECORD_OVERHEAD,
+            max_jitter_ms: 5,
+            queue_len: 256,
+        }
+    }
+}
+
+impl CoverTrafficConfig {
+    fn validate(&self) -> Result<(), TunnelError> {
+        let max_record = pmtu::BASE_PLPMTU as usize - pmtu::RECORD_OVERHEAD;
+        if !(self.packets_per_second > 0.0 && self.packets_per_second <= 1000.0) {
+            return Err(TunnelError::InvalidConfig(format!(
+                "cover traffic rate {} outside (0, 1000] packets per second",
+                self.packets_per_second
+            )));
+        }
+        if !(MIN_COVER_RECORD_SIZE..=max_record).contains(&self.record_size) {
+            return Err(TunnelError::InvalidConfig(format!(
+                "cover record size {} outside {}..={}",
+                self.record_size, MIN_COVER_RECORD_SIZE, max_record
+            )));
+        }
+        if self.queue_len == 0 {
+            return Err(TunnelError::InvalidConfig(
+                "cover queue length must be positive".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Sender side of a running cover schedule: sealed records awaiting a slot
+#[derive(Clone)]
+pub(crate) struct CoverQueue {
+    /// Sealed records, each with whether a backed-up stream may drop it
+    tx: mpsc::Sender<(Vec<u8>, bool)>,
+    record_size: usize,
+}
+
 /// Traffic shaper for constant-rate traffic generation
 pub struct TrafficShaper {
     /// Target packets per second
@@ -24,8 +95,7 @@ impl TrafficShaper {
     ///
     /// * `packets_per_second` - Target packet rate
     pub fn new(packets_per_second: f64) -> Self {
-        let interval_ms = (1000.0 / packets_per_second) as u64;
-        let mut interval = interval(Duration::from_millis(interval_ms));
+        let mut interval = interval(Duration::from_secs_f64(1.0 / packets_per_second));
         interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
 
         Self {
@@ -63,6 +133,139 @@ impl TrafficShaper {
     }
 }
 
+impl Tunnel {
+    /// Send fixed-size records at a fixed rate to hide traffic patterns
+    ///
+    /// Every record is padded to `record_size` and sent in the next slot of
+    /// a `packets_per_second` schedule (plus jitter). Slots without data
+    /// carry a PADDING record, which the peer discards. Replaces a running
+    /// schedule; see `stop_cover_traffic`. Overhead is counted in
+    /// `stats::cover_bytes`.
+    pub fn start_cover_traffic(
+        self: &Arc<Self>,
+        config: CoverTrafficConfig,
+    ) -> Result<(), TunnelError> {
+        config.validate()?;
+        let (tx, rx) = mpsc::channel(config.queue_len);
+        *self
+            .cover
+            .write()
+            .map_err(|e| TunnelError::LockPoisoned(e.to_string()))? = Some(CoverQueue {
+            tx,
+            record_size: config.record_size,
+        });
+        log::info!(
+            "event=cover_traffic status=started pps={} record_size={}",
+            config.packets_per_second,
+            config.record_size
+        );
+        tokio::spawn(cover_loop(Arc::downgrade(self), rx, config));
+        Ok(())
+    }
+
+    /// Stop cover traffic once the records already queued have been sent
+    pub fn stop_cover_traffic(&self) -> Result<(), TunnelError> {
+        let stopped = self
+            .cover
+            .write()
+            .map_err(|e| TunnelError::LockPoisoned(e.to_string()))?
+            .take();
+        if stopped.is_some() {
+            log::info!("event=cover_traffic status=stopped");
+        }
+        Ok(())
+    }
+
+    pub(crate) fn cover_queue(&self) -> Result<Option<CoverQueue>, TunnelError> {
+        Ok(self
+            .cover
+            .read()
+            .map_err(|e| TunnelError::LockPoisoned(e.to_string()))?
+            .clone())
+    }
+
+    /// Seal a payload into cover-sized records and wait for queue space
+    pub(crate) async fn queue_cover_record(
+        &self,
+        cover: &CoverQueue,
+        stream_id: u32,
+        message_type: u8,
+        flags: u8,
+        payload: &[u8],
+    ) -> Result<(), TunnelError> {
+        let records = self.seal_records(
+            stream_id,
+            message_type,
+            flags,
+            payload,
+            &PaddingPolicy::Mtu,
+            cover.record_size,
+        )?;
+        let wire_bytes: usize = records.iter().map(Vec::len).sum();
+        record_cover_bytes(CoverBytes::Data, payload.len() as u64);
+        record_cover_bytes(
+            CoverBytes::Padding,
+            wire_bytes.saturating_sub(payload.len()) as u64,
+        );
+        let droppable = is_droppable(message_type);
+        for record in records {
+            cover
+                .tx
+                .send((record, droppable))
+                .await
+                .map_err(|_| TunnelError::SessionClosed)?;
+        }
+        Ok(())
+    }
+
+    /// Sealed PADDING record filling an empty slot
+    fn seal_cover_dummy(&self, record_size: usize) -> Result<Vec<u8>, TunnelError> {
+        self.seal_records(
+            CONTROL_STREAM_ID,
+            MSG_TYPE_PADDING,
+            0,
+            &[],
+            &PaddingPolicy::Mtu,
+            record_size,
+        )?
+        .pop()
+        .ok_or(TunnelError::EncryptionFailed)
+    }
+}
+
+/// Send one record per slot until the schedule is stopped or the tunnel dropped
+async fn cover_loop(
+    tunnel: Weak<Tunnel>,
+    mut rx: mpsc::Receiver<(Vec<u8>, bool)>,
+    config: CoverTrafficConfig,
+) {
+    let mut shaper = TrafficShaper::new(config.packets_per_second);
+    loop {
+        shaper.wait_for_slot().await;
+        tokio::time::sleep(shaper.jitter_delay(config.max_jitter_ms)).await;
+        let Some(tunnel) = tunnel.upgrade().filter(|tunnel| !tunnel.is_closed()) else {
+            return;
+        };
+        let (record, droppable) = match rx.try_recv() {
+            Ok(queued) => queued,
+            Err(TryRecvError::Empty) => match tunnel.seal_cover_dummy(config.record_size) {
+                Ok(record) => {
+                    record_cover_bytes(CoverBytes::Dummy, record.len() as u64);
+                    (record, true)
+                }
+                Err(e) => {
+                    log::error!("event=cover_traffic status=stopped error={}", e);
+                    return;
+                }
+            },
+            Err(TryRecvError::Disconnected) => return,
+        };
+        if let Err(e) = tunnel.send_sealed(&record, droppable).await {
+            log::debug!("event=cover_traffic status=send_failed error={}", e);
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -73,6 +276,19 @@ mod tests {
         assert_eq!(shaper.pps, 10.0);
     }
 
+    #[tokio::test]
+    async fn test_shaper_holds_rate_between_whole_milliseconds() {
+        // 300 pps is a 3.33 ms slot; whole milliseconds would give 333 pps
+        let mut shaper = TrafficShaper::new(300.0);
+        shaper.wait_for_slot().await;
+        let start = Instant::now();
+        for _ in 0..60 {
+            shaper.wait_for_slot().await;
+        }
+        let rate = 60.0 / start.elapsed().as_secs_f64();
+        assert!((250.0..=301.0).contains(&rate), "achieved {rate} pps");
+    }
+
     #[tokio::test]
     async fn test_should_send() {
         let mut shaper = TrafficShaper::new(1.0);
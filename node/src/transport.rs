@@ -0,0 +1,182 @@
+// Copyright (c) 2025 Thor Thor
+// Author: Thor Thor (GitHub: https://github.com/codethor0)
+// Contact: codethor@gmail.com
+// LinkedIn: https://www.linkedin.com/in/thor-thor0
+// License: MIT (see LICENSE file for details)
+
+use std::io;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use rand::Rng;
+use zeroize::Zeroize;
+
+/// Obfuscation layer between the record layer and the UDP socket (Section 3.5)
+///
+/// A transport rewrites every datagram the tunnel sends and reverses the
+/// rewrite on receipt, so that on-path observers do not see CrypRQ's record
+/// header. Both peers must use matching transports. Datagrams that fail
+/// `deobfuscate` are dropped before the record layer sees them.
+pub trait PluggableTransport: Send + Sync {
+    /// Short name used in logs
+    fn name(&self) -> &'static str;
+
+    /// Wrap an outgoing datagram for `peer`
+    fn obfuscate(&self, peer: SocketAddr, datagram: &[u8]) -> io::Result<Vec<u8>>;
+
+    /// Recover the datagram `peer` sent
+    fn deobfuscate(&self, peer: SocketAddr, datagram: &[u8]) -> io::Result<Vec<u8>>;
+}
+
+/// DTLS 1.2 application data record header: type, version, epoch, sequence, length
+const DTLS_HEADER_SIZE: usize = 13;
+const DTLS_APPLICATION_DATA: u8 = 0x17;
+const DTLS_1_2: [u8; 2] = [0xFE, 0xFD];
+/// Epoch of a DTLS session after its handshake
+const DTLS_EPOCH: [u8; 2] = [0x00, 0x01];
+/// Largest DTLS record body (2^14 plus expansion)
+const DTLS_MAX_BODY: usize = 16384 + 2048;
+const DTLS_SEQUENCE_MASK: u64 = (1 << 48) - 1;
+
+const CLIENT_MASK_CONTEXT: &str = "cryprq dtls mask v1 client to server";
+const SERVER_MASK_CONTEXT: &str = "cryprq dtls mask v1 server to client";
+
+/// Transport framing every datagram as a DTLS 1.2 application data record
+///
+/// The body is masked with a keystream derived from a secret both peers
+/// share and from the record's epoch and sequence number, so the wire shows
+/// a DTLS header followed by bytes that look like ciphertext. This changes
+/// how the traffic is classified; it does not replay a DTLS handshake.
+pub struct DtlsTransport {
+    send_key: [u8; 32],
+    recv_key: [u8; 32],
+    next_sequence: AtomicU64,
+}
+
+impl DtlsTransport {
+    /// Client side of a tunnel using `secret`
+    pub fn client(secret: &[u8; 32]) -> Self {
+        Self::new(secret, CLIENT_MASK_CONTEXT, SERVER_MASK_CONTEXT)
+    }
+
+    /// Server side of a tunnel using `secret`
+    pub fn server(secret: &[u8; 32]) -> Self {
+        Self::new(secret, SERVER_MASK_CONTEXT, CLIENT_MASK_CONTEXT)
+    }
+
+    fn new(secret: &[u8; 32], send_context: &str, recv_context: &str) -> Self {
+        // Start at a random sequence number like a session after a handshake
+        let start = rand::thread_rng().gen_range(1..1u64 << 16);
+        Self {
+            send_key: blake3::derive_key(send_context, secret),
+            recv_key: blake3::derive_key(recv_context, secret),
+            next_sequence: AtomicU64::new(start),
+        }
+    }
+}
+
+/// XOR `body` with the keystream for one record
+fn apply_mask(key: &[u8; 32], epoch_and_sequence: &[u8], body: &mut [u8]) {
+    let mut keystream = vec![0u8; body.len()];
+    blake3::Hasher::new_keyed(key)
+        .update(epoch_and_sequence)
+        .finalize_xof()
+        .fill(&mut keystream);
+    for (byte, mask) in body.iter_mut().zip(&keystream) {
+        *byte ^= mask;
+    }
+}
+
+fn invalid_record() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        "not a DTLS application data record",
+    )
+}
+
+impl PluggableTransport for DtlsTransport {
+    fn name(&self) -> &'static str {
+        "dtls"
+    }
+
+    fn obfuscate(&self, _peer: SocketAddr, datagram: &[u8]) -> io::Result<Vec<u8>> {
+        if datagram.len() > DTLS_MAX_BODY {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "datagram too large for a DTLS record",
+            ));
+        }
+        let sequence = self.next_sequence.fetch_add(1, Ordering::Relaxed) & DTLS_SEQUENCE_MASK;
+        let mut out = Vec::with_capacity(DTLS_HEADER_SIZE + datagram.len());
+        out.push(DTLS_APPLICATION_DATA);
+        out.extend_from_slice(&DTLS_1_2);
+        out.extend_from_slice(&DTLS_EPOCH);
+        out.extend_from_slice(&sequence.to_be_bytes()[2..]);
+        out.extend_from_slice(&(datagram.len() as u16).to_be_bytes());
+        out.extend_from_slice(datagram);
+        let (header, body) = out.split_at_mut(DTLS_HEADER_SIZE);
+        apply_mask(&self.send_key, &header[3..11], body);
+        Ok(out)
+    }
+
+    fn deobfuscate(&self, _peer: SocketAddr, datagram: &[u8]) -> io::Result<Vec<u8>> {
+        if datagram.len() < DTLS_HEADER_SIZE
+            || datagram[0] != DTLS_APPLICATION_DATA
+            || datagram[1..3] != DTLS_1_2
+        {
+            return Err(invalid_record());
+        }
+        let length = u16::from_be_bytes([datagram[11], datagram[12]]) as usize;
+        if length != datagram.len() - DTLS_HEADER_SIZE {
+            return Err(invalid_record());
+        }
+        let mut body = datagram[DTLS_HEADER_SIZE..].to_vec();
+        apply_mask(&self.recv_key, &datagram[3..11], &mut body);
+        Ok(body)
+    }
+}
+
+impl Drop for DtlsTransport {
+    fn drop(&mut self) {
+        self.send_key.zeroize();
+        self.recv_key.zeroize();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dtls_framing_roundtrip() {
+        let peer: SocketAddr = "192.0.2.1:443".parse().expect("addr in test");
+        let client = DtlsTransport::client(&[7; 32]);
+        let server = DtlsTransport::server(&[7; 32]);
+        let record = [0x01, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0xAA, 0xBB];
+
+        let first = client.obfuscate(peer, &record).expect("frame in test");
+        let second = client.obfuscate(peer, &record).expect("frame in test");
+        assert_eq!(first.len(), DTLS_HEADER_SIZE + record.len());
+        assert_eq!(first[..5], [0x17, 0xFE, 0xFD, 0x00, 0x01]);
+        assert_eq!(first[11..13], (record.len() as u16).to_be_bytes());
+        // The record header is masked differently in every datagram
+        assert_ne!(first[DTLS_HEADER_SIZE..], record);
+        assert_ne!(first[DTLS_HEADER_SIZE..], second[DTLS_HEADER_SIZE..]);
+
+        for framed in [&first, &second] {
+            assert_eq!(
+                server.deobfuscate(peer, framed).expect("unframe in test"),
+                record
+            );
+        }
+        let reply = server.obfuscate(peer, &record).expect("frame in test");
+        assert_eq!(
+            client.deobfuscate(peer, &reply).expect("unframe in test"),
+            record
+        );
+
+        // Non-DTLS and truncated datagrams are rejected
+        assert!(server.deobfuscate(peer, &record).is_err());
+        assert!(server.deobfuscate(peer, &first[..first.len() - 1]).is_err());
+    }
+}
@@ -6,7 +6,13 @@
 
 use anyhow::{Context, Result};
 use async_trait::async_trait;
+
+use crate::addr_pool::prefix_to_netmask;
+use crate::udp_batch::BATCH_SIZE;
+use cryprq_core::AddressLease;
 use std::io::{Read, Write};
+use std::net::Ipv4Addr;
+use std::os::fd::{AsRawFd, RawFd};
 use std::process::Command;
 use std::sync::Arc;
 
@@ -17,6 +23,12 @@ pub struct TunConfig {
     pub address: String,
     pub netmask: String,
     pub mtu: u16,
+    /// Extra routes sent through the interface (destination, prefix length)
+    pub routes: Vec<(Ipv4Addr, u8)>,
+    /// Create a layer 2 TAP device carrying Ethernet frames (Linux only)
+    ///
+    /// An empty `address` leaves the TAP unnumbered, e.g. for bridging.
+    pub tap: bool,
 }
 
 impl Default for TunConfig {
@@ -26,6 +38,22 @@ impl Default for TunConfig {
             address: "10.0.0.1".to_string(),
             netmask: "255.255.255.0".to_string(),
             mtu: 1420,
+            routes: Vec::new(),
+            tap: false,
+        }
+    }
+}
+
+impl TunConfig {
+    /// Build a TUN configuration from an address lease granted by the listener
+    pub fn from_lease(name: &str, lease: &AddressLease) -> Self {
+        Self {
+            name: name.to_string(),
+            address: lease.address.to_string(),
+            netmask: prefix_to_netmask(lease.prefix_len).to_string(),
+            mtu: lease.mtu,
+            routes: lease.routes.clone(),
+            tap: false,
         }
     }
 }
@@ -35,6 +63,15 @@ impl Default for TunConfig {
 pub trait PacketForwarder: Send + Sync {
     async fn send_packet(&self, packet: &[u8]) -> Result<()>;
     async fn recv_packet(&mut self) -> Result<Vec<u8>>;
+
+    /// Send packets read together from the TUN; forwarders that can batch
+    /// (e.g. `Tunnel`) override this
+    async fn send_packets(&self, packets: &[&[u8]]) -> Result<()> {
+        for packet in packets {
+            self.send_packet(packet).await?;
+        }
+        Ok(())
+    }
 }
 
 /// TUN interface handle
@@ -76,6 +113,12 @@ impl TunInterface {
     fn create_device(config: &TunConfig, name: &str) -> Result<tun::platform::macos::Device> {
         log::info!("Creating TUN interface {} for VPN mode", name);
 
+        if config.tap {
+            return Err(anyhow::anyhow!(
+                "TAP (layer 2) mode is only supported on Linux"
+            ));
+        }
+
         let mut config_builder = tun::Configuration::default();
         let addr: std::net::Ipv4Addr = config
             .address
@@ -102,24 +145,25 @@ impl TunInterface {
 
     #[cfg(target_os = "linux")]
     fn create_device(config: &TunConfig, name: &str) -> Result<tun::platform::linux::Device> {
-        log::info!("Creating TUN interface {} for VPN mode", name);
+        let kind = if config.tap { "TAP" } else { "TUN" };
+        log::info!("Creating {} interface {} for VPN mode", kind, name);
 
         let mut config_builder = tun::Configuration::default();
-        let addr: std::net::Ipv4Addr = config
-            .address
-            .parse()
-            .context("Invalid TUN address (must be IPv4)")?;
-        let netmask: std::net::Ipv4Addr = config
-            .netmask
-            .parse()
-            .context("Invalid netmask (must be IPv4)")?;
-
-        config_builder
-            .name(name)
-            .address(addr)
-            .netmask(netmask)
-            .mtu(config.mtu as i32)
-            .up();
+        config_builder.name(name).mtu(config.mtu as i32).up();
+        if config.tap {
+            config_builder.layer(tun::Layer::L2);
+        }
+        if !(config.tap && config.address.is_empty()) {
+            let addr: std::net::Ipv4Addr = config
+                .address
+                .parse()
+                .context("Invalid TUN address (must be IPv4)")?;
+            let netmask: std::net::Ipv4Addr = config
+                .netmask
+                .parse()
+                .context("Invalid netmask (must be IPv4)")?;
+            config_builder.address(addr).netmask(netmask);
+        }
 
         let device = tun::platform::linux::create(&config_builder)
             .context("Failed to create TUN device (requires root/admin privileges)")?;
@@ -133,19 +177,63 @@ impl TunInterface {
         &self.interface_name
     }
 
+    /// Configured interface MTU
+    pub fn mtu(&self) -> u16 {
+        self.config.mtu
+    }
+
     /// Configure the interface IP address (requires root/admin)
     pub async fn configure_ip(&self) -> Result<()> {
-        #[cfg(target_os = "macos")]
-        {
-            self.configure_ip_macos().await
+        if self.config.address.is_empty() {
+            // Unnumbered TAP: the device is already up, nothing to assign
+            return Ok(());
         }
+        #[cfg(target_os = "macos")]
+        self.configure_ip_macos().await?;
         #[cfg(target_os = "linux")]
-        {
-            self.configure_ip_linux().await
-        }
-        #[cfg(not(any(target_os = "macos", target_os = "linux")))]
-        {
-            Ok(())
+        self.configure_ip_linux().await?;
+
+        self.install_routes();
+        Ok(())
+    }
+
+    /// Install the configured extra routes through this interface
+    ///
+    /// Failures are logged rather than returned: a route that already exists
+    /// must not prevent the tunnel from coming up.
+    fn install_routes(&self) {
+        for (dest, prefix_len) in &self.config.routes {
+            let cidr = format!("{}/{}", dest, prefix_len);
+            #[cfg(target_os = "linux")]
+            let args = ["ip", "route", "add", &cidr, "dev", self.name()];
+            #[cfg(target_os = "macos")]
+            let args = [
+                "route",
+                "-n",
+                "add",
+                "-net",
+                &cidr,
+                "-interface",
+                self.name(),
+            ];
+            #[cfg(not(any(target_os = "macos", target_os = "linux")))]
+            {
+                log::warn!("Route {} not installed: unsupported platform", cidr);
+                continue;
+            }
+
+            #[cfg(any(target_os = "macos", target_os = "linux"))]
+            match Command::new("sudo").args(args).output() {
+                Ok(output) if output.status.success() => {
+                    log::info!("Added route {} via {}", cidr, self.name());
+                }
+                Ok(output) => log::warn!(
+                    "Failed to add route {}: {}",
+                    cidr,
+                    String::from_utf8_lossy(&output.stderr)
+                ),
+                Err(e) => log::warn!("Failed to add route {}: {}", cidr, e),
+            }
         }
     }
 
@@ -264,13 +352,11 @@ impl TunInterface {
         let forwarder_read = forwarder.clone();
         let forwarder_write = forwarder.clone();
 
-        // Spawn task to read from TUN and send via forwarder
+        // Spawn task to read from TUN and send via forwarder, a batch at a time
         let tun_read_task = tokio::spawn(async move {
-            let buf = vec![0u8; 65535];
             loop {
-                let n = match tokio::task::spawn_blocking({
+                let packets = match tokio::task::spawn_blocking({
                     let dev = device_read.clone();
-                    let mut buf_clone = buf.clone();
                     move || {
                         let mut dev_guard = dev.lock().map_err(|e| {
                             std::io::Error::new(
@@ -278,12 +364,12 @@ impl TunInterface {
                                 format!("Mutex lock failed: {}", e),
                             )
                         })?;
-                        dev_guard.read(&mut buf_clone)
+                        read_batch(&mut *dev_guard)
                     }
                 })
                 .await
                 {
-                    Ok(Ok(n)) => n,
+                    Ok(Ok(packets)) => packets,
                     Ok(Err(e)) => {
                         log::error!("Error reading from TUN: {}", e);
                         break;
@@ -294,17 +380,20 @@ impl TunInterface {
                     }
                 };
 
-                if n > 0 {
-                    let packet = buf[..n].to_vec();
-                    log::debug!("Read {} bytes from TUN, encrypting and forwarding", n);
-
-                    // Send via forwarder
-                    let fwd = forwarder_read.lock().await;
-                    if let Err(e) = fwd.send_packet(&packet).await {
-                        log::error!("Failed to forward packet: {}", e);
-                    }
-                } else {
+                if packets.is_empty() {
                     tokio::time::sleep(tokio::time::Duration::from_millis(10)).await;
+                    continue;
+                }
+                log::debug!(
+                    "Read {} packets from TUN, encrypting and forwarding",
+                    packets.len()
+                );
+
+                // Send via forwarder
+                let packets: Vec<&[u8]> = packets.iter().map(Vec::as_slice).collect();
+                let fwd = forwarder_read.lock().await;
+                if let Err(e) = fwd.send_packets(&packets).await {
+                    log::error!("Failed to forward packets: {}", e);
                 }
             }
         });
@@ -363,3 +452,75 @@ impl TunInterface {
         Ok(())
     }
 }
+
+/// Read one packet, waiting for it, then whatever else the TUN already has
+/// queued, up to `BATCH_SIZE` packets
+fn read_batch<D: Read + AsRawFd>(device: &mut D) -> std::io::Result<Vec<Vec<u8>>> {
+    let mut buf = vec![0u8; 65535];
+    let mut packets = Vec::new();
+    loop {
+        let n = device.read(&mut buf)?;
+        if n == 0 {
+            return Ok(packets);
+        }
+        packets.push(buf[..n].to_vec());
+        if packets.len() >= BATCH_SIZE || !readable(device.as_raw_fd()) {
+            return Ok(packets);
+        }
+    }
+}
+
+/// Whether a read on `fd` would return without blocking
+#[cfg(target_os = "linux")]
+fn readable(fd: RawFd) -> bool {
+    let mut pollfd = libc::pollfd {
+        fd,
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    // SAFETY: one valid pollfd, and a zero timeout never blocks
+    let ready = unsafe { libc::poll(&mut pollfd, 1, 0) };
+    ready > 0 && pollfd.revents & libc::POLLIN != 0
+}
+
+#[cfg(not(target_os = "linux"))]
+fn readable(_fd: RawFd) -> bool {
+    false
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+    use std::os::unix::net::UnixDatagram;
+
+    /// Datagram socket standing in for the TUN device
+    struct Packets(UnixDatagram);
+
+    impl Read for Packets {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.0.recv(buf)
+        }
+    }
+
+    impl AsRawFd for Packets {
+        fn as_raw_fd(&self) -> RawFd {
+            self.0.as_raw_fd()
+        }
+    }
+
+    #[test]
+    fn test_read_batch_drains_queued_packets() {
+        let (tx, rx) = UnixDatagram::pair().expect("socket pair in test");
+        let mut device = Packets(rx);
+        for i in 0..BATCH_SIZE + 3 {
+            tx.send(&[i as u8; 20]).expect("send in test");
+        }
+
+        let first = read_batch(&mut device).expect("read in test");
+        assert_eq!(first.len(), BATCH_SIZE);
+        assert_eq!(first[1], vec![1u8; 20]);
+        let rest = read_batch(&mut device).expect("read in test");
+        assert_eq!(rest.len(), 3);
+        assert_eq!(rest[0], vec![BATCH_SIZE as u8; 20]);
+    }
+}
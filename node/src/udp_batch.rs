@@ -0,0 +1,597 @@
+// Copyright (c) 2025 Thor Thor
+// Author: Thor Thor (GitHub: https://github.com/codethor0)
+// Contact: codethor@gmail.com
+// LinkedIn: https://www.linkedin.com/in/thor-thor0
+// License: MIT (see LICENSE file for details)
+
+use std::collections::VecDeque;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use bytes::BytesMut;
+use tokio::net::UdpSocket;
+
+use crate::stats::{record_drop, DropReason};
+use crate::{BufferPool, BUFFER_SIZE};
+
+/// Datagrams (or GSO/GRO messages) moved per syscall
+pub(crate) const BATCH_SIZE: usize = 16;
+
+/// Most segments the kernel accepts in one UDP_SEGMENT send
+#[cfg(target_os = "linux")]
+const MAX_GSO_SEGMENTS: usize = 64;
+
+/// Largest UDP payload over IPv4, which also bounds one GSO send
+#[cfg(target_os = "linux")]
+const MAX_GSO_BYTES: usize = 65_507;
+
+/// Room for one UDP_SEGMENT or UDP_GRO control message
+#[cfg(target_os = "linux")]
+const CONTROL_LEN: usize = 32;
+
+/// Batched datagram I/O on the tunnel's socket
+///
+/// On Linux, receives use `recvmmsg` with UDP_GRO and sends use `sendmmsg`
+/// with UDP_SEGMENT, so one syscall moves many datagrams. GSO is turned
+/// off for good if the kernel or NIC rejects it. Elsewhere, or with
+/// batching disabled, each datagram takes its own `recv_from`/`send_to`.
+pub(crate) struct UdpBatch {
+    enabled: AtomicBool,
+    gso: AtomicBool,
+    recv: tokio::sync::Mutex<RecvQueue>,
+}
+
+impl UdpBatch {
+    pub(crate) fn new(socket: &UdpSocket) -> Self {
+        let gso = gso_supported(socket);
+        let batch = Self {
+            enabled: AtomicBool::new(true),
+            gso: AtomicBool::new(gso),
+            recv: tokio::sync::Mutex::new(RecvQueue::default()),
+        };
+        batch.set_enabled(socket, true);
+        log::debug!("event=udp_batch_ready gso={}", gso);
+        batch
+    }
+
+    /// Switch between batched and per-datagram I/O
+    ///
+    /// GRO is only left on while batching, since a plain `recv_from` cannot
+    /// tell where coalesced datagrams begin.
+    pub(crate) fn set_enabled(&self, socket: &UdpSocket, enabled: bool) {
+        if let Err(e) = set_gro(socket, enabled) {
+            if enabled {
+                log::debug!("event=udp_gro_unavailable error={}", e);
+            }
+        }
+        self.enabled.store(enabled, Ordering::Relaxed);
+    }
+
+    /// Receive at least one datagram, appending everything the last batch
+    /// read to `out` in arrival order
+    pub(crate) async fn recv_batch(
+        &self,
+        socket: &UdpSocket,
+        pool: &BufferPool,
+        out: &mut Vec<(BytesMut, SocketAddr)>,
+    ) -> io::Result<()> {
+        let mut queue = self.recv.lock().await;
+        while queue.ready.is_empty() {
+            if !self.enabled.load(Ordering::Relaxed) {
+                let mut buf = pool.get();
+                buf.resize(BUFFER_SIZE, 0);
+                let (len, addr) = socket.recv_from(&mut buf).await?;
+                buf.truncate(len);
+                out.push((buf, addr));
+                return Ok(());
+            }
+            queue.fill(socket).await?;
+        }
+        queue.drain(pool, out);
+        Ok(())
+    }
+
+    /// Send `datagrams` to `addr` in order, batching them when possible
+    pub(crate) async fn send_to(
+        &self,
+        socket: &UdpSocket,
+        datagrams: &[&[u8]],
+        addr: SocketAddr,
+    ) -> io::Result<()> {
+        if datagrams.len() > 1 && self.enabled.load(Ordering::Relaxed) {
+            return self.send_batch(socket, datagrams, addr).await;
+        }
+        for datagram in datagrams {
+            socket.send_to(datagram, addr).await?;
+        }
+        Ok(())
+    }
+
+    #[cfg(target_os = "linux")]
+    async fn send_batch(
+        &self,
+        socket: &UdpSocket,
+        datagrams: &[&[u8]],
+        addr: SocketAddr,
+    ) -> io::Result<()> {
+        use std::os::fd::AsRawFd;
+        use tokio::io::Interest;
+
+        let addr = socket2::SockAddr::from(addr);
+        let mut sent = 0;
+        while sent < datagrams.len() {
+            let gso = self.gso.load(Ordering::Relaxed);
+            let result = socket
+                .async_io(Interest::WRITABLE, || {
+                    send_mmsg(socket.as_raw_fd(), &datagrams[sent..], &addr, gso)
+                })
+                .await;
+            match result {
+                Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                Ok(count) => sent += count,
+                Err(e) if gso && gso_rejected(&e) => {
+                    log::warn!("event=udp_gso_disabled error={}", e);
+                    self.gso.store(false, Ordering::Relaxed);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    async fn send_batch(
+        &self,
+        socket: &UdpSocket,
+        datagrams: &[&[u8]],
+        addr: SocketAddr,
+    ) -> io::Result<()> {
+        for datagram in datagrams {
+            socket.send_to(datagram, addr).await?;
+        }
+        Ok(())
+    }
+}
+
+/// Datagrams read by the last batch, waiting to be handed out
+#[derive(Default)]
+struct RecvQueue {
+    bufs: Vec<Vec<u8>>,
+    ready: VecDeque<Received>,
+}
+
+/// One datagram within a receive buffer
+struct Received {
+    slot: usize,
+    start: usize,
+    len: usize,
+    addr: SocketAddr,
+}
+
+impl RecvQueue {
+    fn drain(&mut self, pool: &BufferPool, out: &mut Vec<(BytesMut, SocketAddr)>) {
+        for received in self.ready.drain(..) {
+            let start = received.start;
+            let mut buf = pool.get();
+            buf.extend_from_slice(&self.bufs[received.slot][start..start + received.len]);
+            out.push((buf, received.addr));
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    async fn fill(&mut self, socket: &UdpSocket) -> io::Result<()> {
+        use std::os::fd::AsRawFd;
+        use tokio::io::Interest;
+
+        if self.bufs.is_empty() {
+            self.bufs = vec![vec![0u8; BUFFER_SIZE]; BATCH_SIZE];
+        }
+        let Self { bufs, ready } = self;
+        socket
+            .async_io(Interest::READABLE, || {
+                recv_mmsg(socket.as_raw_fd(), bufs, ready)
+            })
+            .await
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    async fn fill(&mut self, socket: &UdpSocket) -> io::Result<()> {
+        if self.bufs.is_empty() {
+            self.bufs = vec![vec![0u8; BUFFER_SIZE]];
+        }
+        let (len, addr) = socket.recv_from(&mut self.bufs[0]).await?;
+        self.ready.push_back(Received {
+            slot: 0,
+            start: 0,
+            len,
+            addr,
+        });
+        Ok(())
+    }
+}
+
+/// Control message buffer, aligned for `cmsghdr`
+#[cfg(target_os = "linux")]
+#[derive(Clone, Copy)]
+#[repr(C, align(8))]
+struct Control([u8; CONTROL_LEN]);
+
+#[cfg(target_os = "linux")]
+fn recv_mmsg(
+    fd: std::os::fd::RawFd,
+    bufs: &mut [Vec<u8>],
+    ready: &mut VecDeque<Received>,
+) -> io::Result<()> {
+    use std::{mem, ptr};
+
+    let count = bufs.len();
+    // SAFETY: all-zero bytes are a valid sockaddr_storage and mmsghdr
+    let mut names: Vec<libc::sockaddr_storage> =
+        (0..count).map(|_| unsafe { mem::zeroed() }).collect();
+    let mut hdrs: Vec<libc::mmsghdr> = (0..count).map(|_| unsafe { mem::zeroed() }).collect();
+    let mut controls = vec![Control([0; CONTROL_LEN]); count];
+    let mut iovecs: Vec<libc::iovec> = bufs
+        .iter_mut()
+        .map(|buf| libc::iovec {
+            iov_base: buf.as_mut_ptr() as *mut libc::c_void,
+            iov_len: buf.len(),
+        })
+        .collect();
+    for (i, hdr) in hdrs.iter_mut().enumerate() {
+        let msg = &mut hdr.msg_hdr;
+        msg.msg_name = &mut names[i] as *mut libc::sockaddr_storage as *mut libc::c_void;
+        msg.msg_namelen = mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+        msg.msg_iov = &mut iovecs[i];
+        msg.msg_iovlen = 1;
+        msg.msg_control = controls[i].0.as_mut_ptr() as *mut libc::c_void;
+        msg.msg_controllen = CONTROL_LEN as _;
+    }
+
+    // SAFETY: each header points at a live buffer, address and control
+    // area of the sizes it states, all outliving the call
+    let received = unsafe {
+        libc::recvmmsg(
+            fd,
+            hdrs.as_mut_ptr(),
+            count as libc::c_uint,
+            libc::MSG_DONTWAIT,
+            ptr::null_mut(),
+        )
+    };
+    if received < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    for (slot, hdr) in hdrs.iter().take(received as usize).enumerate() {
+        // SAFETY: the kernel wrote a socket address of msg_namelen bytes
+        let addr = unsafe { socket2::SockAddr::new(names[slot], hdr.msg_hdr.msg_namelen) };
+        let Some(addr) = addr.as_socket() else {
+            continue;
+        };
+        // Larger than the buffer: the rest is gone, so the record cannot open
+        if hdr.msg_hdr.msg_flags & libc::MSG_TRUNC != 0 {
+            record_drop(DropReason::Truncated);
+            continue;
+        }
+        let len = hdr.msg_len as usize;
+        // With GRO, one message holds several datagrams of `segment` bytes
+        // (the last may be shorter)
+        let segment = match gro_segment(&hdr.msg_hdr) {
+            Some(segment) if segment > 0 => segment,
+            _ => len.max(1),
+        };
+        let mut start = 0;
+        loop {
+            ready.push_back(Received {
+                slot,
+                start,
+                len: segment.min(len - start),
+                addr,
+            });
+            start += segment;
+            if start >= len {
+                break;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Errors with which the kernel or NIC refuses UDP_SEGMENT sends; others
+/// (e.g. EMSGSIZE, ENOBUFS, ECONNREFUSED) are not GSO's fault
+#[cfg(target_os = "linux")]
+fn gso_rejected(e: &io::Error) -> bool {
+    matches!(
+        e.raw_os_error(),
+        Some(libc::EIO | libc::EINVAL | libc::EOPNOTSUPP)
+    )
+}
+
+/// Segment size from a UDP_GRO control message, if the kernel coalesced
+#[cfg(target_os = "linux")]
+fn gro_segment(msg: &libc::msghdr) -> Option<usize> {
+    // SAFETY: the control area was filled in by recvmmsg and is bounded
+    // by msg_controllen, which the CMSG macros respect
+    unsafe {
+        let mut cmsg = libc::CMSG_FIRSTHDR(msg);
+        while !cmsg.is_null() {
+            if (*cmsg).cmsg_level == libc::SOL_UDP && (*cmsg).cmsg_type == libc::UDP_GRO {
+                let segment = std::ptr::read_unaligned(libc::CMSG_DATA(cmsg) as *const libc::c_int);
+                return usize::try_from(segment).ok();
+            }
+            cmsg = libc::CMSG_NXTHDR(msg, cmsg);
+        }
+    }
+    None
+}
+
+/// Send a prefix of `datagrams` with one `sendmmsg`, returning how many
+/// datagrams went out
+#[cfg(target_os = "linux")]
+fn send_mmsg(
+    fd: std::os::fd::RawFd,
+    datagrams: &[&[u8]],
+    addr: &socket2::SockAddr,
+    gso: bool,
+) -> io::Result<usize> {
+    use std::{mem, ptr};
+
+    // Each message is a run of datagrams: (first, count, segment size)
+    let mut messages = Vec::with_capacity(BATCH_SIZE);
+    let mut next = 0;
+    while next < datagrams.len() && messages.len() < BATCH_SIZE {
+        let (count, segment) = match gso {
+            true => gso_run(&datagrams[next..]),
+            false => (1, datagrams[next].len()),
+        };
+        messages.push((next, count, segment));
+        next += count;
+    }
+
+    let mut iovecs: Vec<libc::iovec> = datagrams[..next]
+        .iter()
+        .map(|datagram| libc::iovec {
+            iov_base: datagram.as_ptr() as *mut libc::c_void,
+            iov_len: datagram.len(),
+        })
+        .collect();
+    let mut controls = vec![Control([0; CONTROL_LEN]); messages.len()];
+    // SAFETY: all-zero bytes are a valid mmsghdr
+    let mut hdrs: Vec<libc::mmsghdr> = (0..messages.len())
+        .map(|_| unsafe { mem::zeroed() })
+        .collect();
+    for (i, &(first, count, segment)) in messages.iter().enumerate() {
+        let msg = &mut hdrs[i].msg_hdr;
+        msg.msg_name = addr.as_ptr() as *mut libc::c_void;
+        msg.msg_namelen = addr.len();
+        msg.msg_iov = iovecs[first..].as_mut_ptr();
+        msg.msg_iovlen = count as _;
+        if count > 1 {
+            msg.msg_control = controls[i].0.as_mut_ptr() as *mut libc::c_void;
+            // SAFETY: the control area is CONTROL_LEN bytes, enough for
+            // one cmsghdr carrying a u16
+            unsafe {
+                msg.msg_controllen = libc::CMSG_SPACE(mem::size_of::<u16>() as u32) as _;
+                let cmsg = libc::CMSG_FIRSTHDR(msg);
+                (*cmsg).cmsg_level = libc::SOL_UDP;
+                (*cmsg).cmsg_type = libc::UDP_SEGMENT;
+                (*cmsg).cmsg_len = libc::CMSG_LEN(mem::size_of::<u16>() as u32) as _;
+                ptr::write_unaligned(libc::CMSG_DATA(cmsg) as *mut u16, segment as u16);
+            }
+        }
+    }
+
+    // SAFETY: each header points at live iovecs, the address and its
+    // control area, all outliving the call; the kernel only reads them
+    let sent = unsafe {
+        libc::sendmmsg(
+            fd,
+            hdrs.as_mut_ptr(),
+            hdrs.len() as libc::c_uint,
+            libc::MSG_DONTWAIT,
+        )
+    };
+    if sent < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(messages[..sent as usize]
+        .iter()
+        .map(|&(_, count, _)| count)
+        .sum())
+}
+
+/// Leading datagrams that can share one GSO send: all the size of the
+/// first, except that the last may be shorter
+#[cfg(target_os = "linux")]
+fn gso_run(datagrams: &[&[u8]]) -> (usize, usize) {
+    let segment = datagrams[0].len();
+    let mut count = 1;
+    let mut total = segment;
+    for datagram in &datagrams[1..] {
+        if count == MAX_GSO_SEGMENTS
+            || datagram.len() > segment
+            || total + datagram.len() > MAX_GSO_BYTES
+        {
+            break;
+        }
+        count += 1;
+        total += datagram.len();
+        if datagram.len() < segment {
+            break;
+        }
+    }
+    (count, segment)
+}
+
+/// Whether the kernel supports UDP_SEGMENT (Linux 4.18+)
+#[cfg(target_os = "linux")]
+fn gso_supported(socket: &UdpSocket) -> bool {
+    use std::os::fd::AsRawFd;
+
+    let mut value: libc::c_int = 0;
+    let mut len = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+    // SAFETY: the fd is a live socket owned by `socket`, and the option
+    // value is a c_int whose size is passed alongside it
+    let rc = unsafe {
+        libc::getsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_UDP,
+            libc::UDP_SEGMENT,
+            &mut value as *mut libc::c_int as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    rc == 0
+}
+
+#[cfg(not(target_os = "linux"))]
+fn gso_supported(_socket: &UdpSocket) -> bool {
+    false
+}
+
+/// Let the kernel coalesce incoming datagrams (UDP_GRO, Linux 5.0+)
+#[cfg(target_os = "linux")]
+fn set_gro(socket: &UdpSocket, enabled: bool) -> io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    let value = libc::c_int::from(enabled);
+    // SAFETY: the fd is a live socket owned by `socket`, and the option
+    // value is a c_int whose size is passed alongside it
+    let rc = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_UDP,
+            libc::UDP_GRO,
+            &value as *const libc::c_int as *const libc::c_void,
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if rc != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn set_gro(_socket: &UdpSocket, _enabled: bool) -> io::Result<()> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    async fn pair() -> (UdpSocket, UdpSocket, SocketAddr) {
+        let sender = UdpSocket::bind("127.0.0.1:0")
+            .await
+            .expect("bind sender in test");
+        let receiver = UdpSocket::bind("127.0.0.1:0")
+            .await
+            .expect("bind receiver in test");
+        let addr = receiver.local_addr().expect("receiver addr in test");
+        (sender, receiver, addr)
+    }
+
+    /// Equal-size runs (GSO candidates) broken up by other sizes
+    fn datagrams() -> Vec<Vec<u8>> {
+        let mut datagrams = Vec::new();
+        for i in 0..60u8 {
+            let len = match i % 20 {
+                19 => 300,
+                18 => 1400,
+                _ => 1200,
+            };
+            datagrams.push(vec![i; len]);
+        }
+        datagrams
+    }
+
+    async fn round_trip(enabled: bool) {
+        let (sender, receiver, addr) = pair().await;
+        let tx = UdpBatch::new(&sender);
+        let rx = UdpBatch::new(&receiver);
+        tx.set_enabled(&sender, enabled);
+        rx.set_enabled(&receiver, enabled);
+
+        let datagrams = datagrams();
+        let refs: Vec<&[u8]> = datagrams.iter().map(|d| d.as_slice()).collect();
+        tx.send_to(&sender, &refs, addr)
+            .await
+            .expect("send batch in test");
+
+        let pool = BufferPool::new(BATCH_SIZE);
+        let mut received = Vec::new();
+        while received.len() < datagrams.len() {
+            tokio::time::timeout(
+                Duration::from_secs(2),
+                rx.recv_batch(&receiver, &pool, &mut received),
+            )
+            .await
+            .expect("datagram in time in test")
+            .expect("recv in test");
+        }
+        assert_eq!(received.len(), datagrams.len());
+        for ((buf, from), expected) in received.iter().zip(&datagrams) {
+            assert_eq!(*from, sender.local_addr().expect("sender addr in test"));
+            assert_eq!(&buf[..], &expected[..]);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_batched_datagrams_keep_boundaries_and_order() {
+        round_trip(true).await;
+    }
+
+    #[tokio::test]
+    async fn test_unbatched_fallback() {
+        round_trip(false).await;
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_truncated_datagrams_dropped_and_counted() {
+        use crate::stats::dropped_packets;
+        use std::os::fd::AsRawFd;
+
+        let receiver = std::net::UdpSocket::bind("127.0.0.1:0").expect("bind receiver in test");
+        let sender = std::net::UdpSocket::bind("127.0.0.1:0").expect("bind sender in test");
+        let addr = receiver.local_addr().expect("receiver addr in test");
+        sender.send_to(&[1; 100], addr).expect("send in test");
+        sender.send_to(&[2; 10], addr).expect("send in test");
+
+        let dropped = dropped_packets(DropReason::Truncated);
+        let mut bufs = vec![vec![0u8; 50]; 2];
+        let mut ready = VecDeque::new();
+        recv_mmsg(receiver.as_raw_fd(), &mut bufs, &mut ready).expect("recv in test");
+        assert_eq!(ready.len(), 1);
+        assert_eq!(ready[0].len, 10);
+        assert!(dropped_packets(DropReason::Truncated) > dropped);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_only_gso_rejections_disable_gso() {
+        for errno in [libc::EIO, libc::EINVAL, libc::EOPNOTSUPP] {
+            assert!(gso_rejected(&io::Error::from_raw_os_error(errno)));
+        }
+        for errno in [libc::EMSGSIZE, libc::ENOBUFS, libc::ECONNREFUSED] {
+            assert!(!gso_rejected(&io::Error::from_raw_os_error(errno)));
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_gso_runs() {
+        let full = [0u8; 1200];
+        let short = [0u8; 500];
+        let long = [0u8; 1400];
+        assert_eq!(gso_run(&[&full, &full, &short, &full]), (3, 1200));
+        assert_eq!(gso_run(&[&full, &long]), (1, 1200));
+        let many = vec![&full[..]; 100];
+        assert_eq!(gso_run(&many), (MAX_GSO_BYTES / 1200, 1200));
+        let tiny = [0u8; 100];
+        assert_eq!(gso_run(&vec![&tiny[..]; 100]), (MAX_GSO_SEGMENTS, 100));
+    }
+}
@@ -0,0 +1,303 @@
+// Copyright (c) 2025 Thor Thor
+// Author: Thor Thor (GitHub: https://github.com/codethor0)
+// Contact: codethor@gmail.com
+// LinkedIn: https://www.linkedin.com/in/thor-thor0
+// License: MIT (see LICENSE file for details)
+
+use anyhow::{Context, Result};
+use libp2p::{request_response::OutboundRequestId, PeerId, Swarm};
+use node::{AddressLease, AddressPool, ControlMessage};
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::net::Ipv4Addr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{oneshot, RwLock};
+
+use crate::MyBehaviour;
+
+/// Frame marker for CONTROL messages carried over request-response
+///
+/// Matches `MSG_TYPE_CONTROL`. A control frame is this byte followed by an
+/// encoded `ControlMessage`; it can't be confused with a file transfer packet
+/// (first u32 < 3) and is never forwarded to TUN.
+pub const CONTROL_FRAME_MARKER: u8 = 0x10;
+
+static ADDRESS_POOL: Lazy<RwLock<Option<Arc<Mutex<AddressPool>>>>> =
+    Lazy::new(|| RwLock::new(None));
+
+// Callback for leases granted to peers (e.g. to install mesh routes)
+pub type LeaseCallback = Arc<dyn Fn(PeerId, &AddressLease) + Send + Sync>;
+static LEASE_CALLBACK: Lazy<RwLock<Option<LeaseCallback>>> = Lazy::new(|| RwLock::new(None));
+
+// Pending control requests waiting for a response, keyed by request ID
+type PendingControl = HashMap<OutboundRequestId, oneshot::Sender<Vec<u8>>>;
+static PENDING_CONTROL: Lazy<Mutex<PendingControl>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Set the address pool used to answer address requests (listener side)
+pub async fn set_address_pool(pool: Arc<Mutex<AddressPool>>) {
+    *ADDRESS_POOL.write().await = Some(pool);
+}
+
+/// Set callback invoked whenever a lease is granted to a peer (listener side)
+pub async fn set_lease_callback(callback: LeaseCallback) {
+    *LEASE_CALLBACK.write().await = Some(callback);
+}
+
+/// Encode a control message as a request-response frame
+pub fn control_frame(msg: &ControlMessage) -> Vec<u8> {
+    let mut frame = vec![CONTROL_FRAME_MARKER];
+    frame.extend_from_slice(&msg.to_bytes());
+    frame
+}
+
+/// Returns true if a request-response payload is a control frame
+pub fn is_control_frame(frame: &[u8]) -> bool {
+    frame.first() == Some(&CONTROL_FRAME_MARKER)
+}
+
+/// Handle an incoming control frame and build the response frame
+///
+/// Returns an empty response when the request can't be answered.
+pub(crate) async fn handle_control_request(peer: &PeerId, frame: &[u8]) -> Vec<u8> {
+    let msg = match ControlMessage::from_bytes(&frame[1..]) {
+        Ok(msg) => msg,
+        Err(e) => {
+            log::warn!("Invalid control frame from peer {}: {}", peer, e);
+            return Vec::new();
+        }
+    };
+
+    let pool = ADDRESS_POOL.read().await.clone();
+    let Some(pool) = pool else {
+        log::debug!("Control request from {} but no address pool set", peer);
+        return Vec::new();
+    };
+    let Some(lease) = apply_control(&pool, peer, msg) else {
+        return Vec::new();
+    };
+
+    if let Some(callback) = LEASE_CALLBACK.read().await.as_ref() {
+        callback(*peer, &lease);
+    }
+    control_frame(&ControlMessage::AddressLease(lease))
+}
+
+/// Apply a control request to the address pool, returning the granted lease
+fn apply_control(
+    pool: &Mutex<AddressPool>,
+    peer: &PeerId,
+    msg: ControlMessage,
+) -> Option<AddressLease> {
+    let mut pool = match pool.lock() {
+        Ok(guard) => guard,
+        Err(e) => {
+            log::error!("Address pool lock poisoned: {}", e);
+            return None;
+        }
+    };
+
+    match msg {
+        ControlMessage::AddressRequest { requested } => {
+            match pool.lease_for(&peer.to_bytes(), requested) {
+                Ok(lease) => Some(lease),
+                Err(e) => {
+                    log::warn!(
+                        "event=address_lease status=failed peer={} error={}",
+                        peer,
+                        e
+                    );
+                    None
+                }
+            }
+        }
+        ControlMessage::AddressRelease => {
+            pool.release(&peer.to_bytes());
+            None
+        }
+        ControlMessage::AddressLease(_) => {
+            log::warn!("Unexpected address lease from peer {}", peer);
+            None
+        }
+        ControlMessage::Error { code, reason } => {
+            log::warn!(
+                "event=peer_error peer={} code={} reason={:?}",
+                peer,
+                code,
+                reason
+            );
+            None
+        }
+        ControlMessage::Close { .. } => {
+            // libp2p connections are closed by the swarm
+            log::debug!("Ignoring close message from peer {}", peer);
+            None
+        }
+        ControlMessage::Ping(_) | ControlMessage::Pong(_) | ControlMessage::Keepalive => {
+            // Liveness is covered by the libp2p ping behaviour
+            log::debug!("Ignoring liveness message from peer {}", peer);
+            None
+        }
+        ControlMessage::PathChallenge(_)
+        | ControlMessage::PathResponse(_)
+        | ControlMessage::PmtuProbe { .. }
+        | ControlMessage::PmtuAck(_) => {
+            // libp2p handles connection migration and PMTU (QUIC) itself
+            log::debug!("Ignoring path message from peer {}", peer);
+            None
+        }
+        ControlMessage::StreamOpen { .. }
+        | ControlMessage::StreamClose { .. }
+        | ControlMessage::StreamAck(_) => {
+            // Streams live on the UDP tunnel; libp2p has its own substreams
+            log::debug!("Ignoring stream control message from peer {}", peer);
+            None
+        }
+    }
+}
+
+/// Resolve a pending control request with its response
+///
+/// Returns false if the response doesn't belong to a control request.
+pub(crate) fn complete_pending(request_id: OutboundRequestId, response: &[u8]) -> bool {
+    let sender = match PENDING_CONTROL.lock() {
+        Ok(mut pending) => pending.remove(&request_id),
+        Err(_) => None,
+    };
+    match sender {
+        Some(tx) => {
+            let _ = tx.send(response.to_vec());
+            true
+        }
+        None => false,
+    }
+}
+
+/// Drop a pending control request after an outbound failure
+pub(crate) fn fail_pending(request_id: OutboundRequestId) {
+    if let Ok(mut pending) = PENDING_CONTROL.lock() {
+        pending.remove(&request_id);
+    }
+}
+
+/// Send a request whose response is delivered to the returned receiver
+///
+/// The receiver errors if the request fails outright (e.g. the connection
+/// closed).
+pub(crate) async fn send_tracked_request(
+    swarm: &tokio::sync::Mutex<Swarm<MyBehaviour>>,
+    peer_id: &PeerId,
+    payload: Vec<u8>,
+) -> Result<oneshot::Receiver<Vec<u8>>> {
+    let (tx, rx) = oneshot::channel();
+    // Register while holding the swarm lock so the response can't race us
+    let mut s = swarm.lock().await;
+    let request_id = s
+        .behaviour_mut()
+        .request_response
+        .send_request(peer_id, payload);
+    PENDING_CONTROL
+        .lock()
+        .map_err(|e| anyhow::anyhow!("Pending control lock poisoned: {}", e))?
+        .insert(request_id, tx);
+    Ok(rx)
+}
+
+/// Request a tunnel address lease from a connected listener (dialer side)
+pub async fn request_address_lease(
+    swarm: Arc<tokio::sync::Mutex<Swarm<MyBehaviour>>>,
+    peer_id: PeerId,
+    requested: Option<Ipv4Addr>,
+    timeout: Duration,
+) -> Result<AddressLease> {
+    let rx = send_tracked_request(
+        &swarm,
+        &peer_id,
+        control_frame(&ControlMessage::AddressRequest { requested }),
+    )
+    .await?;
+
+    let response = tokio::time::timeout(timeout, rx)
+        .await
+        .context("Address lease request timed out")?
+        .context("Address lease request failed")?;
+
+    if !is_control_frame(&response) {
+        anyhow::bail!("Peer {} has no address pool", peer_id);
+    }
+    match ControlMessage::from_bytes(&response[1..]).context("Invalid address lease")? {
+        ControlMessage::AddressLease(lease) => Ok(lease),
+        other => anyhow::bail!("Unexpected control response: {:?}", other),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_control_frame_not_file_transfer() {
+        let frame = control_frame(&ControlMessage::AddressRequest { requested: None });
+        assert!(is_control_frame(&frame));
+        // File transfer packets start with a big-endian u32 below 3
+        let first = u32::from_be_bytes([frame[0], frame[1], frame[2], 0]);
+        assert!(first >= 3);
+        assert!(!is_control_frame(&[0, 0, 0, 1]));
+    }
+
+    #[tokio::test]
+    async fn test_handle_control_request_leases_address() {
+        let pool = AddressPool::new(
+            node::AddressPoolConfig::from_cidr("10.7.0.0/24").expect("valid cidr in test"),
+        )
+        .expect("pool creation in test");
+        set_address_pool(Arc::new(Mutex::new(pool))).await;
+
+        let peer = PeerId::random();
+        let frame = control_frame(&ControlMessage::AddressRequest { requested: None });
+        let response = handle_control_request(&peer, &frame).await;
+        assert!(is_control_frame(&response));
+        let decoded = ControlMessage::from_bytes(&response[1..]).expect("decode lease in test");
+        assert!(matches!(
+            decoded,
+            ControlMessage::AddressLease(AddressLease { address, .. })
+                if address == Ipv4Addr::new(10, 7, 0, 2)
+        ));
+    }
+
+    #[test]
+    fn test_reconnect_with_same_identity_keeps_address() {
+        let dir = std::env::temp_dir().join(format!("cryprq-reconnect-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).expect("state dir in test");
+        let identity_file = dir.join("identity.key");
+        let mut config =
+            node::AddressPoolConfig::from_cidr("10.7.1.0/24").expect("valid cidr in test");
+        config.state_file = Some(dir.join("leases.txt"));
+        let request = || ControlMessage::AddressRequest { requested: None };
+
+        // Another peer takes the first address, then the dialer connects
+        let pool = Mutex::new(AddressPool::new(config.clone()).expect("pool creation in test"));
+        apply_control(&pool, &PeerId::random(), request()).expect("lease in test");
+        let dialer = crate::load_or_create_identity(&identity_file)
+            .expect("dialer identity in test")
+            .public()
+            .to_peer_id();
+        let first = apply_control(&pool, &dialer, request()).expect("lease in test");
+
+        // Both ends restart; the dialer reloads its identity and reconnects
+        let pool = Mutex::new(AddressPool::new(config).expect("pool reload in test"));
+        let restarted = crate::load_or_create_identity(&identity_file)
+            .expect("dialer identity in test")
+            .public()
+            .to_peer_id();
+        assert_eq!(restarted, dialer);
+        let second = apply_control(&pool, &restarted, request()).expect("lease in test");
+        assert_eq!(second.address, first.address);
+
+        // A dialer with a new identity is a new peer
+        let stranger = apply_control(&pool, &PeerId::random(), request()).expect("lease in test");
+        assert_ne!(stranger.address, first.address);
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
@@ -0,0 +1,79 @@
+// Copyright (c) 2025 Thor Thor
+// Author: Thor Thor (GitHub: https://github.com/codethor0)
+// Contact: codethor@gmail.com
+// LinkedIn: https://www.linkedin.com/in/thor-thor0
+// License: MIT (see LICENSE file for details)
+
+use anyhow::{Context, Result};
+use libp2p::identity::Keypair;
+use std::path::Path;
+
+/// Load the libp2p identity stored at `path`, creating it if missing
+///
+/// Listeners key address leases by PeerId, so a dialer that keeps its
+/// identity across restarts gets its old address back.
+pub fn load_or_create_identity(path: &Path) -> Result<Keypair> {
+    if path.exists() {
+        let bytes = std::fs::read(path)
+            .with_context(|| format!("Failed to read identity {}", path.display()))?;
+        return Keypair::from_protobuf_encoding(&bytes)
+            .with_context(|| format!("Invalid identity in {}", path.display()));
+    }
+
+    let keypair = Keypair::generate_ed25519();
+    let bytes = keypair
+        .to_protobuf_encoding()
+        .context("Failed to encode identity")?;
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create {}", dir.display()))?;
+    }
+    write_private(path, &bytes)
+        .with_context(|| format!("Failed to write identity {}", path.display()))?;
+    log::info!(
+        "event=identity_created peer_id={} path={}",
+        keypair.public().to_peer_id(),
+        path.display()
+    );
+    Ok(keypair)
+}
+
+#[cfg(unix)]
+fn write_private(path: &Path, bytes: &[u8]) -> std::io::Result<()> {
+    use std::io::Write;
+    use std::os::unix::fs::OpenOptionsExt;
+
+    std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(path)?
+        .write_all(bytes)
+}
+
+#[cfg(not(unix))]
+fn write_private(path: &Path, bytes: &[u8]) -> std::io::Result<()> {
+    std::fs::write(path, bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_identity_survives_reload() {
+        let path = std::env::temp_dir().join(format!(
+            "cryprq-identity-{}/identity.key",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+
+        let first = load_or_create_identity(&path).expect("create identity in test");
+        let second = load_or_create_identity(&path).expect("reload identity in test");
+        assert_eq!(first.public().to_peer_id(), second.public().to_peer_id());
+
+        std::fs::write(&path, b"garbage").expect("corrupt identity in test");
+        assert!(load_or_create_identity(&path).is_err());
+        let _ = std::fs::remove_file(&path);
+    }
+}
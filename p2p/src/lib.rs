@@ -38,6 +38,12 @@ pub use metrics::start_metrics_server;
 pub mod packet_forwarder;
 pub use packet_forwarder::Libp2pPacketForwarder;
 
+pub mod control;
+pub use control::{request_address_lease, set_address_pool, set_lease_callback, LeaseCallback};
+
+mod keyfile;
+pub use keyfile::load_or_create_identity;
+
 pub mod file_transfer;
 pub use file_transfer::{
     calculate_file_hash, create_end_packet, is_end_packet, DataChunk, FileMetadata,
@@ -55,6 +61,11 @@ pub type ConnectionCallback =
 static CONNECTION_CALLBACK: Lazy<RwLock<Option<ConnectionCallback>>> =
     Lazy::new(|| RwLock::new(None));
 
+// Callback for when the last connection to a peer closes
+pub type DisconnectCallback = Arc<dyn Fn(PeerId) + Send + Sync>;
+static DISCONNECT_CALLBACK: Lazy<RwLock<Option<DisconnectCallback>>> =
+    Lazy::new(|| RwLock::new(None));
+
 // Callback for file transfer requests (receiving files)
 pub type FileTransferCallback =
     Arc<dyn Fn(PeerId, Vec<u8>) -> Result<Vec<u8>, String> + Send + Sync>;
@@ -66,12 +77,20 @@ pub async fn set_file_transfer_callback(callback: FileTransferCallback) {
     *FILE_TRANSFER_CALLBACK.write().await = Some(callback);
 }
 
+/// How long a file transfer request may wait for its response
+const FILE_REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Sends of one chunk before the transfer is abandoned
+const MAX_CHUNK_ATTEMPTS: u32 = 4;
+
 /// Send a file to a peer using the file transfer protocol
 pub async fn send_file_to_peer(
     swarm: Arc<tokio::sync::Mutex<Swarm<MyBehaviour>>>,
     peer_id: PeerId,
     file_path: std::path::PathBuf,
 ) -> Result<()> {
+    use node::{NewReno, Pacer};
+    use std::collections::VecDeque;
     use std::fs::File;
     use std::io::Read;
 
@@ -93,15 +112,12 @@ pub async fn send_file_to_peer(
     // We need to wait and ensure the swarm event loop is processing events
     tokio::time::sleep(tokio::time::Duration::from_millis(2000)).await;
 
-    // Send metadata - release lock immediately to allow event loop to process
-    {
-        let mut swarm_guard = swarm.lock().await;
-        swarm_guard
-            .behaviour_mut()
-            .request_response
-            .send_request(&peer_id, metadata_packet.clone());
-    }
-    // Lock released here - event loop can now process protocol negotiation
+    // Metadata must be acknowledged before chunks reference it
+    let rx = control::send_tracked_request(&swarm, &peer_id, metadata_packet).await?;
+    tokio::time::timeout(FILE_REQUEST_TIMEOUT, rx)
+        .await
+        .context("File metadata request timed out")?
+        .context("File metadata request failed")?;
 
     log::info!(
         "Sent file metadata to {}: {} ({} bytes)",
@@ -110,68 +126,107 @@ pub async fn send_file_to_peer(
         file_size
     );
 
-    // Yield to allow event loop to process protocol negotiation
-    // Protocol negotiation happens when send_request is called, but needs event loop processing
-    tokio::task::yield_now().await;
-    tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
-
-    // Read and send file in chunks
+    // Chunks are paced and windowed by NewReno; each response acknowledges
+    // its chunk and a timeout counts as a loss
+    let mss = CHUNK_SIZE as u64;
+    let mut cc = NewReno::new(mss);
+    let mut pacer = Pacer::new(mss);
+    let mut srtt: Option<Duration> = None;
+    let mut in_flight_bytes = 0u64;
+    let mut in_flight = tokio::task::JoinSet::new();
+    let mut retransmit: VecDeque<(DataChunk, u32)> = VecDeque::new();
     let mut file = File::open(&file_path)?;
-    let mut chunk_id = 0u32;
     let mut buffer = vec![0u8; CHUNK_SIZE];
+    let mut next_chunk_id = 0u32;
+    let mut eof = false;
 
     loop {
-        let bytes_read = file.read(&mut buffer)?;
-        if bytes_read == 0 {
-            break;
+        // Fill the congestion window, retransmissions first
+        while cc.can_send(in_flight_bytes) {
+            let (chunk, attempt) = match retransmit.pop_front() {
+                Some(entry) => entry,
+                None if !eof => {
+                    let bytes_read = file.read(&mut buffer)?;
+                    if bytes_read == 0 {
+                        eof = true;
+                        continue;
+                    }
+                    let chunk = DataChunk {
+                        chunk_id: next_chunk_id,
+                        data: buffer[..bytes_read].to_vec(),
+                    };
+                    next_chunk_id += 1;
+                    (chunk, 0)
+                }
+                None => break,
+            };
+            if let Err(delay) = pacer.try_send(chunk.data.len(), Instant::now()) {
+                tokio::time::sleep(delay).await;
+            }
+
+            let len = chunk.data.len() as u64;
+            let rx = control::send_tracked_request(&swarm, &peer_id, chunk.serialize()).await?;
+            in_flight_bytes += len;
+            log::debug!(
+                "Sent chunk {} to {} ({} bytes, attempt {})",
+                chunk.chunk_id,
+                peer_id,
+                len,
+                attempt
+            );
+            let sent_at = Instant::now();
+            in_flight.spawn(async move {
+                let acked = matches!(
+                    tokio::time::timeout(FILE_REQUEST_TIMEOUT, rx).await,
+                    Ok(Ok(_))
+                );
+                (chunk, attempt, sent_at, acked)
+            });
         }
 
-        let chunk = DataChunk {
-            chunk_id,
-            data: buffer[..bytes_read].to_vec(),
+        let Some(done) = in_flight.join_next().await else {
+            break;
         };
-        let chunk_packet = chunk.serialize();
-
-        {
-            let mut swarm_guard = swarm.lock().await;
-            swarm_guard
-                .behaviour_mut()
-                .request_response
-                .send_request(&peer_id, chunk_packet);
+        let (chunk, attempt, sent_at, acked) = done.context("File chunk task failed")?;
+        let len = chunk.data.len() as u64;
+        let offset = chunk.chunk_id as u64 * mss;
+        let sent_offset = next_chunk_id as u64 * mss;
+        in_flight_bytes -= len;
+        if acked {
+            let sample = sent_at.elapsed();
+            let smoothed = srtt.map_or(sample, |srtt| (srtt * 7 + sample) / 8);
+            srtt = Some(smoothed);
+            cc.on_ack(len, offset + len);
+            pacer.set_rate(cc.window(), smoothed);
+        } else {
+            cc.on_loss(offset, in_flight_bytes + len, sent_offset);
+            if attempt + 1 >= MAX_CHUNK_ATTEMPTS {
+                anyhow::bail!(
+                    "Chunk {} to {} not acknowledged after {} attempts",
+                    chunk.chunk_id,
+                    peer_id,
+                    MAX_CHUNK_ATTEMPTS
+                );
+            }
+            log::warn!(
+                "Chunk {} to {} lost, retransmitting (cwnd={})",
+                chunk.chunk_id,
+                peer_id,
+                cc.window()
+            );
+            retransmit.push_back((chunk, attempt + 1));
         }
-        // Lock released immediately to allow event loop processing
-
-        log::debug!(
-            "Sent chunk {} to {} ({} bytes)",
-            chunk_id,
-            peer_id,
-            bytes_read
-        );
-
-        // Yield to allow event loop to process
-        tokio::task::yield_now().await;
-        tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
-
-        chunk_id += 1;
     }
 
-    // Send end packet
-    let end_packet = create_end_packet();
-    {
-        let mut swarm_guard = swarm.lock().await;
-        swarm_guard
-            .behaviour_mut()
-            .request_response
-            .send_request(&peer_id, end_packet);
-    }
-    // Lock released immediately
+    // Every chunk is acknowledged: the receiver can assemble the file
+    let rx = control::send_tracked_request(&swarm, &peer_id, create_end_packet()).await?;
+    tokio::time::timeout(FILE_REQUEST_TIMEOUT, rx)
+        .await
+        .context("File end request timed out")?
+        .context("File end request failed")?;
 
     log::debug!("Sent end packet to {}", peer_id);
 
-    // Yield to allow event loop to process end packet
-    tokio::task::yield_now().await;
-    tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
-
     log::info!("File transfer complete: {} sent to {}", filename, peer_id);
     Ok(())
 }
@@ -198,6 +253,20 @@ pub async fn set_connection_callback(callback: ConnectionCallback) {
     *CONNECTION_CALLBACK.write().await = Some(callback);
 }
 
+// Set callback for peer disconnection
+pub async fn set_disconnect_callback(callback: DisconnectCallback) {
+    *DISCONNECT_CALLBACK.write().await = Some(callback);
+}
+
+/// Notify the disconnect callback once no connection to `peer_id` remains
+async fn notify_disconnect(peer_id: PeerId, num_established: u32) {
+    if num_established == 0 {
+        if let Some(callback) = DISCONNECT_CALLBACK.read().await.as_ref() {
+            callback(peer_id);
+        }
+    }
+}
+
 // Define the error type correctly
 #[derive(Debug, Error)]
 pub enum P2PError {
@@ -212,6 +281,8 @@ static KEYS: Lazy<RwLock<Option<(KyberPublicKey, KyberSecretKey)>>> =
     Lazy::new(|| RwLock::new(None));
 static ALLOWED_PEERS: Lazy<RwLock<Option<HashSet<PeerId>>>> = Lazy::new(|| RwLock::new(None));
 // PPK store for post-quantum pre-shared keys
+// Identity used by new swarms; a fresh one per swarm when unset
+static LOCAL_IDENTITY: Lazy<RwLock<Option<identity::Keypair>>> = Lazy::new(|| RwLock::new(None));
 static PPK_STORE: Lazy<RwLock<PPKStore>> = Lazy::new(|| RwLock::new(PPKStore::new()));
 static BACKOFF_CONFIG: Lazy<BackoffConfig> = Lazy::new(|| BackoffConfig {
     base_ms: read_env_u64("CRYPRQ_BACKOFF_BASE_MS").unwrap_or(500),
@@ -400,9 +471,21 @@ pub async fn get_ppk_for_peer(peer_id_bytes: &[u8; 32]) -> Option<PostQuantumPSK
     store.get(peer_id_bytes, now).cloned()
 }
 
+/// Use `keypair` as the identity of swarms created from now on
+///
+/// Without one, every swarm gets a new PeerId, so listeners treat a
+/// restarted node as a new peer (see `load_or_create_identity`).
+pub async fn set_local_identity(keypair: identity::Keypair) {
+    *LOCAL_IDENTITY.write().await = Some(keypair);
+}
+
 pub async fn init_swarm(
 ) -> Result<Swarm<MyBehaviour>, Box<dyn std::error::Error + Send + Sync + 'static>> {
-    let local_key = identity::Keypair::generate_ed25519();
+    let local_key = LOCAL_IDENTITY
+        .read()
+        .await
+        .clone()
+        .unwrap_or_else(identity::Keypair::generate_ed25519);
     let swarm = SwarmBuilder::with_existing_identity(local_key)
         .with_tokio()
         .with_tcp(
@@ -528,8 +611,13 @@ pub async fn start_listener(addr: &str) -> Result<()> {
                 record_failure(&send_back_addr);
                 println!("Incoming connection error: {error:?}");
             }
-            SwarmEvent::ConnectionClosed { .. } => {
+            SwarmEvent::ConnectionClosed {
+                peer_id,
+                num_established,
+                ..
+            } => {
                 metrics::dec_active_peers();
+                notify_disconnect(peer_id, num_established).await;
             }
             SwarmEvent::Behaviour(MyBehaviourEvent::Ping(event)) => {
                 println!("Ping event: {event:?}");
@@ -548,7 +636,16 @@ pub async fn start_listener(addr: &str) -> Result<()> {
                                         request[0], request[1], request[2], request[3],
                                     ]) < 3; // File transfer packet types are 0, 1, or 2
 
-                                if is_file_transfer {
+                                if control::is_control_frame(&request) {
+                                    // Control request (address lease etc.) - answer directly
+                                    let response =
+                                        control::handle_control_request(&peer, &request).await;
+                                    let mut s = swarm_for_loop.lock().await;
+                                    let _ = s
+                                        .behaviour_mut()
+                                        .request_response
+                                        .send_response(channel, response);
+                                } else if is_file_transfer {
                                     // Handle file transfer request
                                     let callback = FILE_TRANSFER_CALLBACK.read().await.clone();
                                     let mut s = swarm_for_loop.lock().await;
@@ -624,6 +721,9 @@ pub async fn start_listener(addr: &str) -> Result<()> {
                                 request_id,
                                 ..
                             } => {
+                                if control::complete_pending(request_id, &response) {
+                                    continue;
+                                }
                                 // Response to our request - acknowledgment
                                 log::debug!(
                                     "Received response for request {:?} ({} bytes)",
@@ -636,6 +736,7 @@ pub async fn start_listener(addr: &str) -> Result<()> {
                     request_response::Event::OutboundFailure {
                         error, request_id, ..
                     } => {
+                        control::fail_pending(request_id);
                         log::warn!(
                             "Request-response outbound failure for {:?}: {:?}",
                             request_id,
@@ -768,7 +869,16 @@ pub async fn dial_peer(addr: String) -> Result<()> {
                                         request[0], request[1], request[2], request[3],
                                     ]) < 3; // File transfer packet types are 0, 1, or 2
 
-                                if is_file_transfer {
+                                if control::is_control_frame(&request) {
+                                    // Control request (address lease etc.) - answer directly
+                                    let response =
+                                        control::handle_control_request(&peer, &request).await;
+                                    let mut s = swarm_for_loop.lock().await;
+                                    let _ = s
+                                        .behaviour_mut()
+                                        .request_response
+                                        .send_response(channel, response);
+                                } else if is_file_transfer {
                                     // Handle file transfer request
                                     let callback = FILE_TRANSFER_CALLBACK.read().await.clone();
                                     let mut s = swarm_for_loop.lock().await;
@@ -844,6 +954,9 @@ pub async fn dial_peer(addr: String) -> Result<()> {
                                 request_id,
                                 ..
                             } => {
+                                if control::complete_pending(request_id, &response) {
+                                    continue;
+                                }
                                 // Response to our request - acknowledgment
                                 log::debug!(
                                     "Received response for request {:?} ({} bytes)",
@@ -856,6 +969,7 @@ pub async fn dial_peer(addr: String) -> Result<()> {
                     request_response::Event::OutboundFailure {
                         error, request_id, ..
                     } => {
+                        control::fail_pending(request_id);
                         log::warn!(
                             "Request-response outbound failure for {:?}: {:?}",
                             request_id,
@@ -879,8 +993,13 @@ pub async fn dial_peer(addr: String) -> Result<()> {
                     println!("Dialing peer (connection {connection_id:?})");
                 }
             }
-            SwarmEvent::ConnectionClosed { peer_id, .. } => {
+            SwarmEvent::ConnectionClosed {
+                peer_id,
+                num_established,
+                ..
+            } => {
                 println!("Connection closed with {peer_id:?}");
+                notify_disconnect(peer_id, num_established).await;
                 // For VPN mode, we might want to reconnect
                 // For now, just log and continue
             }
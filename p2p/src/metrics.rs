@@ -11,8 +11,11 @@ use hyper::{
     service::{make_service_fn, service_fn},
     Response, Server, StatusCode,
 };
+use node::stats::{cover_bytes, dropped_packets, CoverBytes, DropReason};
 use once_cell::sync::Lazy;
-use prometheus::{opts, Encoder, Gauge, IntCounter, IntGauge, Registry, TextEncoder};
+use prometheus::{
+    opts, Encoder, Gauge, IntCounter, IntCounterVec, IntGauge, Registry, TextEncoder,
+};
 use std::{
     convert::Infallible,
     net::SocketAddr,
@@ -47,6 +50,14 @@ fn register_int_gauge(name: &str, help: &str) -> IntGauge {
     gauge
 }
 
+fn register_counter_vec(name: &str, help: &str, labels: &[&str]) -> IntCounterVec {
+    let counter = IntCounterVec::new(opts!(name, help), labels).expect("counter vec");
+    REGISTRY
+        .register(Box::new(counter.clone()))
+        .expect("register counter vec");
+    counter
+}
+
 static HANDSHAKES_ATTEMPTED: Lazy<IntCounter> =
     Lazy::new(|| register_counter("handshakes_attempted", "Total handshake attempts"));
 static HANDSHAKES_SUCCESS: Lazy<IntCounter> =
@@ -77,6 +88,22 @@ static ROTATION_EPOCH: Lazy<IntGauge> = Lazy::new(|| {
 static ACTIVE_PEERS: Lazy<IntGauge> =
     Lazy::new(|| register_int_gauge("current_peers", "Current active peers"));
 
+static PACKETS_DROPPED: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_counter_vec(
+        "packets_dropped_total",
+        "VPN packets dropped before TUN/peer, by reason",
+        &["reason"],
+    )
+});
+
+static COVER_BYTES: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_counter_vec(
+        "cover_bytes_total",
+        "Bytes sent in constant-rate cover traffic slots, by data, padding or dummy",
+        &["kind"],
+    )
+});
+
 static HEALTHY: AtomicBool = AtomicBool::new(false);
 static ROTATION_COUNTER: AtomicU64 = AtomicU64::new(0);
 
@@ -104,7 +131,26 @@ async fn handle_request(req: hyper::Request<Body>) -> Result<Response<Body>, Inf
     Ok(response)
 }
 
+/// Bring counters kept by the node crate up to date before export
+fn sync_node_counters() {
+    for reason in DropReason::ALL {
+        let counter = PACKETS_DROPPED.with_label_values(&[reason.as_str()]);
+        let total = dropped_packets(reason);
+        if total > counter.get() {
+            counter.inc_by(total - counter.get());
+        }
+    }
+    for kind in CoverBytes::ALL {
+        let counter = COVER_BYTES.with_label_values(&[kind.as_str()]);
+        let total = cover_bytes(kind);
+        if total > counter.get() {
+            counter.inc_by(total - counter.get());
+        }
+    }
+}
+
 fn encode_metrics_response() -> Response<Body> {
+    sync_node_counters();
     let metric_families = REGISTRY.gather();
     let mut buffer = Vec::new();
     let encoder = TextEncoder::new();